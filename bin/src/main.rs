@@ -1,24 +1,51 @@
-use build::{build_links, install, migrate, pages::Builder, update};
-use persistance::fs::{
-    config::read_config,
-    create_journal_entry,
-    utils::{get_config_location, get_data_dir_location, normalize_wiki_location},
+use build::{
+    build_links, encryption,
+    epub::{export_epub, Selection},
+    import::{import, Source},
+    install, migrate,
+    pages::Builder,
+    update,
+};
+use persistance::{
+    fs::{
+        config::{read_config, Config},
+        create_journal_entry,
+        journal::{today, update_rollups},
+        utils::{
+            derive_notebook_data_location, get_config_location, get_data_dir_location,
+            get_wiki_location, normalize_wiki_location, parse_location, BASE_PATH_VAR,
+            CONFIG_FILE_VAR, NOTEBOOK_DATA_VAR,
+        },
+    },
+    media::thumbnail_widths,
 };
 use search_engine::build_search_index;
-use std::{path::PathBuf, process::exit, sync::Arc, time::Instant};
+use std::{
+    env,
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+    process::exit,
+    sync::Arc,
+    time::Instant,
+};
 use task_queue::process_tasks;
-use task_runners::{git_update, sync, JobQueue};
+use task_runners::{
+    backup, git_update, images, runners::bibliography_runner::citation_label, sync, trash, watcher,
+    JobQueue,
+};
 use tokio::{fs, sync::Mutex, task::spawn_blocking};
 use www::server;
 
 #[macro_use]
 extern crate lazy_static;
 
+mod notebooks;
+mod reload;
 mod task_queue;
 
 #[tokio::main]
 async fn main() {
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    let custom_notebook = select_notebook(&mut args);
     let mut build_all = false;
     if !args.is_empty() {
         let arg = args[0].as_str();
@@ -28,7 +55,36 @@ async fn main() {
             "-b" | "--build" => build_all = true,
             "-i" | "--init" => return install(),
             "-u" | "--update" => return update(),
-            "-m" | "--migrate" => return migrate(),
+            "-m" | "--migrate" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                return migrate();
+            }
+            "-e" | "--encrypt" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                return encryption::encrypt();
+            }
+            "-d" | "--decrypt" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                return encryption::decrypt();
+            }
+            "--backup" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                encryption::unlock();
+                return backup_notebook(args.get(1).map(PathBuf::from));
+            }
+            "--restore" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                return restore_notebook(&args[1..]).await;
+            }
+            "--epub" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                encryption::unlock();
+                return export_book(&args[1..]).await;
+            }
+            "--import" => {
+                use_notebook_data(&read_config(), custom_notebook);
+                return import_notes(&args[1..]);
+            }
             _ => {
                 if arg.starts_with('-') {
                     eprintln!("unknown option: {}", arg);
@@ -36,10 +92,15 @@ async fn main() {
                 }
                 if !arg.is_empty() {
                     let config = read_config();
-                    let location = normalize_wiki_location(&config.general.wiki_location);
+                    use_notebook_data(&config, custom_notebook);
+                    let location = get_wiki_location().to_string_lossy().into_owned();
+                    encryption::unlock();
                     create_journal_entry(args.join(" ")).await.unwrap();
+                    if let Err(e) = update_rollups(today()) {
+                        eprintln!("could not update journal rollups: {}", e);
+                    }
                     if config.sync.use_git {
-                        git_update(&location, config.sync.branch);
+                        git_update(&location, config.sync.branch.clone());
                     }
                     exit(0);
                 }
@@ -47,41 +108,199 @@ async fn main() {
         }
     }
     let config = read_config();
-    let location = normalize_wiki_location(&config.general.wiki_location);
+    if let Some(wikis) = config.wikis.clone().filter(|wikis| !wikis.is_empty()) {
+        if build_all {
+            eprintln!("-b builds a single notebook, run it with the notebook's own config");
+            exit(1);
+        }
+        return notebooks::serve(config.general.port, &config.general.host, wikis).await;
+    }
+    use_notebook_data(&config, custom_notebook);
+    let location = get_wiki_location().to_string_lossy().into_owned();
+    wikitext::parsers::set_thumbnail_widths(thumbnail_widths());
+    wikitext::parsers::set_id_resolver(persistance::ids::title_for);
+    wikitext::parsers::set_citation_resolver(citation_label);
+    if let Ok(base_path) = env::var(BASE_PATH_VAR) {
+        wikitext::parsers::set_base_path(&base_path);
+        notebooks::exit_with_hub();
+    }
+    encryption::unlock();
     if build_all {
         let now = Instant::now();
         if PathBuf::from("./public").exists() {
             fs::remove_dir_all("./public").await.unwrap();
         }
         let builder = Builder::new();
-        builder.sweep(&location).await;
+        builder.sweep().await;
         builder.compile_all().await;
         println!("Built static site in: {}ms", now.elapsed().as_millis());
     } else {
         let job_queue = Arc::new(JobQueue::default());
         if config.sync.use_git {
-            sync(
-                &location,
-                config.sync.sync_interval,
-                config.sync.branch.clone(),
-                job_queue.clone(),
-            )
-            .await;
+            sync(&location, job_queue.clone()).await;
         }
+        backup::schedule(job_queue.clone());
+        watcher::watch(job_queue.clone());
         let now = Instant::now();
-        // TODO: Don't clone so much...
-        let spec_loc = location.clone();
-        let loc = Arc::new(location);
-        let (links, _) = tokio::join!(
-            build_links(loc.clone()),
-            spawn_blocking(move || build_search_index(spec_loc.as_str()))
-        );
+        spawn_blocking(trash::purge_expired);
+        spawn_blocking(images::generate_missing_thumbnails);
+        let (links, _) = tokio::join!(build_links(), spawn_blocking(build_search_index));
         println!("<indexing took: {:?}>", now.elapsed());
         let links = Arc::new(Mutex::new(links));
         let queue = job_queue.clone();
-        tokio::spawn(process_tasks(queue, loc.clone(), links.clone()));
-        server(config.general, (links, job_queue.clone())).await
+        tokio::spawn(process_tasks(queue, links.clone()));
+        tokio::spawn(reload::watch_config());
+        server(config.general.clone(), (links, job_queue.clone())).await
+    }
+}
+
+/// Runs `--backup [<file>]`, writing to the backup location unless a file is given.
+fn backup_notebook(file: Option<PathBuf>) {
+    let result = match file {
+        Some(file) => backup::backup_to(&file).map(|manifest| (file, manifest)),
+        None => backup::backup(),
+    };
+    match result {
+        Ok((path, manifest)) => {
+            println!("Backed up {} files to {:?}", manifest.entries.len(), path)
+        }
+        Err(e) => {
+            eprintln!("Could not back up the wiki: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Runs `--restore <file>`. Nothing is written unless the whole backup checks out. The search
+/// index and backlinks are rebuilt from the restored notes.
+async fn restore_notebook(args: &[String]) {
+    if args.len() != 1 {
+        eprintln!("usage: tendril --restore <file>");
+        exit(1);
+    }
+    let mut archive = match backup::Backup::open(Path::new(&args[0])) {
+        Ok(archive) => archive,
+        Err(e) => {
+            eprintln!("Could not restore {}: {}", args[0], e);
+            exit(1);
+        }
+    };
+    println!("Restoring the backup taken {}", archive.manifest.created);
+    // The encryption key is one of the restored files, so unlock only once they're back.
+    let restored = archive.restore_files().and_then(|files| {
+        encryption::unlock();
+        archive.restore_notes().map(|notes| (files, notes))
+    });
+    match restored {
+        Ok((files, notes)) => println!("Restored {} notes and {} files", notes, files),
+        Err(e) => {
+            eprintln!("Could not restore {}: {}", args[0], e);
+            exit(1);
+        }
     }
+    spawn_blocking(build_search_index).await.unwrap();
+    println!("<rebuilt the search index>");
+}
+
+/// Runs `--epub <file> (--tag <tag> | --query <query> | <title>...)`.
+async fn export_book(args: &[String]) {
+    let usage = "usage: tendril --epub <file> (--tag <tag> | --query <query> | <title>...)";
+    if args.len() < 2 {
+        eprintln!("{}", usage);
+        exit(1);
+    }
+    let selection = match args[1].as_str() {
+        "--tag" if args.len() == 3 => Selection::Tag(args[2].clone()),
+        "--query" if args.len() >= 3 => Selection::Query(args[2..].join(" ")),
+        arg if !arg.starts_with('-') => Selection::Titles(args[1..].to_vec()),
+        _ => {
+            eprintln!("{}", usage);
+            exit(1);
+        }
+    };
+    match export_epub(&selection, Path::new(&args[0])).await {
+        Ok(titles) => println!("Wrote {} notes to {}", titles.len(), args[0]),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Runs `--import <source> <path> [--dry-run]`.
+fn import_notes(args: &[String]) {
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let args = args
+        .iter()
+        .filter(|arg| *arg != "--dry-run")
+        .collect::<Vec<&String>>();
+    if args.len() != 2 {
+        eprintln!("usage: tendril --import <obsidian|logseq|roam> <path> [--dry-run]");
+        exit(1);
+    }
+    let source = match args[0].parse::<Source>() {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    if !dry_run {
+        encryption::unlock();
+    }
+    match import(source, Path::new(args[1]), dry_run) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Applies `-c <path>` and a wiki directory given as the only argument, removing them from
+/// `args`. Returns whether either was given. A directory has to be written as a path, like
+/// `./notes` or `~/notes`, since a single word is a journal entry.
+fn select_notebook(args: &mut Vec<String>) -> bool {
+    let mut custom_notebook = false;
+    if let Some(idx) = args.iter().position(|arg| arg == "-c" || arg == "--config") {
+        if idx + 1 >= args.len() {
+            eprintln!("missing config path for {}", args[idx]);
+            exit(1);
+        }
+        let path = PathBuf::from(args.remove(idx + 1));
+        args.remove(idx);
+        // `-i` creates the config file, so it doesn't have to exist yet.
+        let path =
+            std::fs::canonicalize(&path).unwrap_or_else(|_| env::current_dir().unwrap().join(path));
+        env::set_var(CONFIG_FILE_VAR, path);
+        custom_notebook = true;
+    }
+    let is_path =
+        |arg: &str| arg.starts_with('.') || arg.contains('/') || arg.contains(MAIN_SEPARATOR);
+    if args.len() == 1 && is_path(&args[0]) && Path::new(&args[0]).is_dir() {
+        let location = std::fs::canonicalize(args.remove(0)).unwrap();
+        env::set_var(
+            "TENDRIL_WIKI_DIR",
+            normalize_wiki_location(&location.to_string_lossy()),
+        );
+        custom_notebook = true;
+    }
+    custom_notebook
+}
+
+/// Points the notebook's archive, search index, history, trash and tasks at its own data
+/// location, before anything reads them.
+fn use_notebook_data(config: &Config, custom_notebook: bool) {
+    if env::var(NOTEBOOK_DATA_VAR).is_ok() {
+        return;
+    }
+    let location = match &config.general.data_location {
+        Some(location) => parse_location(location),
+        None if custom_notebook => derive_notebook_data_location(&get_wiki_location()),
+        None => return,
+    };
+    std::fs::create_dir_all(location.join("archive")).unwrap();
+    env::set_var(NOTEBOOK_DATA_VAR, location);
 }
 
 fn print_version() {
@@ -91,29 +310,42 @@ fn print_version() {
 fn print_help() {
     println!(
         "\nConfig file found at \x1b[38;5;47m{:#?}\x1b[0m\nInstall files found at \x1b[38;5;37m{:#?}\x1b[0m\n",
-        get_config_location().0,
+        get_config_location().1,
         get_data_dir_location());
     print!(
-        "Usage: tendril [options]
+        "Usage: tendril [options] [<dir> | <journal entry>]
         Options:
+        -c, --config <path>          Use the config file at <path>.
         -i, --init                   Initialize config file and install
         -b, --build                  Build all pages as HTML and output to ./public
         -v, --version                Print version.
         -h, --help                   Show this message.
         -u, --update                 Update the installation by copying over any new files or updating config.toml.
+        --backup [<file>]            Back up notes, media, archives, tasks and config into one file.
+                                     Written to the backup location unless a file is given.
+        --restore <file>             Restore a backup and rebuild the search index.
+        -e, --encrypt                Encrypt every note, archive and revision with a passphrase.
+        -d, --decrypt                Decrypt the wiki and stop asking for a passphrase.
+        --epub <file> <notes>        Export notes as an EPUB book. <notes> is --tag <tag>,
+                                     --query <query> or a list of note titles.
+        --import <source> <path>     Import an Obsidian vault, Logseq graph or Roam JSON export.
+                                     <source> is obsidian, logseq or roam. Add --dry-run to
+                                     list what would be imported without writing anything.
+
+        Examples:
+          - Start the wiki in the ~/work/wiki directory, a directory is written as a path
+                $ tendril ~/work/wiki
+          - Add an entry to today's journal
+                $ tendril finished the quarterly report
+          - Start the wiki at the location set in the config file
+                $ tendril
+          - Start the wiki in the current directory with a custom config file
+                $ tendril . -c ./config.toml
+          - Export every note tagged \"reading\" as a book
+                $ tendril --epub reading.epub --tag reading
+          - See what importing an Obsidian vault would create
+                $ tendril --import obsidian ~/Documents/vault --dry-run
 
 ",
     );
 }
-
-// TODO: Maybe later add in multi-config, multi-folder stuff
-// -c <path>, --config <path>   Use config at <path>
-//
-// Examples:
-
-//   - Start wiki in the ~/work/wiki directory
-//         $ tendril ~/work/wiki
-//   - Start wiki at location specified in config file
-//         $ tendril
-//   - Start wiki in current folder with a custom config file
-//         $ tendril . -c ./config.toml
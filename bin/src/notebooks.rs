@@ -0,0 +1,239 @@
+use std::{
+    env, io,
+    path::{PathBuf, MAIN_SEPARATOR},
+    process::{exit, Stdio},
+    sync::Arc,
+    thread,
+};
+
+use persistance::fs::{
+    config::{load_config_at, Wiki},
+    utils::{get_config_location, parse_location, BASE_PATH_VAR, NOTEBOOK_DATA_VAR},
+};
+use tokio::{
+    net::TcpStream,
+    process::{Child, Command},
+    sync::{watch, Mutex},
+    time::{sleep, Duration, Instant},
+};
+use www::hub::{hub, is_valid_prefix, Mount};
+
+/// How long to wait before starting a notebook that stopped, doubled each time it stops again
+/// soon after starting.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// A notebook that ran for this long before stopping is restarted without waiting long.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+/// How often to check whether a starting notebook is listening yet.
+const STARTUP_POLL: Duration = Duration::from_millis(250);
+
+struct Notebook {
+    prefix: String,
+    config: PathBuf,
+    port: u16,
+    /// Where the hub serves the notebook, passed on as the notebook's `host`.
+    host: String,
+}
+
+/// Config paths in `[[wikis]]` are relative to the hub's own config file.
+fn config_path(config: &str) -> PathBuf {
+    let path = parse_location(config);
+    let path = PathBuf::from(path.to_string_lossy().trim_end_matches(MAIN_SEPARATOR));
+    if path.is_absolute() {
+        path
+    } else {
+        get_config_location().0.join(path)
+    }
+}
+
+/// Checks every notebook in `[[wikis]]` before any of them are started.
+fn notebooks(port: u16, host: &str, wikis: Vec<Wiki>) -> Vec<Notebook> {
+    let mut notebooks: Vec<Notebook> = Vec::with_capacity(wikis.len());
+    for wiki in wikis {
+        if !is_valid_prefix(&wiki.prefix) {
+            eprintln!(
+                "invalid prefix {:?}, use letters, numbers, - and _",
+                wiki.prefix
+            );
+            exit(1);
+        }
+        if notebooks
+            .iter()
+            .any(|notebook| notebook.prefix == wiki.prefix)
+        {
+            eprintln!("prefix {:?} is used by more than one notebook", wiki.prefix);
+            exit(1);
+        }
+        let config = config_path(&wiki.config);
+        if !config.exists() {
+            eprintln!(
+                "could not find the config for {:?} at {:?}",
+                wiki.prefix, config
+            );
+            exit(1);
+        }
+        let notebook_port = match load_config_at(&config) {
+            Ok(notebook) => notebook.general.port,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        };
+        if notebook_port == port
+            || notebooks
+                .iter()
+                .any(|notebook| notebook.port == notebook_port)
+        {
+            eprintln!(
+                "{:?} needs a port of its own, {} is already in use",
+                wiki.prefix, notebook_port
+            );
+            exit(1);
+        }
+        notebooks.push(Notebook {
+            host: format!("{}/{}", host.trim_end_matches('/'), wiki.prefix),
+            prefix: wiki.prefix,
+            config,
+            port: notebook_port,
+        });
+    }
+    notebooks
+}
+
+/// Starts a tendril process for every notebook in `[[wikis]]`, each with its own statics, job
+/// queue and backlinks, and serves them from `port` under their prefixes. The notebooks only
+/// listen on localhost, are restarted if they stop and are stopped along with the hub.
+pub async fn serve(port: u16, host: &str, wikis: Vec<Wiki>) {
+    let notebooks = notebooks(port, host, wikis);
+    let mounts = notebooks
+        .iter()
+        .map(|notebook| Mount {
+            prefix: notebook.prefix.clone(),
+            port: notebook.port,
+        })
+        .collect();
+    let (stop, stopped) = watch::channel(false);
+    let starting = Arc::new(Mutex::new(()));
+    let supervisors = notebooks
+        .into_iter()
+        .map(|notebook| tokio::spawn(supervise(notebook, starting.clone(), stopped.clone())))
+        .collect::<Vec<_>>();
+    tokio::select! {
+        _ = hub(port, mounts) => {}
+        _ = shutdown() => {}
+    }
+    stop.send(true).ok();
+    for supervisor in supervisors {
+        supervisor.await.ok();
+    }
+}
+
+fn start(notebook: &Notebook) -> io::Result<Child> {
+    let tendril = env::current_exe()?;
+    // Each notebook picks its own wiki and data locations from its config. It keeps the pipe to
+    // its stdin open until the hub goes away, see [exit_with_hub].
+    Command::new(tendril)
+        .arg("-c")
+        .arg(&notebook.config)
+        .env_remove(NOTEBOOK_DATA_VAR)
+        .env_remove("TENDRIL_WIKI_DIR")
+        .env(BASE_PATH_VAR, format!("/{}", notebook.prefix))
+        .env("TENDRIL_GENERAL_HOST", &notebook.host)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+/// Waits until a starting notebook listens on its port, or stops.
+async fn started(notebook: &Notebook, child: &mut Child) {
+    loop {
+        tokio::select! {
+            _ = child.wait() => return,
+            _ = sleep(STARTUP_POLL) => {
+                if TcpStream::connect(("127.0.0.1", notebook.port)).await.is_ok() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Runs a notebook until the hub stops, starting it again whenever it stops by itself.
+/// Notebooks are started one at a time, so an encrypted notebook has the terminal to itself
+/// while it asks for its passphrase.
+async fn supervise(
+    notebook: Notebook,
+    starting: Arc<Mutex<()>>,
+    mut stopped: watch::Receiver<bool>,
+) {
+    let mut delay = RESTART_DELAY;
+    loop {
+        let launched = Instant::now();
+        let mut child = {
+            let _terminal = starting.lock().await;
+            if *stopped.borrow() {
+                return;
+            }
+            println!("<starting the {} notebook>", notebook.prefix);
+            let mut child = match start(&notebook) {
+                Ok(child) => child,
+                Err(e) => {
+                    eprintln!("could not start the {:?} notebook: {}", notebook.prefix, e);
+                    return;
+                }
+            };
+            started(&notebook, &mut child).await;
+            child
+        };
+        tokio::select! {
+            status = child.wait() => match status {
+                Ok(status) => eprintln!("the {} notebook stopped: {}", notebook.prefix, status),
+                Err(e) => eprintln!("lost track of the {} notebook: {}", notebook.prefix, e),
+            },
+            _ = stopped.changed() => {
+                if let Err(e) = child.kill().await {
+                    eprintln!("could not stop the {} notebook: {}", notebook.prefix, e);
+                }
+                return;
+            }
+        }
+        delay = if launched.elapsed() > STABLE_RUN {
+            RESTART_DELAY
+        } else {
+            (delay * 2).min(MAX_RESTART_DELAY)
+        };
+        eprintln!(
+            "restarting the {} notebook in {}s",
+            notebook.prefix,
+            delay.as_secs()
+        );
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = stopped.changed() => return,
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn shutdown() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown() {
+    tokio::signal::ctrl_c().await.ok();
+}
+
+/// Stops a notebook started by the hub once the hub is gone, which closes the notebook's stdin.
+/// That covers the hub being killed before it could stop its notebooks.
+pub fn exit_with_hub() {
+    thread::spawn(|| {
+        io::copy(&mut io::stdin(), &mut io::sink()).ok();
+        exit(0);
+    });
+}
@@ -0,0 +1,68 @@
+use std::{fs, path::Path, time::SystemTime};
+
+use persistance::{
+    fs::{config::reload_config, utils::get_config_location},
+    media::thumbnail_widths,
+};
+use task_runners::images;
+use tokio::{
+    task::spawn_blocking,
+    time::{sleep, Duration},
+};
+
+/// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn modified_at(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|meta| meta.modified()).ok()
+}
+
+async fn file_changed(file: &Path, last_modified: Option<SystemTime>) {
+    loop {
+        sleep(POLL_INTERVAL).await;
+        if modified_at(file) != last_modified {
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn config_changed(file: &Path, last_modified: Option<SystemTime>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+    tokio::select! {
+        _ = hangup.recv() => {}
+        _ = file_changed(file, last_modified) => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn config_changed(file: &Path, last_modified: Option<SystemTime>) {
+    file_changed(file, last_modified).await
+}
+
+/// Reloads the config when it's saved, or when the process gets SIGHUP. Settings that are only
+/// read on start are left as they are until the next restart.
+pub async fn watch_config() {
+    let (_, file) = get_config_location();
+    loop {
+        config_changed(&file, modified_at(&file)).await;
+        match reload_config() {
+            Ok(needs_restart) => {
+                println!("<config reloaded>");
+                if !needs_restart.is_empty() {
+                    eprintln!(
+                        "Restart tendril to apply the changes to {}",
+                        needs_restart.join(", ")
+                    );
+                }
+                wikitext::parsers::set_thumbnail_widths(thumbnail_widths());
+                spawn_blocking(images::generate_missing_thumbnails);
+            }
+            Err(e) => eprintln!(
+                "Could not reload the config, keeping the current one: {}",
+                e
+            ),
+        }
+    }
+}
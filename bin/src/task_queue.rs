@@ -1,25 +1,35 @@
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use build::{build_links, delete_from_global_store, rename_in_global_store, update_global_store};
+use build::{
+    build_links, delete_from_global_store, forget_in_global_store, rename_in_global_store,
+    update_global_store,
+};
 use futures::{stream, StreamExt};
-use persistance::fs::{
-    move_archive, path_to_data_structure,
-    utils::{archive_file_exists, get_file_path},
-    write, write_archive,
+use persistance::{
+    archive::{compress, decompress},
+    fs::{
+        move_archive,
+        utils::{archive_file_exists, get_archive_file_path},
+        write, write_archive,
+    },
+    ids::{self, note_ids},
+    store::note_store,
 };
 use regex::Regex;
 use search_engine::{
-    delete_archived_file, delete_entry_from_update, patch_search_from_archive,
-    patch_search_from_update,
+    delete_entry_from_update, patch_search_from_archive, patch_search_from_update,
+    remove_from_title_index, update_title_index,
 };
 use task_runners::{
-    archive::{compress, extract},
-    cache::update_mru_cache,
+    archive::extract,
+    backup,
+    cache::{purge_mru_cache, update_mru_cache},
     messages::Message,
+    trash,
     verify::verify_data_installation,
     JobQueue, Queue,
 };
-use tokio::time::sleep;
+use tokio::{fs, task::spawn_blocking, time::sleep};
 use wikitext::{processors::sanitize_html, GlobalBacklinks, PatchData};
 
 const NUM_JOBS: u32 = 50;
@@ -28,7 +38,7 @@ lazy_static! {
     static ref TITLE_RGX: Regex = Regex::new(r"\?|\\|/|\||:|;|>|<|,|\.|\n|\$|&").unwrap();
 }
 
-pub async fn process_tasks(queue: Arc<JobQueue>, location: Arc<String>, links: GlobalBacklinks) {
+pub async fn process_tasks(queue: Arc<JobQueue>, links: GlobalBacklinks) {
     loop {
         let jobs = match queue.pull(NUM_JOBS).await {
             Ok(jobs) => jobs,
@@ -43,29 +53,67 @@ pub async fn process_tasks(queue: Arc<JobQueue>, location: Arc<String>, links: G
                     Message::Rebuild => {
                         let mut links = links.lock().await;
                         links.clear();
-                        links.extend(build_links(location.clone()).await);
+                        links.extend(build_links().await);
                     }
                     Message::Patch { patch } => {
                         let note = patch.clone().into();
 
                         update_global_store(&patch.title, &note, links.clone()).await;
                         patch_search_from_update(&note);
+                        update_title_index(&patch.title);
 
                         if !patch.old_title.is_empty() && patch.old_title != patch.title {
                             rename_in_global_store(&patch.title, &patch.old_title, links.clone())
                                 .await;
+                            remove_from_title_index(&patch.old_title);
                         }
                         update_mru_cache(&patch.old_title, &patch.title).await;
                     }
                     Message::Delete { title } => {
-                        let path = get_file_path(&title).unwrap_or_else(|_| {
+                        let note = note_store().read_note(&title).unwrap_or_else(|_| {
                             panic!("Failed to find file for deletion: {}", title)
                         });
-                        let note = path_to_data_structure(&path).unwrap();
                         delete_from_global_store(&title, &note, links.clone()).await;
                         delete_entry_from_update(&title).await;
-                        delete_archived_file(&title).await;
-                        persistance::fs::delete(&title).await.unwrap();
+                        remove_from_title_index(&title);
+                        note_ids().forget(&title);
+                        // The note's archive goes into the trash with it.
+                        if let Err(e) = spawn_blocking(move || trash::move_to_trash(&title))
+                            .await
+                            .unwrap()
+                        {
+                            eprintln!("Could not move note to the trash: {}", e);
+                        }
+                    }
+                    Message::Removed { title } => {
+                        forget_in_global_store(&title, links.clone()).await;
+                        delete_entry_from_update(&title).await;
+                        remove_from_title_index(&title);
+                        note_ids().forget(&title);
+                        purge_mru_cache(&title).await;
+                    }
+                    Message::Restore { title } => {
+                        let note = match note_store().read_note(&title) {
+                            Ok(note) => note,
+                            Err(e) => {
+                                eprintln!("Could not read restored note {}: {}", title, e);
+                                return;
+                            }
+                        };
+                        ids::index(&title, &note);
+                        update_global_store(&title, &note, links.clone()).await;
+                        patch_search_from_update(&note);
+                        update_title_index(&title);
+                        let archive = match get_archive_file_path(&title) {
+                            Ok(archive) if archive.is_file() => archive,
+                            _ => return,
+                        };
+                        match fs::read(archive).await.map(decompress) {
+                            Ok(Ok(text)) => patch_search_from_archive((title, text)).await,
+                            Ok(Err(e)) | Err(e) => {
+                                eprintln!("Could not read archive of {}: {}", title, e)
+                            }
+                        }
                     }
                     Message::Archive { url, title } => {
                         let product = tokio::task::spawn_blocking(|| extract(url)).await.unwrap();
@@ -99,11 +147,13 @@ pub async fn process_tasks(queue: Arc<JobQueue>, location: Arc<String>, links: G
                             title: note_title.clone(),
                             old_title: String::with_capacity(0),
                             metadata,
+                            revision: String::new(),
                         };
                         write(&patch).await.unwrap();
                         let note = patch.clone().into();
                         update_global_store(&patch.title, &note, links.clone()).await;
                         patch_search_from_update(&note);
+                        update_title_index(&patch.title);
                         update_mru_cache(&patch.old_title, &patch.title).await;
                     }
                     Message::ArchiveBody { title, body } => {
@@ -111,6 +161,9 @@ pub async fn process_tasks(queue: Arc<JobQueue>, location: Arc<String>, links: G
                         write_archive(compressed, &title).await;
                         patch_search_from_archive((title.clone(), body)).await;
                     }
+                    Message::Backup => {
+                        spawn_blocking(backup::scheduled_backup).await.unwrap();
+                    }
                     Message::VerifyDataInstallation {
                         dataset,
                         install_location,
@@ -0,0 +1,412 @@
+pub mod parse;
+
+use parse::{clean_value, parse_entries};
+use std::fmt::Write as _;
+use std::{collections::HashMap, str::FromStr};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BibParseErr {
+    #[error("Unexpected end of bibliography file")]
+    UnexpectedEnd,
+    #[error("Entry on line {0} is missing a citation key")]
+    MissingKey(usize),
+    #[error("Malformed entry on line {line}: {msg}")]
+    Malformed { line: usize, msg: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CitationStyle {
+    #[default]
+    Apa,
+    Mla,
+    Chicago,
+}
+
+impl FromStr for CitationStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "apa" => Ok(CitationStyle::Apa),
+            "mla" => Ok(CitationStyle::Mla),
+            "chicago" => Ok(CitationStyle::Chicago),
+            _ => Err(format!("Unknown citation style: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Name {
+    pub first: String,
+    pub last: String,
+}
+
+impl Name {
+    fn initials(&self) -> String {
+        self.first
+            .split(|c: char| c.is_whitespace() || c == '-')
+            .filter(|part| !part.is_empty())
+            .filter_map(|part| part.chars().next())
+            .map(|c| format!("{}.", c))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn full(&self) -> String {
+        if self.first.is_empty() {
+            self.last.clone()
+        } else {
+            format!("{} {}", self.first, self.last)
+        }
+    }
+
+    fn inverted(&self) -> String {
+        if self.first.is_empty() {
+            self.last.clone()
+        } else {
+            format!("{}, {}", self.last, self.first)
+        }
+    }
+}
+
+/// BibTeX names are either `First Last` or `Last, First`. Anything wrapped in braces, like
+/// `{World Health Organization}`, is kept as a single last name.
+fn parse_name(raw: &str) -> Name {
+    let raw = raw.trim();
+    if raw.starts_with('{') && raw.ends_with('}') {
+        return Name {
+            first: String::new(),
+            last: clean_value(raw),
+        };
+    }
+    let cleaned = clean_value(raw);
+    if let Some((last, first)) = cleaned.split_once(',') {
+        return Name {
+            first: first.trim().into(),
+            last: last.trim().into(),
+        };
+    }
+    match cleaned.rsplit_once(' ') {
+        Some((first, last)) => Name {
+            first: first.trim().into(),
+            last: last.trim().into(),
+        },
+        None => Name {
+            first: String::new(),
+            last: cleaned,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub kind: String,
+    pub fields: HashMap<String, String>,
+}
+
+impl Entry {
+    /// Returns the field with BibTeX markup removed.
+    pub fn field(&self, name: &str) -> Option<String> {
+        self.fields
+            .get(name)
+            .map(|v| clean_value(v))
+            .filter(|v| !v.is_empty())
+    }
+
+    pub fn authors(&self) -> Vec<Name> {
+        let raw = match self.fields.get("author").or_else(|| self.fields.get("editor")) {
+            Some(raw) => raw,
+            None => return Vec::new(),
+        };
+        split_names(raw).iter().map(|n| parse_name(n)).collect()
+    }
+
+    pub fn title(&self) -> String {
+        self.field("title").unwrap_or_else(|| self.key.clone())
+    }
+
+    pub fn year(&self) -> String {
+        self.field("year").unwrap_or_else(|| "n.d.".into())
+    }
+
+    /// The container the work was published in, e.g. the journal or the book a chapter is in.
+    fn container(&self) -> Option<String> {
+        self.field("journal").or_else(|| self.field("booktitle"))
+    }
+
+    fn publisher(&self) -> Option<String> {
+        self.field("publisher")
+            .or_else(|| self.field("howpublished"))
+            .or_else(|| self.field("institution"))
+            .or_else(|| self.field("school"))
+    }
+
+    fn link(&self) -> Option<String> {
+        self.field("url")
+            .or_else(|| self.field("doi").map(|doi| format!("https://doi.org/{}", doi)))
+    }
+
+    /// Short in-text label, e.g. `Knuth, 1984`.
+    pub fn label(&self) -> String {
+        let authors = self.authors();
+        let names = match authors.len() {
+            0 => self.key.clone(),
+            1 => authors[0].last.clone(),
+            2 => format!("{} & {}", authors[0].last, authors[1].last),
+            _ => format!("{} et al.", authors[0].last),
+        };
+        format!("{}, {}", names, self.year())
+    }
+
+    /// Renders the entry as a single HTML reference in the given style.
+    pub fn format(&self, style: CitationStyle) -> String {
+        let authors = self.authors();
+        let title = escape(&self.title());
+        let year = escape(&self.year());
+        let volume = self.field("volume").map(|v| escape(&v));
+        let number = self.field("number").map(|n| escape(&n));
+        let pages = self.field("pages").map(|p| escape(&p));
+        let mut html = String::new();
+        match style {
+            CitationStyle::Apa => {
+                let names = authors
+                    .iter()
+                    .map(|a| {
+                        let initials = a.initials();
+                        if initials.is_empty() {
+                            escape(&a.last)
+                        } else {
+                            format!("{}, {}", escape(&a.last), escape(&initials))
+                        }
+                    })
+                    .collect::<Vec<String>>();
+                if !names.is_empty() {
+                    write!(html, "{} ", join_names(&names, "&amp;")).unwrap();
+                }
+                write!(html, "({}). ", year).unwrap();
+                match self.container() {
+                    Some(container) => {
+                        write!(html, "{}. <em>{}</em>", title, escape(&container)).unwrap();
+                        if let Some(volume) = volume {
+                            write!(html, ", <em>{}</em>", volume).unwrap();
+                        }
+                        if let Some(number) = number {
+                            write!(html, "({})", number).unwrap();
+                        }
+                        if let Some(pages) = pages {
+                            write!(html, ", {}", pages).unwrap();
+                        }
+                    }
+                    None => write!(html, "<em>{}</em>", title).unwrap(),
+                }
+                html.push('.');
+                if let Some(publisher) = self.publisher() {
+                    write!(html, " {}.", escape(&publisher)).unwrap();
+                }
+            }
+            CitationStyle::Mla | CitationStyle::Chicago => {
+                let is_mla = style == CitationStyle::Mla;
+                let names = authors
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, a)| escape(&if idx == 0 { a.inverted() } else { a.full() }))
+                    .collect::<Vec<String>>();
+                if !names.is_empty() {
+                    let names = if is_mla && names.len() > 2 {
+                        format!("{}, et al", names[0])
+                    } else {
+                        join_names(&names, "and")
+                    };
+                    write!(html, "{}. ", names.trim_end_matches('.')).unwrap();
+                }
+                if !is_mla {
+                    write!(html, "{}. ", year).unwrap();
+                }
+                match self.container() {
+                    Some(container) => {
+                        write!(html, "&ldquo;{}.&rdquo; <em>{}</em>", title, escape(&container))
+                            .unwrap();
+                        if let Some(volume) = volume {
+                            let label = if is_mla { "vol. " } else { "" };
+                            write!(html, ", {}{}", label, volume).unwrap();
+                        }
+                        if let Some(number) = number {
+                            write!(html, ", no. {}", number).unwrap();
+                        }
+                        if is_mla {
+                            write!(html, ", {}", year).unwrap();
+                        }
+                        if let Some(pages) = pages {
+                            if is_mla {
+                                write!(html, ", pp. {}", pages).unwrap();
+                            } else {
+                                write!(html, ": {}", pages).unwrap();
+                            }
+                        }
+                    }
+                    None => {
+                        write!(html, "<em>{}</em>", title).unwrap();
+                        if let Some(publisher) = self.publisher() {
+                            write!(html, ". {}", escape(&publisher)).unwrap();
+                        }
+                        if is_mla {
+                            write!(html, ", {}", year).unwrap();
+                        }
+                    }
+                }
+                html.push('.');
+            }
+        }
+        if let Some(link) = self.link() {
+            let link = escape(&link);
+            write!(
+                html,
+                " <a href=\"{}\" rel=\"noopener noreferrer\">{}</a>",
+                link, link
+            )
+            .unwrap();
+        }
+        html
+    }
+}
+
+fn split_names(raw: &str) -> Vec<String> {
+    // Split on " and " at brace depth zero so `{Smith and Sons}` stays a single author.
+    let mut names = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let words = raw.split_whitespace();
+    for word in words {
+        if depth == 0 && word.eq_ignore_ascii_case("and") {
+            names.push(current.trim().to_string());
+            current.clear();
+            continue;
+        }
+        depth += word.matches('{').count() as i32;
+        depth -= word.matches('}').count() as i32;
+        current.push_str(word);
+        current.push(' ');
+    }
+    if !current.trim().is_empty() {
+        names.push(current.trim().to_string());
+    }
+    names.into_iter().filter(|n| !n.is_empty()).collect()
+}
+
+fn join_names(names: &[String], conjunction: &str) -> String {
+    match names.len() {
+        0 => String::new(),
+        1 => names[0].clone(),
+        2 => format!("{} {} {}", names[0], conjunction, names[1]),
+        _ => {
+            let (last, rest) = names.split_last().unwrap();
+            format!("{}, {} {}", rest.join(", "), conjunction, last)
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, Default)]
+pub struct Library {
+    entries: Vec<Entry>,
+}
+
+impl FromStr for Library {
+    type Err = BibParseErr;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = parse_entries(s)?;
+        entries.sort_by_key(|e| {
+            let authors = e.authors();
+            let first = authors.first().map(|a| a.last.to_lowercase());
+            (first.unwrap_or_else(|| e.key.to_lowercase()), e.year())
+        });
+        Ok(Library { entries })
+    }
+}
+
+impl Library {
+    pub fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIB: &str = r#"
+@article{knuth1984,
+  author = {Knuth, Donald E.},
+  title = {Literate Programming},
+  journal = {The Computer Journal},
+  year = {1984},
+  volume = {27},
+  number = {2},
+  pages = {97--111},
+  doi = {10.1093/comjnl/27.2.97},
+}
+@book{sicp,
+  author = {Harold Abelson and Gerald Jay Sussman and Julie Sussman},
+  title = {Structure and Interpretation of Computer Programs},
+  publisher = {MIT Press},
+  year = 1996,
+}
+"#;
+
+    #[test]
+    fn parses_names() {
+        let lib: Library = BIB.parse().unwrap();
+        let sicp = lib.get("sicp").unwrap();
+        let authors = sicp.authors();
+        assert_eq!(authors.len(), 3);
+        assert_eq!(authors[1].first, "Gerald Jay");
+        assert_eq!(authors[1].last, "Sussman");
+        assert_eq!(sicp.label(), "Abelson et al., 1996");
+        let knuth = lib.get("knuth1984").unwrap();
+        assert_eq!(knuth.authors()[0].last, "Knuth");
+        assert_eq!(knuth.label(), "Knuth, 1984");
+    }
+
+    #[test]
+    fn formats_in_styles() {
+        let lib: Library = BIB.parse().unwrap();
+        let knuth = lib.get("knuth1984").unwrap();
+        assert_eq!(
+            knuth.format(CitationStyle::Apa),
+            "Knuth, D. E. (1984). Literate Programming. <em>The Computer Journal</em>, <em>27</em>(2), 97–111. <a href=\"https://doi.org/10.1093/comjnl/27.2.97\" rel=\"noopener noreferrer\">https://doi.org/10.1093/comjnl/27.2.97</a>"
+        );
+        let sicp = lib.get("sicp").unwrap();
+        assert_eq!(
+            sicp.format(CitationStyle::Mla),
+            "Abelson, Harold, et al. <em>Structure and Interpretation of Computer Programs</em>. MIT Press, 1996."
+        );
+        assert!(sicp
+            .format(CitationStyle::Chicago)
+            .starts_with("Abelson, Harold, Gerald Jay Sussman, and Julie Sussman. 1996."));
+    }
+
+    #[test]
+    fn parses_style_names() {
+        assert_eq!("MLA".parse::<CitationStyle>(), Ok(CitationStyle::Mla));
+        assert!("harvard".parse::<CitationStyle>().is_err());
+    }
+}
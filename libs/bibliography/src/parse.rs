@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+
+use crate::{BibParseErr, Entry};
+
+/// A small recursive descent parser for the subset of BibTeX that reference managers like Zotero
+/// and JabRef export. `@comment` and `@preamble` blocks are skipped, `@string` macros are expanded
+/// when they're used as bare field values.
+struct BibParser {
+    chars: Vec<char>,
+    pos: usize,
+    macros: HashMap<String, String>,
+}
+
+impl BibParser {
+    fn new(input: &str) -> Self {
+        BibParser {
+            chars: input.chars().collect(),
+            pos: 0,
+            macros: HashMap::new(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn line(&self) -> usize {
+        self.chars[..self.pos.min(self.chars.len())]
+            .iter()
+            .filter(|&&c| c == '\n')
+            .count()
+            + 1
+    }
+
+    fn malformed(&self, msg: &str) -> BibParseErr {
+        BibParseErr::Malformed {
+            line: self.line(),
+            msg: msg.into(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), BibParseErr> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(self.malformed(&format!("expected '{}'", expected))),
+            None => Err(BibParseErr::UnexpectedEnd),
+        }
+    }
+
+    fn read_identifier(&mut self) -> String {
+        self.skip_whitespace();
+        let mut ident = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || "_-:./+".contains(c) {
+                ident.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        ident
+    }
+
+    /// Reads until the matching closing brace, assuming the opening brace was already consumed.
+    fn read_braced(&mut self) -> Result<String, BibParseErr> {
+        let mut depth = 1;
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(value);
+                    }
+                }
+                _ => {}
+            }
+            value.push(c);
+        }
+        Err(BibParseErr::UnexpectedEnd)
+    }
+
+    fn read_quoted(&mut self) -> Result<String, BibParseErr> {
+        let mut depth = 0;
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '"' if depth == 0 => return Ok(value),
+                _ => {}
+            }
+            value.push(c);
+        }
+        Err(BibParseErr::UnexpectedEnd)
+    }
+
+    /// Field values can be concatenated with `#`, e.g. `month = jan # " 2nd"`.
+    fn read_value(&mut self) -> Result<String, BibParseErr> {
+        let mut value = String::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('{') => {
+                    self.pos += 1;
+                    value.push_str(&self.read_braced()?);
+                }
+                Some('"') => {
+                    self.pos += 1;
+                    value.push_str(&self.read_quoted()?);
+                }
+                Some(_) => {
+                    let word = self.read_identifier();
+                    if word.is_empty() {
+                        return Err(self.malformed("expected a field value"));
+                    }
+                    match self.macros.get(&word.to_lowercase()) {
+                        Some(expanded) => value.push_str(expanded),
+                        None => value.push_str(&word),
+                    }
+                }
+                None => return Err(BibParseErr::UnexpectedEnd),
+            }
+            self.skip_whitespace();
+            if self.peek() == Some('#') {
+                self.pos += 1;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn closing_for(open: char) -> char {
+        if open == '(' {
+            ')'
+        } else {
+            '}'
+        }
+    }
+
+    fn read_open_delimiter(&mut self) -> Result<char, BibParseErr> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == '{' || c == '(' => {
+                self.pos += 1;
+                Ok(c)
+            }
+            Some(_) => Err(self.malformed("expected '{' or '('")),
+            None => Err(BibParseErr::UnexpectedEnd),
+        }
+    }
+
+    fn parse_string_macro(&mut self) -> Result<(), BibParseErr> {
+        let open = self.read_open_delimiter()?;
+        let name = self.read_identifier();
+        self.expect('=')?;
+        let value = self.read_value()?;
+        self.expect(Self::closing_for(open))?;
+        self.macros.insert(name.to_lowercase(), value);
+        Ok(())
+    }
+
+    fn parse_entry(&mut self, kind: String) -> Result<Entry, BibParseErr> {
+        let open = self.read_open_delimiter()?;
+        let close = Self::closing_for(open);
+        let key = self.read_identifier();
+        if key.is_empty() {
+            return Err(BibParseErr::MissingKey(self.line()));
+        }
+        let mut fields = HashMap::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.pos += 1;
+                }
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => {
+                    let name = self.read_identifier();
+                    if name.is_empty() {
+                        return Err(self.malformed(&format!("bad field name in entry {}", key)));
+                    }
+                    self.expect('=')?;
+                    let value = self.read_value()?;
+                    fields.insert(name.to_lowercase(), value);
+                }
+                None => return Err(BibParseErr::UnexpectedEnd),
+            }
+        }
+        Ok(Entry { key, kind, fields })
+    }
+}
+
+pub(crate) fn parse_entries(input: &str) -> Result<Vec<Entry>, BibParseErr> {
+    let mut parser = BibParser::new(input);
+    let mut entries = Vec::new();
+    while let Some(c) = parser.peek() {
+        // Anything outside of an entry is treated as a comment, same as BibTeX itself.
+        if c != '@' {
+            parser.pos += 1;
+            continue;
+        }
+        parser.pos += 1;
+        let kind = parser.read_identifier().to_lowercase();
+        match kind.as_str() {
+            "comment" | "preamble" => {
+                let open = parser.read_open_delimiter()?;
+                if open == '{' {
+                    parser.read_braced()?;
+                } else {
+                    while let Some(c) = parser.peek() {
+                        parser.pos += 1;
+                        if c == ')' {
+                            break;
+                        }
+                    }
+                }
+            }
+            "string" => parser.parse_string_macro()?,
+            "" => return Err(parser.malformed("expected an entry type after '@'")),
+            _ => entries.push(parser.parse_entry(kind)?),
+        }
+    }
+    Ok(entries)
+}
+
+const ACCENTS: [(&str, char, char); 20] = [
+    ("\"", 'a', 'ä'),
+    ("\"", 'o', 'ö'),
+    ("\"", 'u', 'ü'),
+    ("\"", 'e', 'ë'),
+    ("\"", 'i', 'ï'),
+    ("'", 'a', 'á'),
+    ("'", 'e', 'é'),
+    ("'", 'i', 'í'),
+    ("'", 'o', 'ó'),
+    ("'", 'u', 'ú'),
+    ("`", 'a', 'à'),
+    ("`", 'e', 'è'),
+    ("`", 'o', 'ò'),
+    ("^", 'a', 'â'),
+    ("^", 'e', 'ê'),
+    ("^", 'o', 'ô'),
+    ("~", 'n', 'ñ'),
+    ("~", 'a', 'ã'),
+    ("c", 'c', 'ç'),
+    ("r", 'a', 'å'),
+];
+
+/// Strips BibTeX markup (protective braces, common LaTeX escapes and accents) from a raw field
+/// value so it can be displayed.
+pub(crate) fn clean_value(raw: &str) -> String {
+    let mut cleaned = raw.to_string();
+    for (command, letter, accented) in ACCENTS {
+        for variant in [
+            format!("{{\\{}{}}}", command, letter),
+            format!("\\{}{{{}}}", command, letter),
+            format!("\\{} {}", command, letter),
+            format!("\\{}{}", command, letter),
+        ] {
+            if cleaned.contains(&variant) {
+                cleaned = cleaned.replace(&variant, &accented.to_string());
+            }
+        }
+        let upper_letter = letter.to_ascii_uppercase();
+        let upper_accented = accented.to_uppercase().to_string();
+        for variant in [
+            format!("{{\\{}{}}}", command, upper_letter),
+            format!("\\{}{}", command, upper_letter),
+        ] {
+            if cleaned.contains(&variant) {
+                cleaned = cleaned.replace(&variant, &upper_accented);
+            }
+        }
+    }
+    cleaned = cleaned
+        .replace("\\&", "&")
+        .replace("\\%", "%")
+        .replace("\\_", "_")
+        .replace("\\$", "$")
+        .replace("\\#", "#")
+        .replace("---", "—")
+        .replace("--", "–")
+        .replace('~', " ")
+        .replace(['{', '}'], "");
+    cleaned.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_braced_and_quoted_fields() {
+        let input = r#"
+@article{knuth1984,
+  author = {Donald E. Knuth},
+  title = "Literate {Programming}",
+  journal = {The Computer Journal},
+  year = 1984,
+  volume = {27},
+  number = {2},
+  pages = {97--111},
+}
+"#;
+        let entries = parse_entries(input).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.key, "knuth1984");
+        assert_eq!(entry.kind, "article");
+        assert_eq!(entry.fields.get("year").unwrap(), "1984");
+        assert_eq!(entry.fields.get("title").unwrap(), "Literate {Programming}");
+        assert_eq!(entry.fields.get("pages").unwrap(), "97--111");
+    }
+
+    #[test]
+    fn skips_comments_and_expands_strings() {
+        let input = r#"
+This line is ignored.
+@comment{ @article{not_an_entry, title = {nope}} }
+@string{tcj = "The Computer Journal"}
+@ARTICLE(short, journal = tcj # " (online)", title = {Short})
+"#;
+        let entries = parse_entries(input).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "short");
+        assert_eq!(entries[0].kind, "article");
+        assert_eq!(
+            entries[0].fields.get("journal").unwrap(),
+            "The Computer Journal (online)"
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_entries() {
+        let input = "@book{unfinished, title = {Never closed";
+        assert!(matches!(
+            parse_entries(input),
+            Err(BibParseErr::UnexpectedEnd)
+        ));
+        let input = "@book{, title = {No key}}";
+        assert!(matches!(
+            parse_entries(input),
+            Err(BibParseErr::MissingKey(1))
+        ));
+    }
+
+    #[test]
+    fn cleans_latex_markup() {
+        assert_eq!(clean_value("Schr{\\\"o}dinger's {Cat}"), "Schrödinger's Cat");
+        assert_eq!(clean_value("Pages 1--10 \\& more"), "Pages 1–10 & more");
+        assert_eq!(clean_value("Multi\n   line"), "Multi line");
+    }
+}
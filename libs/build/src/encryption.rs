@@ -0,0 +1,115 @@
+use std::{env, fs, io, path::Path, process::exit};
+
+use persistance::{
+    crypto::{self, encrypt_all, is_configured, reseal_file, set_encryption_header, CryptoError},
+    fs::utils::{
+        get_archive_location, get_history_location, get_search_index_location, get_trash_location,
+    },
+    store::note_store,
+};
+use rpassword::prompt_password;
+
+/// Read instead of prompting when set, for running the wiki as a service.
+const PASSPHRASE_VAR: &str = "TENDRIL_PASSPHRASE";
+/// Trashed notes keep their title in a plain file so the trash can be listed while locked.
+const TRASH_TITLE_FILE: &str = "title";
+
+fn read_passphrase() -> String {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return passphrase;
+    }
+    let passphrase = prompt_password("Passphrase: ").unwrap();
+    if !is_configured() {
+        let confirmation = prompt_password("Confirm passphrase: ").unwrap();
+        if passphrase != confirmation {
+            eprintln!("Passphrases did not match");
+            exit(1);
+        }
+    }
+    passphrase
+}
+
+fn unlock_with_passphrase() {
+    match crypto::unlock(&read_passphrase()) {
+        Ok(()) => {}
+        Err(CryptoError::WrongPassphrase) => {
+            eprintln!("Wrong passphrase");
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("Could not unlock the notebook: {}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Unlocks the notebook on startup if it uses encryption, exiting when the passphrase is wrong.
+pub fn unlock() {
+    if env::var(PASSPHRASE_VAR).is_ok() || is_configured() || encrypt_all() {
+        unlock_with_passphrase();
+    }
+}
+
+/// Rewrites every file below `location`, encrypting or decrypting it.
+fn reseal_dir(location: &Path, encrypt: bool) -> io::Result<()> {
+    if !location.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(location)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            reseal_dir(&path, encrypt)?;
+        } else if path.file_name() != Some(TRASH_TITLE_FILE.as_ref()) {
+            reseal_file(&path, encrypt)?;
+        }
+    }
+    Ok(())
+}
+
+fn convert(encrypt: bool) {
+    unlock_with_passphrase();
+    let store = note_store();
+    let mut converted = 0;
+    for title in store.list().unwrap() {
+        match store.update(&title, &mut |current| {
+            Ok(set_encryption_header(&current.unwrap_or_default(), encrypt))
+        }) {
+            Ok(_) => converted += 1,
+            Err(e) => eprintln!("Could not convert {}: {}", title, e),
+        }
+    }
+    for location in [
+        get_archive_location(),
+        get_history_location(),
+        get_trash_location(),
+    ] {
+        if let Err(e) = reseal_dir(&location, encrypt) {
+            eprintln!("Could not convert {:?}: {}", location, e);
+            exit(1);
+        }
+    }
+    // The index is rebuilt on the next start, with term names hidden or not to match.
+    let index = get_search_index_location();
+    if index.exists() {
+        fs::remove_dir_all(index).unwrap();
+    }
+    println!("<{} notes converted>", converted);
+}
+
+/// Encrypts every note in the wiki along with its archives, history and trash.
+pub fn encrypt() {
+    convert(true);
+}
+
+/// Decrypts the wiki again and forgets the passphrase, notes are written back as plaintext.
+pub fn decrypt() {
+    if encrypt_all() {
+        eprintln!(
+            "encrypt_all is set in config.toml, notes will be encrypted again when they're saved"
+        );
+    }
+    convert(false);
+    if let Err(e) = crypto::reset() {
+        eprintln!("Could not remove the encryption key: {}", e);
+    }
+}
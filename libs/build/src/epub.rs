@@ -0,0 +1,731 @@
+//! Exports a selection of notes as an EPUB3 book, for reading them offline on an e-reader.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Seek, Write},
+    path::Path,
+};
+
+use chrono::Utc;
+use html5ever::{
+    tendril::StrTendril,
+    tokenizer::{
+        states::RawKind, BufferQueue, Tag, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer,
+        TokenizerOpts,
+    },
+};
+use persistance::{
+    fs::config::read_config,
+    media::{media_library, MediaLibrary},
+    store::{note_store, revision, StoreError},
+};
+use search_engine::{semantic_search, QueryError};
+use thiserror::Error;
+use urlencoding::{decode, encode};
+use wikitext::{
+    parsers::{format_links, Note},
+    processors::tags::TagsArray,
+};
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipWriter};
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+const STYLESHEET: &str = "body { font-family: serif; line-height: 1.5; }
+.text-block { margin: 0.5em 0; }
+.text-block[data-indent=\"1\"] { margin-left: 1.5em; }
+.text-block[data-indent=\"2\"] { margin-left: 3em; }
+.text-block[data-indent=\"3\"] { margin-left: 4.5em; }
+.tags { font-size: 0.9em; font-style: italic; }
+img { max-width: 100%; }
+pre { white-space: pre-wrap; }
+";
+
+/// Elements without content or an end tag, XHTML writes them as `<br />`.
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+#[derive(Error, Debug)]
+pub enum EpubError {
+    #[error("no note titled {0:?}")]
+    NotFound(String),
+    #[error("no notes matched, nothing to export")]
+    Empty,
+    #[error("could not search for notes: {0}")]
+    Query(#[from] QueryError),
+    #[error("could not read notes: {0}")]
+    Store(#[from] StoreError),
+    #[error("could not write the book: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not write the book: {0}")]
+    Zip(#[from] ZipError),
+}
+
+/// Which notes go into the book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    /// Every note with the tag, in alphabetical order.
+    Tag(String),
+    /// The results of a search, best match first.
+    Query(String),
+    /// The notes with these titles, in the order given.
+    Titles(Vec<String>),
+}
+
+impl Selection {
+    fn book_title(&self) -> String {
+        match self {
+            Selection::Tag(tag) => format!("#{}", tag),
+            Selection::Query(query) => format!("Notes on “{}”", query),
+            Selection::Titles(titles) if titles.len() == 1 => titles[0].clone(),
+            Selection::Titles(_) => String::from("Notes"),
+        }
+    }
+
+    async fn notes(&self) -> Result<Vec<Note>, EpubError> {
+        let store = note_store();
+        let titles = match self {
+            Selection::Tag(tag) => {
+                let mut tagged = Vec::new();
+                for title in store.list()? {
+                    let note = match store.read_note(&title) {
+                        Ok(note) => note,
+                        Err(_) => continue,
+                    };
+                    let has_tag = note
+                        .header
+                        .get("tags")
+                        .map(|tags| TagsArray::new(tags).values.contains(&tag.as_str()))
+                        .unwrap_or(false);
+                    if has_tag {
+                        tagged.push(note);
+                    }
+                }
+                tagged.sort_by(|a, b| a.header.get("title").cmp(&b.header.get("title")));
+                return Ok(tagged);
+            }
+            // Bookmark archives are in the search index too, only notes make it into the book.
+            Selection::Query(query) => semantic_search(query)
+                .await?
+                .into_iter()
+                .filter(|title| store.exists(title))
+                .collect(),
+            Selection::Titles(titles) => titles.clone(),
+        };
+        let mut notes = Vec::with_capacity(titles.len());
+        for title in titles {
+            match store.read_note(&title) {
+                Ok(note) => notes.push(note),
+                Err(_) => return Err(EpubError::NotFound(title)),
+            }
+        }
+        Ok(notes)
+    }
+}
+
+struct Chapter {
+    title: String,
+    file: String,
+    body: String,
+}
+
+struct Image {
+    file: String,
+    media_type: &'static str,
+    bytes: Vec<u8>,
+}
+
+/// The uploaded images the book embeds, by their name in the media location.
+struct Images {
+    library: MediaLibrary,
+    included: HashMap<String, Option<usize>>,
+    files: Vec<Image>,
+}
+
+impl Images {
+    fn new(library: MediaLibrary) -> Self {
+        Self {
+            library,
+            included: HashMap::new(),
+            files: Vec::new(),
+        }
+    }
+
+    /// The path of the image inside the book, if `name` is an uploaded image.
+    fn add(&mut self, name: &str) -> Option<String> {
+        if let Some(&included) = self.included.get(name) {
+            return included.map(|idx| self.files[idx].file.clone());
+        }
+        let included = media_type(name).and_then(|media_type| {
+            let bytes = fs::read(self.library.path_of(name).ok()?).ok()?;
+            self.files.push(Image {
+                file: format!("media/{}", name),
+                media_type,
+                bytes,
+            });
+            Some(self.files.len() - 1)
+        });
+        self.included.insert(name.to_string(), included);
+        included.map(|idx| self.files[idx].file.clone())
+    }
+}
+
+/// The image types every EPUB3 reader has to support.
+fn media_type(name: &str) -> Option<&'static str> {
+    let (_, extension) = name.rsplit_once('.')?;
+    match extension.to_lowercase().as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The uploaded file a `/files/` URL points at, thumbnails count as their original.
+fn uploaded_file(url: &str) -> Option<String> {
+    let (_, name) = url.split_once("/files/")?;
+    let name = match name
+        .strip_prefix(".thumbnails/")
+        .and_then(|thumbnail| thumbnail.split_once('/'))
+    {
+        Some((_, name)) => name,
+        None => name,
+    };
+    decode(name).map(|name| name.into_owned()).ok()
+}
+
+/// Rewrites a note rendered for the wiki so it works inside the book. Links to notes in the book
+/// point at their chapter, links to the rest of the wiki are left as plain text, and uploaded
+/// images are embedded. The HTML is read with html5ever's tokenizer and written back out as well
+/// formed XHTML, so notes with unclosed elements or unquoted attributes don't break the book.
+fn rewrite_body(body: &str, chapters: &HashMap<String, String>, images: &mut Images) -> String {
+    let mut input = BufferQueue::new();
+    input.push_back(StrTendril::from_slice(body));
+    let mut tokenizer = Tokenizer::new(
+        Xhtml {
+            chapters,
+            images,
+            out: String::with_capacity(body.len()),
+            open: Vec::new(),
+            skipping: None,
+        },
+        TokenizerOpts::default(),
+    );
+    let _ = tokenizer.feed(&mut input);
+    tokenizer.end();
+    tokenizer.sink.out
+}
+
+/// Writes the tokens of a note's HTML as XHTML.
+struct Xhtml<'a> {
+    chapters: &'a HashMap<String, String>,
+    images: &'a mut Images,
+    out: String,
+    /// The elements that are open, along with the name each one was written as.
+    open: Vec<(String, String)>,
+    /// Set inside a script or iframe, their content is left out of the book.
+    skipping: Option<String>,
+}
+
+impl Xhtml<'_> {
+    fn start_tag(&mut self, tag: Tag) -> TokenSinkResult<()> {
+        let name = tag.name.to_string();
+        let attr = |attr: &str| {
+            tag.attrs
+                .iter()
+                .find(|a| &*a.name.local == attr)
+                .map(|a| a.value.to_string())
+        };
+        match name.as_str() {
+            "script" => {
+                self.skipping = Some(name);
+                return TokenSinkResult::RawData(RawKind::ScriptData);
+            }
+            "iframe" => {
+                if let Some(src) = attr("src") {
+                    let src = escape(&src);
+                    self.out
+                        .push_str(&format!(r#"<a href="{}">{}</a>"#, src, src));
+                }
+                self.skipping = Some(name);
+                return TokenSinkResult::RawData(RawKind::Rawtext);
+            }
+            "a" => {
+                let href = attr("href").unwrap_or_default();
+                if let Some(file) = self.chapters.get(&href) {
+                    let href = vec![(String::from("href"), file.clone())];
+                    self.open_element(name, "a", &href);
+                } else if href.starts_with('/') {
+                    self.open_element(name, "span", &[]);
+                } else {
+                    self.open_element(name, "a", &attributes(&tag));
+                }
+            }
+            "img" => {
+                let src = attr("src").unwrap_or_default();
+                let alt = attr("alt").unwrap_or_default();
+                let image = match uploaded_file(&src).and_then(|name| self.images.add(&name)) {
+                    Some(file) => format!(
+                        r#"<img src="{}" alt="{}" />"#,
+                        encode_path(&file),
+                        escape(&alt)
+                    ),
+                    // Readers don't load remote images, link to them instead.
+                    None if src.starts_with("http") => format!(
+                        r#"<a href="{}">{}</a>"#,
+                        escape(&src),
+                        escape(if alt.is_empty() { &src } else { &alt })
+                    ),
+                    None => format!("<span>{}</span>", escape(&alt)),
+                };
+                self.out.push_str(&image);
+            }
+            _ if is_xml_name(&name) => {
+                self.close_implied(&name);
+                let raw = match name.as_str() {
+                    "style" => Some(RawKind::Rawtext),
+                    "textarea" | "title" => Some(RawKind::Rcdata),
+                    _ => None,
+                };
+                let attributes = attributes(&tag);
+                self.open_element(name.clone(), &name, &attributes);
+                if let Some(raw) = raw {
+                    return TokenSinkResult::RawData(raw);
+                }
+            }
+            _ => {}
+        }
+        TokenSinkResult::Continue
+    }
+
+    fn open_element(&mut self, name: String, written: &str, attributes: &[(String, String)]) {
+        self.out.push('<');
+        self.out.push_str(written);
+        for (attr, value) in attributes {
+            self.out
+                .push_str(&format!(r#" {}="{}""#, attr, escape(value)));
+        }
+        if VOID_ELEMENTS.contains(&name.as_str()) {
+            self.out.push_str(" />");
+        } else {
+            self.out.push('>');
+            self.open.push((name, written.to_string()));
+        }
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        if self.skipping.as_deref() == Some(name) {
+            self.skipping = None;
+        } else if let Some(idx) = self.open.iter().rposition(|(open, _)| open == name) {
+            self.close_from(idx);
+        }
+    }
+
+    /// HTML lets some elements end where the next one starts, XHTML needs them closed.
+    fn close_implied(&mut self, name: &str) {
+        let (closes, within): (&[&str], &[&str]) = match name {
+            "li" => (&["li"], &["ul", "ol"]),
+            "dt" | "dd" => (&["dt", "dd"], &["dl"]),
+            "tr" => (&["tr"], &["table"]),
+            "td" | "th" => (&["td", "th"], &["tr", "table"]),
+            "option" => (&["option"], &["select"]),
+            "p" | "div" | "ul" | "ol" | "dl" | "table" | "pre" | "blockquote" | "section"
+            | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "hr" => {
+                (&["p"], &["div", "li", "td", "th", "blockquote", "section"])
+            }
+            _ => return,
+        };
+        for idx in (0..self.open.len()).rev() {
+            let open = self.open[idx].0.as_str();
+            if closes.contains(&open) {
+                self.close_from(idx);
+                return;
+            } else if within.contains(&open) {
+                return;
+            }
+        }
+    }
+
+    /// Closes the open element at `idx` along with every element opened inside it.
+    fn close_from(&mut self, idx: usize) {
+        for (_, written) in self.open.drain(idx..).rev() {
+            self.out.push_str(&format!("</{}>", written));
+        }
+    }
+}
+
+impl TokenSink for Xhtml<'_> {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => match tag.kind {
+                TagKind::StartTag if self.skipping.is_none() => return self.start_tag(tag),
+                TagKind::StartTag => {}
+                TagKind::EndTag => self.end_tag(&tag.name),
+            },
+            Token::CharacterTokens(text) if self.skipping.is_none() => {
+                // Non-breaking spaces are written as a reference so they can still be told apart.
+                self.out
+                    .push_str(&escape(&text).replace('\u{a0}', "&#160;"));
+            }
+            Token::EOFToken => self.close_from(0),
+            _ => {}
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+/// The attributes of a tag that can be written as XML.
+fn attributes(tag: &Tag) -> Vec<(String, String)> {
+    tag.attrs
+        .iter()
+        .filter(|attr| is_xml_name(&attr.name.local))
+        .map(|attr| (attr.name.local.to_string(), attr.value.to_string()))
+        .collect()
+}
+
+/// HTML accepts almost anything as a tag or attribute name, XML doesn't.
+fn is_xml_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| encode(segment).into_owned())
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn xhtml(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head>
+<meta charset="UTF-8" />
+<title>{}</title>
+<link rel="stylesheet" type="text/css" href="style.css" />
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        escape(title),
+        body
+    )
+}
+
+struct Book {
+    title: String,
+    author: String,
+    chapters: Vec<Chapter>,
+    images: Vec<Image>,
+}
+
+impl Book {
+    fn new(title: String, author: String, notes: &[Note], library: MediaLibrary) -> Self {
+        let mut files = HashMap::new();
+        for (idx, note) in notes.iter().enumerate() {
+            let file = format!("note-{}.xhtml", idx + 1);
+            let title = note.header.get("title").unwrap();
+            files.insert(format_links(title), file.clone());
+            if let Some(id) = note.header.get("id") {
+                files.insert(format_links(&format!("id:{}", id)), file.clone());
+            }
+        }
+        let mut images = Images::new(library);
+        let chapters = notes
+            .iter()
+            .enumerate()
+            .map(|(idx, note)| {
+                let page = note.to_template().page;
+                let tags = if page.tags.is_empty() {
+                    String::new()
+                } else {
+                    let tags = page
+                        .tags
+                        .iter()
+                        .map(|tag| format!("#{}", escape(tag)))
+                        .collect::<Vec<String>>()
+                        .join(" ");
+                    format!("<p class=\"tags\">{}</p>\n", tags)
+                };
+                let body = format!(
+                    "<section epub:type=\"chapter\">\n<h1>{}</h1>\n{}{}\n</section>",
+                    escape(&page.title),
+                    tags,
+                    rewrite_body(&page.body, &files, &mut images)
+                );
+                Chapter {
+                    file: format!("note-{}.xhtml", idx + 1),
+                    body: xhtml(&page.title, &body),
+                    title: page.title,
+                }
+            })
+            .collect();
+        Self {
+            title,
+            author,
+            chapters,
+            images: images.files,
+        }
+    }
+
+    fn nav(&self) -> String {
+        let entries = self
+            .chapters
+            .iter()
+            .map(|chapter| {
+                format!(
+                    "<li><a href=\"{}\">{}</a></li>",
+                    chapter.file,
+                    escape(&chapter.title)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        xhtml(
+            &self.title,
+            &format!(
+                "<nav epub:type=\"toc\" id=\"toc\">\n<h1>{}</h1>\n<ol>\n{}\n</ol>\n</nav>",
+                escape(&self.title),
+                entries
+            ),
+        )
+    }
+
+    fn package(&self) -> String {
+        let titles = self
+            .chapters
+            .iter()
+            .map(|chapter| chapter.title.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let mut manifest = vec![
+            r#"<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#.to_string(),
+            r#"<item id="style" href="style.css" media-type="text/css"/>"#.to_string(),
+        ];
+        let mut spine = vec![r#"<itemref idref="nav"/>"#.to_string()];
+        for (idx, chapter) in self.chapters.iter().enumerate() {
+            manifest.push(format!(
+                r#"<item id="note-{}" href="{}" media-type="application/xhtml+xml"/>"#,
+                idx + 1,
+                chapter.file
+            ));
+            spine.push(format!(r#"<itemref idref="note-{}"/>"#, idx + 1));
+        }
+        for (idx, image) in self.images.iter().enumerate() {
+            manifest.push(format!(
+                r#"<item id="image-{}" href="{}" media-type="{}"/>"#,
+                idx + 1,
+                escape(&encode_path(&image.file)),
+                image.media_type
+            ));
+        }
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="book-id">urn:tendril:{}</dc:identifier>
+<dc:title>{}</dc:title>
+<dc:creator>{}</dc:creator>
+<dc:language>en</dc:language>
+<meta property="dcterms:modified">{}</meta>
+</metadata>
+<manifest>
+{}
+</manifest>
+<spine>
+{}
+</spine>
+</package>
+"#,
+            revision(&titles),
+            escape(&self.title),
+            escape(&self.author),
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            manifest.join("\n"),
+            spine.join("\n")
+        )
+    }
+
+    fn write<W: Write + Seek>(&self, writer: W) -> Result<(), EpubError> {
+        let mut zip = ZipWriter::new(writer);
+        // Readers find out what the file is from the `mimetype` entry, so it has to come first
+        // and can't be compressed.
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(b"application/epub+zip")?;
+        let options = FileOptions::default();
+        zip.start_file("META-INF/container.xml", options)?;
+        zip.write_all(CONTAINER.as_bytes())?;
+        zip.start_file("OEBPS/content.opf", options)?;
+        zip.write_all(self.package().as_bytes())?;
+        zip.start_file("OEBPS/nav.xhtml", options)?;
+        zip.write_all(self.nav().as_bytes())?;
+        zip.start_file("OEBPS/style.css", options)?;
+        zip.write_all(STYLESHEET.as_bytes())?;
+        for chapter in &self.chapters {
+            zip.start_file(format!("OEBPS/{}", chapter.file), options)?;
+            zip.write_all(chapter.body.as_bytes())?;
+        }
+        for image in &self.images {
+            zip.start_file(format!("OEBPS/{}", image.file), options)?;
+            zip.write_all(&image.bytes)?;
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+/// Writes the selected notes to `output` as an EPUB, returning the titles of the chapters.
+pub async fn export_epub(selection: &Selection, output: &Path) -> Result<Vec<String>, EpubError> {
+    let notes = selection.notes().await?;
+    if notes.is_empty() {
+        return Err(EpubError::Empty);
+    }
+    let author = read_config().general.user.clone();
+    let book = Book::new(selection.book_title(), author, &notes, media_library());
+    book.write(fs::File::create(output)?)?;
+    Ok(book
+        .chapters
+        .into_iter()
+        .map(|chapter| chapter.title)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, io::Read, path::PathBuf};
+
+    use zip::ZipArchive;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/epub/";
+
+    fn library() -> MediaLibrary {
+        let location = PathBuf::from(TEST_DIR);
+        fs::create_dir_all(&location).unwrap();
+        fs::write(location.join("cat.png"), [0u8; 4]).unwrap();
+        MediaLibrary::new(location.clone(), location.join("index.json"))
+    }
+
+    fn note(title: &str, content: &str) -> Note {
+        let mut header = HashMap::new();
+        header.insert("title".to_string(), title.to_string());
+        header.insert("tags".to_string(), "[reading]".to_string());
+        Note {
+            header,
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn rewrites_links_and_images_for_the_book() {
+        let mut chapters = HashMap::new();
+        chapters.insert(String::from("/Some%20Page"), String::from("note-2.xhtml"));
+        let mut images = Images::new(library());
+        let body = r#"<a href="/Some%20Page">Some Page</a> <a href="/Elsewhere">elsewhere</a> <a href="https://example.com">out</a><br>
+<img src="http://localhost:6683/files/.thumbnails/320/cat.png" srcset="x 320w" loading="lazy" /> <img src=https://example.com/dog.jpg /> <img src="/files/gone.png">&nbsp;R&D"#;
+        assert_eq!(
+            rewrite_body(body, &chapters, &mut images),
+            r#"<a href="note-2.xhtml">Some Page</a> <span>elsewhere</span> <a href="https://example.com">out</a><br />
+<img src="media/cat.png" alt="" /> <a href="https://example.com/dog.jpg">https://example.com/dog.jpg</a> <span></span>&#160;R&amp;D"#
+        );
+        assert_eq!(images.files.len(), 1);
+    }
+
+    #[test]
+    fn writes_bookmarks_as_well_formed_xhtml() {
+        let mut bookmark = note(
+            "Saved Article",
+            r#"<div class=article><p>First paragraph<p>Second &amp; <b>bold<br>
+<ul><li>one<li>two <img src=/files/cat.png alt=cat></ul>
+<p title='a "quoted" title' data-x=1>Last<script>if (a < b) {}</script></div></span>"#,
+        );
+        bookmark
+            .header
+            .insert("content-type".to_string(), "html".to_string());
+        let book = Book::new("Bookmarks".into(), "tendril".into(), &[bookmark], library());
+        let chapter = &book.chapters[0].body;
+        let document =
+            roxmltree::Document::parse(chapter).unwrap_or_else(|e| panic!("{}\n{}", e, chapter));
+        let items = document
+            .descendants()
+            .filter(|node| node.has_tag_name("li"))
+            .map(|node| node.text().unwrap_or_default())
+            .collect::<Vec<&str>>();
+        assert_eq!(items, ["one", "two "]);
+        assert!(chapter.contains(r#"<img src="media/cat.png" alt="cat" />"#));
+        assert!(!chapter.contains("script"));
+    }
+
+    #[test]
+    fn writes_a_packaged_book() {
+        let notes = vec![
+            note(
+                "Alpha",
+                "See [[Beta]] and [[Gamma]].\nhttp://localhost:6683/files/cat.png",
+            ),
+            note("Beta", "Back to [[Alpha]]."),
+        ];
+        let book = Book::new("#reading".into(), "tendril".into(), &notes, library());
+        let mut bytes = Cursor::new(Vec::new());
+        book.write(&mut bytes).unwrap();
+        let mut archive = ZipArchive::new(bytes).unwrap();
+        let mimetype = archive.by_index(0).unwrap();
+        assert_eq!(mimetype.name(), "mimetype");
+        assert_eq!(mimetype.compression(), CompressionMethod::Stored);
+        drop(mimetype);
+        let names = archive.file_names().collect::<Vec<&str>>();
+        for name in [
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/nav.xhtml",
+            "OEBPS/note-1.xhtml",
+            "OEBPS/note-2.xhtml",
+            "OEBPS/media/cat.png",
+        ] {
+            assert!(names.contains(&name), "missing {}", name);
+        }
+        let mut chapter = String::new();
+        archive
+            .by_name("OEBPS/note-1.xhtml")
+            .unwrap()
+            .read_to_string(&mut chapter)
+            .unwrap();
+        assert!(chapter.contains(r#"<a href="note-2.xhtml">Beta</a>"#));
+        assert!(chapter.contains("<span>Gamma</span>"));
+        let mut nav = String::new();
+        archive
+            .by_name("OEBPS/nav.xhtml")
+            .unwrap()
+            .read_to_string(&mut nav)
+            .unwrap();
+        assert!(nav.contains(r#"<li><a href="note-2.xhtml">Beta</a></li>"#));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use persistance::fs::journal::DailyNotes;
+use regex::{Captures, Regex};
+use urlencoding::decode;
+
+use super::{
+    attachment_link, convert_outline_text, daily_link, daily_note, file_created, read_file, walk,
+    Attachments, ImportError, ImportedNote, MARKDOWN_LINK,
+};
+
+lazy_static! {
+    static ref PROPERTY: Regex = Regex::new(r"^([A-Za-z][\w-]*):: ?(.*)$").unwrap();
+    static ref BLOCK_ID: Regex = Regex::new(r"^\s*id:: ([0-9a-f-]{36})\s*$").unwrap();
+    static ref TASK: Regex = Regex::new(r"(?m)^(\s*- )(TODO|DOING|NOW|LATER|WAITING|DONE|CANCELED) ").unwrap();
+    static ref EMBED_MACRO: Regex =
+        Regex::new(r"\{\{embed (\(\([^)]+\)\)|\[\[[^\]]+\]\])\}\}").unwrap();
+    // Image sizes Logseq adds after an image, like `{:height 200, :width 300}`.
+    static ref IMAGE_SIZE: Regex = Regex::new(r"\)\{:[^}]*\}").unwrap();
+}
+
+struct Page {
+    path: PathBuf,
+    note: ImportedNote,
+    body: String,
+}
+
+/// Pages are stored with `/` written as `___`, and other characters percent encoded.
+fn page_title(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .replace("___", "/");
+    decode(&stem)
+        .map(|title| title.into_owned())
+        .unwrap_or(stem)
+}
+
+/// Moves the `key:: value` lines at the top of a page into the note's tags and properties.
+fn read_page_properties(raw: &str, note: &mut ImportedNote) -> String {
+    let mut lines = raw.lines().peekable();
+    while let Some(caps) = lines.peek().and_then(|line| PROPERTY.captures(line)) {
+        let key = caps[1].to_lowercase();
+        let value = caps[2].trim().to_string();
+        lines.next();
+        let values = value
+            .split(',')
+            .map(|value| {
+                value
+                    .trim()
+                    .trim_start_matches('#')
+                    .trim_start_matches("[[")
+                    .trim_end_matches("]]")
+                    .to_string()
+            })
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<String>>();
+        match key.as_str() {
+            "title" => note.title = value,
+            "tags" => {
+                for tag in values {
+                    note.add_tag(&tag);
+                }
+            }
+            "alias" => {
+                note.properties
+                    .insert("aliases".into(), format!("[{}]", values.join(",")));
+            }
+            "id" | "created" | "content-type" => {}
+            _ => {
+                note.properties.insert(key, values.join(", "));
+            }
+        }
+    }
+    lines
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim_start_matches('\n')
+        .to_string()
+}
+
+/// Reads the pages and journals of a Logseq graph, along with the assets they use.
+pub(crate) fn read_graph(
+    graph: &Path,
+    attachments: &mut Attachments,
+    daily: &DailyNotes,
+) -> Result<(Vec<ImportedNote>, Vec<String>), ImportError> {
+    let dirs = [graph.join("pages"), graph.join("journals")];
+    if !dirs.iter().any(|dir| dir.is_dir()) {
+        return Err(ImportError::Read(
+            graph.to_owned(),
+            io::Error::new(io::ErrorKind::NotFound, "no pages or journals directory"),
+        ));
+    }
+    let mut warnings = Vec::new();
+    let mut pages = Vec::new();
+    for (dir, is_journal) in dirs.iter().zip([false, true]) {
+        if !dir.is_dir() {
+            continue;
+        }
+        for path in walk(dir)? {
+            if !matches!(path.extension(), Some(ext) if ext == "md") {
+                warnings.push(format!("{:?} is not a Markdown page, skipped", path));
+                continue;
+            }
+            let raw = read_file(&path)?;
+            let mut note = ImportedNote {
+                title: page_title(&path),
+                created: file_created(&path),
+                ..ImportedNote::default()
+            };
+            let body = read_page_properties(&raw, &mut note);
+            let date = path.file_stem().and_then(|stem| {
+                NaiveDate::parse_from_str(&stem.to_string_lossy(), "%Y_%m_%d").ok()
+            });
+            if let (true, Some(date)) = (is_journal, date) {
+                daily_note(&mut note, date, daily);
+            }
+            pages.push(Page { path, note, body });
+        }
+    }
+    // Page names are case insensitive, and aliases link to the page too.
+    let mut titles = HashMap::new();
+    for page in &pages {
+        titles.insert(page.note.title.to_lowercase(), page.note.title.clone());
+        if let Some(aliases) = page.note.properties.get("aliases") {
+            for alias in aliases.trim_matches(|c| c == '[' || c == ']').split(',') {
+                titles.insert(alias.trim().to_lowercase(), page.note.title.clone());
+            }
+        }
+    }
+    // Blocks with an `id::` property can be referenced from anywhere in the graph.
+    let mut blocks = HashMap::new();
+    for page in pages.iter_mut() {
+        let mut block = "";
+        let mut kept = Vec::new();
+        for line in page.body.lines() {
+            if let Some(caps) = BLOCK_ID.captures(line) {
+                blocks.insert(caps[1].to_string(), block.to_string());
+                continue;
+            }
+            if let Some(text) = line.trim_start().strip_prefix("- ") {
+                block = text;
+            }
+            kept.push(line);
+        }
+        page.body = kept.join("\n");
+    }
+    let rename = |target: &str| match titles.get(&target.to_lowercase()) {
+        Some(title) => title.clone(),
+        None => daily_link(target, daily),
+    };
+    let mut notes = Vec::with_capacity(pages.len());
+    for mut page in pages {
+        let body = EMBED_MACRO.replace_all(&page.body, "$1");
+        let body = TASK.replace_all(&body, |caps: &Captures| {
+            let done = matches!(&caps[2], "DONE" | "CANCELED");
+            format!("{}[{}] ", &caps[1], if done { "x" } else { " " })
+        });
+        let body = convert_outline_text(&body, &mut page.note, &blocks, &mut warnings, &rename);
+        let body = IMAGE_SIZE.replace_all(&body, ")");
+        let assets = graph.join("assets");
+        let body = MARKDOWN_LINK.replace_all(&body, |caps: &Captures| {
+            let url = decode(&caps[3])
+                .map(|url| url.into_owned())
+                .unwrap_or_else(|_| caps[3].to_string());
+            let asset = match url.split_once("assets/") {
+                Some((before, name)) if before.is_empty() || before.ends_with("../") => {
+                    assets.join(name)
+                }
+                _ => return caps[0].to_string(),
+            };
+            let label = Some(&caps[2]).filter(|label| !label.is_empty());
+            match attachments.add(&asset) {
+                Some(name) => attachment_link(&name, label, !caps[1].is_empty()),
+                None => {
+                    warnings.push(format!("{:?}: missing asset {}", page.path, url));
+                    caps[0].to_string()
+                }
+            }
+        });
+        page.note.content = body.into_owned();
+        page.note.attachments = attachments.take();
+        page.note.add_inline_tags();
+        notes.push(page.note);
+    }
+    Ok((notes, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/import/logseq/";
+
+    #[test]
+    fn converts_outlines_properties_and_references() {
+        let graph = PathBuf::from(TEST_DIR);
+        if graph.exists() {
+            fs::remove_dir_all(&graph).unwrap();
+        }
+        for dir in ["pages", "journals", "assets"] {
+            fs::create_dir_all(graph.join(dir)).unwrap();
+        }
+        fs::write(graph.join("assets/diagram.png"), [0u8; 4]).unwrap();
+        fs::write(
+            graph.join("pages/Projects___Alpha.md"),
+            "tags:: work, [[deep work]]\nalias:: alpha\nstatus:: active\n\n- TODO Plan the launch\n  id:: 6262a8f2-1d4e-4a2b-9d8e-000000000001\n\t- DONE see [the notes]([[Beta]]) #[[big ideas]]\n- ![diagram](../assets/diagram.png){:height 200, :width 300}",
+        )
+        .unwrap();
+        fs::write(
+            graph.join("journals/2022_05_03.md"),
+            "- Worked on [[ALPHA]], ((6262a8f2-1d4e-4a2b-9d8e-000000000001)) on [[May 4th, 2022]]",
+        )
+        .unwrap();
+        let mut attachments = Attachments::default();
+        let (notes, warnings) =
+            read_graph(&graph, &mut attachments, &DailyNotes::from(None)).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let alpha = notes.iter().find(|n| n.title == "Projects/Alpha").unwrap();
+        assert_eq!(
+            alpha.content,
+            "- [ ] Plan the launch\n\t- [x] see [[the notes|Beta]] [[big ideas]]\n- ![diagram](/files/diagram.png)"
+        );
+        assert_eq!(alpha.tags, vec!["work", "deep work", "big ideas"]);
+        assert_eq!(alpha.properties["aliases"], "[alpha]");
+        assert_eq!(alpha.properties["status"], "active");
+        let journal = notes.iter().find(|n| n.title == "2022-05-03").unwrap();
+        assert_eq!(
+            journal.content,
+            "- Worked on [[ALPHA|Projects/Alpha]], TODO Plan the launch on [[May 4th, 2022|2022-05-04]]"
+        );
+    }
+}
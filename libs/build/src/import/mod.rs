@@ -0,0 +1,565 @@
+//! Imports notes from other note taking tools. Every importer turns its source into Markdown
+//! notes with front matter, which are written to the store along with their attachments.
+
+mod logseq;
+mod obsidian;
+mod roam;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use lazy_static::lazy_static;
+use persistance::{
+    fs::journal::{daily_notes, DailyNotes},
+    ids,
+    media::{media_library, sanitize_filename},
+    store::note_store,
+};
+use regex::Regex;
+use thiserror::Error;
+use wikitext::{parsers::Note, processors::tags::tag_string_from_vec};
+
+const DT_FORMAT: &str = "%Y%m%d%H%M%S";
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "svg"];
+
+lazy_static! {
+    // Daily notes in Logseq and Roam are titled like `May 3rd, 2022` or `Jan 1st, 2022`.
+    static ref DAILY_TITLE: Regex =
+        Regex::new(r"^([A-Z][a-z]+) (\d{1,2})(?:st|nd|rd|th), (\d{4})$").unwrap();
+    static ref INLINE_TAG: Regex = Regex::new(r"(?:^|\s)#([\w/-]+)").unwrap();
+    /// `[label](url)` and `![alt](url)` links.
+    pub(crate) static ref MARKDOWN_LINK: Regex =
+        Regex::new(r"(!?)\[([^\]]*)\]\(([^)\s]+)\)").unwrap();
+    /// `[label]([[page]])`, a labelled page link in Logseq and Roam.
+    static ref LABELLED_LINK: Regex = Regex::new(r"\[([^\]]+)\]\(\[\[([^\]]+)\]\]\)").unwrap();
+    /// `#[[multi word tag]]`
+    static ref TAG_LINK: Regex = Regex::new(r"#\[\[([^\]]+)\]\]").unwrap();
+    static ref BLOCK_REF: Regex = Regex::new(r"\(\(([\w-]+)\)\)").unwrap();
+}
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("could not read {0:?}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("could not parse {0:?}: {1}")]
+    Parse(PathBuf, String),
+    #[error("unknown import source {0:?}, expected obsidian, logseq or roam")]
+    UnknownSource(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// A vault of Markdown notes with attachments kept alongside them.
+    Obsidian,
+    /// A graph of outline Markdown pages and journals, with `key:: value` properties.
+    Logseq,
+    /// The JSON export of a Roam graph.
+    Roam,
+}
+
+impl FromStr for Source {
+    type Err = ImportError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        match source.to_lowercase().as_str() {
+            "obsidian" => Ok(Source::Obsidian),
+            "logseq" => Ok(Source::Logseq),
+            "roam" => Ok(Source::Roam),
+            _ => Err(ImportError::UnknownSource(source.into())),
+        }
+    }
+}
+
+/// A note read from another tool, ready to be written as Markdown.
+#[derive(Debug, Default)]
+pub(crate) struct ImportedNote {
+    pub title: String,
+    pub tags: Vec<String>,
+    /// Properties carried over into the front matter.
+    pub properties: HashMap<String, String>,
+    pub created: Option<DateTime<Local>>,
+    pub content: String,
+    /// Files the note links to, see [Attachments::take].
+    pub attachments: Vec<(PathBuf, String)>,
+}
+
+impl ImportedNote {
+    fn add_tag(&mut self, tag: &str) {
+        let tag = tag.trim().trim_start_matches('#');
+        if !tag.is_empty() && !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    /// Picks up `#tags` written in the text, leaving them in place.
+    fn add_inline_tags(&mut self) {
+        let tags = INLINE_TAG
+            .captures_iter(&self.content)
+            .map(|caps| caps[1].to_string())
+            .filter(|tag| !tag.chars().all(|c| c.is_ascii_digit()))
+            .collect::<Vec<String>>();
+        for tag in tags {
+            self.add_tag(&tag);
+        }
+    }
+
+    /// Points the links to an attachment at the name it was stored under.
+    fn relink(&mut self, linked: &str, name: &str) {
+        self.content = self
+            .content
+            .replace(
+                &format!("(/files/{})", linked),
+                &format!("(/files/{})", name),
+            )
+            .replace(&format!("files:{}]]", linked), &format!("files:{}]]", name));
+    }
+
+    fn into_note(self, id: String) -> Note {
+        let mut header = self.properties;
+        header.insert("title".into(), self.title);
+        header.insert("tags".into(), tag_string_from_vec(self.tags));
+        header.insert(
+            "created".into(),
+            format!("{:?}", self.created.unwrap_or_else(Local::now)),
+        );
+        header.insert("id".into(), id);
+        header.insert("content-type".into(), "markdown".into());
+        Note {
+            header,
+            content: self.content,
+        }
+    }
+}
+
+/// Rewrites the outline syntax Logseq and Roam share: block references become the text of the
+/// block, and labelled links and `#[[tags]]` become wiki links. Links are renamed with `rename`,
+/// keeping what they were written as for their label.
+pub(crate) fn convert_outline_text(
+    text: &str,
+    note: &mut ImportedNote,
+    blocks: &HashMap<String, String>,
+    warnings: &mut Vec<String>,
+    rename: &dyn Fn(&str) -> String,
+) -> String {
+    let text = BLOCK_REF.replace_all(text, |caps: &regex::Captures| match blocks.get(&caps[1]) {
+        Some(block) => block.clone(),
+        None => {
+            warnings.push(format!(
+                "{}: could not find block (({}))",
+                note.title, &caps[1]
+            ));
+            caps[0].to_string()
+        }
+    });
+    let text = TAG_LINK.replace_all(&text, |caps: &regex::Captures| {
+        note.add_tag(&caps[1]);
+        format!("[[{}]]", &caps[1])
+    });
+    let text = LABELLED_LINK.replace_all(&text, |caps: &regex::Captures| {
+        format!("[[{}|{}]]", &caps[1], &caps[2])
+    });
+    map_links(&text, |link| match link.split_once('|') {
+        Some((label, target)) => wiki_link(&rename(target), Some(label)),
+        None => wiki_link(&rename(link), Some(link)),
+    })
+}
+
+/// The files notes link to. Reading a note only works out the name each file will be stored
+/// under, files are copied once a note that links to them is written, see [Attachments::save].
+#[derive(Default)]
+pub(crate) struct Attachments {
+    planned: HashMap<PathBuf, String>,
+    /// Files linked from the note being read, see [Attachments::take].
+    linked: Vec<(PathBuf, String)>,
+    saved: HashMap<PathBuf, String>,
+    report: Vec<(String, String)>,
+    warnings: Vec<String>,
+}
+
+impl Attachments {
+    /// The name `path` is linked as. Files with the same name in different folders get names of
+    /// their own.
+    pub fn add(&mut self, path: &Path) -> Option<String> {
+        let name = match self.planned.get(path) {
+            Some(name) => name.clone(),
+            None => {
+                if !path.is_file() {
+                    self.warnings
+                        .push(format!("attachment {:?} does not exist", path));
+                    return None;
+                }
+                let filename = path.file_name()?.to_string_lossy();
+                let name = self.unique_name(sanitize_filename(&filename));
+                self.planned.insert(path.to_owned(), name.clone());
+                name
+            }
+        };
+        if !self.linked.iter().any(|(linked, _)| linked == path) {
+            self.linked.push((path.to_owned(), name.clone()));
+        }
+        Some(name)
+    }
+
+    fn unique_name(&self, name: String) -> String {
+        let taken = |candidate: &String| self.planned.values().any(|planned| planned == candidate);
+        if !taken(&name) {
+            return name;
+        }
+        let (stem, extension) = match name.rfind('.') {
+            Some(idx) if idx > 0 => name.split_at(idx),
+            _ => (name.as_str(), ""),
+        };
+        (1..)
+            .map(|n| format!("{}-{}{}", stem, n, extension))
+            .find(|candidate| !taken(candidate))
+            .unwrap()
+    }
+
+    /// The files linked from the note that was just read, along with the names they're linked as.
+    pub fn take(&mut self) -> Vec<(PathBuf, String)> {
+        std::mem::take(&mut self.linked)
+    }
+
+    /// Copies the file into the media location, unless it's a dry run, returning the name it's
+    /// stored under. The library may keep it under another name than it's linked as, when it
+    /// already has the file or the name is in use.
+    fn save(&mut self, path: &Path, planned: &str, dry_run: bool) -> Option<String> {
+        if let Some(name) = self.saved.get(path) {
+            return Some(name.clone());
+        }
+        let filename = path.file_name()?.to_string_lossy().into_owned();
+        let name = if dry_run {
+            planned.to_string()
+        } else {
+            let saved =
+                fs::read(path).and_then(|bytes| media_library().save(&filename, &bytes, "import"));
+            match saved {
+                Ok(file) => file.name,
+                Err(e) => {
+                    self.warnings
+                        .push(format!("could not copy attachment {:?}: {}", path, e));
+                    return None;
+                }
+            }
+        };
+        self.saved.insert(path.to_owned(), name.clone());
+        self.report.push((filename, name.clone()));
+        Some(name)
+    }
+}
+
+/// What an import changed, or would change on a dry run.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    /// Notes that already exist in the wiki, they're left alone.
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,
+    /// Attachments by their original name and the name they're stored under.
+    pub attachments: Vec<(String, String)>,
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (create, copy) = if self.dry_run {
+            ("Would create", "Would copy")
+        } else {
+            ("Created", "Copied")
+        };
+        writeln!(f, "{} {} notes", create, self.created.len())?;
+        for title in &self.created {
+            writeln!(f, "  + {}", title)?;
+        }
+        if !self.skipped.is_empty() {
+            writeln!(f, "Skipped {} notes that already exist", self.skipped.len())?;
+            for title in &self.skipped {
+                writeln!(f, "  = {}", title)?;
+            }
+        }
+        if !self.failed.is_empty() {
+            writeln!(f, "Could not write {} notes", self.failed.len())?;
+            for (title, e) in &self.failed {
+                writeln!(f, "  ! {}: {}", title, e)?;
+            }
+        }
+        writeln!(
+            f,
+            "{} {} attachments to the media location",
+            copy,
+            self.attachments.len()
+        )?;
+        for (original, name) in &self.attachments {
+            if original == name {
+                writeln!(f, "  + {}", name)?;
+            } else {
+                writeln!(f, "  + {} as {}", original, name)?;
+            }
+        }
+        if !self.warnings.is_empty() {
+            writeln!(f, "{} warnings", self.warnings.len())?;
+            for warning in &self.warnings {
+                writeln!(f, "  ? {}", warning)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Imports the vault, graph or export at `path`. Nothing is written on a dry run, the report
+/// lists what would be.
+pub fn import(source: Source, path: &Path, dry_run: bool) -> Result<ImportReport, ImportError> {
+    let mut attachments = Attachments::default();
+    let (notes, mut warnings) = match source {
+        Source::Obsidian => obsidian::read_vault(path, &mut attachments)?,
+        Source::Logseq => logseq::read_graph(path, &mut attachments, &daily_notes())?,
+        Source::Roam => roam::read_export(path, &daily_notes())?,
+    };
+    let mut report = ImportReport {
+        dry_run,
+        ..ImportReport::default()
+    };
+    let store = note_store();
+    let mut taken = ids::index_store();
+    for mut note in notes {
+        let title = note.title.clone();
+        if store.exists(&title) || report.created.contains(&title) {
+            report.skipped.push(title);
+            continue;
+        }
+        let id = unique_id(note.created, &mut taken);
+        // Only the attachments of notes that are written are copied.
+        for (path, linked) in std::mem::take(&mut note.attachments) {
+            if let Some(name) = attachments.save(&path, &linked, dry_run) {
+                if name != linked {
+                    note.relink(&linked, &name);
+                }
+            }
+        }
+        if !dry_run {
+            let content: String = note.into_note(id).into();
+            if let Err(e) = store.write(&title, &content) {
+                report.failed.push((title, e.to_string()));
+                continue;
+            }
+        }
+        report.created.push(title);
+    }
+    warnings.append(&mut attachments.warnings);
+    report.attachments = attachments.report;
+    report.warnings = warnings;
+    Ok(report)
+}
+
+/// Ids come from the creation time, which imported notes often share with each other or with
+/// notes already in the wiki.
+fn unique_id(created: Option<DateTime<Local>>, taken: &mut HashSet<String>) -> String {
+    let mut created = created.unwrap_or_else(Local::now);
+    loop {
+        let id = created.format(DT_FORMAT).to_string();
+        if taken.insert(id.clone()) {
+            return id;
+        }
+        created += Duration::seconds(1);
+    }
+}
+
+/// The date of a Logseq or Roam daily note title.
+pub(crate) fn parse_daily_title(title: &str) -> Option<NaiveDate> {
+    let caps = DAILY_TITLE.captures(title)?;
+    let date = format!("{} {} {}", &caps[1], &caps[2], &caps[3]);
+    NaiveDate::parse_from_str(&date, "%B %d %Y")
+        .or_else(|_| NaiveDate::parse_from_str(&date, "%b %d %Y"))
+        .ok()
+}
+
+/// Turns a daily note into one of tendril's, titled by the `[journal]` config.
+pub(crate) fn daily_note(note: &mut ImportedNote, date: NaiveDate, daily: &DailyNotes) {
+    note.title = daily.title(date);
+    for tag in &daily.tags {
+        note.add_tag(tag);
+    }
+}
+
+/// Renames links to daily notes the same way their notes are.
+pub(crate) fn daily_link(target: &str, daily: &DailyNotes) -> String {
+    match parse_daily_title(target) {
+        Some(date) => daily.title(date),
+        None => target.to_string(),
+    }
+}
+
+/// Calls `map` with the inside of every `[[link]]` in `text`, replacing the link with what it
+/// returns. Nested links are mapped from the inside.
+pub(crate) fn map_links(text: &str, mut map: impl FnMut(&str) -> String) -> String {
+    let mut mapped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        let inner = &rest[start + 2..];
+        let end = match inner.find("]]") {
+            Some(end) => end,
+            None => break,
+        };
+        if let Some(nested) = inner[..end].find("[[") {
+            mapped.push_str(&rest[..start + 2 + nested]);
+            rest = &inner[nested..];
+            continue;
+        }
+        mapped.push_str(&rest[..start]);
+        mapped.push_str(&map(&inner[..end]));
+        rest = &inner[end + 2..];
+    }
+    mapped.push_str(rest);
+    mapped
+}
+
+/// A wiki link in tendril's `[[label|target]]` order.
+pub(crate) fn wiki_link(target: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) if label != target => format!("[[{}|{}]]", label, target),
+        _ => format!("[[{}]]", target),
+    }
+}
+
+/// Links to a stored attachment, embedding images.
+pub(crate) fn attachment_link(name: &str, label: Option<&str>, embed: bool) -> String {
+    let is_image = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .map(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+        .unwrap_or(false);
+    if embed && is_image {
+        format!("![{}](/files/{})", label.unwrap_or(name), name)
+    } else {
+        wiki_link(&format!("files:{}", name), label)
+    }
+}
+
+/// The creation time of a file, falling back to when it was last changed.
+pub(crate) fn file_created(path: &Path) -> Option<DateTime<Local>> {
+    let metadata = fs::metadata(path).ok()?;
+    let time = metadata.created().or_else(|_| metadata.modified()).ok()?;
+    Some(DateTime::from(time))
+}
+
+/// Every file below `dir`, leaving out hidden files and directories like `.obsidian`.
+pub(crate) fn walk(dir: &Path) -> Result<Vec<PathBuf>, ImportError> {
+    let mut files = Vec::new();
+    let entries = fs::read_dir(dir).map_err(|e| ImportError::Read(dir.to_owned(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| ImportError::Read(dir.to_owned(), e))?
+            .path();
+        let hidden = path
+            .file_name()
+            .map(|name| name.to_string_lossy().starts_with('.'))
+            .unwrap_or(true);
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            files.extend(walk(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub(crate) fn read_file(path: &Path) -> Result<String, ImportError> {
+    fs::read_to_string(path).map_err(|e| ImportError::Read(path.to_owned(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_wiki_links() {
+        let text = "See [[Alpha|the plan]], [[[[nested]] link]] and [[unclosed";
+        assert_eq!(
+            map_links(text, |link| format!("<{}>", link)),
+            "See <Alpha|the plan>, [[<nested> link]] and [[unclosed"
+        );
+        assert_eq!(wiki_link("Alpha", Some("the plan")), "[[the plan|Alpha]]");
+        assert_eq!(wiki_link("Alpha", Some("Alpha")), "[[Alpha]]");
+        assert_eq!(
+            attachment_link("cat.png", None, true),
+            "![cat.png](/files/cat.png)"
+        );
+        assert_eq!(
+            attachment_link("paper.pdf", Some("paper"), true),
+            "[[paper|files:paper.pdf]]"
+        );
+    }
+
+    #[test]
+    fn reads_daily_note_titles() {
+        assert_eq!(
+            parse_daily_title("May 3rd, 2022"),
+            NaiveDate::from_ymd_opt(2022, 5, 3)
+        );
+        assert_eq!(
+            parse_daily_title("Jan 21st, 2021"),
+            NaiveDate::from_ymd_opt(2021, 1, 21)
+        );
+        assert_eq!(parse_daily_title("May the 4th"), None);
+    }
+
+    #[test]
+    fn gives_every_note_its_own_id() {
+        let created = DateTime::parse_from_rfc3339("2022-05-03T10:11:12+00:00")
+            .unwrap()
+            .with_timezone(&Local);
+        let mut taken = HashSet::new();
+        let first = unique_id(Some(created), &mut taken);
+        let second = unique_id(Some(created), &mut taken);
+        assert_ne!(first, second);
+        assert_eq!(taken.len(), 2);
+    }
+
+    #[test]
+    fn links_attachments_by_the_name_they_are_stored_under() {
+        let dir = PathBuf::from("/tmp/tendril-test/import/attachments/");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/cat.png"), [0u8; 4]).unwrap();
+        fs::write(dir.join("b/cat.png"), [1u8; 4]).unwrap();
+        let mut attachments = Attachments::default();
+        assert_eq!(
+            attachments.add(&dir.join("a/cat.png")),
+            Some("cat.png".into())
+        );
+        assert_eq!(
+            attachments.add(&dir.join("b/cat.png")),
+            Some("cat-1.png".into())
+        );
+        assert_eq!(
+            attachments.add(&dir.join("a/cat.png")),
+            Some("cat.png".into())
+        );
+        assert_eq!(attachments.add(&dir.join("c/cat.png")), None);
+        assert_eq!(attachments.take().len(), 2);
+        assert!(attachments.take().is_empty());
+
+        let mut note = ImportedNote {
+            content: "![cat](/files/cat.png) [[cat|files:cat.png]] [[files:cat.png]]".into(),
+            ..ImportedNote::default()
+        };
+        note.relink("cat.png", "cat-2.png");
+        assert_eq!(
+            note.content,
+            "![cat](/files/cat-2.png) [[cat|files:cat-2.png]] [[files:cat-2.png]]"
+        );
+    }
+}
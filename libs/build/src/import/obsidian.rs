@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use urlencoding::decode;
+use wikitext::{parsers::Note, processors::tags::TagsArray};
+
+use super::{
+    attachment_link, file_created, map_links, read_file, walk, wiki_link, Attachments, ImportError,
+    ImportedNote, MARKDOWN_LINK,
+};
+
+lazy_static! {
+    static ref EMBED: Regex = Regex::new(r"!\[\[([^\[\]]+)\]\]").unwrap();
+}
+
+/// Front matter that's replaced by the importer instead of carried over.
+const REPLACED_PROPERTIES: [&str; 5] = ["title", "tags", "created", "id", "content-type"];
+
+enum Target {
+    Note(String),
+    File(PathBuf),
+    Missing,
+}
+
+/// Obsidian links by the shortest unique path, usually just the note's name, so links are
+/// looked up by full path first and then by name.
+struct Vault {
+    notes_by_path: HashMap<String, String>,
+    notes_by_name: HashMap<String, String>,
+    files_by_path: HashMap<String, PathBuf>,
+    files_by_name: HashMap<String, PathBuf>,
+}
+
+impl Vault {
+    fn resolve(&self, target: &str) -> Target {
+        let key = target.trim().trim_start_matches("./").to_lowercase();
+        let note_key = key.strip_suffix(".md").unwrap_or(&key);
+        let name = key.rsplit('/').next().unwrap_or(&key);
+        let note_name = name.strip_suffix(".md").unwrap_or(name);
+        if let Some(title) = self.notes_by_path.get(note_key) {
+            Target::Note(title.clone())
+        } else if let Some(path) = self.files_by_path.get(&key) {
+            Target::File(path.clone())
+        } else if let Some(title) = self.notes_by_name.get(note_name) {
+            Target::Note(title.clone())
+        } else if let Some(path) = self.files_by_name.get(name) {
+            Target::File(path.clone())
+        } else {
+            Target::Missing
+        }
+    }
+
+    /// Converts the inside of a `[[link]]` or `![[embed]]`, dropping heading and block anchors
+    /// since tendril links to whole notes.
+    fn convert_link(&self, link: &str, embed: bool, attachments: &mut Attachments) -> String {
+        let (target, label) = match link.split_once('|') {
+            Some((target, label)) => (target.trim_end_matches('\\'), Some(label)),
+            None => (link, None),
+        };
+        let target = target.split(['#', '^']).next().unwrap_or(target);
+        if target.is_empty() {
+            return label.unwrap_or(link).to_string();
+        }
+        match self.resolve(target) {
+            Target::Note(title) => wiki_link(&title, label.or(Some(target))),
+            Target::File(path) => match attachments.add(&path) {
+                Some(name) => attachment_link(&name, label, embed),
+                None => wiki_link(target, label),
+            },
+            Target::Missing => wiki_link(target, label),
+        }
+    }
+}
+
+fn title_of(vault: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(vault).unwrap_or(path).with_extension("");
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Reads the Markdown notes in an Obsidian vault, along with the files they link to.
+pub(crate) fn read_vault(
+    vault: &Path,
+    attachments: &mut Attachments,
+) -> Result<(Vec<ImportedNote>, Vec<String>), ImportError> {
+    if !vault.is_dir() {
+        return Err(ImportError::Read(
+            vault.to_owned(),
+            io::Error::new(io::ErrorKind::NotFound, "not a vault directory"),
+        ));
+    }
+    let mut pages = Vec::new();
+    let mut index = Vault {
+        notes_by_path: HashMap::new(),
+        notes_by_name: HashMap::new(),
+        files_by_path: HashMap::new(),
+        files_by_name: HashMap::new(),
+    };
+    for path in walk(vault)? {
+        let relative = title_of(vault, &path).to_lowercase();
+        let name = relative.rsplit('/').next().unwrap_or(&relative).to_string();
+        if matches!(path.extension(), Some(ext) if ext == "md") {
+            let title = title_of(vault, &path);
+            index.notes_by_path.insert(relative, title.clone());
+            index
+                .notes_by_name
+                .entry(name)
+                .or_insert_with(|| title.clone());
+            pages.push((path, title));
+        } else {
+            let file_name = path.file_name().unwrap().to_string_lossy().to_lowercase();
+            let file_path = path
+                .strip_prefix(vault)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .replace('\\', "/")
+                .to_lowercase();
+            index.files_by_path.insert(file_path, path.clone());
+            index.files_by_name.entry(file_name).or_insert(path);
+        }
+    }
+    let mut notes = Vec::with_capacity(pages.len());
+    for (path, title) in pages {
+        let raw = read_file(&path)?;
+        let mut note = ImportedNote {
+            title,
+            created: file_created(&path),
+            ..ImportedNote::default()
+        };
+        let body = if raw.starts_with("---") {
+            let parsed = Note::from(raw);
+            if let Some(tags) = parsed.header.get("tags") {
+                for tag in TagsArray::new(tags).values {
+                    note.add_tag(tag);
+                }
+            }
+            for (key, value) in parsed.header {
+                if !REPLACED_PROPERTIES.contains(&key.as_str()) {
+                    note.properties.insert(key, value);
+                }
+            }
+            parsed.content
+        } else {
+            raw
+        };
+        note.content = convert(&body, &index, attachments);
+        note.attachments = attachments.take();
+        note.add_inline_tags();
+        notes.push(note);
+    }
+    Ok((notes, Vec::new()))
+}
+
+fn convert(body: &str, vault: &Vault, attachments: &mut Attachments) -> String {
+    let body = EMBED.replace_all(body, |caps: &Captures| {
+        vault.convert_link(&caps[1], true, attachments)
+    });
+    let body = map_links(&body, |link| vault.convert_link(link, false, attachments));
+    MARKDOWN_LINK
+        .replace_all(&body, |caps: &Captures| {
+            let url = &caps[3];
+            if url.contains("://") || url.starts_with('#') || url.starts_with("mailto:") {
+                return caps[0].to_string();
+            }
+            let target = decode(url)
+                .map(|url| url.into_owned())
+                .unwrap_or_else(|_| url.into());
+            let label = Some(&caps[2]).filter(|label| !label.is_empty());
+            match vault.resolve(&target) {
+                Target::Note(title) => wiki_link(&title, label),
+                Target::File(path) => match attachments.add(&path) {
+                    Some(name) => attachment_link(&name, label, !caps[1].is_empty()),
+                    None => caps[0].to_string(),
+                },
+                Target::Missing => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/import/obsidian/";
+
+    #[test]
+    fn converts_links_and_attachments() {
+        let vault = PathBuf::from(TEST_DIR);
+        if vault.exists() {
+            fs::remove_dir_all(&vault).unwrap();
+        }
+        fs::create_dir_all(vault.join("Projects")).unwrap();
+        fs::create_dir_all(vault.join("attachments")).unwrap();
+        fs::create_dir_all(vault.join(".obsidian")).unwrap();
+        fs::write(vault.join(".obsidian/app.json"), "{}").unwrap();
+        fs::write(vault.join("attachments/Cat Photo.png"), [0u8; 4]).unwrap();
+        fs::write(
+            vault.join("Projects/Alpha.md"),
+            "---\ntags: [work]\nstatus: active\n---\nSee [[Beta#Plan|the plan]] and ![[Cat Photo.png]].\n#todo [notes](Beta.md)",
+        )
+        .unwrap();
+        fs::write(vault.join("Beta.md"), "Back to [[Projects/Alpha]].").unwrap();
+        let mut attachments = Attachments::default();
+        let (notes, _) = read_vault(&vault, &mut attachments).unwrap();
+        assert_eq!(notes.len(), 2);
+        let alpha = notes.iter().find(|n| n.title == "Projects/Alpha").unwrap();
+        assert_eq!(
+            alpha.content,
+            "See [[the plan|Beta]] and ![Cat-Photo.png](/files/Cat-Photo.png).\n#todo [[notes|Beta]]"
+        );
+        assert_eq!(alpha.tags, vec!["work", "todo"]);
+        assert_eq!(alpha.properties["status"], "active");
+        let beta = notes.iter().find(|n| n.title == "Beta").unwrap();
+        assert_eq!(beta.content, "Back to [[Projects/Alpha]].");
+        assert_eq!(
+            alpha.attachments,
+            vec![(
+                vault.join("attachments/Cat Photo.png"),
+                "Cat-Photo.png".to_string()
+            )]
+        );
+        assert!(beta.attachments.is_empty());
+    }
+}
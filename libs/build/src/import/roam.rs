@@ -0,0 +1,183 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use chrono::{DateTime, Local, TimeZone};
+use lazy_static::lazy_static;
+use persistance::fs::journal::DailyNotes;
+use regex::{Captures, Regex};
+use serde_derive::Deserialize;
+
+use super::{
+    convert_outline_text, daily_link, daily_note, parse_daily_title, ImportError, ImportedNote,
+};
+
+lazy_static! {
+    static ref PROPERTY: Regex = Regex::new(r"^([A-Za-z][\w -]*):: ?(.*)$").unwrap();
+    static ref TASK: Regex = Regex::new(r"^\{\{\[?\[?(TODO|DONE)\]?\]?\}\} ?").unwrap();
+    static ref EMBED_MACRO: Regex =
+        Regex::new(r"\{\{\[?\[?embed\]?\]?: ?(\(\([^)]+\)\)|\[\[[^\]]+\]\])\}\}").unwrap();
+    static ref ITALIC: Regex = Regex::new(r"__([^_]+)__").unwrap();
+    static ref HIGHLIGHT: Regex = Regex::new(r"\^\^([^^]+)\^\^").unwrap();
+}
+
+#[derive(Deserialize)]
+struct Page {
+    title: String,
+    #[serde(default)]
+    children: Vec<Block>,
+    #[serde(rename = "create-time")]
+    create_time: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct Block {
+    #[serde(default)]
+    string: String,
+    uid: Option<String>,
+    #[serde(default)]
+    children: Vec<Block>,
+    heading: Option<usize>,
+}
+
+/// Rewrites Roam's own formatting as Markdown.
+fn format_text(text: &str) -> String {
+    let text = EMBED_MACRO.replace_all(text, "$1");
+    let text = ITALIC.replace_all(&text, "*$1*");
+    HIGHLIGHT.replace_all(&text, "$1").into_owned()
+}
+
+/// Block references are replaced by the text of the block, without its task marker.
+fn collect_blocks(blocks: &[Block], map: &mut HashMap<String, String>) {
+    for block in blocks {
+        if let Some(uid) = &block.uid {
+            map.insert(uid.clone(), format_text(&TASK.replace(&block.string, "")));
+        }
+        collect_blocks(&block.children, map);
+    }
+}
+
+/// Writes blocks as a Markdown list, indented by two spaces per level.
+fn render_outline(blocks: &[Block], depth: usize, out: &mut Vec<String>) {
+    for block in blocks {
+        let text = TASK.replace(&block.string, |caps: &Captures| {
+            format!("[{}] ", if &caps[1] == "DONE" { "x" } else { " " })
+        });
+        let text = format_text(&text);
+        let heading = match block.heading {
+            Some(level) if level > 0 => format!("{} ", "#".repeat(level)),
+            _ => String::new(),
+        };
+        // Continuation lines of a multi line block stay inside its list item.
+        let indent = "  ".repeat(depth);
+        let text = text.replace('\n', &format!("\n{}  ", indent));
+        out.push(format!("{}- {}{}", indent, heading, text));
+        render_outline(&block.children, depth + 1, out);
+    }
+}
+
+fn created(millis: Option<i64>) -> Option<DateTime<Local>> {
+    Local.timestamp_millis_opt(millis?).single()
+}
+
+/// Reads the pages of a Roam JSON export.
+pub(crate) fn read_export(
+    path: &Path,
+    daily: &DailyNotes,
+) -> Result<(Vec<ImportedNote>, Vec<String>), ImportError> {
+    let raw = fs::read_to_string(path).map_err(|e| ImportError::Read(path.to_owned(), e))?;
+    let pages: Vec<Page> = serde_json::from_str(&raw)
+        .map_err(|e| ImportError::Parse(path.to_owned(), e.to_string()))?;
+    let mut blocks = HashMap::new();
+    for page in &pages {
+        collect_blocks(&page.children, &mut blocks);
+    }
+    let mut warnings = Vec::new();
+    let mut notes = Vec::with_capacity(pages.len());
+    for page in pages {
+        let mut note = ImportedNote {
+            title: page.title.clone(),
+            created: created(page.create_time),
+            ..ImportedNote::default()
+        };
+        if let Some(date) = parse_daily_title(&page.title) {
+            daily_note(&mut note, date, daily);
+        }
+        // Attributes on the page's top level blocks become its properties.
+        let mut children = page.children;
+        children.retain(|block| match PROPERTY.captures(&block.string) {
+            Some(caps) if block.children.is_empty() => {
+                let key = caps[1].trim().to_lowercase();
+                let value = caps[2].trim();
+                if key == "tags" {
+                    for tag in value.split(',') {
+                        let tag = tag.trim().trim_start_matches('#');
+                        note.add_tag(tag.trim_start_matches("[[").trim_end_matches("]]"));
+                    }
+                } else {
+                    note.properties.insert(key.replace(' ', "-"), value.into());
+                }
+                false
+            }
+            _ => true,
+        });
+        let mut lines = Vec::new();
+        render_outline(&children, 0, &mut lines);
+        note.content = convert_outline_text(
+            &lines.join("\n"),
+            &mut note,
+            &blocks,
+            &mut warnings,
+            &|target| daily_link(target, daily),
+        );
+        note.add_inline_tags();
+        notes.push(note);
+    }
+    Ok((notes, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/import/roam/";
+
+    #[test]
+    fn converts_pages_and_blocks() {
+        let dir = PathBuf::from(TEST_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        let export = dir.join("export.json");
+        fs::write(
+            &export,
+            r#"[
+                {"title": "Alpha", "create-time": 1651572672000, "children": [
+                    {"string": "status:: active", "uid": "a0"},
+                    {"string": "Tags:: #work, [[deep work]]", "uid": "a1"},
+                    {"string": "Plan", "uid": "a2", "heading": 2, "children": [
+                        {"string": "{{[[TODO]]}} ship __it__ ^^soon^^ #[[big ideas]]", "uid": "a3"}
+                    ]}
+                ]},
+                {"title": "May 3rd, 2022", "children": [
+                    {"string": "{{[[DONE]]}} see ((a3)) on [[May 4th, 2022]]", "uid": "b1"},
+                    {"string": "{{embed: ((a2))}}", "uid": "b2"}
+                ]}
+            ]"#,
+        )
+        .unwrap();
+        let (notes, warnings) = read_export(&export, &DailyNotes::from(None)).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let alpha = notes.iter().find(|n| n.title == "Alpha").unwrap();
+        assert_eq!(
+            alpha.content,
+            "- ## Plan\n  - [ ] ship *it* soon [[big ideas]]"
+        );
+        assert_eq!(alpha.tags, vec!["work", "deep work", "big ideas"]);
+        assert_eq!(alpha.properties["status"], "active");
+        assert!(alpha.created.is_some());
+        let journal = notes.iter().find(|n| n.title == "2022-05-03").unwrap();
+        assert_eq!(
+            journal.content,
+            "- [x] see ship *it* soon [[big ideas]] on [[May 4th, 2022|2022-05-04]]\n- Plan"
+        );
+    }
+}
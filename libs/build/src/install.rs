@@ -6,7 +6,9 @@ use std::{
 
 use persistance::fs::{
     config::Config,
-    utils::{get_config_location, get_data_dir_location, get_wiki_location},
+    utils::{
+        get_config_location, get_data_dir_location, get_notebook_data_location, get_wiki_location,
+    },
 };
 use task_runners::hash_password;
 use wikitext::parsers::Note;
@@ -95,7 +97,7 @@ fn migrate_md_to_wikitext() {
 
 fn process_migration_dir(original_dir: PathBuf, backup_dir: PathBuf) {
     let wiki_dir = get_wiki_location();
-    let data_dir = get_data_dir_location();
+    let data_dir = get_notebook_data_location();
     for file in fs::read_dir(original_dir).unwrap() {
         let entry = file.unwrap();
         if entry.path().to_str().unwrap().contains(".git") {
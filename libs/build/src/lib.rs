@@ -1,4 +1,7 @@
 pub mod config;
+pub mod encryption;
+pub mod epub;
+pub mod import;
 pub mod install;
 pub mod pages;
 pub mod references;
@@ -1,23 +1,23 @@
-use async_recursion::async_recursion;
 use futures::{stream, StreamExt};
 use std::fmt::Write;
 
 use render::static_site_page::StaticSitePage;
 use wikitext::{
-    parsers::{ParsedPages, TemplattedPage},
+    parsers::{format_links, resolve_link, ParsedPages, TemplattedPage},
     GlobalBacklinks,
 };
 
-use persistance::fs::path_to_data_structure;
 use persistance::fs::utils::get_config_location;
+use persistance::ids;
+use persistance::store::note_store;
 use render::Render;
 use tokio::sync::Mutex;
 use wikitext::processors::update_templatted_pages;
 
 use std::{
     collections::{BTreeMap, HashMap},
-    fs::{self, read_dir},
-    path::{Path, PathBuf},
+    fs,
+    path::Path,
     sync::Arc,
 };
 
@@ -63,13 +63,13 @@ impl Builder {
         }
     }
 
-    pub async fn sweep(&self, wiki_location: &str) {
+    pub async fn sweep(&self) {
         if !Path::new("./public").exists() {
             fs::create_dir_all("./public").unwrap();
         }
         let links = Arc::clone(&self.backlinks);
         let pages = Arc::clone(&self.pages);
-        parse_entries(PathBuf::from(wiki_location), links, pages).await;
+        parse_entries(links, pages).await;
     }
 }
 
@@ -79,39 +79,36 @@ impl Default for Builder {
     }
 }
 
-async fn process_file(path: PathBuf, backlinks: &GlobalBacklinks, pages: ParsedPages) {
-    let note = path_to_data_structure(&path).unwrap();
-    let structured = note.to_structured().as_owned();
+async fn process_file(title: String, backlinks: &GlobalBacklinks, pages: ParsedPages) {
+    let note = note_store().read_note(&title).unwrap();
+    let links_and_tags = note
+        .to_structured()
+        .links_and_tags
+        .iter()
+        .map(|link| resolve_link(link).into_owned())
+        .collect::<Vec<String>>();
     let mut backlinks = backlinks.lock().await;
-    add_to_global_store(&structured.0, &structured.1, &mut backlinks).await;
+    add_to_global_store(&title, &links_and_tags, &mut backlinks).await;
     let templatted = note.to_template();
     update_templatted_pages(templatted.page, pages).await;
 }
 
-#[async_recursion]
-async fn parse_entries(
-    entrypoint: PathBuf,
-    backlinks: GlobalBacklinks,
-    rendered_pages: ParsedPages,
-) {
-    let entries = read_dir(entrypoint).unwrap();
-    let pipeline = stream::iter(entries).for_each(|entry| async {
+async fn parse_entries(backlinks: GlobalBacklinks, rendered_pages: ParsedPages) {
+    let titles = note_store().list().unwrap();
+    // `[[id:...]]` links are written out with the title of the note they point at.
+    for title in titles.iter() {
+        if let Ok(note) = note_store().read_note(title) {
+            ids::index(title, &note);
+        }
+    }
+    let pipeline = stream::iter(titles).for_each(|title| async {
         let links = Arc::clone(&backlinks);
         let pages = Arc::clone(&rendered_pages);
-        let entry = entry.unwrap();
-        let file_name = entry.file_name();
-        let file_name = file_name.to_str().unwrap();
-        if entry.file_type().unwrap().is_file() && file_name.ends_with(".txt") {
-            tokio::spawn(async move {
-                process_file(entry.path(), &links, pages).await;
-            })
-            .await
-            .unwrap();
-        } else if entry.file_type().unwrap().is_dir()
-            && !entry.path().to_str().unwrap().contains(".git")
-        {
-            parse_entries(entry.path(), links, pages).await;
-        }
+        tokio::spawn(async move {
+            process_file(title, &links, pages).await;
+        })
+        .await
+        .unwrap();
     });
     pipeline.await
 }
@@ -122,7 +119,8 @@ async fn write_index_page(pages: &ParsedPages) {
         let _ = write!(
             output,
             r#"<li><a href="{}">{}</a></li>"#,
-            page.title, page.title
+            format_links(&page.title),
+            page.title
         );
         output
     });
@@ -148,13 +146,13 @@ async fn write_entries(pages: &ParsedPages, backlinks: &GlobalBacklinks) {
     for page in page_vals.iter() {
         let links = link_vals.get(&page.title);
         let output = StaticSitePage::new(page, links).render().await;
-        let formatted_title = page.title.replace('/', "-");
-        let out_dir = format!("public/{}", formatted_title);
-        // TODO use path here instead of title? Since `/` in title can cause issues in fs::write
-        tokio::fs::create_dir(&out_dir)
+        // Namespaced notes map onto nested directories, so `Projects/Alpha` ends up at
+        // `public/Projects/Alpha/index.html` and keeps the same URL as in the dynamic wiki.
+        let out_dir = format!("public/{}", page.title);
+        tokio::fs::create_dir_all(&out_dir)
             .await
             .unwrap_or_else(|e| eprintln!("{:?}\nCould not create dir: {}", e, out_dir));
-        let out_file = format!("public/{}/index.html", formatted_title);
+        let out_file = format!("public/{}/index.html", page.title);
         tokio::fs::write(&out_file, output)
             .await
             .unwrap_or_else(|e| eprintln!("{:?}\nCould not write file: {}", e, out_file));
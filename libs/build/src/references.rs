@@ -1,29 +1,48 @@
-use std::{collections::BTreeMap, io, path::PathBuf, sync::Arc};
+use std::collections::BTreeMap;
 
-use async_recursion::async_recursion;
 use futures::{stream, StreamExt};
-use persistance::fs::{path_to_data_structure, utils::get_file_path};
-use tokio::fs::{self, read_dir};
-use wikitext::{parsers::Note, Backlinks, GlobalBacklinks};
+use persistance::{
+    ids,
+    store::{note_store, StoreError},
+};
+use wikitext::{
+    parsers::{resolve_link, Note},
+    Backlinks, GlobalBacklinks,
+};
 
 // TODO: Reduce these duplicated functions, think of a better abstraction
-#[async_recursion]
-pub async fn parse_entries(entrypoint: PathBuf) -> Vec<(String, Vec<String>)> {
-    let mut entries = read_dir(entrypoint).await.unwrap();
-    let mut result = Vec::new();
-    while let Some(entry) = entries.next_entry().await.unwrap() {
-        if entry.file_type().await.unwrap().is_file()
-            && entry.file_name().to_str().unwrap().ends_with(".txt")
-        {
-            let note = path_to_data_structure(&entry.path()).unwrap();
-            let structured = note.to_structured();
-            result.push(structured.as_owned());
-        } else if entry.file_type().await.unwrap().is_dir()
-            && !entry.path().to_str().unwrap().contains(".git")
-        {
-            let results = parse_entries(entry.path()).await;
-            result.extend(results);
+pub async fn parse_entries() -> Vec<(String, Vec<String>)> {
+    let store = note_store();
+    let mut notes = Vec::new();
+    for title in store.list().unwrap() {
+        // Encrypted notes can't be read while the notebook is locked, leave them out.
+        let note = match store.read_note(&title) {
+            Ok(note) => note,
+            Err(e) => {
+                eprintln!("Could not read {}: {}", title, e);
+                continue;
+            }
+        };
+        ids::index(&title, &note);
+        notes.push((title, note));
+    }
+    // Every id has to be known before `[[id:...]]` links can be resolved to titles.
+    let mut result = Vec::with_capacity(notes.len());
+    for (title, note) in notes {
+        // The store title is the canonical one, it includes the namespace the note lives in.
+        let mut links_and_tags = note
+            .to_structured()
+            .links_and_tags
+            .iter()
+            .map(|link| resolve_link(link).into_owned())
+            .collect::<Vec<String>>();
+        // Uploaded files the note uses are tracked as `files:name`.
+        for media_link in note.media_links() {
+            if !links_and_tags.contains(&media_link) {
+                links_and_tags.push(media_link);
+            }
         }
+        result.push((title, links_and_tags));
     }
     result
 }
@@ -59,8 +78,8 @@ pub async fn add_to_global_store<'a>(
     }
 }
 
-pub async fn build_links(wiki_location: Arc<String>) -> Backlinks {
-    let entries = parse_entries(PathBuf::from(wiki_location.as_str())).await;
+pub async fn build_links() -> Backlinks {
+    let entries = parse_entries().await;
     create_global_store(entries).await
 }
 
@@ -68,7 +87,8 @@ pub async fn update_global_store(current_title: &str, note: &Note, links: Global
     let mut links = links.lock().await;
     let structured = note.to_structured();
     for link in structured.links_and_tags.iter() {
-        match links.get_mut(*link) {
+        let link = resolve_link(link);
+        match links.get_mut(link.as_ref()) {
             Some(exists) => {
                 if exists.contains(&String::from(current_title)) {
                     continue;
@@ -81,13 +101,29 @@ pub async fn update_global_store(current_title: &str, note: &Note, links: Global
             }
         }
     }
+    let media_links = note.media_links();
+    for media_link in media_links.iter() {
+        let linked_by = links.entry(media_link.to_string()).or_default();
+        if !linked_by.contains(&String::from(current_title)) {
+            linked_by.push(current_title.into());
+        }
+    }
+    // Files are tracked to find the unused ones, so forget the files an edit stopped using.
+    links.retain(|link, linked_by| {
+        if link.starts_with("files:") && !media_links.contains(link) {
+            linked_by.retain(|title| title != current_title);
+            !linked_by.is_empty()
+        } else {
+            true
+        }
+    });
 }
 
 pub async fn delete_from_global_store(title: &str, note: &Note, links: GlobalBacklinks) {
     let mut links = links.lock().await;
     let templatted = note.to_template();
     for link in templatted.outlinks {
-        let link = link.to_string();
+        let link = resolve_link(&link).into_owned();
         if let Some(exists) = links.get(&link) {
             if exists.contains(&title.to_string()) {
                 let filtered = exists
@@ -112,9 +148,42 @@ pub async fn delete_from_global_store(title: &str, note: &Note, links: GlobalBac
             }
         }
     }
+    for media_link in note.media_links() {
+        if let Some(linked_by) = links.get_mut(&media_link) {
+            linked_by.retain(|note| note != title);
+            if linked_by.is_empty() {
+                links.remove(&media_link);
+            }
+        }
+    }
     links.remove(title);
 }
 
+/// Drops a note from every list of backlinks, for notes whose content is already gone, e.g. ones
+/// deleted outside of the wiki.
+pub async fn forget_in_global_store(title: &str, links: GlobalBacklinks) {
+    let mut links = links.lock().await;
+    for linked_by in links.values_mut() {
+        linked_by.retain(|note| note != title);
+    }
+    links.retain(|link, linked_by| !(link.starts_with("files:") && linked_by.is_empty()));
+    links.remove(title);
+}
+
+/// Points wiki links at a renamed note, leaving any other mention of the old title alone. Both
+/// `[[old]]` and `[[label|old]]` are rewritten.
+fn relink(raw_page: &str, old_title: &str, current_title: &str) -> String {
+    raw_page
+        .replace(
+            &format!("[[{}]]", old_title),
+            &format!("[[{}]]", current_title),
+        )
+        .replace(
+            &format!("|{}]]", old_title),
+            &format!("|{}]]", current_title),
+        )
+}
+
 pub async fn rename_in_global_store(
     current_title: &str,
     old_title: &str,
@@ -125,16 +194,14 @@ pub async fn rename_in_global_store(
     if let Some(linked_pages) = linked_pages {
         stream::iter(linked_pages)
             .for_each(|page| async {
-                let location = get_file_path(page).unwrap();
-                match fs::read_to_string(&location).await {
+                let store = note_store();
+                match store.read(page) {
                     Ok(raw_page) => {
-                        let relinked_page = raw_page.replace(old_title, current_title);
-                        fs::write(location, relinked_page).await.unwrap();
+                        let relinked_page = relink(&raw_page, old_title, current_title);
+                        store.write(page, &relinked_page).unwrap();
                     }
-                    Err(e) => match e.kind() {
-                        io::ErrorKind::NotFound => {}
-                        _ => std::panic::panic_any(e),
-                    },
+                    Err(StoreError::NotFound) => {}
+                    Err(e) => std::panic::panic_any(e),
                 }
             })
             .await;
@@ -142,22 +209,33 @@ pub async fn rename_in_global_store(
         backlinks.insert(current_title.into(), pages);
         backlinks.remove(old_title);
     }
+    // The files the note uses now belong to its new title.
+    for (_, linked_by) in backlinks
+        .iter_mut()
+        .filter(|(link, _)| link.starts_with("files:"))
+    {
+        if linked_by.iter().any(|title| title == old_title) {
+            linked_by.retain(|title| title != old_title && title != current_title);
+            linked_by.push(current_title.into());
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
     use std::sync::Arc;
     use std::{env, fs};
     use tokio::sync::Mutex;
 
-    use persistance::fs::utils::get_file_path;
-
     use super::*;
 
     const TEST_DIR: &str = "/tmp/tendril-test/references/";
 
     fn init_temp_wiki(namespace: &str) {
         env::set_var("TENDRIL_WIKI_DIR", TEST_DIR);
+        env::set_var("TENDRIL_STORAGE", "plaintext");
+        env::set_var("TENDRIL_ENCRYPT_ALL", "false");
         fs::create_dir_all(format!("{}{}", TEST_DIR, namespace)).unwrap();
         for entry in fs::read_dir("../wikitext/fixtures").unwrap() {
             let mut dest = PathBuf::from(TEST_DIR);
@@ -191,8 +269,7 @@ mod tests {
         let mut link_tree = BTreeMap::new();
         link_tree.insert(title.into(), vec!["wiki page".into()]);
         let links: GlobalBacklinks = Arc::new(Mutex::new(link_tree));
-        let path = get_file_path(title).unwrap();
-        let note = path_to_data_structure(&path).unwrap();
+        let note = note_store().read_note(title).unwrap();
         update_global_store(title, &note, links.clone()).await;
         let updated_links = links.lock().await;
         let entry = updated_links.get(title).unwrap();
@@ -216,6 +293,14 @@ mod tests {
         assert_eq!(renamed_entry, &vec![String::from("wiki page")]);
         teardown_temp_wiki("rename");
     }
+    #[test]
+    fn relinks_only_wiki_links() {
+        let raw = "See [[Alpha]] and [[the plan|Alpha]], not Alpha itself or [[Alpha Beta]].";
+        assert_eq!(
+            relink(raw, "Alpha", "Projects/Alpha"),
+            "See [[Projects/Alpha]] and [[the plan|Projects/Alpha]], not Alpha itself or [[Alpha Beta]]."
+        );
+    }
     #[tokio::test]
     async fn deletes_from_global_store() {
         init_temp_wiki("delete");
@@ -223,12 +308,35 @@ mod tests {
         let mut link_tree = BTreeMap::new();
         link_tree.insert(title.into(), vec!["wiki page".into()]);
         let links: GlobalBacklinks = Arc::new(Mutex::new(link_tree));
-        let path = get_file_path(title).unwrap();
-        let note = path_to_data_structure(&path).unwrap();
+        let note = note_store().read_note(title).unwrap();
         delete_from_global_store(title, &note, links.clone()).await;
         let updated_links = links.lock().await;
         let entry = updated_links.get(title);
         assert_eq!(entry, None);
         teardown_temp_wiki("delete");
     }
+    #[tokio::test]
+    async fn tracks_media_used_by_notes() {
+        let mut link_tree = BTreeMap::new();
+        link_tree.insert("files:old.png".into(), vec!["Cats".into(), "Dogs".into()]);
+        link_tree.insert("files:gone.png".into(), vec!["Cats".into()]);
+        let links: GlobalBacklinks = Arc::new(Mutex::new(link_tree));
+        let mut header = std::collections::HashMap::new();
+        header.insert("title".to_string(), "Cats".to_string());
+        let note = Note {
+            header,
+            content: String::from("![cat](http://localhost:6683/files/new.png)"),
+        };
+        update_global_store("Cats", &note, links.clone()).await;
+        let updated_links = links.lock().await;
+        assert_eq!(
+            updated_links.get("files:new.png"),
+            Some(&vec![String::from("Cats")])
+        );
+        assert_eq!(
+            updated_links.get("files:old.png"),
+            Some(&vec![String::from("Dogs")])
+        );
+        assert_eq!(updated_links.get("files:gone.png"), None);
+    }
 }
@@ -0,0 +1,26 @@
+use std::io;
+
+use compression::prelude::*;
+
+use crate::crypto::{open, protect};
+
+/// Compresses archives and revisions, which are encrypted too while the notebook is unlocked.
+pub fn compress(text: &str) -> Vec<u8> {
+    protect(
+        text.as_bytes()
+            .iter()
+            .cloned()
+            .encode(&mut BZip2Encoder::new(9), Action::Finish)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap(),
+    )
+}
+
+pub fn decompress(bytes: Vec<u8>) -> io::Result<String> {
+    let decompressed = open(bytes)?
+        .into_iter()
+        .decode(&mut BZip2Decoder::new())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+    String::from_utf8(decompressed).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,375 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use argon2::Argon2;
+use blake2::{Blake2s256, Digest};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use wikitext::parsers::has_front_matter;
+
+use crate::fs::{config::read_config, utils::get_notebook_data_location};
+
+/// Starts every encrypted file, followed by the nonce and the ciphertext.
+const MAGIC: &[u8] = b"tendril-encrypted\x01";
+/// Encrypted notes stay text files, holding this prefix and the encrypted note in base64.
+const ARMOR_PREFIX: &str = "tendril-encrypted: ";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+/// Encrypted with the key when encryption is set up, to tell a wrong passphrase from the right one.
+const CHECK_VALUE: &[u8] = b"tendril";
+const KEY_FILE: &str = "encryption.toml";
+const ENCRYPTED_HEADER: &str = "encrypted";
+
+lazy_static::lazy_static! {
+    static ref KEY: RwLock<Option<Key>> = RwLock::new(None);
+}
+
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("the notebook is locked, a passphrase is needed for encrypted notes")]
+    Locked,
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("could not decrypt, the data is damaged or was encrypted with another passphrase")]
+    Corrupted,
+    #[error("could not derive a key from the passphrase: {0}")]
+    KeyDerivation(String),
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl From<CryptoError> for io::Error {
+    fn from(e: CryptoError) -> Self {
+        match e {
+            CryptoError::Io(e) => e,
+            CryptoError::Locked | CryptoError::WrongPassphrase => {
+                io::Error::new(io::ErrorKind::PermissionDenied, e.to_string())
+            }
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+/// Kept in the data directory once encryption is set up. The passphrase itself is never stored.
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    salt: String,
+    check: String,
+}
+
+fn key_file_location() -> PathBuf {
+    get_notebook_data_location().join(KEY_FILE)
+}
+
+/// Whether a passphrase has been set for this notebook.
+pub fn is_configured() -> bool {
+    key_file_location().exists()
+}
+
+pub fn is_unlocked() -> bool {
+    KEY.read().unwrap().is_some()
+}
+
+/// Whether every note is encrypted, rather than only the ones with an `encrypted: true` header.
+/// `TENDRIL_ENCRYPT_ALL` overrides the config, like `TENDRIL_STORAGE` does for the storage.
+pub fn encrypt_all() -> bool {
+    match env::var("TENDRIL_ENCRYPT_ALL") {
+        Ok(val) => val == "true",
+        _ => read_config()
+            .encryption
+            .as_ref()
+            .and_then(|e| e.encrypt_all)
+            .unwrap_or(false),
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Key, CryptoError> {
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| CryptoError::KeyDerivation(e.to_string()))?;
+    Ok(key)
+}
+
+fn decode(encoded: &str) -> Result<Vec<u8>, CryptoError> {
+    base64::decode(encoded.trim()).map_err(|_| CryptoError::Corrupted)
+}
+
+fn unlock_at(location: &Path, passphrase: &str) -> Result<(), CryptoError> {
+    let key = if location.exists() {
+        let key_file: KeyFile =
+            toml::from_str(&fs::read_to_string(location)?).map_err(|_| CryptoError::Corrupted)?;
+        let key = derive_key(passphrase, &decode(&key_file.salt)?)?;
+        match open_with(&key, &decode(&key_file.check)?) {
+            Ok(check) if check == CHECK_VALUE => key,
+            _ => return Err(CryptoError::WrongPassphrase),
+        }
+    } else {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(passphrase, &salt)?;
+        let key_file = KeyFile {
+            salt: base64::encode(salt),
+            check: base64::encode(seal_with(&key, CHECK_VALUE)),
+        };
+        if let Some(parent) = location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(location, toml::to_string(&key_file).unwrap())?;
+        key
+    };
+    *KEY.write().unwrap() = Some(key);
+    Ok(())
+}
+
+/// Derives the key from a passphrase and keeps it for the rest of the process. The first
+/// passphrase used sets up encryption for the notebook, later ones have to match it.
+pub fn unlock(passphrase: &str) -> Result<(), CryptoError> {
+    unlock_at(&key_file_location(), passphrase)
+}
+
+/// Forgets the passphrase of a notebook that has been decrypted, so it starts without asking for
+/// one and writes plaintext again.
+pub fn reset() -> Result<(), CryptoError> {
+    let location = key_file_location();
+    if location.exists() {
+        fs::remove_file(location)?;
+    }
+    *KEY.write().unwrap() = None;
+    Ok(())
+}
+
+fn seal_with(key: &Key, plaintext: &[u8]) -> Vec<u8> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(key)
+        .encrypt(&nonce, plaintext)
+        .expect("plaintext too large to encrypt");
+    [MAGIC, nonce.as_slice(), &ciphertext].concat()
+}
+
+fn open_with(key: &Key, sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let body = sealed.strip_prefix(MAGIC).ok_or(CryptoError::Corrupted)?;
+    if body.len() < NONCE_LEN {
+        return Err(CryptoError::Corrupted);
+    }
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(key)
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CryptoError::Corrupted)
+}
+
+fn with_key<T>(apply: impl FnOnce(&Key) -> Result<T, CryptoError>) -> Result<T, CryptoError> {
+    match KEY.read().unwrap().as_ref() {
+        Some(key) => apply(key),
+        None => Err(CryptoError::Locked),
+    }
+}
+
+pub fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Encrypts with the notebook's key, failing if the notebook is locked.
+pub fn seal(plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    with_key(|key| Ok(seal_with(key, plaintext)))
+}
+
+/// Decrypts data written by [seal] or [protect], anything else is returned as it is.
+pub fn open(bytes: Vec<u8>) -> Result<Vec<u8>, CryptoError> {
+    if !is_sealed(&bytes) {
+        return Ok(bytes);
+    }
+    with_key(|key| open_with(key, &bytes))
+}
+
+/// Encrypts files derived from notes, like archives, revisions and the search index, whenever
+/// the notebook is unlocked. They're written as they are otherwise.
+pub fn protect(bytes: Vec<u8>) -> Vec<u8> {
+    match KEY.read().unwrap().as_ref() {
+        Some(key) => seal_with(key, &bytes),
+        None => bytes,
+    }
+}
+
+/// Rewrites a file encrypted or decrypted, used when encryption is turned on or off for a
+/// notebook that already has archives and revisions.
+pub fn reseal_file(path: &Path, encrypt: bool) -> Result<(), CryptoError> {
+    let bytes = open(fs::read(path)?)?;
+    let bytes = if encrypt { seal(&bytes)? } else { bytes };
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Names a file after a search term without giving the term away while the notebook is unlocked.
+pub fn blind_name(name: &str) -> String {
+    match KEY.read().unwrap().as_ref() {
+        Some(key) => Blake2s256::new()
+            .chain_update(key)
+            .chain_update(name.as_bytes())
+            .finalize()
+            .iter()
+            .take(16)
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        None => name.to_string(),
+    }
+}
+
+fn is_encrypted_header(line: &str) -> bool {
+    matches!(line.split_once(':'), Some((key, _)) if key.trim() == ENCRYPTED_HEADER)
+}
+
+/// The lines of a note and where its header ends, at the first blank line or at the closing
+/// `---` of Markdown front matter.
+fn split_header(content: &str) -> (Vec<&str>, usize) {
+    let lines = content.split('\n').collect::<Vec<&str>>();
+    let header_end = if has_front_matter(content) {
+        lines
+            .iter()
+            .skip(1)
+            .position(|line| line.trim_end() == "---")
+            .map_or(lines.len(), |idx| idx + 1)
+    } else {
+        lines
+            .iter()
+            .position(|line| line.trim().is_empty())
+            .unwrap_or(lines.len())
+    };
+    (lines, header_end)
+}
+
+/// Whether a note asks to be encrypted with an `encrypted: true` header.
+pub fn wants_encryption(content: &str) -> bool {
+    let (lines, header_end) = split_header(content);
+    lines[..header_end]
+        .iter()
+        .any(|line| is_encrypted_header(line) && line.split_once(':').unwrap().1.trim() == "true")
+}
+
+/// Adds or removes the `encrypted: true` header of a note, leaving the rest of it untouched.
+pub fn set_encryption_header(content: &str, encrypted: bool) -> String {
+    let (lines, header_end) = split_header(content);
+    let mut updated = lines[..header_end]
+        .iter()
+        .copied()
+        .filter(|line| !is_encrypted_header(line))
+        .collect::<Vec<&str>>();
+    if encrypted {
+        updated.push("encrypted: true");
+    }
+    updated.extend_from_slice(&lines[header_end..]);
+    updated.join("\n")
+}
+
+/// Whether a note should be encrypted on disk.
+pub(crate) fn needs_sealing(content: &str, encrypt_all: bool) -> bool {
+    encrypt_all || wants_encryption(content)
+}
+
+pub fn is_sealed_note(stored: &str) -> bool {
+    stored.starts_with(ARMOR_PREFIX)
+}
+
+/// Encrypts a note into the text stored on disk.
+pub fn seal_note(content: &str) -> Result<String, CryptoError> {
+    let sealed = seal(content.as_bytes())?;
+    Ok(format!("{}{}\n", ARMOR_PREFIX, base64::encode(sealed)))
+}
+
+/// Decrypts a note as stored on disk, notes that aren't encrypted are returned as they are.
+pub fn open_note(stored: String) -> Result<String, CryptoError> {
+    match stored.strip_prefix(ARMOR_PREFIX) {
+        Some(armored) => {
+            let sealed = decode(armored)?;
+            let content = with_key(|key| open_with(key, &sealed))?;
+            String::from_utf8(content).map_err(|_| CryptoError::Corrupted)
+        }
+        None => Ok(stored),
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn unlock_for_tests() {
+    let location = PathBuf::from("/tmp/tendril-test/crypto/").join(KEY_FILE);
+    unlock_at(&location, "correct horse battery staple").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seals_and_opens() {
+        let key = derive_key("passphrase", b"saltsaltsaltsalt").unwrap();
+        let sealed = seal_with(&key, b"secret note");
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        assert_eq!(open_with(&key, &sealed).unwrap(), b"secret note");
+        // A fresh nonce is used every time.
+        assert_ne!(sealed, seal_with(&key, b"secret note"));
+
+        let other = derive_key("other passphrase", b"saltsaltsaltsalt").unwrap();
+        assert!(matches!(
+            open_with(&other, &sealed),
+            Err(CryptoError::Corrupted)
+        ));
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open_with(&key, &tampered).is_err());
+    }
+
+    #[test]
+    fn checks_the_passphrase() {
+        let location = PathBuf::from("/tmp/tendril-test/crypto-check/").join(KEY_FILE);
+        fs::remove_file(&location).ok();
+        unlock_at(&location, "first").unwrap();
+        assert!(location.exists());
+        unlock_at(&location, "first").unwrap();
+        assert!(matches!(
+            unlock_at(&location, "second"),
+            Err(CryptoError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn round_trips_notes() {
+        unlock_for_tests();
+        let note = "title: Plans\nencrypted: true\n\nWorld domination";
+        let stored = seal_note(note).unwrap();
+        assert!(is_sealed_note(&stored));
+        assert!(!stored.contains("domination"));
+        assert_eq!(open_note(stored).unwrap(), note);
+        assert_eq!(
+            open_note(String::from("title: a\n\nb")).unwrap(),
+            "title: a\n\nb"
+        );
+        assert_ne!(blind_name("domination"), "domination");
+        assert_eq!(blind_name("domination"), blind_name("domination"));
+    }
+
+    #[test]
+    fn reads_the_encryption_header() {
+        assert!(wants_encryption("title: a\nencrypted: true\n\nbody"));
+        assert!(!wants_encryption("title: a\nencrypted: false\n\nbody"));
+        assert!(!wants_encryption("title: a\n\nencrypted: true"));
+        assert_eq!(
+            set_encryption_header("title: a\ntags: [x]\n\nbody\n\nmore", true),
+            "title: a\ntags: [x]\nencrypted: true\n\nbody\n\nmore"
+        );
+        assert_eq!(
+            set_encryption_header("title: a\nencrypted: true\n\nbody", false),
+            "title: a\n\nbody"
+        );
+        assert_eq!(
+            set_encryption_header("---\ntitle: a\n---\n\nbody", true),
+            "---\ntitle: a\nencrypted: true\n---\n\nbody"
+        );
+    }
+}
@@ -1,17 +1,64 @@
-use std::fs;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::exit,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
 
+use lazy_static::lazy_static;
 use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+use toml::{value::Table, Value};
 
-use super::utils::get_config_location;
+use crate::store::Storage;
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+use super::{journal::is_valid_format, utils::get_config_location};
+
+/// Every key can be overridden with `TENDRIL_<SECTION>_<KEY>`, like `TENDRIL_GENERAL_PORT`.
+const ENV_PREFIX: &str = "TENDRIL_";
+const SECTIONS: [&str; 10] = [
+    "general",
+    "sync",
+    "externals",
+    "bibliography",
+    "history",
+    "trash",
+    "media",
+    "encryption",
+    "journal",
+    "backup",
+];
+
+lazy_static! {
+    static ref CURRENT: RwLock<Arc<Config>> = {
+        let config = load_config().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            exit(1);
+        });
+        RwLock::new(Arc::new(config))
+    };
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("could not read config file at {0:?}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("could not parse config file at {0:?}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("invalid {0} in config: {1}")]
+    Invalid(&'static str, String),
+}
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
 pub struct Sync {
     pub use_git: bool,
     pub sync_interval: u8,
     pub branch: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
 pub struct General {
     pub wiki_location: String,
     pub port: u16,
@@ -21,22 +68,453 @@ pub struct General {
     pub media_location: String,
     pub host: String,
     pub check_for_updates: bool,
+    /// Where notes are kept, either `plaintext` (the default) or `sqlite`.
+    pub storage: Option<String>,
+    /// Where the archive, search index, history, trash and tasks are kept. Defaults to the install
+    /// location, or a directory of its own for notebooks started with `-c` or a wiki directory.
+    pub data_location: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
 pub struct Config {
     pub general: General,
     pub sync: Sync,
     pub externals: Option<Externals>,
+    pub bibliography: Option<Bibliography>,
+    pub history: Option<History>,
+    pub trash: Option<Trash>,
+    pub media: Option<Media>,
+    pub encryption: Option<Encryption>,
+    pub journal: Option<Journal>,
+    pub backup: Option<Backup>,
+    /// Notebooks served from this one under their own URL prefix.
+    pub wikis: Option<Vec<Wiki>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Wiki {
+    /// The notebook is served under `/<prefix>/`.
+    pub prefix: String,
+    /// The notebook's own config file, which sets the port it's run on.
+    pub config: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct History {
+    /// How many prior revisions to keep for each note, defaults to 50.
+    pub max_revisions: Option<usize>,
+    /// Revisions older than this many days are removed, they're kept forever if unset.
+    pub max_age_days: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Media {
+    /// Widths in pixels of the thumbnails generated for uploaded images.
+    pub thumbnail_widths: Option<Vec<u32>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Encryption {
+    /// Encrypt every note instead of only those with an `encrypted: true` header.
+    pub encrypt_all: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct Journal {
+    /// Format of daily note titles, defaults to `%Y-%m-%d`.
+    pub title_format: Option<String>,
+    /// Tags given to new daily notes, defaults to `["daily notes"]`.
+    pub tags: Option<Vec<String>>,
+    /// Put in front of every entry, defaults to `[%H:%M]`.
+    pub timestamp_format: Option<String>,
+    /// Text new daily notes start with. `<%= title %>`, `<%= week %>` and `<%= month %>` are
+    /// replaced with the titles of the day's journal notes.
+    pub template: Option<String>,
+    /// Format of weekly note titles, defaults to `%G-W%V`.
+    pub week_format: Option<String>,
+    /// Format of monthly note titles, defaults to `%Y-%m`.
+    pub month_format: Option<String>,
+    /// Generate weekly notes listing the daily notes in the week, defaults to true.
+    pub weekly: Option<bool>,
+    /// Generate monthly notes listing the daily notes in the month, defaults to true.
+    pub monthly: Option<bool>,
+    /// Copy the entries of daily notes into weekly and monthly notes instead of linking them.
+    pub embed: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Trash {
+    /// Deleted notes are purged from the trash after this many days, defaults to 30.
+    pub retention_days: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Backup {
+    /// Directory backups are written to, defaults to `backups` in the notebook's data location.
+    pub location: Option<String>,
+    /// Hours between backups taken while the wiki is running, none are taken if unset.
+    pub interval_hours: Option<u64>,
+    /// How many scheduled backups are kept, defaults to 7.
+    pub keep: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Bibliography {
+    /// Path to a BibTeX file. Relative paths are resolved against the media location.
+    pub file: Option<String>,
+    /// One of `apa`, `mla` or `chicago`.
+    pub style: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Externals {
     pub data: Vec<String>,
 }
 
-pub fn read_config() -> Config {
+impl Default for General {
+    fn default() -> Self {
+        Self {
+            wiki_location: "~/wiki".into(),
+            port: 6683,
+            user: String::new(),
+            pass: String::new(),
+            version: String::new(),
+            media_location: "~/wiki_media".into(),
+            host: "http://localhost:6683".into(),
+            check_for_updates: false,
+            storage: None,
+            data_location: None,
+        }
+    }
+}
+
+impl Default for Sync {
+    fn default() -> Self {
+        Self {
+            use_git: false,
+            sync_interval: 60,
+            branch: "main".into(),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.general.port == 0 {
+            return Err(ConfigError::Invalid(
+                "general.port",
+                "expected a port between 1 and 65535".into(),
+            ));
+        }
+        if !(self.general.host.starts_with("http://") || self.general.host.starts_with("https://"))
+        {
+            return Err(ConfigError::Invalid(
+                "general.host",
+                format!(
+                    "expected an http:// or https:// URL, got {:?}",
+                    self.general.host
+                ),
+            ));
+        }
+        if let Some(storage) = &self.general.storage {
+            Storage::from_str(storage).map_err(|e| ConfigError::Invalid("general.storage", e))?;
+        }
+        if self.sync.use_git && self.sync.sync_interval == 0 {
+            return Err(ConfigError::Invalid(
+                "sync.sync_interval",
+                "expected at least 1 second when use_git is set".into(),
+            ));
+        }
+        if self.sync.use_git && self.sync.branch.is_empty() {
+            return Err(ConfigError::Invalid(
+                "sync.branch",
+                "expected a branch to push to when use_git is set".into(),
+            ));
+        }
+        let style = self
+            .bibliography
+            .as_ref()
+            .and_then(|bib| bib.style.as_ref());
+        if let Some(style) = style {
+            if !matches!(
+                style.trim().to_lowercase().as_str(),
+                "apa" | "mla" | "chicago"
+            ) {
+                return Err(ConfigError::Invalid(
+                    "bibliography.style",
+                    format!("expected apa, mla or chicago, got {:?}", style),
+                ));
+            }
+        }
+        if let Some(journal) = &self.journal {
+            let formats = [
+                ("journal.title_format", &journal.title_format),
+                ("journal.timestamp_format", &journal.timestamp_format),
+                ("journal.week_format", &journal.week_format),
+                ("journal.month_format", &journal.month_format),
+            ];
+            for (name, format) in formats {
+                if let Some(format) = format {
+                    if !is_valid_format(format) {
+                        return Err(ConfigError::Invalid(
+                            name,
+                            format!("expected a date format like %Y-%m-%d, got {:?}", format),
+                        ));
+                    }
+                }
+            }
+        }
+        if let Some(backup) = &self.backup {
+            if backup.interval_hours == Some(0) {
+                return Err(ConfigError::Invalid(
+                    "backup.interval_hours",
+                    "expected at least 1 hour, leave it unset to turn off scheduled backups".into(),
+                ));
+            }
+            if backup.keep == Some(0) {
+                return Err(ConfigError::Invalid(
+                    "backup.keep",
+                    "expected at least 1 backup to keep".into(),
+                ));
+            }
+        }
+        let widths = self
+            .media
+            .as_ref()
+            .and_then(|media| media.thumbnail_widths.as_ref());
+        if widths.into_iter().flatten().any(|width| *width == 0) {
+            return Err(ConfigError::Invalid(
+                "media.thumbnail_widths",
+                "widths must be at least 1 pixel".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The config the wiki is running with. It's loaded and validated the first time it's needed,
+/// and replaced by [reload_config]. Settings that apply without a restart are read through here
+/// whenever they're used, rather than kept around.
+pub fn read_config() -> Arc<Config> {
+    CURRENT.read().unwrap().clone()
+}
+
+/// Loads and validates the config file, see [load_config_at].
+pub fn load_config() -> Result<Config, ConfigError> {
     let (_, file) = get_config_location();
-    let config: Config = toml::from_str(&fs::read_to_string(file).unwrap()).unwrap();
-    config
+    load_config_at(&file)
+}
+
+/// Loads the config file at `file`, filling in defaults for missing keys and applying the
+/// `TENDRIL_<SECTION>_<KEY>` environment variables over it.
+pub fn load_config_at(file: &Path) -> Result<Config, ConfigError> {
+    let content = fs::read_to_string(file).map_err(|e| ConfigError::Read(file.to_owned(), e))?;
+    let mut table: Table =
+        toml::from_str(&content).map_err(|e| ConfigError::Parse(file.to_owned(), e))?;
+    apply_overrides(&mut table, env::vars());
+    let config: Config = Value::Table(table)
+        .try_into()
+        .map_err(|e| ConfigError::Parse(file.to_owned(), e))?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Loads the config file again and switches to it, except for the settings only read on start.
+/// Returns the names of those that changed, so they can be reported as needing a restart.
+pub fn reload_config() -> Result<Vec<&'static str>, ConfigError> {
+    let mut loaded = load_config()?;
+    let current = read_config();
+    let needs_restart = keep_startup_settings(&current, &mut loaded);
+    *CURRENT.write().unwrap() = Arc::new(loaded);
+    Ok(needs_restart)
+}
+
+fn keep_startup_settings(current: &Config, loaded: &mut Config) -> Vec<&'static str> {
+    let mut kept = Vec::new();
+    let general = &mut loaded.general;
+    keep(
+        "general.wiki_location",
+        &current.general.wiki_location,
+        &mut general.wiki_location,
+        &mut kept,
+    );
+    keep(
+        "general.media_location",
+        &current.general.media_location,
+        &mut general.media_location,
+        &mut kept,
+    );
+    keep(
+        "general.port",
+        &current.general.port,
+        &mut general.port,
+        &mut kept,
+    );
+    keep(
+        "general.host",
+        &current.general.host,
+        &mut general.host,
+        &mut kept,
+    );
+    keep(
+        "general.storage",
+        &current.general.storage,
+        &mut general.storage,
+        &mut kept,
+    );
+    keep(
+        "general.data_location",
+        &current.general.data_location,
+        &mut general.data_location,
+        &mut kept,
+    );
+    keep(
+        "sync.use_git",
+        &current.sync.use_git,
+        &mut loaded.sync.use_git,
+        &mut kept,
+    );
+    keep(
+        "encryption",
+        &current.encryption,
+        &mut loaded.encryption,
+        &mut kept,
+    );
+    keep("wikis", &current.wikis, &mut loaded.wikis, &mut kept);
+    kept
+}
+
+fn keep<T: PartialEq + Clone>(
+    name: &'static str,
+    current: &T,
+    loaded: &mut T,
+    kept: &mut Vec<&'static str>,
+) {
+    if current != loaded {
+        *loaded = current.clone();
+        kept.push(name);
+    }
+}
+
+fn apply_overrides(table: &mut Table, vars: impl IntoIterator<Item = (String, String)>) {
+    let defaults =
+        Value::try_from(Config::default()).unwrap_or_else(|_| Value::Table(Table::new()));
+    for (name, raw) in vars {
+        let name = match name.strip_prefix(ENV_PREFIX) {
+            Some(name) => name.to_lowercase(),
+            None => continue,
+        };
+        let (section_name, key) = match name.split_once('_') {
+            Some((section, key)) if SECTIONS.contains(&section) => (section, key),
+            _ => continue,
+        };
+        let section = table
+            .entry(section_name.to_string())
+            .or_insert_with(|| Value::Table(Table::new()));
+        let section = match section.as_table_mut() {
+            Some(section) => section,
+            None => continue,
+        };
+        // `TENDRIL_GENERAL_USER=1234` is still a user name, only parse values that aren't strings.
+        let current = section
+            .get(key)
+            .or_else(|| defaults.get(section_name).and_then(|d| d.get(key)));
+        let is_string = matches!(current, Some(Value::String(_)));
+        let value = if is_string {
+            Value::String(raw)
+        } else {
+            parse_value(&raw)
+        };
+        section.insert(key.to_string(), value);
+    }
+}
+
+/// Reads an environment variable as a TOML value, so `true`, `8080` and `[320, 640]` get their
+/// types, falling back to a string.
+fn parse_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str, vars: Vec<(&str, &str)>) -> Result<Config, toml::de::Error> {
+        let mut table: Table = toml::from_str(content)?;
+        apply_overrides(
+            &mut table,
+            vars.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        Value::Table(table).try_into()
+    }
+
+    #[test]
+    fn fills_in_missing_keys() {
+        let config = parse(
+            "[general]\nwiki_location = \"~/notes\"\nport = 8080\n",
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(config.general.wiki_location, "~/notes");
+        assert_eq!(config.general.port, 8080);
+        assert!(!config.general.check_for_updates);
+        assert_eq!(config.sync, Sync::default());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn applies_environment_overrides() {
+        let config = parse(
+            "[general]\nuser = \"taylor\"\n",
+            vec![
+                ("TENDRIL_GENERAL_PORT", "9000"),
+                ("TENDRIL_GENERAL_USER", "1234"),
+                ("TENDRIL_SYNC_USE_GIT", "true"),
+                ("TENDRIL_MEDIA_THUMBNAIL_WIDTHS", "[320, 640]"),
+                ("TENDRIL_TRASH_RETENTION_DAYS", "7"),
+                ("TENDRIL_WIKI_DIR", "/tmp/wiki"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.general.port, 9000);
+        assert_eq!(config.general.user, "1234");
+        assert!(config.sync.use_git);
+        assert_eq!(config.media.unwrap().thumbnail_widths, Some(vec![320, 640]));
+        assert_eq!(config.trash.unwrap().retention_days, Some(7));
+        assert_eq!(config.general.wiki_location, "~/wiki");
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let config = parse("[general]\nport = 0\n", vec![]).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid("general.port", _))
+        ));
+        let config = parse("[bibliography]\nstyle = \"ieee\"\n", vec![]).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Invalid("bibliography.style", _))
+        ));
+        assert!(parse("[general]\nport = \"high\"\n", vec![]).is_err());
+    }
+
+    #[test]
+    fn keeps_settings_read_on_start() {
+        let current = Config::default();
+        let mut loaded = Config::default();
+        loaded.general.port = 9000;
+        loaded.general.check_for_updates = true;
+        loaded.sync.sync_interval = 10;
+        let kept = keep_startup_settings(&current, &mut loaded);
+        assert_eq!(kept, vec!["general.port"]);
+        assert_eq!(loaded.general.port, 6683);
+        assert!(loaded.general.check_for_updates);
+        assert_eq!(loaded.sync.sync_interval, 10);
+    }
 }
@@ -0,0 +1,373 @@
+use std::{collections::HashMap, fmt::Write as _, io};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Datelike, Duration, Local, NaiveDate,
+};
+use wikitext::{parsers::Note, processors::tags::tag_string_from_vec, PatchData};
+
+use crate::{
+    ids::note_ids,
+    store::{note_store, parse_note},
+};
+
+use super::{
+    config::{read_config, Journal},
+    DT_FORMAT,
+};
+
+const TITLE_FORMAT: &str = "%Y-%m-%d";
+const TIMESTAMP_FORMAT: &str = "[%H:%M]";
+const WEEK_FORMAT: &str = "%G-W%V";
+const MONTH_FORMAT: &str = "%Y-%m";
+const DAILY_TAG: &str = "daily notes";
+const WEEKLY_TAG: &str = "weekly notes";
+const MONTHLY_TAG: &str = "monthly notes";
+
+/// The `[journal]` config, with defaults filled in.
+#[derive(Debug, Clone)]
+struct Settings {
+    title_format: String,
+    tags: Vec<String>,
+    timestamp_format: String,
+    template: Option<String>,
+    week_format: String,
+    month_format: String,
+    weekly: bool,
+    monthly: bool,
+    embed: bool,
+}
+
+impl From<Option<&Journal>> for Settings {
+    fn from(journal: Option<&Journal>) -> Self {
+        let journal = journal.cloned().unwrap_or_default();
+        Self {
+            title_format: journal.title_format.unwrap_or_else(|| TITLE_FORMAT.into()),
+            tags: journal.tags.unwrap_or_else(|| vec![DAILY_TAG.into()]),
+            timestamp_format: journal
+                .timestamp_format
+                .unwrap_or_else(|| TIMESTAMP_FORMAT.into()),
+            template: journal.template,
+            week_format: journal.week_format.unwrap_or_else(|| WEEK_FORMAT.into()),
+            month_format: journal.month_format.unwrap_or_else(|| MONTH_FORMAT.into()),
+            weekly: journal.weekly.unwrap_or(true),
+            monthly: journal.monthly.unwrap_or(true),
+            embed: journal.embed.unwrap_or(false),
+        }
+    }
+}
+
+fn settings() -> Settings {
+    Settings::from(read_config().journal.as_ref())
+}
+
+/// Whether `format` is a chrono format string, used to validate the `[journal]` config.
+pub fn is_valid_format(format: &str) -> bool {
+    !format.trim().is_empty() && !StrftimeItems::new(format).any(|item| matches!(item, Item::Error))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    fn format(self, settings: &Settings) -> &str {
+        match self {
+            Period::Day => &settings.title_format,
+            Period::Week => &settings.week_format,
+            Period::Month => &settings.month_format,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+
+    /// The first and last day of the period `date` is in.
+    pub fn range(self, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+        match self {
+            Period::Day => (date, date),
+            Period::Week => {
+                let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+                (start, start + Duration::days(6))
+            }
+            Period::Month => {
+                let start = date.with_day(1).unwrap();
+                let next = if start.month() == 12 {
+                    NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)
+                } else {
+                    NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1)
+                };
+                (start, next.unwrap() - Duration::days(1))
+            }
+        }
+    }
+
+    /// A day in the period before the one `date` is in.
+    pub fn previous(self, date: NaiveDate) -> NaiveDate {
+        self.range(date).0 - Duration::days(1)
+    }
+
+    /// A day in the period after the one `date` is in.
+    pub fn next(self, date: NaiveDate) -> NaiveDate {
+        self.range(date).1 + Duration::days(1)
+    }
+}
+
+/// Today's date, in the wiki's local time.
+pub fn today() -> NaiveDate {
+    Local::now().naive_local().date()
+}
+
+fn title(period: Period, date: NaiveDate, settings: &Settings) -> String {
+    date.format(period.format(settings)).to_string()
+}
+
+/// The title of the journal note for `period` containing `date`.
+pub fn journal_title(period: Period, date: NaiveDate) -> String {
+    title(period, date, &settings())
+}
+
+/// How daily notes are titled and tagged, for creating them outside of the journal.
+#[derive(Debug, Clone)]
+pub struct DailyNotes {
+    pub title_format: String,
+    pub tags: Vec<String>,
+}
+
+impl DailyNotes {
+    pub fn title(&self, date: NaiveDate) -> String {
+        date.format(&self.title_format).to_string()
+    }
+}
+
+impl From<Option<&Journal>> for DailyNotes {
+    fn from(journal: Option<&Journal>) -> Self {
+        let settings = Settings::from(journal);
+        Self {
+            title_format: settings.title_format,
+            tags: settings.tags,
+        }
+    }
+}
+
+pub fn daily_notes() -> DailyNotes {
+    DailyNotes::from(read_config().journal.as_ref())
+}
+
+/// The journal notes around `date`, shown on the index page to move between periods.
+pub struct JournalNav {
+    pub previous_day: String,
+    pub next_day: String,
+    pub week: Option<String>,
+    pub month: Option<String>,
+}
+
+pub fn journal_nav(date: NaiveDate) -> JournalNav {
+    let settings = settings();
+    JournalNav {
+        previous_day: title(Period::Day, Period::Day.previous(date), &settings),
+        next_day: title(Period::Day, Period::Day.next(date), &settings),
+        week: if settings.weekly {
+            Some(title(Period::Week, date, &settings))
+        } else {
+            None
+        },
+        month: if settings.monthly {
+            Some(title(Period::Month, date, &settings))
+        } else {
+            None
+        },
+    }
+}
+
+fn fill_template(template: &str, date: NaiveDate, settings: &Settings) -> String {
+    template
+        .replace("<%= title %>", &title(Period::Day, date, settings))
+        .replace("<%= week %>", &title(Period::Week, date, settings))
+        .replace("<%= month %>", &title(Period::Month, date, settings))
+}
+
+/// Adds a timestamped entry to today's daily note, creating it if needed.
+pub async fn create_journal_entry(entry: String) -> Result<PatchData, io::Error> {
+    let settings = settings();
+    let now = Local::now();
+    let daily_file = title(Period::Day, now.naive_local().date(), &settings);
+    let timestamp = now.format(&settings.timestamp_format).to_string();
+    let id = now.format(DT_FORMAT).to_string();
+    // The server and the CLI can both append to today's entry, so do the read and write under
+    // the store's lock instead of checking whether the file exists first.
+    let journal_entry = note_store().update(&daily_file, &mut |current| match current {
+        Some(mut entry_file) => {
+            write!(entry_file, "\n\n{} {}", timestamp, entry).unwrap();
+            Ok(entry_file)
+        }
+        None => {
+            let mut new_file = format!(
+                "title: {}\ntags: {}\ncreated: {:?}\nid: {}\n\n",
+                daily_file,
+                tag_string_from_vec(settings.tags.clone()),
+                now,
+                id
+            );
+            if let Some(template) = &settings.template {
+                new_file.push_str(&fill_template(
+                    template,
+                    now.naive_local().date(),
+                    &settings,
+                ));
+                new_file.push_str("\n\n");
+            }
+            writeln!(new_file, "{} {}", timestamp, entry).unwrap();
+            Ok(new_file)
+        }
+    })?;
+    println!("<daily journal updated>");
+    let note = Note::from(journal_entry);
+    if let Some(id) = note.header.get("id") {
+        note_ids().insert(id, &daily_file);
+    }
+    Ok(note.into())
+}
+
+/// Regenerates the weekly and monthly notes containing `date`, returning them so their links
+/// can be indexed.
+pub fn update_rollups(date: NaiveDate) -> Result<Vec<PatchData>, io::Error> {
+    let settings = settings();
+    let mut updated = Vec::with_capacity(2);
+    let periods = [
+        (Period::Week, settings.weekly),
+        (Period::Month, settings.monthly),
+    ];
+    for (period, enabled) in periods {
+        if !enabled {
+            continue;
+        }
+        let rollup_title = title(period, date, &settings);
+        let now = Local::now();
+        let body = rollup_body(period, date, &settings);
+        let content = note_store().update(&rollup_title, &mut |current| {
+            // Keep the header of an existing rollup, so its id and creation date stay put.
+            let mut note = match &current {
+                Some(current) => parse_note(current, &rollup_title),
+                None => {
+                    let mut header = HashMap::new();
+                    header.insert("created".into(), format!("{:?}", now));
+                    header.insert("id".into(), now.format(DT_FORMAT).to_string());
+                    header.insert("content-type".into(), "wikitext".into());
+                    Note {
+                        header,
+                        content: String::new(),
+                    }
+                }
+            };
+            let tag = match period {
+                Period::Month => MONTHLY_TAG,
+                _ => WEEKLY_TAG,
+            };
+            note.header.insert("title".into(), rollup_title.clone());
+            note.header
+                .insert("tags".into(), tag_string_from_vec(vec![tag.into()]));
+            note.content = body.clone();
+            Ok(note.into())
+        })?;
+        let note = parse_note(&content, &rollup_title);
+        if let Some(id) = note.header.get("id") {
+            note_ids().insert(id, &rollup_title);
+        }
+        updated.push(note.into());
+    }
+    Ok(updated)
+}
+
+/// Links to the previous and next period, followed by the daily notes in the period, either as
+/// links or with their entries copied in.
+fn rollup_body(period: Period, date: NaiveDate, settings: &Settings) -> String {
+    let store = note_store();
+    let mut body = format!(
+        "[[‹ previous {}|{}]] [[next {} ›|{}]]\n",
+        period.name(),
+        title(period, period.previous(date), settings),
+        period.name(),
+        title(period, period.next(date), settings)
+    );
+    let (start, end) = period.range(date);
+    let mut day = start;
+    while day <= end {
+        let daily_title = title(Period::Day, day, settings);
+        day += Duration::days(1);
+        if !store.exists(&daily_title) {
+            continue;
+        }
+        if settings.embed {
+            writeln!(body, "\n[[{}]]", daily_title).unwrap();
+            // Encrypted notes can't be copied while the notebook is locked, they're only linked.
+            if let Ok(daily) = store.read(&daily_title) {
+                body.push_str(parse_note(&daily, &daily_title).content.trim_end());
+                body.push('\n');
+            }
+        } else {
+            writeln!(body, "[[{}]]", daily_title).unwrap();
+        }
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn finds_the_days_in_each_period() {
+        let day = date(2022, 5, 3);
+        assert_eq!(
+            Period::Week.range(day),
+            (date(2022, 5, 2), date(2022, 5, 8))
+        );
+        assert_eq!(
+            Period::Month.range(date(2022, 12, 31)),
+            (date(2022, 12, 1), date(2022, 12, 31))
+        );
+        assert_eq!(Period::Week.previous(day), date(2022, 5, 1));
+        assert_eq!(Period::Month.next(day), date(2022, 6, 1));
+        assert_eq!(Period::Day.next(date(2022, 2, 28)), date(2022, 3, 1));
+    }
+
+    #[test]
+    fn titles_journal_notes_from_the_config() {
+        let settings = Settings::from(None);
+        let day = date(2022, 1, 2);
+        assert_eq!(title(Period::Day, day, &settings), "2022-01-02");
+        // ISO weeks can start in the previous year.
+        assert_eq!(title(Period::Week, day, &settings), "2021-W52");
+        assert_eq!(title(Period::Month, day, &settings), "2022-01");
+        let journal = Journal {
+            title_format: Some("Journal/%d %B %Y".into()),
+            template: Some("Week: [[<%= week %>]]".into()),
+            ..Journal::default()
+        };
+        let settings = Settings::from(Some(&journal));
+        assert_eq!(
+            title(Period::Day, day, &settings),
+            "Journal/02 January 2022"
+        );
+        assert_eq!(
+            fill_template(settings.template.as_ref().unwrap(), day, &settings),
+            "Week: [[2021-W52]]"
+        );
+        assert!(is_valid_format("%Y-%m-%d"));
+        assert!(!is_valid_format("%Q"));
+        assert!(!is_valid_format(""));
+    }
+}
@@ -1,42 +1,35 @@
 pub mod config;
+pub mod journal;
 pub mod utils;
 
-use std::fmt::Write as _;
-use std::{
-    env, io,
-    path::{Path, PathBuf},
-};
+pub use self::journal::create_journal_entry;
+
+use std::{env, io, path::PathBuf};
 
 use chrono::{DateTime, FixedOffset, Local};
-use directories::ProjectDirs;
 use tokio::fs::{self, read_to_string};
 use tokio::task::spawn_blocking;
-use wikitext::parsers::{parse_meta, Note};
+use wikitext::parsers::Note;
 use wikitext::PatchData;
 
 use thiserror::Error;
 
-use crate::fs::{
-    config::read_config,
-    utils::{get_file_path, normalize_wiki_location},
-};
+use crate::fs::{config::read_config, utils::normalize_wiki_location};
+use crate::ids::note_ids;
+use crate::store::{note_store, parse_note, revision, StoreError};
 
-use self::{
-    config::Config,
-    utils::{get_archive_file_path, get_archive_location},
-};
+use self::utils::{get_archive_file_path, get_notebook_data_location};
 
 lazy_static::lazy_static! {
-    static ref CONFIG: Config = read_config();
     pub(crate) static ref WIKI_LOCATION: PathBuf = {
         match env::var("TENDRIL_WIKI_DIR") {
             Ok(val) => PathBuf::from(val),
             _ => {
-                PathBuf::from(&normalize_wiki_location(&CONFIG.general.wiki_location))
+                PathBuf::from(&normalize_wiki_location(&read_config().general.wiki_location))
             }
         }
     };
-    pub(crate) static ref MEDIA_LOCATION: PathBuf = PathBuf::from(&normalize_wiki_location(&CONFIG.general.media_location));
+    pub(crate) static ref MEDIA_LOCATION: PathBuf = PathBuf::from(&normalize_wiki_location(&read_config().general.media_location));
 }
 
 #[derive(Error, Debug)]
@@ -45,6 +38,8 @@ pub enum WriteWikiError {
     TitleInvalid,
     #[error("could not write updated data to file")]
     WriteError(std::io::Error),
+    #[error("note was changed since revision was loaded, current revision is {0}")]
+    Conflict(String),
     #[error("unknown write error")]
     Unknown,
 }
@@ -63,50 +58,42 @@ pub enum ReadPageError {
 
 const DT_FORMAT: &str = "%Y%m%d%H%M%S";
 
-pub async fn write_media(filename: &str, bytes: &[u8]) -> Result<(), io::Error> {
-    let mut file_path = MEDIA_LOCATION.clone();
-    file_path.push(filename);
-    fs::write(file_path, bytes).await?;
-    Ok(())
+/// Fails with [StoreError::Conflict] when a save was made from an outdated copy of the note, the
+/// editor can merge and try again. New notes have no revision to check.
+fn check_revision(expected: &str, current: &str) -> Result<(), StoreError> {
+    let current_revision = revision(current);
+    if expected.is_empty() || current_revision == expected {
+        Ok(())
+    } else {
+        Err(StoreError::Conflict(current_revision))
+    }
 }
 
-pub async fn write(data: &PatchData) -> Result<(), WriteWikiError> {
-    let current_title_on_disk = if data.old_title != data.title && !data.old_title.is_empty() {
-        data.old_title.clone()
-    } else {
-        // wiki entires are stored by title + .md file ending
-        data.title.clone()
-    };
-    let file_path = get_file_path(&current_title_on_disk).unwrap();
+/// The content to store for a save, along with the note's id.
+fn prepare_note(data: &PatchData, is_new: bool, now: &str) -> (String, String) {
     let mut note_meta = Note::from(data);
-    let now = Local::now().format(DT_FORMAT).to_string();
-    // In the case that we're creating a new file
-    if !file_path.exists() && data.old_title.is_empty() {
-        note_meta.header.insert("created".into(), now.clone());
-        note_meta.header.insert("id".into(), now);
+    // Notes are wikitext unless the editor picked another content type.
+    if is_new {
+        note_meta.header.insert("created".into(), now.into());
+        note_meta.header.insert("id".into(), now.into());
         note_meta
             .header
-            .insert("content-type".into(), "wikitext".into());
-        let note: String = note_meta.into();
-        return match fs::write(file_path, note).await {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                eprintln!("Create new file err: {}", e);
-                Err(WriteWikiError::WriteError(e))
-            }
-        };
+            .entry("content-type".into())
+            .or_insert_with(|| "wikitext".into());
+        return (note_meta.into(), now.into());
     }
-    note_meta.header.insert("modified".into(), now.clone());
+    note_meta.header.insert("modified".into(), now.into());
 
     let created = note_meta.header.get("created");
 
     // HACK: Only for legacy notes
     if created.is_none() {
-        note_meta.header.insert("created".into(), now.clone());
-        note_meta.header.insert("id".into(), now);
+        note_meta.header.insert("created".into(), now.into());
+        note_meta.header.insert("id".into(), now.into());
         note_meta
             .header
-            .insert("content-type".into(), "wikitext".into());
+            .entry("content-type".into())
+            .or_insert_with(|| "wikitext".into());
     }
     if note_meta.header.get("id").is_none() {
         let created_time = note_meta.header.get("created").unwrap().to_owned();
@@ -118,174 +105,149 @@ pub async fn write(data: &PatchData) -> Result<(), WriteWikiError> {
     }
     // END HACK
 
-    let final_note: String = note_meta.into();
-    if data.old_title != data.title && !data.old_title.is_empty() {
-        let new_location = PathBuf::from(format!(
-            "{}{}.txt",
-            WIKI_LOCATION.to_str().unwrap(),
-            data.title
-        ));
-        if !PathBuf::from(format!(
-            "{}{}.txt",
-            WIKI_LOCATION.to_str().unwrap(),
-            data.old_title
-        ))
-        .exists()
-        {
-            // This is the case where we've autofilled a default name on the web and are now
-            // renaming the note
-            match fs::write(new_location, final_note).await {
-                Ok(()) => Ok(()),
-                Err(e) => {
-                    eprintln!("write renamed file: {}", e);
-                    Err(WriteWikiError::WriteError(e))
-                }
-            }
-        } else {
-            // Rename the file to the new title
-            match fs::rename(&file_path, &new_location).await {
-                Ok(()) => match fs::write(new_location, final_note).await {
-                    Ok(()) => Ok(()),
-                    Err(e) => {
-                        eprintln!("write renamed file: {}", e);
-                        Err(WriteWikiError::WriteError(e))
-                    }
-                },
+    let id = note_meta.header.get("id").unwrap().to_owned();
+    (note_meta.into(), id)
+}
 
-                Err(e) => {
-                    eprintln!("could not perform rename action on file: {}", e);
-                    Err(WriteWikiError::WriteError(e))
+/// Saves a note, returning the revision of the newly written content. The revision the editor
+/// started from is checked under the store's lock, so of two saves from the same revision only
+/// the first one gets through.
+pub async fn write(data: &PatchData) -> Result<String, WriteWikiError> {
+    let store = note_store();
+    let is_rename = data.old_title != data.title && !data.old_title.is_empty();
+    let now = Local::now().format(DT_FORMAT).to_string();
+    // This is the case where we've autofilled a default name on the web and are now
+    // renaming the note, there's nothing to move.
+    if is_rename && store.exists(&data.old_title) {
+        let (final_note, id) = prepare_note(data, false, &now);
+        let renamed = store.rename_and_write(&data.old_title, &data.title, &mut |current| {
+            check_revision(&data.revision, &current)?;
+            Ok(final_note.clone())
+        });
+        return match renamed {
+            Ok(()) => {
+                // Links by id follow the note, and the old title redirects to the new one.
+                let ids = note_ids();
+                ids.insert(&id, &data.old_title);
+                if let Err(e) = ids.rename(&data.old_title, &data.title) {
+                    eprintln!(
+                        "could not redirect {} to {}: {}",
+                        data.old_title, data.title, e
+                    );
                 }
+                Ok(revision(&final_note))
             }
-        }
-    } else {
-        match fs::write(file_path, final_note).await {
-            Ok(()) => Ok(()),
+            Err(StoreError::Conflict(current)) => Err(WriteWikiError::Conflict(current)),
             Err(e) => {
-                eprintln!("Could not write file: {}", e);
-                Err(WriteWikiError::WriteError(e))
+                eprintln!("could not perform rename action on file: {}", e);
+                Err(e.into())
             }
+        };
+    }
+    let mut id = String::new();
+    let written = store.update(&data.title, &mut |current| {
+        if let Some(current) = &current {
+            check_revision(&data.revision, current)?;
+        }
+        let (note, note_id) =
+            prepare_note(data, current.is_none() && data.old_title.is_empty(), &now);
+        id = note_id;
+        Ok(note)
+    });
+    match written {
+        Ok(note) => {
+            note_ids().insert(&id, &data.title);
+            Ok(revision(&note))
+        }
+        Err(StoreError::Conflict(current)) => Err(WriteWikiError::Conflict(current)),
+        Err(e) => {
+            eprintln!("Could not write file: {}", e);
+            Err(e.into())
         }
     }
 }
 
 pub async fn delete(requested_file: &str) -> Result<(), io::Error> {
-    let file_path = get_file_path(requested_file).unwrap();
-    if !file_path.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Could not find requested file",
-        ));
-    }
-    fs::remove_file(file_path).await?;
+    note_store().delete(requested_file)?;
     Ok(())
 }
 
+/// Reads a note along with the revision of its content on disk.
+pub async fn read_with_revision(requested_file: String) -> Result<(Note, String), ReadPageError> {
+    spawn_blocking(move || {
+        let content = note_store().read(&requested_file)?;
+        let note = parse_note(&content, &requested_file);
+        Ok((note, revision(&content)))
+    })
+    .await
+    .unwrap()
+}
+
+/// Reads and parses a note, decrypting it first if it's encrypted on disk.
 pub async fn read(requested_file: String) -> Result<Note, ReadPageError> {
-    let file_path = get_file_path(&requested_file)?;
-    spawn_blocking(move || path_to_data_structure(&file_path))
+    spawn_blocking(move || note_store().read_note(&requested_file))
         .await
         .unwrap()
 }
 
 pub async fn read_note_cache() -> String {
-    let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
-    let mut data_dir = project_dir.data_dir().to_owned();
+    let mut data_dir = get_notebook_data_location();
     data_dir.push("note_cache");
-    read_to_string(&data_dir).await.unwrap()
+    // A notebook that hasn't been opened in the browser yet has no recent notes.
+    read_to_string(&data_dir).await.unwrap_or_default()
 }
 
 pub async fn write_note_cache(cache: String) {
-    let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
-    let mut data_dir = project_dir.data_dir().to_owned();
+    let mut data_dir = get_notebook_data_location();
     data_dir.push("note_cache");
     fs::write(data_dir, cache).await.unwrap();
 }
 
-pub async fn create_journal_entry(entry: String) -> Result<PatchData, std::io::Error> {
-    let now = Local::now();
-    let daily_file = now.format("%Y-%m-%d").to_string();
-    let path = get_file_path(&daily_file).unwrap();
-    if path.exists() {
-        let mut entry_file = read_to_string(&path).await.unwrap();
-        write!(entry_file, "\n\n[{}] {}", now.format("%H:%M"), entry).unwrap();
-        println!("<daily journal updated>");
-        fs::write(path, &entry_file).await?;
-        Ok(Note::from(entry_file).into())
-    } else {
-        let docstring = format!(
-            r#"title: {}
-tags: [daily notes]
-created: {:?}
-
-[{}] {}
-"#,
-            daily_file,
-            now,
-            now.format("%H:%M"),
-            entry
-        );
-        println!("<daily journal updated>");
-        fs::write(get_file_path(&daily_file).unwrap(), docstring.clone()).await?;
-        Ok(Note::from(docstring).into())
-    }
-}
-
 pub async fn write_archive(compressed: Vec<u8>, title: &str) {
-    let location = get_archive_file_path(title);
+    let location = match get_archive_file_path(title) {
+        Ok(location) => location,
+        Err(e) => {
+            eprintln!("Could not archive page: {}", e);
+            return;
+        }
+    };
+    if let Some(parent) = location.parent() {
+        fs::create_dir_all(parent).await.unwrap();
+    }
     fs::write(location, compressed).await.unwrap();
 }
 
 pub async fn move_archive(old_title: String, new_title: String) {
-    let archive = get_archive_location();
-    let old_location = archive.join(old_title);
-    let new_location = archive.join(new_title);
-    fs::rename(old_location, new_location).await.unwrap();
-}
-
-// TODO: this is really dependent on file system ops, won't be good if we change the storage
-// backend.
-pub fn path_to_string<P: AsRef<Path> + ?Sized>(path: &P) -> Result<String, std::io::Error> {
-    std::fs::read_to_string(path)
-}
-
-pub fn path_to_data_structure(path: &Path) -> Result<Note, ReadPageError> {
-    match path_to_string(path) {
-        Ok(reader) => {
-            let lines = reader.lines();
-            let meta = parse_meta(lines, path.to_str().unwrap());
-            Ok(meta)
+    let (old_location, new_location) = match (
+        get_archive_file_path(&old_title),
+        get_archive_file_path(&new_title),
+    ) {
+        (Ok(old_location), Ok(new_location)) => (old_location, new_location),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Could not move archived page: {}", e);
+            return;
         }
-        Err(e) => match e.kind() {
-            io::ErrorKind::NotFound => Err(ReadPageError::PageNotFoundError),
-            _ => Err(ReadPageError::DeserializationError),
-        },
+    };
+    if let Some(parent) = new_location.parent() {
+        fs::create_dir_all(parent).await.unwrap();
     }
+    fs::rename(old_location, new_location).await.unwrap();
 }
 
 pub fn get_note_titles() -> Result<Vec<String>, io::Error> {
-    let entries = std::fs::read_dir(WIKI_LOCATION.clone())?;
-    let titles = entries
-        .filter_map(|entry| {
-            let entry = entry.unwrap();
-            if entry.file_type().unwrap().is_file()
-                && entry.file_name().to_str().unwrap().ends_with(".txt")
-            {
-                let fname = entry.file_name().to_str().unwrap().to_owned();
-                Some(fname.strip_suffix(".txt").unwrap().to_owned())
-            } else {
-                None
-            }
-        })
-        .collect();
-    Ok(titles)
+    Ok(note_store().list()?)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::fs::utils::parse_location;
+    use super::prepare_note;
+    use crate::{
+        fs::utils::parse_location,
+        store::{NoteStore, PlaintextStore},
+    };
 
-    use std::{env, path::PathBuf};
+    use std::{collections::HashMap, env, fs, path::PathBuf};
+    use wikitext::PatchData;
 
     #[test]
     fn formats_wiki_location() {
@@ -297,4 +259,37 @@ mod tests {
             PathBuf::from("/user/test/wiki/")
         );
     }
+
+    #[test]
+    fn keeps_the_content_type_of_new_notes() {
+        let dir = PathBuf::from("/tmp/tendril-test/fs/new-markdown/");
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        let store = PlaintextStore::new(dir.clone(), dir.join(".locks"));
+        let patch = |title: &str, metadata: HashMap<String, String>| PatchData {
+            body: "# Plans\n\n- [[first]]".into(),
+            tags: Vec::new(),
+            title: title.into(),
+            old_title: String::new(),
+            metadata,
+            revision: String::new(),
+        };
+
+        let markdown = HashMap::from([("content-type".into(), "markdown".into())]);
+        let (content, _) = prepare_note(&patch("plans", markdown), true, "20221019101112");
+        store.write("plans", &content).unwrap();
+        assert!(dir.join("plans.md").exists());
+        let note = store.read_note("plans").unwrap();
+        assert!(note.is_markdown());
+        assert_eq!(note.header["id"], "20221019101112");
+        assert_eq!(note.content.trim(), "# Plans\n\n- [[first]]");
+
+        let (content, _) = prepare_note(&patch("notes", HashMap::new()), true, "20221019101113");
+        store.write("notes", &content).unwrap();
+        assert!(dir.join("notes.txt").exists());
+        let note = store.read_note("notes").unwrap();
+        assert_eq!(note.header["content-type"], "wikitext");
+    }
 }
@@ -1,24 +1,65 @@
 use directories::{ProjectDirs, UserDirs};
-use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::{
+    env,
+    ffi::OsStr,
+    io,
+    path::{Component, Path, PathBuf, MAIN_SEPARATOR},
+};
+use thiserror::Error;
 
-use super::{ReadPageError, WIKI_LOCATION};
+use super::{config::read_config, MEDIA_LOCATION, WIKI_LOCATION};
 
+/// Set by `tendril -c <path>` to read a config file other than the one in the config directory.
+pub const CONFIG_FILE_VAR: &str = "TENDRIL_CONFIG";
+/// Where the notebook keeps its archive, search index, history, trash and tasks.
+pub const NOTEBOOK_DATA_VAR: &str = "TENDRIL_DATA_DIR";
+/// Set by the notebook hub to the prefix it serves a notebook under, like `/work`. Such a
+/// notebook is only reached through the hub.
+pub const BASE_PATH_VAR: &str = "TENDRIL_BASE_PATH";
+
+/// The install location, holding the static files and templates shared by every notebook.
 pub fn get_data_dir_location() -> PathBuf {
     let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
     let data_dir = project_dir.data_dir();
     data_dir.to_owned()
 }
 
+/// The data kept for the notebook being served, the install location unless it's overridden.
+pub fn get_notebook_data_location() -> PathBuf {
+    match env::var(NOTEBOOK_DATA_VAR) {
+        Ok(location) => PathBuf::from(location),
+        _ => get_data_dir_location(),
+    }
+}
+
+/// A data location of its own for a notebook started with `tendril <dir>` or `tendril -c
+/// <path>`, named after its wiki directory so notebooks don't share an index or archive.
+pub fn derive_notebook_data_location(wiki_location: &Path) -> PathBuf {
+    let name = wiki_location
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+    get_data_dir_location().join("wikis").join(name)
+}
+
 pub fn get_search_index_location() -> PathBuf {
-    let data_location = get_data_dir_location();
+    let data_location = get_notebook_data_location();
     data_location.join("search-index")
 }
-pub fn get_search_file_index_location() -> PathBuf {
-    let data_location = get_data_dir_location();
-    data_location.join("search-index").join("file_index")
-}
 
 pub fn get_config_location() -> (PathBuf, PathBuf) {
+    if let Ok(file) = env::var(CONFIG_FILE_VAR) {
+        let config_path = PathBuf::from(file);
+        let config_dir = match config_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        return (config_dir, config_path);
+    }
     let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
     let config_dir = project_dir.config_dir();
     let mut config_path = PathBuf::from(config_dir);
@@ -30,14 +71,17 @@ pub fn get_wiki_location() -> PathBuf {
     WIKI_LOCATION.clone()
 }
 
-/// Returns the PathBuf if an entry exists, returns an error if the file isn't found or it couldn't
-/// parse the location.
-pub fn get_file_path(requested_file: &str) -> Result<PathBuf, ReadPageError> {
-    let mut file_path = WIKI_LOCATION.clone();
-    file_path.push(requested_file);
-    file_path.set_extension("txt");
+pub fn get_media_location() -> PathBuf {
+    MEDIA_LOCATION.clone()
+}
 
-    Ok(file_path)
+/// Where backups are written, `backups` in the notebook's data location unless it's configured.
+pub fn get_backup_location() -> PathBuf {
+    let config = read_config();
+    match config.backup.as_ref().and_then(|b| b.location.as_ref()) {
+        Some(location) => parse_location(location),
+        None => get_notebook_data_location().join("backups"),
+    }
 }
 
 pub fn parse_location(location: &str) -> PathBuf {
@@ -67,24 +111,128 @@ pub fn normalize_wiki_location(wiki_location: &str) -> String {
     location.to_string_lossy().into()
 }
 
+/// A note title, or namespace, that isn't a plain relative path and so could point outside of
+/// the directories notes are kept in.
+#[derive(Error, Debug)]
+#[error("invalid note title: {0:?}")]
+pub struct InvalidTitle(pub String);
+
+impl From<InvalidTitle> for io::Error {
+    fn from(e: InvalidTitle) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+/// Titles become paths under the wiki, history and archive directories, with namespaces as
+/// sub directories. Every part between slashes has to be a single plain name, so empty parts,
+/// `.`, `..`, roots, prefixes and the `.git` directory are refused.
+pub fn validate_title(title: &str) -> Result<(), InvalidTitle> {
+    let is_plain = |part: &str| {
+        let mut components = Path::new(part).components();
+        matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(name)), None) if name == OsStr::new(part) && name != ".git"
+        )
+    };
+    if !title.is_empty() && title.split('/').all(is_plain) {
+        Ok(())
+    } else {
+        Err(InvalidTitle(title.to_string()))
+    }
+}
+
+/// Where a note's `title` goes under `root`, see [validate_title].
+pub fn title_path(root: &Path, title: &str) -> Result<PathBuf, InvalidTitle> {
+    validate_title(title)?;
+    Ok(root.join(title))
+}
+
 pub fn archive_file_exists(title: &str) -> bool {
-    let location = get_archive_file_path(title);
-    location.exists()
+    get_archive_file_path(title).is_ok_and(|location| location.exists())
 }
 
 pub fn get_archive_location() -> PathBuf {
-    let stored_location = get_data_dir_location();
+    let stored_location = get_notebook_data_location();
     stored_location.join("archive")
 }
 
-pub fn get_archive_file_path(title: &str) -> PathBuf {
-    let mut dir_path = get_archive_location();
-    dir_path.push(title);
-    dir_path
+pub fn get_archive_file_path(title: &str) -> Result<PathBuf, InvalidTitle> {
+    title_path(&get_archive_location(), title)
+}
+
+/// Metadata for the files in the media location.
+pub fn get_media_index_location() -> PathBuf {
+    get_notebook_data_location().join("media.toml")
+}
+
+/// Prior revisions of each note, kept in a directory per note title.
+pub fn get_history_location() -> PathBuf {
+    get_notebook_data_location().join("history")
+}
+
+/// Deleted notes along with their archives, kept until they're restored or purged.
+pub fn get_trash_location() -> PathBuf {
+    get_notebook_data_location().join("trash")
+}
+
+/// Old note titles mapped to the ids of the notes they were renamed to.
+pub fn get_redirects_location() -> PathBuf {
+    get_notebook_data_location().join("redirects.toml")
 }
 
 pub fn get_todo_location() -> PathBuf {
-    let mut base_path = get_data_dir_location();
+    let mut base_path = get_notebook_data_location();
     base_path.push("todo.txt");
     base_path
 }
+
+/// The BibTeX file used for citations, defaults to `references.bib` in the media location.
+pub fn get_bibliography_location() -> PathBuf {
+    let config = read_config();
+    let configured = config
+        .bibliography
+        .as_ref()
+        .and_then(|bib| bib.file.as_ref());
+    match configured {
+        Some(file) => {
+            let path = PathBuf::from(file);
+            if file.contains('~') || path.is_absolute() {
+                let location = parse_location(file);
+                PathBuf::from(location.to_string_lossy().trim_end_matches(MAIN_SEPARATOR))
+            } else {
+                MEDIA_LOCATION.join(path)
+            }
+        }
+        None => MEDIA_LOCATION.join("references.bib"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_titles_outside_the_wiki() {
+        for title in ["note", "projects/tendril", "v1.2 notes", "..notes"] {
+            assert!(validate_title(title).is_ok(), "{}", title);
+        }
+        for title in [
+            "",
+            "..",
+            ".",
+            "/etc/passwd",
+            "../secrets",
+            "a/../../b",
+            "a/./b",
+            "a//b",
+            "a/",
+            ".git/config",
+        ] {
+            assert!(validate_title(title).is_err(), "{}", title);
+        }
+        assert_eq!(
+            title_path(Path::new("/wiki"), "a/b").unwrap(),
+            PathBuf::from("/wiki/a/b")
+        );
+    }
+}
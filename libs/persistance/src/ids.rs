@@ -0,0 +1,207 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io,
+    path::PathBuf,
+    sync::{Mutex, RwLock},
+};
+
+use wikitext::parsers::Note;
+
+use crate::{fs::utils::get_redirects_location, store::note_store};
+
+lazy_static::lazy_static! {
+    static ref IDS: NoteIds = NoteIds::new(get_redirects_location());
+}
+
+/// Old titles of renamed notes, mapped to the id of the note they belong to.
+type Redirects = BTreeMap<String, String>;
+
+/// Finds notes by the `id` they're given when created, which stays the same when they're
+/// renamed. Notes are indexed as they're read on start and whenever they're saved, and the
+/// titles notes are renamed from are kept so links to them can be redirected.
+pub struct NoteIds {
+    titles: RwLock<HashMap<String, String>>,
+    redirects_location: PathBuf,
+    // Renames rewrite the redirects file, only let one happen at a time.
+    redirects_lock: Mutex<()>,
+}
+
+impl NoteIds {
+    pub fn new(redirects_location: PathBuf) -> Self {
+        Self {
+            titles: RwLock::new(HashMap::new()),
+            redirects_location,
+            redirects_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn insert(&self, id: &str, title: &str) {
+        let mut titles = self.titles.write().unwrap();
+        if let Some(existing) = titles.get(id) {
+            if existing != title {
+                eprintln!(
+                    "{} and {} have the same id {}, links to it go to {}",
+                    existing, title, id, existing
+                );
+                return;
+            }
+        }
+        titles.insert(id.to_string(), title.to_string());
+    }
+
+    pub fn title_for(&self, id: &str) -> Option<String> {
+        self.titles.read().unwrap().get(id).cloned()
+    }
+
+    /// Every id given to a note.
+    pub fn ids(&self) -> HashSet<String> {
+        self.titles.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn id_for(&self, title: &str) -> Option<String> {
+        self.titles
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(_, indexed)| *indexed == title)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Drops a deleted note from the index, its redirects are kept in case it's restored.
+    pub fn forget(&self, title: &str) {
+        self.titles
+            .write()
+            .unwrap()
+            .retain(|_, indexed| indexed != title);
+    }
+
+    /// Points the note's id at its new title and redirects the old title to it.
+    pub fn rename(&self, old_title: &str, new_title: &str) -> io::Result<()> {
+        let id = {
+            let mut titles = self.titles.write().unwrap();
+            let id = titles
+                .iter()
+                .find(|(_, indexed)| *indexed == old_title)
+                .map(|(id, _)| id.clone());
+            match id {
+                Some(id) => {
+                    titles.insert(id.clone(), new_title.to_string());
+                    id
+                }
+                None => return Ok(()),
+            }
+        };
+        let _lock = self.redirects_lock.lock().unwrap();
+        let mut redirects = self.read_redirects()?;
+        redirects.insert(old_title.to_string(), id);
+        // The new title is a note again, it doesn't redirect anywhere.
+        redirects.remove(new_title);
+        self.write_redirects(&redirects)
+    }
+
+    /// The current title of a note that used to be called `title`.
+    pub fn redirect_for(&self, title: &str) -> Option<String> {
+        let redirects = self.read_redirects().ok()?;
+        let current = self.title_for(redirects.get(title)?)?;
+        if current == title {
+            None
+        } else {
+            Some(current)
+        }
+    }
+
+    fn read_redirects(&self) -> io::Result<Redirects> {
+        match fs::read_to_string(&self.redirects_location) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Redirects::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_redirects(&self, redirects: &Redirects) -> io::Result<()> {
+        let content = toml::to_string(redirects).map_err(io::Error::other)?;
+        if let Some(parent) = self.redirects_location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = self.redirects_location.with_extension("toml.tmp");
+        fs::write(&temp, content)?;
+        fs::rename(temp, &self.redirects_location)
+    }
+}
+
+/// The ids of the notes in the wiki.
+pub fn note_ids() -> &'static NoteIds {
+    &IDS
+}
+
+/// Adds the note to the ids index, notes written before ids were added don't have one yet.
+pub fn index(title: &str, note: &Note) {
+    if let Some(id) = note.header.get("id") {
+        IDS.insert(id, title);
+    }
+}
+
+/// Indexes every note in the store, for commands that need the ids before the wiki is read.
+/// Returns the ids in use.
+pub fn index_store() -> HashSet<String> {
+    let store = note_store();
+    for title in store.list().unwrap_or_default() {
+        if let Ok(note) = store.read_note(&title) {
+            index(&title, &note);
+        }
+    }
+    IDS.ids()
+}
+
+/// The current title of the note with `id`, used to resolve `[[id:...]]` links.
+pub fn title_for(id: &str) -> Option<String> {
+    IDS.title_for(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/ids/";
+
+    fn ids(name: &str) -> NoteIds {
+        let root = PathBuf::from(TEST_DIR).join(name);
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        NoteIds::new(root.join("redirects.toml"))
+    }
+
+    #[test]
+    fn follows_notes_through_renames() {
+        let ids = ids("renames");
+        ids.insert("20220503101112", "Draft");
+        ids.rename("Draft", "Plans").unwrap();
+        ids.rename("Plans", "Projects/Plans").unwrap();
+        assert_eq!(
+            ids.title_for("20220503101112"),
+            Some("Projects/Plans".into())
+        );
+        assert_eq!(ids.id_for("Projects/Plans"), Some("20220503101112".into()));
+        assert_eq!(ids.redirect_for("Draft"), Some("Projects/Plans".into()));
+        assert_eq!(ids.redirect_for("Plans"), Some("Projects/Plans".into()));
+        assert_eq!(ids.redirect_for("Projects/Plans"), None);
+        // Renaming back makes the old title a note again.
+        ids.rename("Projects/Plans", "Draft").unwrap();
+        assert_eq!(ids.redirect_for("Draft"), None);
+        assert_eq!(ids.redirect_for("Plans"), Some("Draft".into()));
+    }
+
+    #[test]
+    fn keeps_the_first_note_with_an_id() {
+        let ids = ids("duplicates");
+        ids.insert("20220503101112", "First");
+        ids.insert("20220503101112", "Second");
+        assert_eq!(ids.title_for("20220503101112"), Some("First".into()));
+        assert_eq!(ids.ids(), HashSet::from(["20220503101112".to_string()]));
+        ids.forget("First");
+        assert_eq!(ids.title_for("20220503101112"), None);
+    }
+}
@@ -1 +1,6 @@
+pub mod archive;
+pub mod crypto;
 pub mod fs;
+pub mod ids;
+pub mod media;
+pub mod store;
@@ -0,0 +1,411 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Local};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    fs::{config::read_config, utils::get_media_index_location, MEDIA_LOCATION},
+    store::fnv_hash,
+};
+
+/// Paths under `/files` that are pages rather than uploads.
+const RESERVED_NAMES: [&str; 2] = ["list", "orphans"];
+/// Thumbnails are kept in `.thumbnails/<width>/<name>`, hidden from the listing.
+const THUMBNAILS_DIR: &str = ".thumbnails";
+const DEFAULT_THUMBNAIL_WIDTHS: [u32; 3] = [320, 640, 1280];
+
+lazy_static::lazy_static! {
+    // Uploads, renames and deletes all rewrite the index, only let one happen at a time.
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// The library of files in the configured media location.
+pub fn media_library() -> MediaLibrary {
+    MediaLibrary::new(MEDIA_LOCATION.clone(), get_media_index_location())
+}
+
+/// Widths of the thumbnails generated for uploaded images, set by `thumbnail_widths` in the
+/// `[media]` section of the config.
+pub fn thumbnail_widths() -> Vec<u32> {
+    read_config()
+        .media
+        .as_ref()
+        .and_then(|media| media.thumbnail_widths.clone())
+        .unwrap_or_else(|| DEFAULT_THUMBNAIL_WIDTHS.to_vec())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MediaFile {
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+    pub content_type: String,
+    /// RFC 3339 timestamp of the upload.
+    pub uploaded: String,
+    pub uploader: String,
+    /// Hash of the content, used to store duplicate uploads only once.
+    pub hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MediaIndex {
+    #[serde(default)]
+    files: BTreeMap<String, MediaFile>,
+}
+
+/// Removes anything from an uploaded file name that could escape the media directory or that
+/// would need escaping in a link. Spaces become dashes.
+pub fn sanitize_filename(filename: &str) -> String {
+    // Browsers may send a full path, only the last component is the name of the file.
+    let base = filename.rsplit(['/', '\\']).next().unwrap();
+    let mut sanitized = String::with_capacity(base.len());
+    for c in base.trim().chars() {
+        if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') {
+            sanitized.push(c);
+        } else if c.is_whitespace() && !sanitized.ends_with('-') {
+            sanitized.push('-');
+        }
+    }
+    // No hidden files, and no `..`.
+    let sanitized = sanitized.trim_start_matches('.');
+    if sanitized.is_empty() {
+        String::from("file")
+    } else {
+        sanitized.to_string()
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_lowercase(),
+        None => return "application/octet-stream",
+    };
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "avif" => "image/avif",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain",
+        "bib" => "application/x-bibtex",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    format!("{:016x}", fnv_hash(bytes))
+}
+
+pub struct MediaLibrary {
+    location: PathBuf,
+    index_location: PathBuf,
+}
+
+impl MediaLibrary {
+    pub fn new(location: PathBuf, index_location: PathBuf) -> Self {
+        Self {
+            location,
+            index_location,
+        }
+    }
+
+    /// Only plain file names inside the media directory can be addressed.
+    pub fn path_of(&self, name: &str) -> io::Result<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid file name: {}", name),
+            ));
+        }
+        Ok(self.location.join(name))
+    }
+
+    pub fn thumbnail_path(&self, name: &str, width: u32) -> PathBuf {
+        self.location
+            .join(THUMBNAILS_DIR)
+            .join(width.to_string())
+            .join(name)
+    }
+
+    /// Existing thumbnails of a file, whatever width they were generated at.
+    fn thumbnails_of(&self, name: &str) -> Vec<PathBuf> {
+        match fs::read_dir(self.location.join(THUMBNAILS_DIR)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().join(name))
+                .filter(|path| path.is_file())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn read_index(&self) -> io::Result<MediaIndex> {
+        match fs::read_to_string(&self.index_location) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(MediaIndex::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_index(&self, index: &MediaIndex) -> io::Result<()> {
+        let content = toml::to_string(index).map_err(io::Error::other)?;
+        if let Some(parent) = self.index_location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = self.index_location.with_extension("toml.tmp");
+        fs::write(&temp, content)?;
+        fs::rename(temp, &self.index_location)
+    }
+
+    /// Metadata for a file that was put in the media directory some other way than an upload.
+    fn describe(&self, path: &Path) -> io::Result<MediaFile> {
+        let metadata = fs::metadata(path)?;
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        let uploaded = metadata
+            .modified()
+            .map(DateTime::<Local>::from)
+            .unwrap_or_else(|_| Local::now());
+        Ok(MediaFile {
+            content_type: content_type(&name).into(),
+            name,
+            size: metadata.len(),
+            uploaded: uploaded.to_rfc3339(),
+            uploader: String::new(),
+            hash: content_hash(&fs::read(path)?),
+        })
+    }
+
+    /// Reads the index, bringing it in line with what's actually in the media directory.
+    fn synced_index(&self) -> io::Result<MediaIndex> {
+        let mut index = self.read_index()?;
+        let mut changed = false;
+        let mut present = Vec::new();
+        if self.location.exists() {
+            for entry in fs::read_dir(&self.location)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                if !index.files.contains_key(&name) {
+                    index
+                        .files
+                        .insert(name.clone(), self.describe(&entry.path())?);
+                    changed = true;
+                }
+                present.push(name);
+            }
+        }
+        let before = index.files.len();
+        index.files.retain(|name, _| present.contains(name));
+        if changed || index.files.len() != before {
+            self.write_index(&index)?;
+        }
+        Ok(index)
+    }
+
+    /// Every file in the library, most recently uploaded first.
+    pub fn list(&self) -> io::Result<Vec<MediaFile>> {
+        let _guard = INDEX_LOCK.lock().unwrap();
+        let mut files = self
+            .synced_index()?
+            .files
+            .into_values()
+            .collect::<Vec<MediaFile>>();
+        files.sort_by(|a, b| b.uploaded.cmp(&a.uploaded).then(a.name.cmp(&b.name)));
+        Ok(files)
+    }
+
+    fn is_taken(&self, name: &str) -> bool {
+        RESERVED_NAMES.contains(&name) || self.location.join(name).exists()
+    }
+
+    /// `name`, or `name` with a number added before the extension if it's already used.
+    fn unique_name(&self, name: String) -> String {
+        if !self.is_taken(&name) {
+            return name;
+        }
+        let (stem, extension) = match name.rfind('.') {
+            Some(idx) if idx > 0 => name.split_at(idx),
+            _ => (name.as_str(), ""),
+        };
+        (1..)
+            .map(|n| format!("{}-{}{}", stem, n, extension))
+            .find(|candidate| !self.is_taken(candidate))
+            .unwrap()
+    }
+
+    /// Stores an upload under a sanitised, unused name. Uploading a file that is already in the
+    /// library returns the existing file instead of storing a copy.
+    pub fn save(&self, filename: &str, bytes: &[u8], uploader: &str) -> io::Result<MediaFile> {
+        let _guard = INDEX_LOCK.lock().unwrap();
+        let mut index = self.synced_index()?;
+        let hash = content_hash(bytes);
+        let duplicate = index.files.values().find(|file| {
+            file.hash == hash
+                && matches!(fs::read(self.location.join(&file.name)), Ok(existing) if existing == bytes)
+        });
+        if let Some(duplicate) = duplicate {
+            return Ok(duplicate.clone());
+        }
+        let name = self.unique_name(sanitize_filename(filename));
+        fs::create_dir_all(&self.location)?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.location.join(&name))?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        let media_file = MediaFile {
+            content_type: content_type(&name).into(),
+            name: name.clone(),
+            size: bytes.len() as u64,
+            uploaded: Local::now().to_rfc3339(),
+            uploader: uploader.into(),
+            hash,
+        };
+        index.files.insert(name, media_file.clone());
+        self.write_index(&index)?;
+        Ok(media_file)
+    }
+
+    /// Renames a file, the new name is sanitised the same way uploads are. Fails if another file
+    /// already has that name.
+    pub fn rename(&self, name: &str, new_name: &str) -> io::Result<MediaFile> {
+        let _guard = INDEX_LOCK.lock().unwrap();
+        let mut index = self.synced_index()?;
+        let path = self.path_of(name)?;
+        let mut media_file = index
+            .files
+            .remove(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no file with that name"))?;
+        let new_name = sanitize_filename(new_name);
+        if new_name == name {
+            return Ok(media_file);
+        }
+        if self.is_taken(&new_name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a file called {} already exists", new_name),
+            ));
+        }
+        fs::rename(path, self.location.join(&new_name))?;
+        for thumbnail in self.thumbnails_of(name) {
+            fs::rename(&thumbnail, thumbnail.with_file_name(&new_name))?;
+        }
+        media_file.content_type = content_type(&new_name).into();
+        media_file.name = new_name.clone();
+        index.files.insert(new_name, media_file.clone());
+        self.write_index(&index)?;
+        Ok(media_file)
+    }
+
+    pub fn delete(&self, name: &str) -> io::Result<()> {
+        let _guard = INDEX_LOCK.lock().unwrap();
+        let mut index = self.synced_index()?;
+        fs::remove_file(self.path_of(name)?)?;
+        for thumbnail in self.thumbnails_of(name) {
+            fs::remove_file(thumbnail)?;
+        }
+        index.files.remove(name);
+        self.write_index(&index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/media/";
+
+    fn library(name: &str) -> MediaLibrary {
+        let root = PathBuf::from(TEST_DIR).join(name);
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        MediaLibrary::new(root.join("files"), root.join("media.toml"))
+    }
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(
+            sanitize_filename("C:\\Users\\me\\My Photo.png"),
+            "My-Photo.png"
+        );
+        assert_eq!(sanitize_filename(".hidden"), "hidden");
+        assert_eq!(sanitize_filename("<script>.js"), "script.js");
+        assert_eq!(sanitize_filename("写真 (1).jpg"), "写真-1.jpg");
+        assert_eq!(sanitize_filename(".."), "file");
+    }
+
+    #[test]
+    fn renames_colliding_uploads_and_dedups_identical_ones() {
+        let library = library("collisions");
+        let first = library.save("cat.png", b"first cat", "me").unwrap();
+        assert_eq!(first.name, "cat.png");
+        assert_eq!(first.content_type, "image/png");
+        assert_eq!(first.size, 9);
+        let second = library.save("cat.png", b"second cat", "me").unwrap();
+        assert_eq!(second.name, "cat-1.png");
+        let again = library
+            .save("another name.png", b"first cat", "me")
+            .unwrap();
+        assert_eq!(again, first);
+        let reserved = library.save("list", b"not a page", "me").unwrap();
+        assert_eq!(reserved.name, "list-1");
+        assert_eq!(library.list().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn renames_and_deletes_files() {
+        let library = library("rename");
+        library.save("notes.pdf", b"pdf", "me").unwrap();
+        library.save("other.pdf", b"other pdf", "me").unwrap();
+        assert!(library.rename("notes.pdf", "other.pdf").is_err());
+        assert!(library.rename("../media.toml", "stolen").is_err());
+        let thumbnail = library.thumbnail_path("notes.pdf", 320);
+        fs::create_dir_all(thumbnail.parent().unwrap()).unwrap();
+        fs::write(&thumbnail, b"small pdf").unwrap();
+        let renamed = library.rename("notes.pdf", "meeting notes.pdf").unwrap();
+        assert!(!thumbnail.exists());
+        assert!(library.thumbnail_path("meeting-notes.pdf", 320).exists());
+        assert_eq!(renamed.name, "meeting-notes.pdf");
+        assert_eq!(renamed.uploader, "me");
+        library.delete("other.pdf").unwrap();
+        let names = library
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["meeting-notes.pdf"]);
+    }
+
+    #[test]
+    fn indexes_files_added_outside_of_uploads() {
+        let library = library("external");
+        fs::create_dir_all(&library.location).unwrap();
+        fs::write(library.location.join("references.bib"), "@book{}").unwrap();
+        let files = library.list().unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].content_type, "application/x-bibtex");
+        assert!(library.index_location.exists());
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::mpsc::Receiver;
+
+use crate::crypto::{needs_sealing, open_note, seal_note};
+
+use super::{NoteStore, StoreError, StoreEvent};
+
+/// Keeps notes encrypted on disk in whichever store holds them. Notes with an `encrypted: true`
+/// header, or every note when `encrypt_all` is set, are encrypted when written. Encrypted notes
+/// are decrypted when read, no matter how the notebook is configured now.
+pub struct EncryptedStore {
+    inner: Box<dyn NoteStore>,
+    encrypt_all: bool,
+}
+
+impl EncryptedStore {
+    pub fn new(inner: Box<dyn NoteStore>, encrypt_all: bool) -> Self {
+        Self { inner, encrypt_all }
+    }
+
+    fn seal(&self, content: &str) -> Result<String, StoreError> {
+        if needs_sealing(content, self.encrypt_all) {
+            Ok(seal_note(content)?)
+        } else {
+            Ok(content.to_string())
+        }
+    }
+}
+
+impl NoteStore for EncryptedStore {
+    fn read(&self, title: &str) -> Result<String, StoreError> {
+        Ok(open_note(self.inner.read(title)?)?)
+    }
+
+    fn write(&self, title: &str, content: &str) -> Result<(), StoreError> {
+        self.inner.write(title, &self.seal(content)?)
+    }
+
+    fn update(
+        &self,
+        title: &str,
+        apply: &mut dyn FnMut(Option<String>) -> Result<String, StoreError>,
+    ) -> Result<String, StoreError> {
+        let mut updated = String::new();
+        self.inner.update(title, &mut |current| {
+            let content = apply(current.map(open_note).transpose()?)?;
+            let stored = self.seal(&content)?;
+            updated = content;
+            Ok(stored)
+        })?;
+        Ok(updated)
+    }
+
+    fn rename(&self, old_title: &str, new_title: &str) -> Result<(), StoreError> {
+        self.inner.rename(old_title, new_title)
+    }
+
+    fn rename_and_write(
+        &self,
+        old_title: &str,
+        new_title: &str,
+        apply: &mut dyn FnMut(String) -> Result<String, StoreError>,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .rename_and_write(old_title, new_title, &mut |current| {
+                self.seal(&apply(open_note(current)?)?)
+            })
+    }
+
+    fn delete(&self, title: &str) -> Result<(), StoreError> {
+        self.inner.delete(title)
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        self.inner.list()
+    }
+
+    fn exists(&self, title: &str) -> bool {
+        self.inner.exists(title)
+    }
+
+    fn watch(&self) -> Receiver<StoreEvent> {
+        self.inner.watch()
+    }
+}
@@ -0,0 +1,498 @@
+mod encrypted;
+mod plaintext;
+mod sqlite;
+
+pub use encrypted::EncryptedStore;
+pub use plaintext::PlaintextStore;
+pub use sqlite::SqliteStore;
+
+use std::{
+    collections::HashMap,
+    env, io,
+    str::FromStr,
+    sync::{mpsc::Receiver, Arc, Mutex},
+};
+
+use thiserror::Error;
+use wikitext::parsers::{parse_meta, Note};
+
+use crate::{
+    crypto::{encrypt_all, CryptoError},
+    fs::{
+        config::read_config,
+        utils::{get_notebook_data_location, InvalidTitle},
+        ReadPageError, WriteWikiError, WIKI_LOCATION,
+    },
+};
+
+lazy_static::lazy_static! {
+    static ref STORE: Box<dyn NoteStore> = {
+        let storage = match env::var("TENDRIL_STORAGE") {
+            Ok(val) => val,
+            _ => read_config().general.storage.clone().unwrap_or_default(),
+        };
+        let storage = Storage::from_str(&storage).unwrap_or_else(|e| panic!("{}", e));
+        let store = storage.open().unwrap_or_else(|e| panic!("Could not open note store: {}", e));
+        Box::new(EncryptedStore::new(store, encrypt_all()))
+    };
+}
+
+/// The store used by the whole wiki. The backend is chosen by the `storage` option in the
+/// `[general]` section of the config (or the `TENDRIL_STORAGE` environment variable), notes are
+/// encrypted and decrypted on their way in and out of it.
+pub fn note_store() -> &'static dyn NoteStore {
+    STORE.as_ref()
+}
+
+/// FNV-1a, small and stable across builds so its output can be stored or shared between processes.
+pub(crate) fn fnv_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Identifies a version of a note's raw content. Editors send back the revision they started
+/// from so saves based on an outdated copy of the note can be detected.
+pub fn revision(content: &str) -> String {
+    format!("{:016x}", fnv_hash(content.as_bytes()))
+}
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("note not found")]
+    NotFound,
+    #[error("a note with that title already exists")]
+    AlreadyExists,
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("storage backend error: {0}")]
+    Backend(String),
+    #[error("encryption error: {0}")]
+    Encryption(#[from] CryptoError),
+    #[error("{0}")]
+    InvalidTitle(#[from] InvalidTitle),
+    #[error("note was changed since revision was loaded, current revision is {0}")]
+    Conflict(String),
+}
+
+impl From<StoreError> for ReadPageError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::NotFound => ReadPageError::PageNotFoundError,
+            StoreError::InvalidTitle(_) => ReadPageError::DecodeError,
+            _ => ReadPageError::DeserializationError,
+        }
+    }
+}
+
+impl From<StoreError> for WriteWikiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Conflict(current) => WriteWikiError::Conflict(current),
+            e => WriteWikiError::WriteError(e.into()),
+        }
+    }
+}
+
+impl From<StoreError> for io::Error {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::Io(e) => e,
+            StoreError::NotFound => io::Error::new(io::ErrorKind::NotFound, e.to_string()),
+            StoreError::AlreadyExists => {
+                io::Error::new(io::ErrorKind::AlreadyExists, e.to_string())
+            }
+            StoreError::Encryption(e) => e.into(),
+            StoreError::InvalidTitle(e) => e.into(),
+            e => io::Error::other(e.to_string()),
+        }
+    }
+}
+
+/// Changes to notes made outside of this process, e.g. by a git pull or another editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreEvent {
+    Changed(String),
+    Removed(String),
+    Renamed { from: String, to: String },
+}
+
+/// What this process last did to each note, so a watcher can tell its own changes apart from
+/// external ones. `None` means the note was deleted or moved away.
+#[derive(Clone, Default)]
+pub(crate) struct OwnWrites(Arc<Mutex<HashMap<String, Option<u64>>>>);
+
+impl OwnWrites {
+    pub(crate) fn wrote(&self, title: &str, content: &[u8]) {
+        let mut writes = self.0.lock().unwrap();
+        writes.insert(title.into(), Some(fnv_hash(content)));
+    }
+
+    pub(crate) fn removed(&self, title: &str) {
+        self.0.lock().unwrap().insert(title.into(), None);
+    }
+
+    /// Whether the note is in the state this process left it in, `None` if it no longer exists.
+    /// Each record is only matched once, a later change to the same content is someone else's.
+    pub(crate) fn is_own(&self, title: &str, content: Option<&[u8]>) -> bool {
+        let recorded = self.0.lock().unwrap().remove(title);
+        recorded == Some(content.map(fnv_hash))
+    }
+}
+
+/// Storage for the raw text of notes, addressed by title. Titles may contain `/` to nest notes,
+/// titles that aren't plain relative paths are refused with [StoreError::InvalidTitle].
+pub trait NoteStore: Send + Sync {
+    /// Returns the raw note, headers included.
+    fn read(&self, title: &str) -> Result<String, StoreError>;
+    /// Replaces the note atomically, readers see either the old or the new content.
+    fn write(&self, title: &str, content: &str) -> Result<(), StoreError>;
+    /// Read-modify-write without other writers getting in between. `apply` receives the current
+    /// content (`None` for a new note) and returns the content to store, which is also returned.
+    /// An error from `apply` leaves the note as it is and is returned.
+    fn update(
+        &self,
+        title: &str,
+        apply: &mut dyn FnMut(Option<String>) -> Result<String, StoreError>,
+    ) -> Result<String, StoreError>;
+    /// Moves a note to a new title, failing if a note with the new title already exists.
+    fn rename(&self, old_title: &str, new_title: &str) -> Result<(), StoreError>;
+    /// Moves a note and replaces its content as one step, so a failure can't leave the note
+    /// under both titles or under neither. `apply` receives the current content and returns the
+    /// content to store, like [NoteStore::update].
+    fn rename_and_write(
+        &self,
+        old_title: &str,
+        new_title: &str,
+        apply: &mut dyn FnMut(String) -> Result<String, StoreError>,
+    ) -> Result<(), StoreError>;
+    fn delete(&self, title: &str) -> Result<(), StoreError>;
+    /// Titles of every note in the store.
+    fn list(&self) -> Result<Vec<String>, StoreError>;
+    fn exists(&self, title: &str) -> bool;
+    /// Emits an event whenever a note changes. Events stop once the receiver is dropped.
+    fn watch(&self) -> Receiver<StoreEvent>;
+
+    fn read_note(&self, title: &str) -> Result<Note, ReadPageError> {
+        let content = self.read(title)?;
+        Ok(parse_note(&content, title))
+    }
+}
+
+/// Parses a note read from the store. Markdown notes written by other tools may not have a title
+/// in their front matter, those are titled after their file.
+pub(crate) fn parse_note(content: &str, title: &str) -> Note {
+    let mut note = parse_meta(content.lines(), title);
+    note.header
+        .entry("title".into())
+        .or_insert_with(|| title.into());
+    note
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    Plaintext,
+    Sqlite,
+}
+
+impl FromStr for Storage {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "" | "plaintext" | "text" => Ok(Storage::Plaintext),
+            "sqlite" => Ok(Storage::Sqlite),
+            _ => Err(format!(
+                "Unknown storage backend: {}, expected plaintext or sqlite",
+                s
+            )),
+        }
+    }
+}
+
+impl Storage {
+    fn open(&self) -> Result<Box<dyn NoteStore>, StoreError> {
+        let location = WIKI_LOCATION.clone();
+        match self {
+            Storage::Plaintext => Ok(Box::new(PlaintextStore::new(
+                location,
+                get_notebook_data_location().join("locks"),
+            ))),
+            Storage::Sqlite => Ok(Box::new(SqliteStore::open(
+                location.join(sqlite::DB_FILE_NAME),
+            )?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::PathBuf,
+        sync::Arc,
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+
+    fn exercise_store(store: &dyn NoteStore) {
+        assert!(matches!(
+            store.write("../escaped", "title: escaped\n\nout"),
+            Err(StoreError::InvalidTitle(_))
+        ));
+        store.write("first", "title: first\n\nhello").unwrap();
+        store
+            .write("nested/second", "title: nested/second\n\nworld")
+            .unwrap();
+        assert!(store.exists("first"));
+        assert_eq!(store.read("first").unwrap(), "title: first\n\nhello");
+        assert_eq!(
+            store.read_note("nested/second").unwrap().content,
+            "world".to_string()
+        );
+
+        assert!(matches!(
+            store.rename("first", "/tmp/first"),
+            Err(StoreError::InvalidTitle(_))
+        ));
+        store.rename("first", "renamed").unwrap();
+        assert!(!store.exists("first"));
+        assert!(matches!(
+            store.rename("renamed", "nested/second"),
+            Err(StoreError::AlreadyExists)
+        ));
+
+        let mut titles = store.list().unwrap();
+        titles.sort();
+        assert_eq!(titles, vec!["nested/second", "renamed"]);
+
+        store
+            .rename_and_write("renamed", "moved", &mut |_| {
+                Ok("title: moved\n\nagain".into())
+            })
+            .unwrap();
+        assert!(!store.exists("renamed"));
+        assert_eq!(store.read("moved").unwrap(), "title: moved\n\nagain");
+        assert!(matches!(
+            store.rename_and_write("moved", "nested/second", &mut |_| Ok("clobbered".into())),
+            Err(StoreError::AlreadyExists)
+        ));
+        assert_eq!(store.read("moved").unwrap(), "title: moved\n\nagain");
+        store.rename("moved", "renamed").unwrap();
+
+        let appended = store
+            .update("journal", &mut |current| {
+                assert!(current.is_none());
+                Ok(String::from("first"))
+            })
+            .unwrap();
+        assert_eq!(appended, "first");
+        store
+            .update("journal", &mut |current| {
+                Ok(format!("{} second", current.unwrap()))
+            })
+            .unwrap();
+        assert_eq!(store.read("journal").unwrap(), "first second");
+        assert!(matches!(
+            store.update("journal", &mut |_| Err(StoreError::Conflict("abc".into()))),
+            Err(StoreError::Conflict(current)) if current == "abc"
+        ));
+        assert!(matches!(
+            store.rename_and_write("journal", "diary", &mut |_| Err(StoreError::NotFound)),
+            Err(StoreError::NotFound)
+        ));
+        assert!(!store.exists("diary"));
+        assert_eq!(store.read("journal").unwrap(), "first second");
+        store.delete("journal").unwrap();
+
+        store.delete("renamed").unwrap();
+        assert!(matches!(store.read("renamed"), Err(StoreError::NotFound)));
+        assert!(matches!(store.delete("renamed"), Err(StoreError::NotFound)));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = PathBuf::from(format!("/tmp/tendril-test/store/{}/", name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn revisions_follow_content() {
+        assert_eq!(revision("title: a\n\nbody"), revision("title: a\n\nbody"));
+        assert_ne!(revision("title: a\n\nbody"), revision("title: a\n\nbody!"));
+        assert_eq!(revision("").len(), 16);
+    }
+
+    #[test]
+    fn plaintext_store() {
+        let dir = temp_dir("plaintext");
+        let store = PlaintextStore::new(dir.clone(), dir.join(".locks"));
+        exercise_store(&store);
+        assert!(dir.join("nested").join("second.txt").exists());
+        store.rename("nested/second", "second").unwrap();
+        assert!(!dir.join("nested").exists());
+        assert!(dir.exists());
+    }
+
+    #[test]
+    fn plaintext_store_keeps_markdown_in_md_files() {
+        let dir = temp_dir("markdown");
+        let store = PlaintextStore::new(dir.clone(), dir.join(".locks"));
+        fs::write(
+            dir.join("from elsewhere.md"),
+            "# Written elsewhere\n\n[[first]]",
+        )
+        .unwrap();
+        let note = store.read_note("from elsewhere").unwrap();
+        assert_eq!(note.header["title"], "from elsewhere");
+        assert_eq!(note.content, "# Written elsewhere\n\n[[first]]");
+        assert_eq!(store.list().unwrap(), vec!["from elsewhere"]);
+
+        store.write("first", "title: first\n\nhello").unwrap();
+        store
+            .write("first", "---\ntitle: first\n---\n\nhello")
+            .unwrap();
+        assert!(dir.join("first.md").exists());
+        assert!(!dir.join("first.txt").exists());
+        store.rename("first", "second").unwrap();
+        assert!(dir.join("second.md").exists());
+        store.write("second", "title: second\n\nhello").unwrap();
+        assert!(dir.join("second.txt").exists());
+        assert!(!dir.join("second.md").exists());
+    }
+
+    #[test]
+    fn plaintext_store_recovers_from_an_interrupted_format_change() {
+        let dir = temp_dir("interrupted");
+        let store = PlaintextStore::new(dir.clone(), dir.join(".locks"));
+        store.write("plans", "title: plans\n\nold").unwrap();
+        // As if the process died between writing the Markdown file and removing the old one.
+        fs::File::options()
+            .write(true)
+            .open(dir.join("plans.txt"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        fs::write(dir.join("plans.md"), "---\ntitle: plans\n---\n\nnew").unwrap();
+        assert_eq!(
+            store.read("plans").unwrap(),
+            "---\ntitle: plans\n---\n\nnew"
+        );
+        store.rename("plans", "ideas").unwrap();
+        assert!(!store.exists("plans"));
+        assert!(!dir.join("plans.txt").exists());
+        store.delete("ideas").unwrap();
+        assert!(!store.exists("ideas"));
+
+        store.write("kept", "title: kept\n\nhello").unwrap();
+        fs::write(dir.join("kept.md"), "---\ntitle: kept\n---\n\nstale").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(dir.join("kept.md"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(store.read("kept").unwrap(), "title: kept\n\nhello");
+        store.write("kept", "title: kept\n\nagain").unwrap();
+        assert!(!dir.join("kept.md").exists());
+        store.delete("kept").unwrap();
+        // Lock files go with the notes they belong to.
+        assert_eq!(fs::read_dir(dir.join(".locks")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn plaintext_store_reports_external_changes() {
+        let dir = temp_dir("watch");
+        let store = PlaintextStore::new(dir.clone(), dir.join(".locks"));
+        store.write("kept", "title: kept\n\nhello").unwrap();
+        let events = store.watch();
+        // Give the watcher time to start.
+        thread::sleep(Duration::from_millis(200));
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // The store's own changes aren't reported.
+        store.write("own", "title: own\n\nmine").unwrap();
+        store.rename("own", "nested/own").unwrap();
+        fs::write(dir.join("external.txt"), "title: external\n\nfrom vim").unwrap();
+        assert_eq!(next(), StoreEvent::Changed("external".into()));
+
+        fs::rename(dir.join("external.txt"), dir.join("moved.txt")).unwrap();
+        assert_eq!(
+            next(),
+            StoreEvent::Renamed {
+                from: "external".into(),
+                to: "moved".into()
+            }
+        );
+        fs::remove_dir_all(dir.join("nested")).unwrap();
+        assert_eq!(next(), StoreEvent::Removed("nested/own".into()));
+        store.delete("kept").unwrap();
+        fs::remove_file(dir.join("moved.txt")).unwrap();
+        assert_eq!(next(), StoreEvent::Removed("moved".into()));
+    }
+
+    #[test]
+    fn plaintext_store_serializes_updates() {
+        let dir = temp_dir("plaintext-concurrent");
+        let store = Arc::new(PlaintextStore::new(dir.clone(), dir.join(".locks")));
+        let workers = (0..4)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        store
+                            .update("journal", &mut |current| {
+                                Ok(format!("{}x", current.unwrap_or_default()))
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(store.read("journal").unwrap().len(), 100);
+    }
+
+    #[test]
+    fn encrypted_store() {
+        crate::crypto::unlock_for_tests();
+        let dir = temp_dir("encrypted");
+        let plaintext = || Box::new(PlaintextStore::new(dir.clone(), dir.join(".locks")));
+        exercise_store(&EncryptedStore::new(plaintext(), true));
+
+        let store = EncryptedStore::new(plaintext(), false);
+        store.write("open", "title: open\n\nvisible").unwrap();
+        store
+            .write("secret", "title: secret\nencrypted: true\n\nhidden")
+            .unwrap();
+        let on_disk = |title: &str| fs::read_to_string(dir.join(format!("{}.txt", title))).unwrap();
+        assert_eq!(on_disk("open"), "title: open\n\nvisible");
+        assert!(!on_disk("secret").contains("hidden"));
+        assert_eq!(
+            store.read_note("secret").unwrap().content,
+            String::from("hidden")
+        );
+        store
+            .update("secret", &mut |current| {
+                Ok(format!("{} and safe", current.unwrap()))
+            })
+            .unwrap();
+        assert!(!on_disk("secret").contains("safe"));
+        assert!(store.read("secret").unwrap().ends_with("hidden and safe"));
+    }
+
+    #[test]
+    fn sqlite_store() {
+        let dir = temp_dir("sqlite");
+        exercise_store(&SqliteStore::open(dir.join(sqlite::DB_FILE_NAME)).unwrap());
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    process,
+    sync::mpsc::{channel, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt;
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use wikitext::parsers::has_front_matter;
+
+use crate::{
+    crypto::is_sealed_note,
+    fs::utils::{title_path, InvalidTitle},
+};
+
+use super::{fnv_hash, NoteStore, OwnWrites, StoreError, StoreEvent};
+
+/// How long the wiki directory has to be quiet before changes are reported. Editors often save
+/// in several steps, e.g. vim moves the old file away before writing the new one.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Changes are reported at least this often, even while files keep changing.
+const MAX_BATCH: Duration = Duration::from_secs(5);
+const WIKITEXT_EXTENSION: &str = "txt";
+const MARKDOWN_EXTENSION: &str = "md";
+
+/// The default store, each note is a `.txt` file in the wiki directory, or a `.md` file for
+/// Markdown notes.
+///
+/// Every change to a note happens while holding an advisory lock on a file in `locks`, which is
+/// shared with any other tendril process (e.g. the CLI appending to the daily journal) using the
+/// same data directory. Writes go to a temporary file which is synced and then renamed over the
+/// note, so a crash leaves either the old or the new version on disk.
+///
+/// The directory is watched with inotify (or the platform's equivalent), changes made by this
+/// process are recorded as they happen so the watcher only reports the ones made by others.
+pub struct PlaintextStore {
+    location: PathBuf,
+    locks: PathBuf,
+    own_writes: OwnWrites,
+}
+
+/// Held for as long as a note is being changed, the lock is released when the file is closed.
+struct NoteLock(File);
+
+impl Drop for NoteLock {
+    fn drop(&mut self) {
+        self.0.unlock().ok();
+    }
+}
+
+/// Gives every note path a short, stable lock file name.
+fn lock_name(path: &Path) -> String {
+    format!("{:016x}.lock", fnv_hash(path.to_string_lossy().as_bytes()))
+}
+
+/// Flushes a directory entry change (create or rename) to disk.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+fn write_synced(temp_path: &Path, path: &Path, content: &str) -> io::Result<()> {
+    let mut file = File::create(temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)?;
+    sync_dir(path.parent().unwrap())
+}
+
+/// Writes `content` to a temporary file next to `path`, syncs it and then renames it into place.
+fn atomic_write(path: &Path, content: &str) -> io::Result<()> {
+    let parent = path.parent().unwrap();
+    fs::create_dir_all(parent)?;
+    // The temporary file doesn't end in `.txt` or `.md`, so it never shows up as a note.
+    let temp_path = parent.join(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap().to_string_lossy(),
+        process::id()
+    ));
+    let result = write_synced(&temp_path, path, content);
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+    result
+}
+
+/// Reads a note file. Markdown written by other tools may not have front matter, it gets an empty
+/// one so the note is still read as Markdown.
+fn read_note_file(path: &Path) -> io::Result<String> {
+    let content = fs::read_to_string(path)?;
+    let is_markdown = matches!(path.extension(), Some(ext) if ext == MARKDOWN_EXTENSION);
+    if is_markdown && !has_front_matter(&content) && !is_sealed_note(&content) {
+        return Ok(format!("---\n---\n\n{}", content));
+    }
+    Ok(content)
+}
+
+fn is_note_file(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == WIKITEXT_EXTENSION || ext == MARKDOWN_EXTENSION)
+}
+
+/// The path of a note on disk, in whichever format it's stored. Changing a note's format writes
+/// the new file before removing the old one, if that's cut short by a crash the file written last
+/// is the note.
+fn existing_note(root: &Path, title: &str) -> Option<PathBuf> {
+    [MARKDOWN_EXTENSION, WIKITEXT_EXTENSION]
+        .iter()
+        .map(|extension| root.join(title).with_extension(extension))
+        .filter_map(|path| {
+            let metadata = fs::metadata(&path).ok().filter(|m| m.is_file())?;
+            Some((metadata.modified().ok()?, path))
+        })
+        .reduce(|newest, other| if other.0 > newest.0 { other } else { newest })
+        .map(|(_, path)| path)
+}
+
+/// Whether `file` is still the file at `path`, and not one that has been removed since it was
+/// opened.
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Open files can't be removed on other platforms, so the file is still there.
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// The path relative to the wiki directory, `None` for paths outside of it or inside `.git`.
+fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) if part != ".git" => parts.push(part.to_string_lossy()),
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+fn title_of(root: &Path, path: &Path) -> Option<String> {
+    if !is_note_file(path) {
+        return None;
+    }
+    relative_path(root, &path.with_extension(""))
+}
+
+impl PlaintextStore {
+    pub fn new(location: PathBuf, locks: PathBuf) -> Self {
+        Self {
+            location,
+            locks,
+            own_writes: OwnWrites::default(),
+        }
+    }
+
+    fn lock_path(&self, title: &str) -> Result<PathBuf, InvalidTitle> {
+        let path = title_path(&self.location, title)?;
+        Ok(self.locks.join(lock_name(&path)))
+    }
+
+    fn lock(&self, title: &str) -> Result<NoteLock, StoreError> {
+        let lock_path = self.lock_path(title)?;
+        fs::create_dir_all(&self.locks)?;
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)?;
+            file.lock_exclusive()?;
+            // The lock file may have been removed along with its note while we waited, the lock
+            // is then taken on the file that replaces it.
+            if is_same_file(&file, &lock_path) {
+                return Ok(NoteLock(file));
+            }
+        }
+    }
+
+    /// Removes the lock file of a note that was deleted or moved, while its lock is still held.
+    fn remove_lock(&self, title: &str) {
+        if let Ok(path) = self.lock_path(title) {
+            fs::remove_file(path).ok();
+        }
+    }
+
+    /// Locks two notes, always in the same order so two renames can't deadlock each other.
+    fn lock_pair(&self, first: &str, second: &str) -> Result<(NoteLock, NoteLock), StoreError> {
+        if first <= second {
+            let a = self.lock(first)?;
+            Ok((a, self.lock(second)?))
+        } else {
+            let b = self.lock(second)?;
+            Ok((self.lock(first)?, b))
+        }
+    }
+
+    /// Moves a note that's already locked. `rename` never replaces an existing note since the
+    /// target is checked under its lock first, and the move itself is a single atomic rename.
+    fn move_locked(&self, old_title: &str, new_title: &str) -> Result<(), StoreError> {
+        let old_path = self.path(old_title)?;
+        if self.exists(new_title) {
+            return Err(StoreError::AlreadyExists);
+        }
+        if !old_path.exists() {
+            return Err(StoreError::NotFound);
+        }
+        // The note keeps its format.
+        let extension = old_path.extension().unwrap().to_string_lossy();
+        let new_path = self.path_with(new_title, &extension)?;
+        let new_parent = new_path.parent().unwrap();
+        fs::create_dir_all(new_parent)?;
+        self.own_writes.removed(old_title);
+        self.own_writes.wrote(new_title, &fs::read(&old_path)?);
+        fs::rename(&old_path, &new_path).map_err(map_not_found)?;
+        self.remove_other_files(old_title, None)?;
+        sync_dir(new_parent)?;
+        sync_dir(old_path.parent().unwrap())?;
+        self.prune_empty_dirs(&old_path);
+        self.remove_lock(old_title);
+        Ok(())
+    }
+
+    fn path_with(&self, title: &str, extension: &str) -> Result<PathBuf, InvalidTitle> {
+        let mut file_path = title_path(&self.location, title)?;
+        file_path.set_extension(extension);
+        Ok(file_path)
+    }
+
+    /// Where a note is on disk, or would be as a wikitext note if it doesn't exist yet.
+    fn path(&self, title: &str) -> Result<PathBuf, InvalidTitle> {
+        let wikitext = self.path_with(title, WIKITEXT_EXTENSION)?;
+        Ok(existing_note(&self.location, title).unwrap_or(wikitext))
+    }
+
+    /// Writes a note that's already locked. Notes with front matter are saved as `.md` files, so
+    /// changing the `content-type` of a note moves it to the other extension. Encrypted notes keep
+    /// the extension they have. The file in the other format is removed once the new one is in
+    /// place, see [existing_note] for a crash in between.
+    fn write_locked(&self, title: &str, content: &str) -> io::Result<()> {
+        let path = if is_sealed_note(content) {
+            self.path(title)?
+        } else if has_front_matter(content) {
+            self.path_with(title, MARKDOWN_EXTENSION)?
+        } else {
+            self.path_with(title, WIKITEXT_EXTENSION)?
+        };
+        self.own_writes.wrote(title, content.as_bytes());
+        atomic_write(&path, content)?;
+        self.remove_other_files(title, Some(&path))
+    }
+
+    /// Removes the files of a note other than `keep`, like the one in the old format once a note
+    /// changed format.
+    fn remove_other_files(&self, title: &str, keep: Option<&Path>) -> io::Result<()> {
+        for extension in [MARKDOWN_EXTENSION, WIKITEXT_EXTENSION] {
+            let path = self.path_with(title, extension)?;
+            if Some(path.as_path()) != keep && path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes namespace directories left empty after a note was moved out of them or deleted.
+    fn prune_empty_dirs(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if current == self.location || !current.starts_with(&self.location) {
+                break;
+            }
+            // `remove_dir` fails on non-empty directories, which is where we want to stop anyway.
+            if fs::remove_dir(current).is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+}
+
+fn map_not_found(e: io::Error) -> StoreError {
+    match e.kind() {
+        io::ErrorKind::NotFound => StoreError::NotFound,
+        _ => StoreError::Io(e),
+    }
+}
+
+/// Recursively collects note titles, skipping the `.git` directory.
+fn walk(root: &Path, dir: &Path, titles: &mut HashSet<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            if entry.file_name() != ".git" {
+                walk(root, &path, titles)?;
+            }
+        } else if file_type.is_file() {
+            titles.extend(title_of(root, &path));
+        }
+    }
+    Ok(())
+}
+
+/// Works out which notes a batch of filesystem events changed by looking at what's on disk now,
+/// since the events of a single save vary from editor to editor. `known` holds every note seen
+/// so far, which is how the notes in a removed directory are found.
+fn changes(
+    root: &Path,
+    known: &mut HashSet<String>,
+    batch: Vec<notify::Result<Event>>,
+) -> Vec<StoreEvent> {
+    let mut renames = Vec::new();
+    let mut titles = BTreeSet::new();
+    for event in batch {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("Error while watching wiki directory: {}", e);
+                continue;
+            }
+        };
+        if let (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to]) =
+            (event.kind, event.paths.as_slice())
+        {
+            if let (Some(from), Some(to)) = (title_of(root, from), title_of(root, to)) {
+                renames.push((from, to));
+            }
+        }
+        for path in event.paths {
+            let relative = match relative_path(root, &path) {
+                Some(relative) => relative,
+                None => continue,
+            };
+            if path.is_dir() {
+                let mut added = HashSet::new();
+                walk(root, &path, &mut added).ok();
+                titles.extend(added);
+            } else if let Some(title) = title_of(root, &path) {
+                titles.insert(title);
+            } else if !path.exists() {
+                // Possibly a directory that was removed or moved away.
+                let prefix = format!("{}/", relative);
+                titles.extend(known.iter().filter(|t| t.starts_with(&prefix)).cloned());
+            }
+        }
+    }
+    let mut events = Vec::new();
+    for (from, to) in renames {
+        if existing_note(root, &from).is_none() && existing_note(root, &to).is_some() {
+            titles.remove(&from);
+            titles.remove(&to);
+            known.remove(&from);
+            known.insert(to.clone());
+            events.push(StoreEvent::Renamed { from, to });
+        }
+    }
+    for title in titles {
+        if existing_note(root, &title).is_some() {
+            known.insert(title.clone());
+            events.push(StoreEvent::Changed(title));
+        } else if known.remove(&title) {
+            events.push(StoreEvent::Removed(title));
+        }
+    }
+    events
+}
+
+/// Whether the notes are as this process left them.
+fn is_own_change(root: &Path, own_writes: &OwnWrites, event: &StoreEvent) -> bool {
+    let content = |title: &str| existing_note(root, title).and_then(|path| fs::read(path).ok());
+    match event {
+        StoreEvent::Changed(title) => own_writes.is_own(title, content(title).as_deref()),
+        StoreEvent::Removed(title) => own_writes.is_own(title, None),
+        StoreEvent::Renamed { from, to } => {
+            // Check both so neither record is left behind.
+            let moved_away = own_writes.is_own(from, None);
+            own_writes.is_own(to, content(to).as_deref()) && moved_away
+        }
+    }
+}
+
+impl NoteStore for PlaintextStore {
+    fn read(&self, title: &str) -> Result<String, StoreError> {
+        read_note_file(&self.path(title)?).map_err(map_not_found)
+    }
+
+    fn write(&self, title: &str, content: &str) -> Result<(), StoreError> {
+        let _lock = self.lock(title)?;
+        self.write_locked(title, content)?;
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        title: &str,
+        apply: &mut dyn FnMut(Option<String>) -> Result<String, StoreError>,
+    ) -> Result<String, StoreError> {
+        let _lock = self.lock(title)?;
+        let current = match read_note_file(&self.path(title)?) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let updated = apply(current)?;
+        self.write_locked(title, &updated)?;
+        Ok(updated)
+    }
+
+    fn rename(&self, old_title: &str, new_title: &str) -> Result<(), StoreError> {
+        if old_title == new_title {
+            return Err(StoreError::AlreadyExists);
+        }
+        let _locks = self.lock_pair(old_title, new_title)?;
+        self.move_locked(old_title, new_title)
+    }
+
+    fn rename_and_write(
+        &self,
+        old_title: &str,
+        new_title: &str,
+        apply: &mut dyn FnMut(String) -> Result<String, StoreError>,
+    ) -> Result<(), StoreError> {
+        if old_title == new_title {
+            return self
+                .update(new_title, &mut |current| {
+                    apply(current.ok_or(StoreError::NotFound)?)
+                })
+                .map(|_| ());
+        }
+        let _locks = self.lock_pair(old_title, new_title)?;
+        if self.exists(new_title) {
+            return Err(StoreError::AlreadyExists);
+        }
+        let current = read_note_file(&self.path(old_title)?).map_err(map_not_found)?;
+        let content = apply(current)?;
+        // Update the content in place first, a crash before the move still leaves exactly one
+        // copy of the note.
+        self.write_locked(old_title, &content)?;
+        self.move_locked(old_title, new_title)
+    }
+
+    fn delete(&self, title: &str) -> Result<(), StoreError> {
+        let _lock = self.lock(title)?;
+        let path = self.path(title)?;
+        self.own_writes.removed(title);
+        fs::remove_file(&path).map_err(map_not_found)?;
+        self.remove_other_files(title, None)?;
+        self.prune_empty_dirs(&path);
+        self.remove_lock(title);
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let mut titles = HashSet::new();
+        walk(&self.location, &self.location, &mut titles)?;
+        Ok(titles.into_iter().collect())
+    }
+
+    fn exists(&self, title: &str) -> bool {
+        self.path(title).is_ok_and(|path| path.exists())
+    }
+
+    fn watch(&self) -> Receiver<StoreEvent> {
+        let (sender, receiver) = channel();
+        let location = self.location.clone();
+        let own_writes = self.own_writes.clone();
+        thread::spawn(move || {
+            let (notify_sender, notifications) = channel();
+            // Watching stops when the watcher is dropped, so it lives as long as this thread.
+            let mut watcher = match notify::recommended_watcher(notify_sender) {
+                Ok(watcher) => watcher,
+                Err(e) => {
+                    eprintln!("Could not watch wiki directory: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = watcher.watch(&location, RecursiveMode::Recursive) {
+                eprintln!("Could not watch wiki directory: {}", e);
+                return;
+            }
+            let mut known = HashSet::new();
+            walk(&location, &location, &mut known).ok();
+            while let Ok(first) = notifications.recv() {
+                let started = Instant::now();
+                let mut batch = vec![first];
+                while started.elapsed() < MAX_BATCH {
+                    match notifications.recv_timeout(DEBOUNCE) {
+                        Ok(event) => batch.push(event),
+                        Err(_) => break,
+                    }
+                }
+                for event in changes(&location, &mut known, batch) {
+                    if is_own_change(&location, &own_writes, &event) {
+                        continue;
+                    }
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver},
+        Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+use crate::fs::utils::validate_title;
+
+use super::{NoteStore, OwnWrites, StoreError, StoreEvent};
+
+pub(crate) const DB_FILE_NAME: &str = "tendril.sqlite";
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+/// How long to wait for another process (e.g. the CLI) to finish its write.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Backend(e.to_string())
+    }
+}
+
+/// Keeps every note in a single embedded SQLite database.
+pub struct SqliteStore {
+    location: PathBuf,
+    connection: Mutex<Connection>,
+    own_writes: OwnWrites,
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i64)
+        .unwrap_or_default()
+}
+
+fn title_taken(transaction: &Transaction, title: &str) -> Result<bool, rusqlite::Error> {
+    let taken: Option<i64> = transaction
+        .query_row(
+            "SELECT 1 FROM notes WHERE title = ?1",
+            params![title],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(taken.is_some())
+}
+
+fn move_note(
+    transaction: &Transaction,
+    old_title: &str,
+    new_title: &str,
+) -> Result<(), StoreError> {
+    if title_taken(transaction, new_title)? {
+        return Err(StoreError::AlreadyExists);
+    }
+    let updated = transaction.execute(
+        "UPDATE notes SET title = ?1, modified = ?2 WHERE title = ?3",
+        params![new_title, now(), old_title],
+    )?;
+    if updated == 0 {
+        return Err(StoreError::NotFound);
+    }
+    Ok(())
+}
+
+fn content_of(connection: &Connection, title: &str) -> Result<Option<String>, rusqlite::Error> {
+    connection
+        .query_row(
+            "SELECT content FROM notes WHERE title = ?1",
+            params![title],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+fn modified_times(connection: &Connection) -> Result<HashMap<String, i64>, rusqlite::Error> {
+    let mut statement = connection.prepare("SELECT title, modified FROM notes")?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+impl SqliteStore {
+    pub fn open(location: PathBuf) -> Result<Self, StoreError> {
+        let connection = Connection::open(&location)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS notes (
+                title TEXT PRIMARY KEY NOT NULL,
+                content TEXT NOT NULL,
+                modified INTEGER NOT NULL
+            );",
+        )?;
+        Ok(Self {
+            location,
+            connection: Mutex::new(connection),
+            own_writes: OwnWrites::default(),
+        })
+    }
+}
+
+impl NoteStore for SqliteStore {
+    fn read(&self, title: &str) -> Result<String, StoreError> {
+        let connection = self.connection.lock().unwrap();
+        content_of(&connection, title)?.ok_or(StoreError::NotFound)
+    }
+
+    fn write(&self, title: &str, content: &str) -> Result<(), StoreError> {
+        validate_title(title)?;
+        let connection = self.connection.lock().unwrap();
+        self.own_writes.wrote(title, content.as_bytes());
+        connection.execute(
+            "INSERT INTO notes (title, content, modified) VALUES (?1, ?2, ?3)
+             ON CONFLICT(title) DO UPDATE SET content = excluded.content, modified = excluded.modified",
+            params![title, content, now()],
+        )?;
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        title: &str,
+        apply: &mut dyn FnMut(Option<String>) -> Result<String, StoreError>,
+    ) -> Result<String, StoreError> {
+        validate_title(title)?;
+        let mut connection = self.connection.lock().unwrap();
+        // Take the write lock up front so another process can't change the note between the
+        // read and the write.
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current = content_of(&transaction, title)?;
+        // Returning early drops the transaction, which rolls it back.
+        let updated = apply(current)?;
+        self.own_writes.wrote(title, updated.as_bytes());
+        transaction.execute(
+            "INSERT INTO notes (title, content, modified) VALUES (?1, ?2, ?3)
+             ON CONFLICT(title) DO UPDATE SET content = excluded.content, modified = excluded.modified",
+            params![title, updated, now()],
+        )?;
+        transaction.commit()?;
+        Ok(updated)
+    }
+
+    fn rename(&self, old_title: &str, new_title: &str) -> Result<(), StoreError> {
+        validate_title(new_title)?;
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        move_note(&transaction, old_title, new_title)?;
+        if let Some(content) = content_of(&transaction, new_title)? {
+            self.own_writes.removed(old_title);
+            self.own_writes.wrote(new_title, content.as_bytes());
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn rename_and_write(
+        &self,
+        old_title: &str,
+        new_title: &str,
+        apply: &mut dyn FnMut(String) -> Result<String, StoreError>,
+    ) -> Result<(), StoreError> {
+        if old_title == new_title {
+            return self
+                .update(new_title, &mut |current| {
+                    apply(current.ok_or(StoreError::NotFound)?)
+                })
+                .map(|_| ());
+        }
+        validate_title(new_title)?;
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current = content_of(&transaction, old_title)?.ok_or(StoreError::NotFound)?;
+        let content = apply(current)?;
+        move_note(&transaction, old_title, new_title)?;
+        transaction.execute(
+            "UPDATE notes SET content = ?1 WHERE title = ?2",
+            params![content, new_title],
+        )?;
+        self.own_writes.removed(old_title);
+        self.own_writes.wrote(new_title, content.as_bytes());
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete(&self, title: &str) -> Result<(), StoreError> {
+        let connection = self.connection.lock().unwrap();
+        self.own_writes.removed(title);
+        let deleted = connection.execute("DELETE FROM notes WHERE title = ?1", params![title])?;
+        if deleted == 0 {
+            return Err(StoreError::NotFound);
+        }
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>, StoreError> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare("SELECT title FROM notes")?;
+        let titles = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+        Ok(titles)
+    }
+
+    fn exists(&self, title: &str) -> bool {
+        let connection = self.connection.lock().unwrap();
+        let found = connection
+            .query_row(
+                "SELECT 1 FROM notes WHERE title = ?1",
+                params![title],
+                |_| Ok(()),
+            )
+            .optional();
+        matches!(found, Ok(Some(())))
+    }
+
+    fn watch(&self) -> Receiver<StoreEvent> {
+        let (sender, receiver) = channel();
+        let location = self.location.clone();
+        let own_writes = self.own_writes.clone();
+        thread::spawn(move || {
+            // Use a separate connection so polling never blocks reads and writes.
+            let connection = match Connection::open(&location) {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("Could not watch note database: {}", e);
+                    return;
+                }
+            };
+            connection.busy_timeout(BUSY_TIMEOUT).ok();
+            let mut known = modified_times(&connection).unwrap_or_default();
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                let current = match modified_times(&connection) {
+                    Ok(current) => current,
+                    Err(e) => {
+                        eprintln!("Could not poll note database: {}", e);
+                        continue;
+                    }
+                };
+                let mut events = Vec::new();
+                for (title, modified) in current.iter() {
+                    if known.get(title) != Some(modified) {
+                        let content = content_of(&connection, title).ok().flatten();
+                        let content = content.as_ref().map(|content| content.as_bytes());
+                        if !own_writes.is_own(title, content) {
+                            events.push(StoreEvent::Changed(title.clone()));
+                        }
+                    }
+                }
+                for title in known.keys() {
+                    if !current.contains_key(title) && !own_writes.is_own(title, None) {
+                        events.push(StoreEvent::Removed(title.clone()));
+                    }
+                }
+                for event in events {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+                known = current;
+            }
+        });
+        receiver
+    }
+}
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use std::fmt::Write as _;
+use wikitext::parsers::format_links;
+
+use crate::{get_template_file, render_includes, Render};
+
+pub struct BibliographyEntry {
+    pub key: String,
+    /// The formatted reference, already rendered to HTML.
+    pub reference: String,
+    pub cited_by: Vec<String>,
+}
+
+pub struct BibliographyPage {
+    pub entries: Vec<BibliographyEntry>,
+    pub error: Option<String>,
+}
+
+impl BibliographyPage {
+    fn render_entries(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!(
+                "<p class=\"bibliography-error\">{}</p>",
+                error.replace('<', "&lt;").replace('>', "&gt;")
+            );
+        }
+        if self.entries.is_empty() {
+            return String::from("<p>No entries found in the bibliography file.</p>");
+        }
+        let mut html = String::from("<ol class=\"references\">");
+        for entry in self.entries.iter() {
+            write!(
+                html,
+                "<li id=\"ref-{}\"><code>@{}</code> {}",
+                entry.key, entry.key, entry.reference
+            )
+            .unwrap();
+            if !entry.cited_by.is_empty() {
+                let notes = entry
+                    .cited_by
+                    .iter()
+                    .map(|n| format!("<a href=\"{}\">{}</a>", format_links(n), n))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(html, "<div class=\"cited-by\">Cited in: {}</div>", notes).unwrap();
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ol>");
+        html
+    }
+}
+
+#[async_trait]
+impl Render for BibliographyPage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("bibliography").await.unwrap();
+        let nav = get_template_file("nav").await.unwrap();
+        ctx = ctx.replace("<%= content %>", &self.render_entries());
+        render_includes(ctx, None).await.replace("<%= nav %>", &nav)
+    }
+}
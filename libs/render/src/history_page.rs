@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use std::fmt::Write as _;
+use wikitext::parsers::{base_path, format_links};
+
+use crate::{get_template_file, render_includes, Render};
+
+pub struct HistoryEntry {
+    pub id: String,
+    /// When the revision was replaced, formatted for display.
+    pub saved: String,
+}
+
+pub enum DiffLine {
+    Unchanged(String),
+    Added(String),
+    Removed(String),
+    /// Unchanged lines that were left out between two changed sections.
+    Skipped,
+}
+
+pub struct RevisionDiff {
+    pub from: String,
+    pub to: String,
+    pub lines: Vec<DiffLine>,
+}
+
+/// Lists the saved revisions of a note, optionally showing the changes between two of them.
+pub struct HistoryPage {
+    pub title: String,
+    /// Newest first.
+    pub entries: Vec<HistoryEntry>,
+    pub diff: Option<RevisionDiff>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl HistoryPage {
+    fn history_link(&self, from: &str, to: &str) -> String {
+        format!(
+            "{}?from={}&to={}",
+            format_links(&format!("history/{}", self.title)),
+            from,
+            to
+        )
+    }
+
+    fn render_entries(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("<p>No earlier revisions of this note have been saved.</p>");
+        }
+        let mut html = String::from(
+            "<table class=\"history\"><thead><tr><th>Saved</th><th>Changes</th><th></th></tr></thead><tbody>",
+        );
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let previous = match self.entries.get(idx + 1) {
+                Some(previous) => format!(
+                    " <a href=\"{}\">previous</a>",
+                    self.history_link(&previous.id, &entry.id)
+                ),
+                None => String::with_capacity(0),
+            };
+            write!(
+                html,
+                r#"<tr><td>{}</td><td><a href="{}">current</a>{}</td><td><form method="post" action="{}/history/restore"><input type="hidden" name="title" value="{}" /><input type="hidden" name="id" value="{}" /><button type="submit">restore</button></form></td></tr>"#,
+                entry.saved,
+                self.history_link(&entry.id, "current"),
+                previous,
+                base_path(),
+                escape(&self.title).replace('"', "&quot;"),
+                entry.id
+            )
+            .unwrap();
+        }
+        html.push_str("</tbody></table>");
+        html
+    }
+
+    fn render_diff(&self) -> String {
+        let diff = match &self.diff {
+            Some(diff) => diff,
+            None => return String::with_capacity(0),
+        };
+        let mut html = format!(
+            "<h3>Changes from {} to {}</h3><pre class=\"diff\">",
+            diff.from, diff.to
+        );
+        if diff.lines.is_empty() {
+            html.push_str("No changes.");
+        }
+        for line in diff.lines.iter() {
+            match line {
+                DiffLine::Unchanged(text) => {
+                    write!(html, "<span>  {}</span>", escape(text)).unwrap()
+                }
+                DiffLine::Added(text) => write!(html, "<ins>+ {}</ins>", escape(text)).unwrap(),
+                DiffLine::Removed(text) => write!(html, "<del>- {}</del>", escape(text)).unwrap(),
+                DiffLine::Skipped => html.push_str("<span class=\"skipped\">⋯</span>"),
+            }
+            html.push('\n');
+        }
+        html.push_str("</pre>");
+        html
+    }
+}
+
+#[async_trait]
+impl Render for HistoryPage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("history").await.unwrap();
+        let nav = get_template_file("nav").await.unwrap();
+        ctx = ctx
+            .replace("<%= note_link %>", &format_links(&self.title))
+            .replace("<%= diff %>", &self.render_diff())
+            .replace("<%= content %>", &self.render_entries())
+            .replace("<%= title %>", &escape(&self.title));
+        render_includes(ctx, None).await.replace("<%= nav %>", &nav)
+    }
+}
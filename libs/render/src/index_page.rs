@@ -2,8 +2,12 @@ use crate::{
     get_template_file, render_includes, render_page_backlinks, render_page_metadata, Render,
 };
 use async_trait::async_trait;
-use persistance::fs::{config::read_config, ReadPageError};
-use wikitext::GlobalBacklinks;
+use persistance::fs::{
+    config::read_config,
+    journal::{journal_nav, journal_title, today, Period},
+    ReadPageError,
+};
+use wikitext::{parsers::format_links, GlobalBacklinks};
 
 pub struct IndexPage {
     pub user: String,
@@ -14,9 +18,7 @@ pub struct IndexPage {
 
 impl IndexPage {
     pub fn new(user: String, host: String, links: GlobalBacklinks) -> Self {
-        use chrono::Local;
-        let now = Local::now();
-        let today = now.format("%Y-%m-%d").to_string();
+        let today = journal_title(Period::Day, today());
         Self {
             user,
             host,
@@ -32,8 +34,36 @@ impl IndexPage {
             String::with_capacity(0)
         }
     }
+    /// Links to yesterday's and tomorrow's notes, and the week and month today is in.
+    fn render_journal_nav(&self) -> String {
+        let nav = journal_nav(today());
+        let mut links = vec![format!(
+            "<a href=\"{}\">‹ {}</a>",
+            format_links(&nav.previous_day),
+            nav.previous_day
+        )];
+        for period in [nav.week, nav.month].iter().flatten() {
+            links.push(format!(
+                "<a href=\"{}\">{}</a>",
+                format_links(period),
+                period
+            ));
+        }
+        links.push(format!(
+            "<a href=\"{}\">{} ›</a>",
+            format_links(&nav.next_day),
+            nav.next_day
+        ));
+        format!(
+            "<nav class=\"breadcrumbs journal-nav\">{}</nav>",
+            links.join(" · ")
+        )
+    }
     async fn render_today(&self) -> String {
-        let mut content = get_template_file("content").await.unwrap();
+        let mut content = get_template_file("content")
+            .await
+            .unwrap()
+            .replace("<%= breadcrumbs %>", &self.render_journal_nav());
         match persistance::fs::read(self.today.clone()).await {
             Ok(note) => {
                 let templatted = note.to_template();
@@ -50,7 +80,7 @@ impl IndexPage {
                     .page
                     .tags
                     .iter()
-                    .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", t, t))
+                    .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", format_links(t), t))
                     .collect::<Vec<String>>()
                     .join("\n");
                 content = content
@@ -1,10 +1,13 @@
 use async_trait::async_trait;
 
-use wikitext::{parsers::TemplattedPage, processors::sanitize_html};
+use wikitext::{
+    parsers::{format_links, TemplattedPage},
+    processors::sanitize_html,
+};
 
 use crate::{
-    get_template_file, render_includes, render_page_backlinks, render_page_metadata,
-    PageRenderLinks, Render,
+    get_template_file, render_includes, render_page_backlinks, render_page_breadcrumbs,
+    render_page_metadata, PageRenderLinks, Render,
 };
 
 pub struct InjectedHTML<'a> {
@@ -31,7 +34,7 @@ impl<'a> Render for InjectedHTML<'a> {
         let tag_string = page
             .tags
             .iter()
-            .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", t, t))
+            .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", format_links(t), t))
             .collect::<Vec<String>>()
             .join("\n");
         let mut ctx = get_template_file("raw_html").await.unwrap();
@@ -39,6 +42,7 @@ impl<'a> Render for InjectedHTML<'a> {
         let nav = get_template_file("nav").await.unwrap();
         ctx = ctx
             .replace("<%= content %>", &content)
+            .replace("<%= breadcrumbs %>", &render_page_breadcrumbs(&page.title))
             .replace("<%= body %>", &sanitize_html(&page.body))
             .replace("<%= tags %>", &tag_string)
             .replace("<%= links %>", &render_page_backlinks(backlinks))
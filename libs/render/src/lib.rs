@@ -9,22 +9,27 @@ use directories::ProjectDirs;
 use async_trait::async_trait;
 use futures::{stream, StreamExt};
 use tokio::fs;
-use wikitext::parsers::{format_links, TemplattedPage};
+use wikitext::parsers::{base_path, format_links, TemplattedPage};
 
 pub mod all_pages;
+pub mod bibliography_page;
 pub mod bookmark_page;
 pub mod error_page;
 pub mod file_upload_page;
 pub mod help_page;
+pub mod history_page;
 pub mod index_page;
 pub mod injected_html;
 pub mod login_page;
+pub mod namespace_page;
 pub mod new_page;
 pub mod opensearch_page;
+pub mod orphaned_files_page;
 pub mod search_results_page;
 pub mod static_site_page;
 pub mod styles_page;
 pub mod tasks_page;
+pub mod trash_page;
 pub mod uploaded_files_page;
 pub mod wiki_page;
 
@@ -58,7 +63,7 @@ async fn process_included_file(file: String, page: Option<&TemplattedPage>) -> S
             let templatefile = get_template_file("meta").await.unwrap();
             let page = page.unwrap();
             let icon_path = match &page.metadata.get("icon") {
-                Some(icon) => format!("/files/{}", icon),
+                Some(icon) => format_links(&format!("files:{}", icon.trim_start_matches("files:"))),
                 None => String::from("static/favicon.ico"),
             };
             templatefile
@@ -84,10 +89,12 @@ pub async fn render_includes(ctx: String, page: Option<&TemplattedPage>) -> Stri
     collected.join("\n")
 }
 
+/// Reads a template, with `<%= base %>` replaced by the path the wiki is served under so its
+/// links work behind the notebook hub too.
 pub async fn get_template_file(requested_file: &str) -> Result<String, io::Error> {
     let file_path = get_template_location(requested_file);
     if let Ok(filestring) = fs::read_to_string(&file_path).await {
-        Ok(filestring)
+        Ok(filestring.replace("<%= base %>", &base_path()))
     } else {
         eprintln!("Could not find {}", requested_file);
         Err(io::Error::new(
@@ -119,6 +126,9 @@ pub fn render_page_metadata(metadata: HashMap<String, String>) -> String {
                 if value.starts_with("http") || value.starts_with("file://") {
                     let val = format!("<img src=\"{}\">", value);
                     write!(metadata_html, "<dd>{}</dd>", val).unwrap();
+                } else if value.starts_with("files:") {
+                    let val = format!("<img src=\"{}\">", format_links(value));
+                    write!(metadata_html, "<dd>{}</dd>", val).unwrap();
                 }
             }
             "isbn" => {
@@ -142,7 +152,6 @@ pub fn render_page_metadata(metadata: HashMap<String, String>) -> String {
     metadata_html
 }
 
-
 #[cfg(debug_assertions)]
 fn get_template_location(requested_file: &str) -> String {
     if requested_file.contains('.') {
@@ -173,6 +182,59 @@ pub fn render_page_backlinks(links: Vec<String>) -> String {
     }
 }
 
+/// Renders a trail of links to each parent namespace of a note, e.g. `Projects/Alpha/Notes`
+/// links to `Projects/` and `Projects/Alpha/`. Top level notes don't get breadcrumbs.
+pub fn render_page_breadcrumbs(title: &str) -> String {
+    let segments = title.split('/').collect::<Vec<&str>>();
+    if segments.len() < 2 {
+        return String::with_capacity(0);
+    }
+    let mut crumbs = Vec::with_capacity(segments.len());
+    for (idx, segment) in segments.iter().enumerate() {
+        if idx == segments.len() - 1 {
+            crumbs.push(segment.to_string());
+        } else {
+            let namespace = segments[..=idx].join("/");
+            crumbs.push(format!(
+                "<a href=\"{}/\">{}</a>",
+                format_links(&namespace),
+                segment
+            ));
+        }
+    }
+    format!("<nav class=\"breadcrumbs\">{}</nav>", crumbs.join(" / "))
+}
+
+/// Renders the reference list for a note from `(citation key, formatted reference)` pairs.
+/// Keys that couldn't be found in the bibliography have no reference.
+pub fn render_page_references(references: Vec<(String, Option<String>)>) -> String {
+    if !references.is_empty() {
+        let references_string = references
+            .iter()
+            .map(|(key, reference)| match reference {
+                Some(reference) => format!("<li id=\"ref-{}\">{}</li>", key, reference),
+                None => format!(
+                    "<li id=\"ref-{}\" class=\"missing-reference\">No entry for <code>@{}</code> in the bibliography</li>",
+                    key, key
+                ),
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+        format!(
+            r#"
+<section class="references-container">
+  <hr />
+  <h3>References</h3>
+  <ol class="references">{}</ol>
+</section>
+"#,
+            references_string
+        )
+    } else {
+        String::with_capacity(0)
+    }
+}
+
 #[cfg(not(debug_assertions))]
 fn get_template_location(requested_file: &str) -> String {
     let project_dir = ProjectDirs::from("", "", "tendril").unwrap();
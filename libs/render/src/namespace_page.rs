@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use std::fmt::Write as _;
+use wikitext::parsers::format_links;
+
+use crate::{get_template_file, render_includes, render_page_breadcrumbs, Render};
+
+/// Lists the direct children of a namespace, e.g. `Projects/` lists `Projects/Alpha/` and
+/// `Projects/Roadmap`.
+pub struct NamespacePage {
+    pub namespace: String,
+    /// Full names of the namespaces directly below this one.
+    pub namespaces: Vec<String>,
+    /// Full titles of the notes directly inside this namespace.
+    pub notes: Vec<String>,
+}
+
+impl NamespacePage {
+    fn basename(title: &str) -> &str {
+        title.rsplit('/').next().unwrap_or(title)
+    }
+
+    fn render_children(&self) -> String {
+        if self.namespaces.is_empty() && self.notes.is_empty() {
+            return String::from("<p>This namespace is empty.</p>");
+        }
+        let mut html = String::from("<ul class=\"namespace-list\">");
+        for namespace in self.namespaces.iter() {
+            write!(
+                html,
+                "<li class=\"namespace\"><a href=\"{}/\">{}/</a></li>",
+                format_links(namespace),
+                Self::basename(namespace)
+            )
+            .unwrap();
+        }
+        for note in self.notes.iter() {
+            write!(
+                html,
+                "<li><a href=\"{}\">{}</a></li>",
+                format_links(note),
+                Self::basename(note)
+            )
+            .unwrap();
+        }
+        html.push_str("</ul>");
+        html
+    }
+}
+
+#[async_trait]
+impl Render for NamespacePage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("namespace").await.unwrap();
+        let nav = get_template_file("nav").await.unwrap();
+        // Render the breadcrumbs as if the namespace were a note inside its parent.
+        let breadcrumbs = render_page_breadcrumbs(&self.namespace);
+        ctx = ctx
+            .replace("<%= title %>", &self.namespace)
+            .replace("<%= breadcrumbs %>", &breadcrumbs)
+            .replace("<%= content %>", &self.render_children());
+        render_includes(ctx, None).await.replace("<%= nav %>", &nav)
+    }
+}
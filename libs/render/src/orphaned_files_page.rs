@@ -0,0 +1,54 @@
+use crate::{
+    get_template_file, render_includes,
+    uploaded_files_page::{escape, format_size, UploadedFilesPage},
+    Render,
+};
+use async_trait::async_trait;
+use persistance::media::MediaFile;
+use std::fmt::Write as _;
+use wikitext::parsers::{base_path, format_links};
+
+/// Uploaded files that no note links to, embeds or uses as its icon or cover.
+pub struct OrphanedFilesPage {
+    pub entries: Vec<MediaFile>,
+}
+
+impl OrphanedFilesPage {
+    fn render_entries(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("<p>Every uploaded file is used by a note.</p>");
+        }
+        let total = self.entries.iter().map(|entry| entry.size).sum::<u64>();
+        let mut entry_list = format!(
+            r#"<form method="post" action="{}/files/orphans/delete" onsubmit="return confirm('Delete the selected files? This cannot be undone.')"><p>{} unused files, {} in total.</p><div class="gallery">"#,
+            base_path(),
+            self.entries.len(),
+            format_size(total)
+        );
+        for entry in &self.entries {
+            let link = format_links(&format!("files:{}", entry.name));
+            let name = escape(&entry.name);
+            write!(
+                entry_list,
+                r#"<figure class="media-card"><a href="{link}">{preview}</a><figcaption><label><input type="checkbox" name="name" value="{name}" checked /> {name}</label><span class="media-meta">{size} · {content_type}</span></figcaption></figure>"#,
+                link = link,
+                preview = UploadedFilesPage::render_preview(entry, &link),
+                name = name,
+                size = format_size(entry.size),
+                content_type = escape(&entry.content_type),
+            )
+            .unwrap();
+        }
+        entry_list.push_str(r#"</div><button type="submit">delete selected</button></form>"#);
+        entry_list
+    }
+}
+
+#[async_trait]
+impl Render for OrphanedFilesPage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("orphaned_files").await.unwrap();
+        ctx = ctx.replace("<%= entries %>", &self.render_entries());
+        render_includes(ctx, None).await
+    }
+}
@@ -2,14 +2,17 @@ use async_trait::async_trait;
 use std::{fmt::Write as _, time::Duration};
 use wikitext::parsers::format_links;
 
-use crate::{get_template_file, render_includes, Render};
+use crate::{get_template_file, render_includes, uploaded_files_page::escape, Render};
 
-type SearchResult = Vec<String>;
+/// Titles of the notes found, each with its passages that best match the search.
+type SearchResult = Vec<(String, Vec<String>)>;
 
 pub struct SearchResultsPage {
     pub pages: SearchResult,
     pub num_results: usize,
     pub time: Duration,
+    /// Why the query could not be run, shown instead of the results.
+    pub error: Option<String>,
 }
 
 impl SearchResultsPage {
@@ -18,6 +21,7 @@ impl SearchResultsPage {
             pages,
             num_results,
             time,
+            error: None,
         }
     }
     async fn render_pages(&self) -> String {
@@ -25,18 +29,27 @@ impl SearchResultsPage {
             return String::with_capacity(0);
         }
         let mut page_list = String::new();
-        for page in self.pages.iter() {
+        for (page, snippets) in self.pages.iter() {
+            // Snippets are escaped when they're made, apart from the <mark>s around matches.
+            let snippets = snippets
+                .iter()
+                .map(|snippet| format!("<p>{}</p>", snippet))
+                .collect::<String>();
             write!(
                 page_list,
-                "<li><div class=\"result\"><h2><a href=\"{}\">{}</a></h2><button class=\"expand\">&#9660;</button></div></li>",
+                "<li><div class=\"result\"><h2><a href=\"{}\">{}</a></h2><button class=\"expand\">&#9660;</button></div><div class=\"snippets\">{}</div></li>",
                 format_links(page),
                 page,
+                snippets,
             )
             .unwrap();
         }
         page_list
     }
     fn render_result_header(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("<h3>{}</h3>", escape(error));
+        }
         if self.pages.is_empty() {
             return String::from("<h3>No search results.</h3>");
         }
@@ -1,10 +1,10 @@
 use async_trait::async_trait;
 
-use wikitext::parsers::TemplattedPage;
+use wikitext::parsers::{format_links, TemplattedPage};
 
 use crate::{
-    get_template_file, render_includes, render_page_backlinks, render_page_metadata,
-    PageRenderLinks, Render,
+    get_template_file, render_includes, render_page_backlinks, render_page_breadcrumbs,
+    render_page_metadata, PageRenderLinks, Render,
 };
 
 pub struct StaticSitePage<'a> {
@@ -31,13 +31,14 @@ impl<'a> Render for StaticSitePage<'a> {
         let tag_string = page
             .tags
             .iter()
-            .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", t, t))
+            .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", format_links(t), t))
             .collect::<Vec<String>>()
             .join("\n");
         let mut ctx = get_template_file("static_site").await.unwrap();
         let content = get_template_file("content").await.unwrap();
         ctx = ctx
             .replace("<%= content %>", &content)
+            .replace("<%= breadcrumbs %>", &render_page_breadcrumbs(&page.title))
             .replace("<%= body %>", &page.body)
             .replace("<%= tags %>", &tag_string)
             .replace("<%= links %>", &render_page_backlinks(backlinks))
@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use std::fmt::Write as _;
+use wikitext::parsers::base_path;
+
+use crate::{get_template_file, render_includes, Render};
+
+pub struct TrashEntry {
+    pub id: String,
+    pub title: String,
+    /// When the note was deleted, formatted for display.
+    pub deleted: String,
+    /// When the note will be purged, formatted for display.
+    pub expires: String,
+}
+
+/// Lists deleted notes that can still be restored.
+pub struct TrashPage {
+    /// Most recently deleted first.
+    pub entries: Vec<TrashEntry>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl TrashPage {
+    fn render_entries(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("<p>The trash is empty.</p>");
+        }
+        let mut html = String::from(
+            "<table class=\"trash\"><thead><tr><th>Note</th><th>Deleted</th><th>Purged</th><th></th></tr></thead><tbody>",
+        );
+        for entry in self.entries.iter() {
+            write!(
+                html,
+                r#"<tr><td>{}</td><td>{}</td><td>{}</td><td><form method="post" action="{base}/trash/restore"><input type="hidden" name="id" value="{id}" /><button type="submit">restore</button></form><form method="post" action="{base}/trash/purge"><input type="hidden" name="id" value="{id}" /><button type="submit">purge</button></form></td></tr>"#,
+                escape(&entry.title),
+                entry.deleted,
+                entry.expires,
+                id = entry.id,
+                base = base_path()
+            )
+            .unwrap();
+        }
+        html.push_str("</tbody></table>");
+        html
+    }
+}
+
+#[async_trait]
+impl Render for TrashPage {
+    async fn render(&self) -> String {
+        let mut ctx = get_template_file("trash").await.unwrap();
+        let nav = get_template_file("nav").await.unwrap();
+        ctx = ctx.replace("<%= content %>", &self.render_entries());
+        render_includes(ctx, None).await.replace("<%= nav %>", &nav)
+    }
+}
@@ -1,20 +1,110 @@
 use crate::{get_template_file, render_includes, Render};
 use async_trait::async_trait;
+use chrono::DateTime;
+use persistance::media::MediaFile;
 use std::fmt::Write as _;
+use wikitext::parsers::{base_path, format_links};
+
+pub struct MediaEntry {
+    pub file: MediaFile,
+    /// Titles of the notes using the file.
+    pub used_by: Vec<String>,
+}
 
 pub struct UploadedFilesPage {
-    pub entries: Vec<String>,
+    pub entries: Vec<MediaEntry>,
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
 }
 
 impl UploadedFilesPage {
-    pub fn new(entries: Vec<String>) -> Self {
+    pub fn new(entries: Vec<MediaEntry>) -> Self {
         Self { entries }
     }
+
+    pub(crate) fn render_preview(entry: &MediaFile, link: &str) -> String {
+        if entry.content_type.starts_with("image/") {
+            format!(
+                "<img loading=\"lazy\" src=\"{}\" alt=\"{}\" />",
+                link,
+                escape(&entry.name)
+            )
+        } else {
+            let extension = entry
+                .name
+                .rsplit_once('.')
+                .map(|(_, extension)| extension)
+                .unwrap_or("file");
+            format!("<span class=\"file-type\">{}</span>", escape(extension))
+        }
+    }
+
     fn render_entries(&self) -> String {
-        let mut entry_list = String::new();
-        for entry in &self.entries {
-            write!(entry_list, "<a href=\"/files/{}\">{}</a>", entry, entry).unwrap();
+        if self.entries.is_empty() {
+            return String::from("<p>No files have been uploaded yet.</p>");
+        }
+        let mut entry_list = String::from("<div class=\"gallery\">");
+        for MediaEntry {
+            file: entry,
+            used_by,
+        } in &self.entries
+        {
+            let link = format_links(&format!("files:{}", entry.name));
+            let name = escape(&entry.name);
+            let uploaded = DateTime::parse_from_rfc3339(&entry.uploaded)
+                .map(|dt| dt.format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+            let uploader = if entry.uploader.is_empty() {
+                String::with_capacity(0)
+            } else {
+                format!(" by {}", escape(&entry.uploader))
+            };
+            let used_by = if used_by.is_empty() {
+                String::from("not used by any note")
+            } else {
+                let notes = used_by
+                    .iter()
+                    .map(|n| format!("<a href=\"{}\">{}</a>", format_links(n), escape(n)))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("used by {}", notes)
+            };
+            write!(
+                entry_list,
+                r#"<figure class="media-card"><a href="{link}">{preview}</a><figcaption><a href="{link}">{name}</a><span class="media-meta">{size} · {content_type}</span><span class="media-meta">uploaded {uploaded}{uploader}</span><span class="media-meta">{used_by}</span><form method="post" action="{base}/files/rename"><input type="hidden" name="name" value="{name}" /><input type="text" name="new_name" value="{name}" aria-label="new name" /><button type="submit">rename</button></form><form method="post" action="{base}/files/delete" onsubmit="return confirm('Delete this file? Links to it will be removed from your notes.')"><input type="hidden" name="name" value="{name}" /><button type="submit">delete</button></form></figcaption></figure>"#,
+                link = link,
+                preview = Self::render_preview(entry, &link),
+                name = name,
+                size = format_size(entry.size),
+                content_type = escape(&entry.content_type),
+                uploaded = uploaded,
+                uploader = uploader,
+                used_by = used_by,
+                base = base_path(),
+            )
+            .unwrap();
         }
+        entry_list.push_str("</div>");
         entry_list
     }
 }
@@ -1,20 +1,31 @@
 use async_trait::async_trait;
 
-use wikitext::parsers::TemplattedPage;
+use wikitext::parsers::{format_links, TemplattedPage};
 
 use crate::{
-    get_template_file, render_includes, render_page_backlinks, render_page_metadata,
-    PageRenderLinks, Render,
+    get_template_file, render_includes, render_page_backlinks, render_page_breadcrumbs,
+    render_page_metadata, PageRenderLinks, Render,
 };
 
 pub struct WikiPage<'a> {
     page: &'a TemplattedPage,
     links: PageRenderLinks<'a>,
+    revision: &'a str,
 }
 
 impl<'a> WikiPage<'a> {
     pub fn new(page: &'a TemplattedPage, links: PageRenderLinks<'a>) -> Self {
-        Self { page, links }
+        Self {
+            page,
+            links,
+            revision: "",
+        }
+    }
+
+    /// The revision the editor sends back when saving, so stale saves can be detected.
+    pub fn with_revision(mut self, revision: &'a str) -> Self {
+        self.revision = revision;
+        self
     }
 
     fn render_body(&self) -> String {
@@ -40,7 +51,7 @@ impl<'a> Render for WikiPage<'a> {
         let tag_string = page
             .tags
             .iter()
-            .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", t, t))
+            .map(|t| format!("<li><a href=\"{}\">#{}</a></li>", format_links(t), t))
             .collect::<Vec<String>>()
             .join("\n");
         let mut ctx = get_template_file("main").await.unwrap();
@@ -48,9 +59,11 @@ impl<'a> Render for WikiPage<'a> {
         let nav = get_template_file("nav").await.unwrap();
         ctx = ctx
             .replace("<%= content %>", &content)
+            .replace("<%= breadcrumbs %>", &render_page_breadcrumbs(&page.title))
             .replace("<%= tags %>", &tag_string)
             .replace("<%= links %>", &render_page_backlinks(backlinks))
             .replace("<%= nav %>", &nav)
+            .replace("<%= revision %>", self.revision)
             .replace("<%= body %>", &self.render_body())
             .replace(
                 "<%= metadata %>",
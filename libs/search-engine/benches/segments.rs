@@ -0,0 +1,212 @@
+//! Compares the segment index with the layout it replaced, a file per search term along with a
+//! file per note listing its terms. Run with `cargo bench -p search_engine`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use search_engine::{segment::Index, Tokens};
+
+const NOTES: usize = 2000;
+const WORDS_PER_NOTE: usize = 300;
+const VOCABULARY: u64 = 8000;
+const QUERY: [&str; 4] = ["word12", "word480", "word3001", "missing"];
+
+type Postings = Vec<(String, f32, Vec<u32>)>;
+type Terms = HashMap<String, (f32, Vec<u32>)>;
+
+/// A made up wiki, the same on every run. Word use is skewed like in real text, a few words show
+/// up in most notes and most words in only a few.
+fn corpus() -> (Tokens, Vec<String>) {
+    let mut seed: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let mut tokens = Tokens::new();
+    let mut docs = Vec::with_capacity(NOTES);
+    for note in 0..NOTES {
+        let title = format!("note-{}", note);
+        let mut counts: Terms = HashMap::new();
+        for position in 0..WORDS_PER_NOTE {
+            let word = (next() % VOCABULARY) * (next() % VOCABULARY) / VOCABULARY;
+            let (count, positions) = counts.entry(format!("word{}", word)).or_default();
+            *count += 1.0;
+            positions.push(position as u32);
+        }
+        for (term, (count, positions)) in counts {
+            tokens.entry(term).or_default().push((
+                title.clone(),
+                count / WORDS_PER_NOTE as f32,
+                positions,
+            ));
+        }
+        docs.push(title);
+    }
+    (tokens, docs)
+}
+
+fn fresh_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("tendril-bench").join(name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    fs::create_dir_all(dir.join("file_index")).unwrap();
+    dir
+}
+
+fn legacy_write(dir: &Path, tokens: &Tokens) {
+    let mut terms_of: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (term, postings) in tokens {
+        fs::write(dir.join(term), bincode::serialize(postings).unwrap()).unwrap();
+        for (title, ..) in postings {
+            terms_of.entry(title).or_default().push(term);
+        }
+    }
+    for (title, terms) in terms_of {
+        let path = dir.join("file_index").join(title);
+        fs::write(path, bincode::serialize(&terms).unwrap()).unwrap();
+    }
+}
+
+fn legacy_read(dir: &Path, term: &str) -> Postings {
+    fs::read(dir.join(term))
+        .map(|bytes| bincode::deserialize(&bytes).unwrap())
+        .unwrap_or_default()
+}
+
+/// Rewrites the file of every term the note has now or had before.
+fn legacy_patch(dir: &Path, title: &str, terms: &Terms) {
+    let index_path = dir.join("file_index").join(title);
+    let old_terms: Vec<String> = fs::read(&index_path)
+        .map(|bytes| bincode::deserialize(&bytes).unwrap())
+        .unwrap_or_default();
+    for (term, (score, positions)) in terms {
+        let mut postings = legacy_read(dir, term);
+        postings.retain(|(doc, ..)| doc != title);
+        postings.push((title.to_string(), *score, positions.clone()));
+        fs::write(dir.join(term), bincode::serialize(&postings).unwrap()).unwrap();
+    }
+    for term in old_terms.iter().filter(|term| !terms.contains_key(*term)) {
+        let mut postings = legacy_read(dir, term);
+        postings.retain(|(doc, ..)| doc != title);
+        fs::write(dir.join(term), bincode::serialize(&postings).unwrap()).unwrap();
+    }
+    let terms = terms.keys().collect::<Vec<_>>();
+    fs::write(index_path, bincode::serialize(&terms).unwrap()).unwrap();
+}
+
+fn edited_note() -> Terms {
+    (0..WORDS_PER_NOTE)
+        .map(|word| {
+            let term = format!("word{}", word * 7);
+            (term, (1.0 / WORDS_PER_NOTE as f32, vec![word as u32]))
+        })
+        .collect()
+}
+
+fn write(c: &mut Criterion) {
+    let (tokens, docs) = corpus();
+    let mut group = c.benchmark_group("write index");
+    group.sample_size(10);
+    group.bench_function("file per term", |b| {
+        b.iter_batched(
+            || fresh_dir("legacy-write"),
+            |dir| legacy_write(&dir, &tokens),
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("segment", |b| {
+        b.iter_batched(
+            || (fresh_dir("segment-write"), tokens.clone()),
+            |(dir, tokens)| Index::open(&dir).unwrap().add(tokens, docs.clone()),
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let (tokens, docs) = corpus();
+    let legacy = fresh_dir("legacy-lookup");
+    legacy_write(&legacy, &tokens);
+    let mut index = Index::open(&fresh_dir("segment-lookup")).unwrap();
+    index.add(tokens, docs).unwrap();
+
+    let mut group = c.benchmark_group("lookup");
+    group.bench_function("file per term", |b| {
+        b.iter(|| {
+            QUERY
+                .iter()
+                .map(|term| legacy_read(&legacy, term).len())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("segment", |b| {
+        b.iter(|| {
+            QUERY
+                .iter()
+                .map(|term| index.postings(term).unwrap().len())
+                .sum::<usize>()
+        })
+    });
+    // Queries on an index that has taken a few edits since it was last merged.
+    for note in 0..5 {
+        let title = format!("note-{}", note);
+        let tokens = edited_note()
+            .into_iter()
+            .map(|(term, (score, positions))| (term, vec![(title.clone(), score, positions)]))
+            .collect();
+        index.add(tokens, vec![title]).unwrap();
+    }
+    group.bench_function("segment with deltas", |b| {
+        b.iter(|| {
+            QUERY
+                .iter()
+                .map(|term| index.postings(term).unwrap().len())
+                .sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+fn patch(c: &mut Criterion) {
+    let (tokens, docs) = corpus();
+    let legacy = fresh_dir("legacy-patch");
+    legacy_write(&legacy, &tokens);
+    let dir = fresh_dir("segment-patch");
+    let mut index = Index::open(&dir).unwrap();
+    index.add(tokens, docs).unwrap();
+    let edit = edited_note();
+
+    let mut group = c.benchmark_group("patch note");
+    group.bench_function("file per term", |b| {
+        b.iter(|| legacy_patch(&legacy, "note-1", &edit))
+    });
+    group.bench_function("segment", |b| {
+        b.iter(|| {
+            let tokens = edit
+                .iter()
+                .map(|(term, (score, positions))| {
+                    let posting = ("note-1".to_string(), *score, positions.clone());
+                    (term.clone(), vec![posting])
+                })
+                .collect();
+            index.add(tokens, vec!["note-1".into()]).unwrap();
+            // Merging is part of the cost of an update, even if the server does it on the side.
+            if index.needs_merge() {
+                let merged = Index::merge(&dir, index.merge_inputs()).unwrap();
+                index.replace(merged).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, write, lookup, patch);
+criterion_main!(benches);
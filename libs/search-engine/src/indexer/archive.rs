@@ -1,4 +1,4 @@
-use compression::prelude::*;
+use persistance::archive::decompress;
 use std::{
     collections::HashMap,
     fs::{read, read_dir},
@@ -26,25 +26,19 @@ impl Proccessor for Archive {
                 if fname.ends_with("pdf") {
                     return;
                 }
-                let content = read(entry.path()).unwrap();
-                let decompressed = content
-                    .iter()
-                    .cloned()
-                    .decode(&mut BZip2Decoder::new())
-                    .collect::<Result<Vec<_>, _>>()
-                    .unwrap();
-                let text_content = String::from_utf8(decompressed).unwrap_or_else(|_| {
-                    panic!(
-                        "Unable to convert compressed text to utf8 string, {}",
-                        fname
-                    );
-                });
+                let text_content = match read(entry.path()).and_then(decompress) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        eprintln!("Could not read archive {}: {}", fname, e);
+                        return;
+                    }
+                };
                 let doc_token_counter = tokenize_document(text_content);
-                for (term, score) in doc_token_counter.iter() {
+                for (term, (score, positions)) in doc_token_counter.iter() {
                     tokens
                         .entry(term.to_owned())
-                        .and_modify(|v| v.push((fname.to_string(), *score)))
-                        .or_insert(vec![(fname.to_string(), *score)]);
+                        .and_modify(|v| v.push((fname.to_string(), *score, positions.clone())))
+                        .or_insert(vec![(fname.to_string(), *score, positions.clone())]);
                     term_index
                         .entry(fname.to_owned())
                         .and_modify(|v| v.push(term.clone()))
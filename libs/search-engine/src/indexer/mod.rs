@@ -1,6 +1,9 @@
 use std::{path::Path, collections::HashMap};
 
-use crate::tokenizer::tokenize;
+use crate::{
+    tokenizer::{is_token, words},
+    NormalizedFrequency, Positions,
+};
 
 pub(crate) mod archive;
 pub(crate) mod notebook;
@@ -8,22 +11,25 @@ pub(crate) mod notebook;
 pub(crate) trait Proccessor {
     fn load(&mut self, location: &Path);
 }
-pub type DocTokenCount = HashMap<String, f32>;
+/// How often each token appears in a document, relative to its length, and where.
+pub type DocTokenCount = HashMap<String, (NormalizedFrequency, Positions)>;
 
 pub fn tokenize_document(content: String) -> DocTokenCount {
     let mut token_counter: DocTokenCount = HashMap::new();
     let mut total_tokens = 0.0;
+    // Positions carry on from line to line, so a phrase can span a line break.
+    let mut line_start = 0;
     for line in content.lines() {
-        let raw_tokens = tokenize(line);
-        total_tokens += raw_tokens.len() as f32;
-        for token in raw_tokens {
-            token_counter
-                .entry(token)
-                .and_modify(|v| *v += 1.0)
-                .or_insert(1.0);
+        let words = words(line);
+        for (index, word) in words.iter().enumerate().filter(|(_, w)| is_token(w)) {
+            total_tokens += 1.0;
+            let (count, positions) = token_counter.entry(word.clone()).or_default();
+            *count += 1.0;
+            positions.push(line_start + index as u32);
         }
+        line_start += words.len() as u32;
     }
-    for (_, val) in token_counter.iter_mut() {
+    for (_, (val, _)) in token_counter.iter_mut() {
         *val /= total_tokens;
     }
     token_counter
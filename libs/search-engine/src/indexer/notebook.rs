@@ -1,41 +1,40 @@
-use super::{Proccessor, tokenize_document};
+use super::tokenize_document;
 use crate::Tokens;
-use persistance::fs::path_to_string;
+use persistance::store::NoteStore;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::read_dir, path::Path};
+use std::collections::HashMap;
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub(crate) struct Notebook {
     pub(crate) tokens: Tokens,
-    // filename, Vec<search_terms>
+    // title, Vec<search_terms>
     pub(crate) file_index: HashMap<String, Vec<String>>,
 }
 
-impl Proccessor for Notebook {
-    fn load(&mut self, location: &Path) {
+impl Notebook {
+    pub(crate) fn load(&mut self, store: &dyn NoteStore) {
         let mut tokens: Tokens = HashMap::new();
         let mut term_index: HashMap<String, Vec<String>> = HashMap::new();
-        let entries = read_dir(location).unwrap();
-        entries.for_each(|entry| {
-            let entry = entry.unwrap();
-            if let Some(fname) = entry.file_name().to_str() {
-                if fname.ends_with(".txt") {
-                    let title = fname.strip_suffix(".txt").unwrap();
-                    let content = path_to_string(&entry.path()).unwrap();
-                    let doc_token_counter = tokenize_document(content);
-                    for (term, score) in doc_token_counter.iter() {
-                        tokens
-                            .entry(term.to_owned())
-                            .and_modify(|v| v.push((title.to_string(), *score)))
-                            .or_insert(vec![(title.to_string(), *score)]);
-                        term_index
-                            .entry(fname.to_owned())
-                            .and_modify(|v| v.push(term.clone()))
-                            .or_insert(vec![term.clone()]);
-                    }
+        for title in store.list().unwrap() {
+            let content = match store.read(&title) {
+                Ok(content) => content,
+                Err(e) => {
+                    eprintln!("Could not index {}: {}", title, e);
+                    continue;
                 }
+            };
+            let doc_token_counter = tokenize_document(content);
+            for (term, (score, positions)) in doc_token_counter.iter() {
+                tokens
+                    .entry(term.to_owned())
+                    .and_modify(|v| v.push((title.clone(), *score, positions.clone())))
+                    .or_insert(vec![(title.clone(), *score, positions.clone())]);
+                term_index
+                    .entry(title.clone())
+                    .and_modify(|v| v.push(term.clone()))
+                    .or_insert(vec![term.clone()]);
             }
-        });
+        }
         self.tokens = tokens;
         self.file_index = term_index;
     }
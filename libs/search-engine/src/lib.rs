@@ -1,17 +1,27 @@
-use indexer::{notebook::Notebook, tokenize_document};
-use persistance::fs::utils::{
-    get_archive_location, get_search_file_index_location, get_search_index_location,
+use indexer::{notebook::Notebook, tokenize_document, DocTokenCount};
+use lazy_static::lazy_static;
+use persistance::{
+    crypto::CryptoError,
+    fs::utils::{get_archive_file_path, get_archive_location, get_search_index_location},
+    store::note_store,
 };
-use searcher::search;
-use serde::{Deserialize, Serialize};
+pub use searcher::QueryError;
+use searcher::{search, search_with_snippets};
+use segment::{Index, Posting};
+use serde::Serialize;
 use std::{
     collections::HashMap,
-    fs::{create_dir, read, write},
-    path::{Path, PathBuf},
+    fs::remove_dir_all,
+    io,
     process::exit,
-    usize,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    thread,
 };
 use thiserror::Error;
+use titles::TitleIndex;
 use wikitext::parsers::Note;
 
 use tokio::fs::remove_file;
@@ -20,12 +30,16 @@ use crate::indexer::{archive::Archive, Proccessor};
 
 mod indexer;
 mod searcher;
+pub mod segment;
+mod titles;
 mod tokenizer;
 
 type SearchTerm = String;
 type DocTitle = String;
 type NormalizedFrequency = f32;
-pub type Tokens = HashMap<SearchTerm, Vec<(DocTitle, NormalizedFrequency)>>;
+/// Where a term appears in a note, counted in words from the start.
+type Positions = Vec<u32>;
+pub type Tokens = HashMap<SearchTerm, Vec<(DocTitle, NormalizedFrequency, Positions)>>;
 
 #[derive(Error, Debug)]
 pub enum SearchIndexErr {
@@ -35,25 +49,71 @@ pub enum SearchIndexErr {
     DeserErr(bincode::Error),
     #[error("Could not write serialized file")]
     WriteErr(String),
+    #[error("Could not decrypt file")]
+    DecryptErr(CryptoError),
+    #[error("Could not read search index: {0}")]
+    Io(io::Error),
+    #[error("Corrupt search index segment {0}")]
+    CorruptSegment(String),
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub(crate) struct Doc {
-    id: String,
-    tokens: Tokens,
+/// A note found by a search.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub title: String,
+    /// Passages of the note, or of its archived page, as HTML with the matched words in `<mark>`.
+    pub snippets: Vec<String>,
 }
 
-pub fn build_search_index(location: &str) {
-    let loc = get_search_index_location();
-    if !loc.exists() {
-        create_dir(&loc).unwrap();
-        create_dir(&get_search_file_index_location()).unwrap();
+lazy_static! {
+    /// Opened on first use, the segments stay mapped for as long as the process runs.
+    static ref INDEX: RwLock<Option<Index>> = RwLock::new(None);
+    /// Loaded from the notes on first use, then kept up to date as notes change.
+    static ref TITLES: RwLock<Option<TitleIndex>> = RwLock::new(None);
+}
+
+static MERGING: AtomicBool = AtomicBool::new(false);
+
+fn read_index<T>(read: impl FnOnce(&Index) -> T) -> Result<T, SearchIndexErr> {
+    if let Some(index) = INDEX.read().unwrap().as_ref() {
+        return Ok(read(index));
+    }
+    update_index(|index| Ok(read(index)))
+}
+
+fn update_index<T>(
+    update: impl FnOnce(&mut Index) -> Result<T, SearchIndexErr>,
+) -> Result<T, SearchIndexErr> {
+    let mut index = INDEX.write().unwrap();
+    if index.is_none() {
+        *index = Some(Index::open(&get_search_index_location())?);
+    }
+    update(index.as_mut().unwrap())
+}
+
+/// Merges the index's segments on another thread, searches and updates carry on meanwhile.
+fn merge_in_background() {
+    if MERGING.swap(true, Ordering::SeqCst) {
+        return;
     }
+    thread::spawn(|| {
+        let merged = read_index(|index| index.merge_inputs())
+            .and_then(|inputs| Index::merge(&get_search_index_location(), inputs))
+            .and_then(|merged| update_index(|index| index.replace(merged)));
+        if let Err(e) = merged {
+            eprintln!("Could not merge search index: {}", e);
+        }
+        MERGING.store(false, Ordering::SeqCst);
+    });
+}
+
+pub fn build_search_index() {
+    let loc = get_search_index_location();
     let archive_location = get_archive_location();
     let mut n = Notebook::default();
     let mut a = Archive::default();
     println!("<indexing notes>");
-    n.load(&PathBuf::from(location));
+    n.load(note_store());
     a.load(&archive_location);
     for (key, value) in a.tokens.iter() {
         if let Some(exists) = n.tokens.get_mut(key) {
@@ -62,86 +122,92 @@ pub fn build_search_index(location: &str) {
             n.tokens.insert(key.to_owned(), value.to_owned());
         }
     }
-    write_search_index(&n.tokens, vec![n.file_index, a.file_index]);
+    let mut docs = n.file_index.into_keys().collect::<Vec<DocTitle>>();
+    docs.extend(a.file_index.into_keys());
+    let mut index = INDEX.write().unwrap();
+    // Start from scratch, so nothing is left of deleted notes or of an index written before the
+    // notebook was encrypted.
+    *index = None;
+    if loc.exists() {
+        remove_dir_all(&loc).unwrap();
+    }
+    match Index::open(&loc).and_then(|mut fresh| fresh.add(n.tokens, docs).map(|_| fresh)) {
+        Ok(fresh) => *index = Some(fresh),
+        Err(e) => {
+            eprintln!("Could not write search index\n{}", e);
+            exit(1);
+        }
+    }
 }
 
-pub async fn semantic_search(term: &str) -> Vec<String> {
+/// Runs a search query. Besides words, a query can have quoted phrases, `NEAR/n`, `AND`, `OR`,
+/// `NOT`, parentheses and filters like `tag:reading` or `created:2022-06`.
+pub async fn semantic_search(term: &str) -> Result<Vec<String>, QueryError> {
     search(term).await
 }
 
-pub(crate) fn write_search_index(
-    search_idx: &Tokens,
-    term_indicies: Vec<HashMap<DocTitle, Vec<SearchTerm>>>,
-) {
-    let loc = get_search_index_location();
-    for (key, value) in search_idx.iter() {
-        let bytes = bincode::serialize(value).unwrap();
-        let file_loc = loc.join(key);
-        match write(file_loc, bytes) {
-            Ok(()) => {}
-            Err(e) => {
-                eprintln!("Could not write key -> {}\n{}", key, e);
-                exit(1);
-            }
-        }
+/// Like [semantic_search], along with the passages of each note that best match the query.
+pub async fn search_results(term: &str) -> Result<Vec<SearchResult>, QueryError> {
+    search_with_snippets(term).await
+}
+
+fn load_titles() -> TitleIndex {
+    let store = note_store();
+    let mut titles = TitleIndex::default();
+    let listed = store.list().unwrap_or_else(|e| {
+        eprintln!("Could not list notes for title suggestions: {}", e);
+        Vec::new()
+    });
+    for title in listed {
+        let recency = store
+            .read_note(&title)
+            .ok()
+            .and_then(|note| titles::recency(&note.header));
+        titles.insert(&title, recency);
     }
-    // write the term_index for easy deletion
-    let term_index_loc = get_search_file_index_location();
-    for index in term_indicies.iter() {
-        for (file, terms) in index.iter() {
-            let bytes = bincode::serialize(terms).unwrap();
-            let index_loc = term_index_loc.join(file);
-            match write(index_loc, bytes) {
-                Ok(()) => {}
-                Err(e) => {
-                    eprintln!("Could not write file index -> {}\n{}", file, e);
-                    exit(1);
-                }
-            }
-        }
+    titles
+}
+
+/// Up to `limit` note titles starting with, or a typo or two away from, `query`. Equally good
+/// matches go by their number of `backlinks`, then by how recently they changed.
+pub fn suggest_titles(query: &str, limit: usize, backlinks: impl Fn(&str) -> usize) -> Vec<String> {
+    if let Some(titles) = TITLES.read().unwrap().as_ref() {
+        return titles.suggest(query, limit, backlinks);
     }
+    TITLES
+        .write()
+        .unwrap()
+        .get_or_insert_with(load_titles)
+        .suggest(query, limit, backlinks)
 }
 
-fn read_file_term_index(location: &Path) -> Result<Vec<String>, SearchIndexErr> {
-    let term_index_loc = get_search_file_index_location();
-    match read(term_index_loc.join(location)) {
-        Ok(content) => {
-            let deserialized_terms = bincode::deserialize(&content);
-            match deserialized_terms {
-                Ok(terms) => Ok(terms),
-                Err(e) => Err(SearchIndexErr::DeserErr(e)),
-            }
-        }
-        Err(_) => Err(SearchIndexErr::NotExistErr),
+/// Adds a note's title to the suggestions, or refreshes when it last changed. Until the first
+/// suggestion loads them, there are no titles to keep up to date.
+pub fn update_title_index(title: &str) {
+    if TITLES.read().unwrap().is_none() {
+        return;
+    }
+    let recency = note_store()
+        .read_note(title)
+        .ok()
+        .and_then(|note| titles::recency(&note.header));
+    if let Some(titles) = TITLES.write().unwrap().as_mut() {
+        titles.insert(title, recency);
     }
 }
 
-fn write_file_term_index(location: &Path, content: Vec<String>) -> Result<(), SearchIndexErr> {
-    let serialized_terms = bincode::serialize(&content);
-    match serialized_terms {
-        Ok(terms) => {
-            write(location, terms).unwrap();
-            Ok(())
-        }
-        Err(e) => Err(SearchIndexErr::DeserErr(e)),
+pub fn remove_from_title_index(title: &str) {
+    if let Some(titles) = TITLES.write().unwrap().as_mut() {
+        titles.remove(title);
     }
 }
 
-pub(crate) fn read_search_index(
-    filename: &str,
-) -> Result<Vec<(DocTitle, NormalizedFrequency)>, SearchIndexErr> {
-    let index_location = get_search_index_location();
-    let read_loc = index_location.join(filename);
-    match read(read_loc) {
-        Ok(content) => {
-            let deserialized_freqs = bincode::deserialize(&content);
-            match deserialized_freqs {
-                Ok(tokens) => Ok(tokens),
-                Err(e) => Err(SearchIndexErr::DeserErr(e)),
-            }
-        }
-        Err(_) => Err(SearchIndexErr::NotExistErr),
+pub(crate) fn read_search_index(filename: &str) -> Result<Vec<Posting>, SearchIndexErr> {
+    let postings = read_index(|index| index.postings(filename))??;
+    if postings.is_empty() {
+        return Err(SearchIndexErr::NotExistErr);
     }
+    Ok(postings)
 }
 
 pub fn patch_search_from_update(note: &Note) {
@@ -153,41 +219,25 @@ pub fn patch_search_from_update(note: &Note) {
     patch(doc_token_count, title.to_owned());
 }
 
-pub fn patch(doc_token_count: HashMap<String, f32>, title: String) {
-    let term_index_loc = get_search_file_index_location();
-    let index_loc = term_index_loc.join(&title);
-    let term_index_doc = read_file_term_index(&index_loc).unwrap();
-    let mut file_terms = Vec::with_capacity(doc_token_count.len());
-    for (term, score) in doc_token_count.iter() {
-        file_terms.push(term.to_owned());
-        if let Ok(mut tokens) = read_search_index(term) {
-            let mut found = false;
-            for data in tokens.iter_mut() {
-                if data.0 == *title {
-                    found = true;
-                    *data = (title.clone(), *score);
-                }
-            }
-            if !found {
-                tokens.push((title.clone(), *score));
-            }
-            write_search_entry(term, &tokens).unwrap();
-        } else {
-            let tokens = vec![(title.to_owned(), *score)];
-            // The term we've parsed doesn't yet exist.
-            write_search_entry(term, &tokens).unwrap();
-        }
+/// Replaces everything the index knows about a note with `tokens`, removing it from the index
+/// when there are none.
+fn update_doc(tokens: Tokens, title: String) {
+    match update_index(|index| {
+        index.add(tokens, vec![title.clone()])?;
+        Ok(index.needs_merge())
+    }) {
+        Ok(true) => merge_in_background(),
+        Ok(false) => {}
+        Err(e) => eprintln!("Could not update search index for {}: {}", title, e),
     }
-    for term in term_index_doc.iter() {
-        if file_terms.contains(term) {
-            continue;
-        }
+}
 
-        let tokens = read_search_index(term).unwrap();
-        let tokens = tokens.into_iter().filter(|t| t.0 != *title).collect();
-        write_search_entry(term, &tokens).unwrap();
-    }
-    write_file_term_index(&index_loc, file_terms).unwrap();
+pub fn patch(doc_token_count: DocTokenCount, title: String) {
+    let tokens = doc_token_count
+        .into_iter()
+        .map(|(term, (score, positions))| (term, vec![(title.clone(), score, positions)]))
+        .collect();
+    update_doc(tokens, title);
 }
 
 type Title = String;
@@ -200,42 +250,15 @@ pub async fn patch_search_from_archive(archive_patch: ArchivePatch) {
     patch(doc_token_count, archive_patch.0);
 }
 
-fn write_search_entry(
-    entry: &str,
-    content: &Vec<(DocTitle, NormalizedFrequency)>,
-) -> Result<(), SearchIndexErr> {
-    let bytes = bincode::serialize(content);
-    let path = get_search_index_location();
-    match bytes {
-        Ok(b) => match write(path.join(entry), b) {
-            Ok(()) => Ok(()),
-            Err(e) => Err(SearchIndexErr::WriteErr(format!(
-                "Could not write {}\n  {}",
-                entry, e
-            ))),
-        },
-        Err(e) => Err(SearchIndexErr::DeserErr(e)),
-    }
-}
-
 pub async fn delete_entry_from_update(entry: &str) {
-    let search_file_idx = get_search_file_index_location();
-    let entry_file = search_file_idx.join(entry);
-    let entries = read_file_term_index(&entry_file).unwrap();
-    for e in entries.iter() {
-        let contents = read_search_index(e).unwrap();
-        let filtered_contents = contents
-            .into_iter()
-            .filter(|c| c.0 == entry)
-            .collect::<Vec<(String, f32)>>();
-        let bytes = bincode::serialize(&filtered_contents).unwrap();
-        write(e, bytes).unwrap();
-    }
+    update_doc(Tokens::new(), entry.to_owned());
 }
 
 pub async fn delete_archived_file(entry: &str) {
-    let mut archive_path = get_archive_location();
-    archive_path.push(entry);
+    let archive_path = match get_archive_file_path(entry) {
+        Ok(archive_path) => archive_path,
+        Err(_) => return,
+    };
     if archive_path.exists() {
         remove_file(archive_path)
             .await
@@ -0,0 +1,661 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use persistance::{
+    archive::decompress,
+    fs::utils::{archive_file_exists, get_archive_file_path},
+    store::note_store,
+};
+use wikitext::processors::tags::TagsArray;
+
+use crate::{read_search_index, segment::Posting, DocTitle, SearchIndexErr, SearchResult};
+
+mod query;
+mod snippets;
+
+pub use query::QueryError;
+use query::{parse_query, Clause, DocType, Filter, Query};
+use snippets::{highlighted_words, snippets};
+
+/// How a note matched one clause of a query.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Hit {
+    score: f32,
+    positions: Vec<u32>,
+}
+
+/// Notes matching a query, along with what they matched of each clause.
+type Matches = HashMap<DocTitle, Vec<Hit>>;
+
+/// What queries run against.
+trait Source {
+    /// Postings of an exact search term.
+    fn postings(&self, term: &str) -> Vec<Posting>;
+    /// Every note, for queries that only filter or exclude notes.
+    fn titles(&self) -> Vec<DocTitle>;
+    /// A note's metadata, `None` when there's no such note, e.g. for the archive of a bookmark
+    /// that was deleted.
+    fn header(&self, title: &str) -> Option<HashMap<String, String>>;
+    fn has_archive(&self, title: &str) -> bool;
+    /// The text of a note, without its metadata.
+    fn body(&self, title: &str) -> Option<String>;
+    /// The text of a bookmark's archived page.
+    fn archive_text(&self, title: &str) -> Option<String>;
+}
+
+/// The search index and the notes of the wiki. Metadata is read once per search.
+#[derive(Default)]
+struct Wiki {
+    headers: RefCell<HashMap<DocTitle, Option<HashMap<String, String>>>>,
+}
+
+impl Source for Wiki {
+    fn postings(&self, term: &str) -> Vec<Posting> {
+        postings_of(term)
+    }
+
+    fn titles(&self) -> Vec<DocTitle> {
+        note_store().list().unwrap_or_else(|e| {
+            eprintln!("Could not list notes to search: {}", e);
+            Vec::new()
+        })
+    }
+
+    fn header(&self, title: &str) -> Option<HashMap<String, String>> {
+        self.headers
+            .borrow_mut()
+            .entry(title.into())
+            .or_insert_with(|| note_store().read_note(title).ok().map(|note| note.header))
+            .clone()
+    }
+
+    fn has_archive(&self, title: &str) -> bool {
+        archive_file_exists(title)
+    }
+
+    fn body(&self, title: &str) -> Option<String> {
+        note_store().read_note(title).ok().map(|note| note.content)
+    }
+
+    fn archive_text(&self, title: &str) -> Option<String> {
+        if !self.has_archive(title) {
+            return None;
+        }
+        match fs::read(get_archive_file_path(title).ok()?).and_then(decompress) {
+            Ok(text) => Some(text),
+            Err(e) => {
+                eprintln!("Could not read archive {}: {}", title, e);
+                None
+            }
+        }
+    }
+}
+
+fn postings_of(term: &str) -> Vec<Posting> {
+    match read_search_index(term) {
+        Ok(entries) => entries,
+        Err(e) => {
+            match e {
+                SearchIndexErr::NotExistErr => {}
+                SearchIndexErr::DeserErr(e) => {
+                    eprintln!("Could not deserialize: {}", e);
+                }
+                SearchIndexErr::WriteErr(e) => {
+                    eprintln!("{}", e);
+                }
+                SearchIndexErr::DecryptErr(e) => {
+                    eprintln!("Could not decrypt search index: {}", e);
+                }
+                e => eprintln!("{}", e),
+            }
+            Vec::new()
+        }
+    }
+}
+
+/// Notes that have the exact word, with where it appears.
+fn word_hits(word: &str, lookup: &impl Fn(&str) -> Vec<Posting>) -> HashMap<DocTitle, Hit> {
+    let mut hits: HashMap<DocTitle, Hit> = HashMap::new();
+    for (title, score, positions) in lookup(word) {
+        let hit = hits.entry(title).or_default();
+        hit.score += score;
+        hit.positions.extend(positions);
+    }
+    hits
+}
+
+/// Notes that have the word or one of its variations.
+fn term_hits(term: &str, lookup: &impl Fn(&str) -> Vec<Posting>) -> HashMap<DocTitle, Hit> {
+    let mut hits: HashMap<DocTitle, Hit> = HashMap::new();
+    let variations = variations_of_word(term).into_iter().collect::<HashSet<_>>();
+    for variation in variations {
+        for (title, hit) in word_hits(&variation, lookup) {
+            let merged = hits.entry(title).or_default();
+            merged.score += hit.score;
+            merged.positions.extend(hit.positions);
+        }
+    }
+    for hit in hits.values_mut() {
+        hit.positions.sort_unstable();
+        hit.positions.dedup();
+    }
+    hits
+}
+
+fn phrase_hits(
+    words: &[(u32, String)],
+    lookup: &impl Fn(&str) -> Vec<Posting>,
+) -> HashMap<DocTitle, Hit> {
+    let per_word = words
+        .iter()
+        .map(|(offset, word)| (*offset, word_hits(word, lookup)))
+        .collect::<Vec<_>>();
+    let (first, rest) = match per_word.split_first() {
+        Some(split) => split,
+        None => return HashMap::new(),
+    };
+    let mut hits = HashMap::new();
+    for (title, hit) in &first.1 {
+        let others = match rest
+            .iter()
+            .map(|(offset, hits)| hits.get(title).map(|hit| (*offset, hit)))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(others) => others,
+            None => continue,
+        };
+        // Positions are where each phrase starts.
+        let positions = hit
+            .positions
+            .iter()
+            .copied()
+            .filter(|start| {
+                others
+                    .iter()
+                    .all(|(offset, other)| other.positions.binary_search(&(start + offset)).is_ok())
+            })
+            .collect::<Vec<_>>();
+        if !positions.is_empty() {
+            let score = hit.score + others.iter().map(|(_, other)| other.score).sum::<f32>();
+            hits.insert(title.clone(), Hit { score, positions });
+        }
+    }
+    hits
+}
+
+fn near_hits(
+    left: &str,
+    right: &str,
+    distance: u32,
+    lookup: &impl Fn(&str) -> Vec<Posting>,
+) -> HashMap<DocTitle, Hit> {
+    let right_hits = term_hits(right, lookup);
+    let mut hits = HashMap::new();
+    for (title, left) in term_hits(left, lookup) {
+        let right = match right_hits.get(&title) {
+            Some(right) => right,
+            None => continue,
+        };
+        if closest(&left.positions, &right.positions).is_some_and(|d| d <= distance) {
+            let mut positions = left.positions;
+            positions.extend(&right.positions);
+            positions.sort_unstable();
+            let score = left.score + right.score;
+            hits.insert(title, Hit { score, positions });
+        }
+    }
+    hits
+}
+
+fn clause_hits(clause: &Clause, lookup: &impl Fn(&str) -> Vec<Posting>) -> HashMap<DocTitle, Hit> {
+    match clause {
+        Clause::Term(term) => term_hits(term, lookup),
+        Clause::Phrase(words) => phrase_hits(words, lookup),
+        Clause::Near {
+            left,
+            right,
+            distance,
+        } => near_hits(left, right, *distance, lookup),
+    }
+}
+
+/// Notes store dates as `20220630143000`, older notes and other tools may use RFC 3339 or plain
+/// dates.
+pub(crate) fn note_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    NaiveDateTime::parse_from_str(value, "%Y%m%d%H%M%S")
+        .or_else(|_| DateTime::parse_from_rfc3339(value).map(|date_time| date_time.naive_local()))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value.get(..10).unwrap_or(value), "%Y-%m-%d")
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+        .ok()
+}
+
+fn note_date(value: &str) -> Option<NaiveDate> {
+    note_time(value).map(|date_time| date_time.date())
+}
+
+fn passes(filter: &Filter, title: &str, source: &impl Source) -> bool {
+    if let Filter::Title(words) = filter {
+        return title.to_lowercase().contains(words.as_str());
+    }
+    let header = source.header(title).unwrap_or_default();
+    let tags = || match header.get("tags") {
+        Some(tags) => TagsArray::new(tags)
+            .values
+            .iter()
+            .map(|tag| tag.to_lowercase())
+            .collect(),
+        None => Vec::new(),
+    };
+    match filter {
+        Filter::Title(_) => unreachable!(),
+        Filter::Tag(tag) => tags().contains(tag),
+        Filter::Type(types) => {
+            let is_bookmark = tags().iter().any(|tag| tag == "bookmark");
+            types.iter().any(|doc_type| match doc_type {
+                DocType::Bookmark => is_bookmark,
+                DocType::Note => !is_bookmark,
+                DocType::Archive => source.has_archive(title),
+            })
+        }
+        Filter::Date { field, from, to } => header
+            .get(field.key())
+            .and_then(|value| note_date(value))
+            .is_some_and(|date| {
+                from.is_none_or(|from| date >= from) && to.is_none_or(|to| date <= to)
+            }),
+        Filter::Field { name, value } => header
+            .get(name)
+            .is_some_and(|found| found.trim().to_lowercase() == *value),
+    }
+}
+
+fn merge(into: &mut Matches, from: Matches) {
+    for (title, hits) in from {
+        into.entry(title).or_default().extend(hits);
+    }
+}
+
+/// Adds what `from` matched of the notes already in `into`.
+fn merge_matched(into: &mut Matches, from: Matches) {
+    for (title, hits) in from {
+        if let Some(matched) = into.get_mut(&title) {
+            matched.extend(hits);
+        }
+    }
+}
+
+/// Notes matching a query. Filters and exclusions only look at the notes in `within`, or every
+/// note when it's `None`, so they don't read more metadata than they need to.
+fn evaluate(query: &Query, source: &impl Source, within: Option<&HashSet<DocTitle>>) -> Matches {
+    let candidates = || match within {
+        Some(within) => within.iter().cloned().collect(),
+        None => source.titles(),
+    };
+    match query {
+        Query::Text(clause) => clause_hits(clause, &|term| source.postings(term))
+            .into_iter()
+            .filter(|(title, _)| within.is_none_or(|within| within.contains(title)))
+            .map(|(title, hit)| (title, vec![hit]))
+            .collect(),
+        Query::Filter(filter) => candidates()
+            .into_iter()
+            .filter(|title| passes(filter, title, source))
+            .map(|title| (title, Vec::new()))
+            .collect(),
+        Query::Not(inner) => {
+            let titles = candidates();
+            let excluded = evaluate(inner, source, Some(&titles.iter().cloned().collect()));
+            titles
+                .into_iter()
+                .filter(|title| !excluded.contains_key(title))
+                .map(|title| (title, Vec::new()))
+                .collect()
+        }
+        Query::Or(parts) => {
+            let mut matches = Matches::new();
+            for part in parts {
+                merge(&mut matches, evaluate(part, source, within));
+            }
+            matches
+        }
+        Query::All { required, optional } => {
+            // Text narrows down the notes first, then filters and exclusions check what's left.
+            let (narrowing, selective): (Vec<&Query>, Vec<&Query>) = required
+                .iter()
+                .partition(|part| matches!(part, Query::Filter(_) | Query::Not(_)));
+            let mut matches: Option<Matches> = None;
+            for part in selective {
+                let found = evaluate(part, source, within);
+                matches = Some(match matches {
+                    Some(mut matches) => {
+                        matches.retain(|title, _| found.contains_key(title));
+                        merge_matched(&mut matches, found);
+                        matches
+                    }
+                    None => found,
+                });
+            }
+            let mut words = Matches::new();
+            for part in optional {
+                merge(&mut words, evaluate(part, source, within));
+            }
+            let mut matches = match matches {
+                Some(mut matches) => {
+                    merge_matched(&mut matches, words);
+                    matches
+                }
+                None if narrowing.is_empty() || !optional.is_empty() => words,
+                None => candidates()
+                    .into_iter()
+                    .map(|title| (title, Vec::new()))
+                    .collect(),
+            };
+            for part in narrowing {
+                let titles = matches.keys().cloned().collect();
+                let passed = evaluate(part, source, Some(&titles));
+                matches.retain(|title, _| passed.contains_key(title));
+            }
+            matches
+        }
+    }
+}
+
+/// Titles of the notes matching a parsed query, best match first.
+fn find(parsed: &Query, query: &str, source: &impl Source) -> Vec<String> {
+    let matches = evaluate(parsed, source, None);
+    rank_docs(matches.into_iter().collect(), query)
+}
+
+pub(crate) async fn search(query: &str) -> Result<Vec<String>, QueryError> {
+    let parsed = parse_query(query)?;
+    Ok(find(&parsed, query, &Wiki::default()))
+}
+
+pub(crate) async fn search_with_snippets(query: &str) -> Result<Vec<SearchResult>, QueryError> {
+    let parsed = parse_query(query)?;
+    let wiki = Wiki::default();
+    let words = highlighted_words(&parsed);
+    let results = find(&parsed, query, &wiki)
+        .into_iter()
+        .map(|title| SearchResult {
+            snippets: snippets(&title, &words, &wiki),
+            title,
+        })
+        .collect();
+    Ok(results)
+}
+
+/// The smallest distance between a position in one list and one in the other. Both lists are
+/// sorted.
+fn closest(a: &[u32], b: &[u32]) -> Option<u32> {
+    let (mut i, mut j) = (0, 0);
+    let mut min = None;
+    while i < a.len() && j < b.len() {
+        let distance = a[i].abs_diff(b[j]);
+        min = Some(min.map_or(distance, |m: u32| m.min(distance)));
+        if a[i] < b[j] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    min
+}
+
+/// Notes where the words of different clauses are close together rank higher, up to twice as
+/// high when they're next to each other.
+fn proximity_boost(hits: &[Hit]) -> f32 {
+    let mut min: Option<u32> = None;
+    for (i, a) in hits.iter().enumerate() {
+        for b in &hits[i + 1..] {
+            if let Some(distance) = closest(&a.positions, &b.positions) {
+                min = Some(min.map_or(distance, |m| m.min(distance)));
+            }
+        }
+    }
+    min.map_or(1.0, |distance| 1.0 + 1.0 / distance.max(1) as f32)
+}
+
+/// use term frequency-inverse document frequency to rank the search results.
+/// We use term frequency adjusted for document length accumulated over all tokens in the search
+/// query, boosted by how many of the query's clauses a document matches and how close together
+/// the matches are.
+/// We use the inverse document frequency smooth weight (log(N / 1 + nt) + 1)
+///
+/// ### What is a document in this context?
+///
+/// A document is a `Doc` data structure which can be derived from multiple sources (though at the
+/// moment it is only derived from wiki notes).
+fn rank_docs(results: Vec<(DocTitle, Vec<Hit>)>, query: &str) -> Vec<String> {
+    let query_lc = query.replace('"', "").trim().to_lowercase();
+    let mut ranked = results
+        .into_iter()
+        .map(|(title, hits)| {
+            let mut score = hits.iter().map(|hit| hit.score).sum::<f32>() * hits.len() as f32;
+            let title_lc = title.to_lowercase();
+            if title_lc.contains(&query_lc) {
+                if title_lc == query_lc {
+                    score *= 5.0;
+                } else {
+                    score *= 2.5;
+                }
+            }
+            score *= proximity_boost(&hits);
+            (title, score)
+        })
+        .collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap().then_with(|| a.0.cmp(&b.0)));
+    ranked.into_iter().map(|r| r.0).collect()
+}
+
+fn variations_of_word(key: &str) -> Vec<String> {
+    let word_stem = stem::get(key).unwrap();
+    let mut variations = Vec::with_capacity(19);
+    // Very very hacky lemmatization
+    for ending in WORD_ENDINGS {
+        variations.push(format!("{}{}", word_stem, ending));
+    }
+    variations.push(key.into());
+    variations.push(word_stem);
+    variations
+}
+
+const WORD_ENDINGS: [&str; 17] = [
+    "e", "s", "ly", "ment", "ed", "'s", "or", "er", "ing", "y", "tion", "ies", "r", "ation", "d",
+    "n", "ian",
+];
+
+#[cfg(test)]
+mod tests {
+    use wikitext::parsers::{parse_meta, Note};
+
+    use super::*;
+    use crate::indexer::tokenize_document;
+
+    #[derive(Default)]
+    struct TestWiki {
+        postings: HashMap<String, Vec<Posting>>,
+        headers: HashMap<DocTitle, HashMap<String, String>>,
+        bodies: HashMap<DocTitle, String>,
+        archives: HashMap<DocTitle, String>,
+    }
+
+    impl Source for TestWiki {
+        fn postings(&self, term: &str) -> Vec<Posting> {
+            self.postings.get(term).cloned().unwrap_or_default()
+        }
+
+        fn titles(&self) -> Vec<DocTitle> {
+            self.headers.keys().cloned().collect()
+        }
+
+        fn header(&self, title: &str) -> Option<HashMap<String, String>> {
+            self.headers.get(title).cloned()
+        }
+
+        fn has_archive(&self, title: &str) -> bool {
+            self.archives.contains_key(title)
+        }
+
+        fn body(&self, title: &str) -> Option<String> {
+            self.bodies.get(title).cloned()
+        }
+
+        fn archive_text(&self, title: &str) -> Option<String> {
+            self.archives.get(title).cloned()
+        }
+    }
+
+    /// A wiki of the given notes, indexed the way the indexer indexes them.
+    fn wiki(notes: &[(&str, &str)]) -> TestWiki {
+        let mut wiki = TestWiki::default();
+        for (title, text) in notes {
+            // Notes in these tests only have a header when they need one.
+            let note = match text.contains("\n\n") {
+                true => parse_meta(text.lines(), title),
+                false => Note {
+                    header: HashMap::new(),
+                    content: text.to_string(),
+                },
+            };
+            for (term, (score, positions)) in tokenize_document(text.to_string()) {
+                wiki.postings
+                    .entry(term)
+                    .or_default()
+                    .push((title.to_string(), score, positions));
+            }
+            wiki.headers.insert(title.to_string(), note.header);
+            wiki.bodies.insert(title.to_string(), note.content);
+        }
+        wiki
+    }
+
+    fn search_wiki(wiki: &TestWiki, query: &str) -> Vec<String> {
+        find(&parse_query(query).unwrap(), query, wiki)
+    }
+
+    fn search_in(notes: &[(&str, &str)], query: &str) -> Vec<String> {
+        search_wiki(&wiki(notes), query)
+    }
+
+    #[test]
+    fn phrases_match_words_in_order() {
+        let notes = [
+            ("Meeting", "Notes from the design review on Monday."),
+            ("Scattered", "A review of the new design."),
+            ("Split", "We need a design\nreview before launch."),
+        ];
+        assert_eq!(
+            search_in(&notes, r#""design review""#),
+            vec!["Meeting", "Split"]
+        );
+        assert_eq!(
+            search_in(&notes, r#""review design""#),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            search_in(&notes, r#""review of the new""#),
+            vec!["Scattered"]
+        );
+    }
+
+    #[test]
+    fn near_limits_distance() {
+        let notes = [
+            ("Close", "The design got a review."),
+            (
+                "Far",
+                "The design was done in a hurry, weeks before anyone thought of a review.",
+            ),
+        ];
+        assert_eq!(search_in(&notes, "design NEAR/3 review"), vec!["Close"]);
+        assert_eq!(search_in(&notes, "review NEAR/20 design").len(), 2);
+    }
+
+    #[test]
+    fn closer_words_rank_higher() {
+        let notes = [
+            (
+                "Far",
+                "Design is the first word here and the last one is review.",
+            ),
+            (
+                "Close",
+                "Somewhere in this note there is a design review, late.",
+            ),
+        ];
+        assert_eq!(search_in(&notes, "design review"), vec!["Close", "Far"]);
+    }
+
+    #[test]
+    fn filters_by_metadata() {
+        let mut wiki = wiki(&[
+            (
+                "Rust book",
+                "tags: [bookmark, rust]\ncreated: 20220310120000\nurl: https://doc.rust-lang.org\n\nThe Rust programming language.",
+            ),
+            (
+                "Learning Rust",
+                "tags: [rust, draft]\ncreated: 20220620090000\nstatus: Reading\n\nNotes on rust ownership.",
+            ),
+            (
+                "Go notes",
+                "tags: [go]\ncreated: 2021-11-02T10:00:00+01:00\n\nGoroutines and channels, nothing on rust.",
+            ),
+        ]);
+        wiki.archives
+            .insert("Rust book".into(), "The archived page.".into());
+        assert_eq!(
+            search_wiki(&wiki, "tag:RUST"),
+            vec!["Learning Rust", "Rust book"]
+        );
+        assert_eq!(
+            search_wiki(&wiki, "rust -tag:draft type:note"),
+            vec!["Go notes"]
+        );
+        assert_eq!(search_wiki(&wiki, "type:archive"), vec!["Rust book"]);
+        assert_eq!(search_wiki(&wiki, "status:reading"), vec!["Learning Rust"]);
+        assert_eq!(
+            search_wiki(&wiki, "created:2022-01..2022-06 NOT type:bookmark"),
+            vec!["Learning Rust"]
+        );
+        assert_eq!(search_wiki(&wiki, "created:<2022"), vec!["Go notes"]);
+        assert_eq!(
+            search_wiki(&wiki, "title:rust (ownership OR language)").len(),
+            2
+        );
+        let mut either = search_wiki(&wiki, "goroutines OR ownership AND tag:draft");
+        either.sort();
+        assert_eq!(either, vec!["Go notes", "Learning Rust"]);
+        assert!(search_wiki(&wiki, "the of").is_empty());
+    }
+
+    #[test]
+    fn snippets_come_from_the_note_or_its_archive() {
+        let mut wiki = wiki(&[("Bookmark", "tags: [bookmark]\n\nA page worth keeping.")]);
+        wiki.archives.insert(
+            "Bookmark".into(),
+            "The page talks about gardening at length.".into(),
+        );
+        let words = |query| highlighted_words(&parse_query(query).unwrap());
+        assert_eq!(
+            snippets("Bookmark", &words("gardening"), &wiki),
+            vec!["The page talks about <mark>gardening</mark> at length."]
+        );
+        assert_eq!(
+            snippets("Bookmark", &words("keeping"), &wiki),
+            vec!["A page worth <mark>keeping</mark>."]
+        );
+        assert_eq!(
+            snippets("Bookmark", &words("tag:bookmark"), &wiki),
+            vec!["A page worth keeping."]
+        );
+    }
+}
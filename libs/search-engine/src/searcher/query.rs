@@ -0,0 +1,663 @@
+//! The search query language. Words are matched against the search index, along with
+//!
+//! - `"quoted phrases"` and `design NEAR/3 review`
+//! - `AND`, `OR`, `NOT` and parentheses, `-word` is short for `NOT word`
+//! - `title:`, `tag:` and `type:bookmark|note|archive`
+//! - `created:` and `modified:` with a date like `2022-06-30`, `2022-06` or `2022`, a range like
+//!   `2022-01..2022-06` or a comparison like `>=2022-01-01`
+//! - any other metadata field, e.g. `status:reading`
+//!
+//! Operators are upper case, so "and", "or", "not" and "near" in a query are just words.
+
+use chrono::NaiveDate;
+use thiserror::Error;
+
+use crate::tokenizer::tokenize_with_positions;
+
+/// How far apart the words of a bare `NEAR` may be.
+pub(crate) const DEFAULT_NEAR_DISTANCE: u32 = 5;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("The parenthesis at column {0} is never closed")]
+    UnclosedParen(usize),
+    #[error("The closing parenthesis at column {0} has no opening one")]
+    UnmatchedParen(usize),
+    #[error("The parentheses at column {0} are empty")]
+    EmptyParens(usize),
+    #[error("{op} at column {column} has nothing on its left")]
+    MissingLeft { op: String, column: usize },
+    #[error("{op} at column {column} has nothing on its right")]
+    MissingRight { op: String, column: usize },
+    #[error("NEAR at column {0} needs a single word on each side, like design NEAR/3 review")]
+    NearOperand(usize),
+    #[error("{field}: at column {column} needs a value, like {field}:{example}")]
+    EmptyValue {
+        field: String,
+        column: usize,
+        example: &'static str,
+    },
+    #[error("Unknown type {value:?} at column {column}, expected bookmark, note or archive")]
+    UnknownType { value: String, column: usize },
+    #[error(
+        "Could not read {value:?} at column {column} as a date, expected a date like 2022-06-30, \
+         a range like 2022-01..2022-06 or a comparison like >=2022-01-01"
+    )]
+    BadDate { value: String, column: usize },
+}
+
+/// A part of a search query matched against the search index.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Clause {
+    /// A word, which matches its variations as well.
+    Term(String),
+    /// Quoted words that have to appear in order, each with how far it is from the first.
+    Phrase(Vec<(u32, String)>),
+    /// Two words at most `distance` words apart, written `design NEAR/3 review`.
+    Near {
+        left: String,
+        right: String,
+        distance: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DocType {
+    /// Notes tagged `bookmark`.
+    Bookmark,
+    /// Notes that aren't bookmarks.
+    Note,
+    /// Bookmarks with an archived copy of the page.
+    Archive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DateField {
+    Created,
+    Modified,
+}
+
+impl DateField {
+    pub(crate) fn key(&self) -> &'static str {
+        match self {
+            DateField::Created => "created",
+            DateField::Modified => "modified",
+        }
+    }
+}
+
+/// Checks a note's metadata rather than its text.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Filter {
+    /// The title contains this, lower case.
+    Title(String),
+    Tag(String),
+    Type(Vec<DocType>),
+    /// A date between `from` and `to`, both included.
+    Date {
+        field: DateField,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+    /// Any other metadata, matched ignoring case.
+    Field {
+        name: String,
+        value: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Query {
+    Text(Clause),
+    Filter(Filter),
+    Not(Box<Query>),
+    Or(Vec<Query>),
+    /// Parts of a query next to each other. Notes have to match every `required` part. Plain
+    /// words are `optional` when something else narrows down the results, otherwise notes have
+    /// to match at least one of them. Either way they count towards ranking.
+    All {
+        required: Vec<Query>,
+        optional: Vec<Query>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Minus,
+    Quoted(String),
+    Word(String),
+    Field(String, String),
+}
+
+/// Splits a query into tokens, each with the column it starts at.
+fn lex(query: &str) -> Vec<(usize, Token)> {
+    let chars = query.chars().collect::<Vec<_>>();
+    let is_boundary = |c: char| c.is_whitespace() || c == '(' || c == ')' || c == '"';
+    // A quote that isn't closed runs to the end of the query.
+    let quoted = |start: usize| {
+        let end = chars[start..]
+            .iter()
+            .position(|&c| c == '"')
+            .map_or(chars.len(), |len| start + len);
+        (chars[start..end].iter().collect::<String>(), end + 1)
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            '(' => {
+                tokens.push((column, Token::Open));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((column, Token::Close));
+                i += 1;
+            }
+            '"' => {
+                let (text, next) = quoted(i + 1);
+                tokens.push((column, Token::Quoted(text)));
+                i = next;
+            }
+            // A hyphen inside a word or on its own doesn't exclude anything.
+            '-' if chars
+                .get(i + 1)
+                .is_some_and(|&c| !c.is_whitespace() && c != ')') =>
+            {
+                tokens.push((column, Token::Minus));
+                i += 1;
+            }
+            _ => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&c| is_boundary(c))
+                    .map_or(chars.len(), |len| i + len);
+                let word = chars[i..end].iter().collect::<String>();
+                i = end;
+                match word.split_once(':') {
+                    Some((name, value)) if is_field_name(name) => {
+                        let mut value = value.to_string();
+                        if value.is_empty() && chars.get(end) == Some(&'"') {
+                            let (text, next) = quoted(end + 1);
+                            value = text;
+                            i = next;
+                        }
+                        tokens.push((column, Token::Field(name.to_lowercase(), value)));
+                    }
+                    _ => tokens.push((column, Token::Word(word))),
+                }
+            }
+        }
+    }
+    tokens
+}
+
+fn is_field_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// `NEAR` or `NEAR/n`.
+fn near_distance(word: &str) -> Option<u32> {
+    match word.strip_prefix("NEAR")? {
+        "" => Some(DEFAULT_NEAR_DISTANCE),
+        rest => rest.strip_prefix('/')?.parse().ok(),
+    }
+}
+
+/// Words become a term, or a phrase when punctuation splits them, e.g. `state-of-the-art`.
+fn text(words: &str) -> Option<Query> {
+    let mut words = tokenize_with_positions(words);
+    match words.len() {
+        0 => None,
+        1 => Some(Query::Text(Clause::Term(words.remove(0).1))),
+        _ => {
+            let first = words[0].0;
+            let words = words.into_iter().map(|(p, w)| (p - first, w)).collect();
+            Some(Query::Text(Clause::Phrase(words)))
+        }
+    }
+}
+
+/// The first and last day of `2022`, `2022-06` or `2022-06-30`.
+fn period(value: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let ymd = |year: u32, month, day| NaiveDate::from_ymd_opt(year as i32, month, day);
+    let parts = value
+        .split('-')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match parts[..] {
+        [year] => Some((ymd(year, 1, 1)?, ymd(year, 12, 31)?)),
+        [year, month] => {
+            let next = if month == 12 {
+                ymd(year + 1, 1, 1)
+            } else {
+                ymd(year, month + 1, 1)
+            };
+            Some((ymd(year, month, 1)?, next?.pred_opt()?))
+        }
+        [year, month, day] => {
+            let date = ymd(year, month, day)?;
+            Some((date, date))
+        }
+        _ => None,
+    }
+}
+
+type DateRange = (Option<NaiveDate>, Option<NaiveDate>);
+
+fn date_range(value: &str) -> Option<DateRange> {
+    if let Some(date) = value.strip_prefix(">=") {
+        return period(date).map(|(first, _)| (Some(first), None));
+    }
+    if let Some(date) = value.strip_prefix("<=") {
+        return period(date).map(|(_, last)| (None, Some(last)));
+    }
+    if let Some(date) = value.strip_prefix('>') {
+        return period(date).and_then(|(_, last)| Some((Some(last.succ_opt()?), None)));
+    }
+    if let Some(date) = value.strip_prefix('<') {
+        return period(date).and_then(|(first, _)| Some((None, Some(first.pred_opt()?))));
+    }
+    match value.split_once("..") {
+        Some((from, to)) if !(from.is_empty() && to.is_empty()) => {
+            let from = match from {
+                "" => None,
+                from => Some(period(from)?.0),
+            };
+            let to = match to {
+                "" => None,
+                to => Some(period(to)?.1),
+            };
+            Some((from, to))
+        }
+        Some(_) => None,
+        None => period(value).map(|(first, last)| (Some(first), Some(last))),
+    }
+}
+
+fn filter(name: &str, value: &str, column: usize) -> Result<Filter, QueryError> {
+    let value = value.trim();
+    if value.is_empty() {
+        let example = match name {
+            "title" => "\"design review\"",
+            "tag" => "reading",
+            "type" => "bookmark",
+            "created" | "modified" => "2022-06",
+            _ => "something",
+        };
+        return Err(QueryError::EmptyValue {
+            field: name.into(),
+            column,
+            example,
+        });
+    }
+    let filter = match name {
+        "title" => Filter::Title(value.to_lowercase()),
+        "tag" => Filter::Tag(value.trim_start_matches('#').to_lowercase()),
+        "type" => Filter::Type(
+            value
+                .split('|')
+                .map(|t| match t.to_lowercase().as_str() {
+                    "bookmark" => Ok(DocType::Bookmark),
+                    "note" => Ok(DocType::Note),
+                    "archive" => Ok(DocType::Archive),
+                    _ => Err(QueryError::UnknownType {
+                        value: t.into(),
+                        column,
+                    }),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        "created" | "modified" => {
+            let (from, to) = date_range(value).ok_or_else(|| QueryError::BadDate {
+                value: value.into(),
+                column,
+            })?;
+            let field = if name == "created" {
+                DateField::Created
+            } else {
+                DateField::Modified
+            };
+            Filter::Date { field, from, to }
+        }
+        _ => Filter::Field {
+            name: name.into(),
+            value: value.to_lowercase(),
+        },
+    };
+    Ok(filter)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.next).map_or(0, |(column, _)| *column)
+    }
+
+    fn peek_operator(&self, operator: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word == operator)
+    }
+
+    /// Whether the next token can't start the right side of an operator.
+    fn at_boundary(&self) -> bool {
+        matches!(self.peek(), None | Some(Token::Close))
+            || self.peek_operator("AND")
+            || self.peek_operator("OR")
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut parts = vec![self.all()?];
+        while self.peek_operator("OR") {
+            let column = self.column();
+            if matches!(parts.last(), Some(Query::All { required, optional }) if required.is_empty() && optional.is_empty())
+            {
+                return Err(QueryError::MissingLeft {
+                    op: "OR".into(),
+                    column,
+                });
+            }
+            self.next += 1;
+            if self.at_boundary() {
+                return Err(QueryError::MissingRight {
+                    op: "OR".into(),
+                    column,
+                });
+            }
+            parts.push(self.all()?);
+        }
+        Ok(if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Query::Or(parts)
+        })
+    }
+
+    fn all(&mut self) -> Result<Query, QueryError> {
+        // Each part along with whether an AND made it required.
+        let mut parts: Vec<(Query, bool)> = Vec::new();
+        while !matches!(self.peek(), None | Some(Token::Close)) && !self.peek_operator("OR") {
+            let column = self.column();
+            if self.peek_operator("AND") {
+                let last = match parts.last_mut() {
+                    Some(last) => last,
+                    None => {
+                        return Err(QueryError::MissingLeft {
+                            op: "AND".into(),
+                            column,
+                        })
+                    }
+                };
+                last.1 = true;
+                self.next += 1;
+                if self.at_boundary() {
+                    return Err(QueryError::MissingRight {
+                        op: "AND".into(),
+                        column,
+                    });
+                }
+                if let Some(part) = self.part()? {
+                    parts.push((part, true));
+                }
+                continue;
+            }
+            if let Some(Token::Word(word)) = self.peek() {
+                if let Some(distance) = near_distance(word) {
+                    self.next += 1;
+                    let left = match parts.pop() {
+                        Some((Query::Text(Clause::Term(left)), _)) => left,
+                        _ => return Err(QueryError::NearOperand(column)),
+                    };
+                    let right = match self.peek() {
+                        Some(Token::Word(_)) => self.part()?,
+                        _ => None,
+                    };
+                    let right = match right {
+                        Some(Query::Text(Clause::Term(right))) => right,
+                        _ => return Err(QueryError::NearOperand(column)),
+                    };
+                    let near = Clause::Near {
+                        left,
+                        right,
+                        distance,
+                    };
+                    parts.push((Query::Text(near), true));
+                    continue;
+                }
+            }
+            if let Some(part) = self.part()? {
+                parts.push((part, false));
+            }
+        }
+        let (optional, required): (Vec<_>, Vec<_>) = parts
+            .into_iter()
+            .partition(|(part, and)| !and && matches!(part, Query::Text(Clause::Term(_))));
+        let mut required = required
+            .into_iter()
+            .map(|(part, _)| part)
+            .collect::<Vec<_>>();
+        let mut optional = optional
+            .into_iter()
+            .map(|(part, _)| part)
+            .collect::<Vec<_>>();
+        if required.len() + optional.len() == 1 {
+            return Ok(required.pop().or_else(|| optional.pop()).unwrap());
+        }
+        Ok(Query::All { required, optional })
+    }
+
+    /// A single part of a query, `None` when it's only stop words.
+    fn part(&mut self) -> Result<Option<Query>, QueryError> {
+        let (column, token) = match self.tokens.get(self.next) {
+            Some((column, token)) => (*column, token.clone()),
+            None => return Ok(None),
+        };
+        self.next += 1;
+        match token {
+            Token::Open => {
+                if matches!(self.peek(), Some(Token::Close)) {
+                    return Err(QueryError::EmptyParens(column));
+                }
+                let inner = self.or()?;
+                if !matches!(self.peek(), Some(Token::Close)) {
+                    return Err(QueryError::UnclosedParen(column));
+                }
+                self.next += 1;
+                Ok(Some(inner))
+            }
+            Token::Close => Err(QueryError::UnmatchedParen(column)),
+            Token::Minus => self.not("-", column),
+            Token::Word(word) if word == "NOT" => self.not("NOT", column),
+            Token::Quoted(words) | Token::Word(words) => Ok(text(&words)),
+            Token::Field(name, value) => Ok(Some(Query::Filter(filter(&name, &value, column)?))),
+        }
+    }
+
+    fn not(&mut self, op: &str, column: usize) -> Result<Option<Query>, QueryError> {
+        if self.at_boundary() {
+            return Err(QueryError::MissingRight {
+                op: op.into(),
+                column,
+            });
+        }
+        Ok(self.part()?.map(|inner| Query::Not(Box::new(inner))))
+    }
+}
+
+/// Parses a search query, see the module documentation for what it can contain.
+pub(crate) fn parse_query(query: &str) -> Result<Query, QueryError> {
+    let mut parser = Parser {
+        tokens: lex(query),
+        next: 0,
+    };
+    let parsed = parser.or()?;
+    match parser.tokens.get(parser.next) {
+        Some((column, _)) => Err(QueryError::UnmatchedParen(*column)),
+        None => Ok(parsed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(word: &str) -> Query {
+        Query::Text(Clause::Term(word.into()))
+    }
+
+    fn date(y: i32, m: u32, d: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(y, m, d)
+    }
+
+    #[test]
+    fn parses_phrases_and_near() {
+        assert_eq!(
+            parse_query(r#"notes "state of the art" design NEAR/3 review"#).unwrap(),
+            Query::All {
+                required: vec![
+                    Query::Text(Clause::Phrase(vec![(0, "state".into()), (3, "art".into())])),
+                    Query::Text(Clause::Near {
+                        left: "design".into(),
+                        right: "review".into(),
+                        distance: 3,
+                    }),
+                ],
+                optional: vec![term("notes")],
+            }
+        );
+        assert_eq!(
+            parse_query(r#"cat NEAR dog "lonely" "unclosed quote"#).unwrap(),
+            Query::All {
+                required: vec![
+                    Query::Text(Clause::Near {
+                        left: "cat".into(),
+                        right: "dog".into(),
+                        distance: DEFAULT_NEAR_DISTANCE,
+                    }),
+                    Query::Text(Clause::Phrase(vec![
+                        (0, "unclosed".into()),
+                        (1, "quote".into())
+                    ])),
+                ],
+                optional: vec![term("lonely")],
+            }
+        );
+        // Lower case "near" is just a word.
+        assert_eq!(
+            parse_query("near home").unwrap(),
+            Query::All {
+                required: vec![],
+                optional: vec![term("near"), term("home")],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_operators() {
+        assert_eq!(
+            parse_query("(rust OR go) AND -draft tag:lang").unwrap(),
+            Query::All {
+                required: vec![
+                    Query::Or(vec![term("rust"), term("go")]),
+                    Query::Not(Box::new(term("draft"))),
+                    Query::Filter(Filter::Tag("lang".into())),
+                ],
+                optional: vec![],
+            }
+        );
+        assert_eq!(
+            parse_query("NOT title:\"Design Review\" status:Reading").unwrap(),
+            Query::All {
+                required: vec![
+                    Query::Not(Box::new(Query::Filter(Filter::Title(
+                        "design review".into()
+                    )))),
+                    Query::Filter(Filter::Field {
+                        name: "status".into(),
+                        value: "reading".into(),
+                    }),
+                ],
+                optional: vec![],
+            }
+        );
+        assert_eq!(
+            parse_query("type:bookmark|archive").unwrap(),
+            Query::Filter(Filter::Type(vec![DocType::Bookmark, DocType::Archive]))
+        );
+        // Hyphens inside a word don't exclude anything.
+        assert_eq!(
+            parse_query("state-of-the-art").unwrap(),
+            Query::Text(Clause::Phrase(vec![(0, "state".into()), (3, "art".into())]))
+        );
+    }
+
+    #[test]
+    fn parses_dates() {
+        let range = |query| match parse_query(query).unwrap() {
+            Query::Filter(Filter::Date { from, to, .. }) => (from, to),
+            parsed => panic!("{:?} is not a date filter", parsed),
+        };
+        assert_eq!(
+            range("created:2022"),
+            (date(2022, 1, 1), date(2022, 12, 31))
+        );
+        assert_eq!(
+            range("created:2024-02"),
+            (date(2024, 2, 1), date(2024, 2, 29))
+        );
+        assert_eq!(
+            range("modified:2022-01..2022-06"),
+            (date(2022, 1, 1), date(2022, 6, 30))
+        );
+        assert_eq!(range("modified:..2022-06-15"), (None, date(2022, 6, 15)));
+        assert_eq!(range("created:>2022-12"), (date(2023, 1, 1), None));
+        assert_eq!(range("created:<=2022-12"), (None, date(2022, 12, 31)));
+    }
+
+    #[test]
+    fn explains_malformed_queries() {
+        let error = |query| parse_query(query).unwrap_err().to_string();
+        assert_eq!(
+            error("(rust OR go"),
+            "The parenthesis at column 1 is never closed"
+        );
+        assert_eq!(
+            error("rust) go"),
+            "The closing parenthesis at column 5 has no opening one"
+        );
+        assert_eq!(error("rust OR"), "OR at column 6 has nothing on its right");
+        assert_eq!(error("AND rust"), "AND at column 1 has nothing on its left");
+        assert_eq!(
+            error("rust NOT"),
+            "NOT at column 6 has nothing on its right"
+        );
+        assert_eq!(
+            error("rust AND"),
+            "AND at column 6 has nothing on its right"
+        );
+        assert_eq!(
+            error("tag: rust"),
+            "tag: at column 1 needs a value, like tag:reading"
+        );
+        assert_eq!(
+            error("type:page"),
+            "Unknown type \"page\" at column 1, expected bookmark, note or archive"
+        );
+        assert!(error("created:yesterday").starts_with("Could not read \"yesterday\""));
+        assert!(error("created:2022-13").starts_with("Could not read \"2022-13\""));
+        assert!(error("\"design review\" NEAR/2 rust").starts_with("NEAR at column 17"));
+    }
+}
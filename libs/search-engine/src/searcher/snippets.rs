@@ -0,0 +1,190 @@
+use std::{collections::HashSet, ops::Range};
+
+use super::{
+    query::{Clause, Query},
+    variations_of_word, Source,
+};
+use crate::tokenizer::word_spans;
+
+/// How many words a passage has.
+const PASSAGE_WORDS: usize = 30;
+/// How many words come before the first match in a passage.
+const LEADING_WORDS: usize = 8;
+const MAX_PASSAGES: usize = 2;
+
+fn collect_words(query: &Query, words: &mut HashSet<String>) {
+    match query {
+        Query::Text(Clause::Term(term)) => words.extend(variations_of_word(term)),
+        Query::Text(Clause::Phrase(phrase)) => {
+            words.extend(phrase.iter().map(|(_, word)| word.clone()))
+        }
+        Query::Text(Clause::Near { left, right, .. }) => {
+            words.extend(variations_of_word(left));
+            words.extend(variations_of_word(right));
+        }
+        // Notes don't have what they were searched without.
+        Query::Filter(_) | Query::Not(_) => {}
+        Query::Or(parts) => parts.iter().for_each(|part| collect_words(part, words)),
+        Query::All { required, optional } => required
+            .iter()
+            .chain(optional)
+            .for_each(|part| collect_words(part, words)),
+    }
+}
+
+/// The words to highlight, each word of the query along with its variations.
+pub(crate) fn highlighted_words(query: &Query) -> HashSet<String> {
+    let mut words = HashSet::new();
+    collect_words(query, &mut words);
+    words
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The words `range` of `spans` as HTML on a single line, with matches in `<mark>`.
+fn render(text: &str, spans: &[Range<usize>], range: Range<usize>, matched: &[bool]) -> String {
+    let mut html = String::new();
+    if range.start > 0 {
+        html.push_str("… ");
+    }
+    let mut end = spans[range.start].start;
+    for i in range.clone() {
+        html.push_str(&escape(&text[end..spans[i].start]));
+        let word = escape(&text[spans[i].clone()]);
+        if matched[i] {
+            html.push_str("<mark>");
+            html.push_str(&word);
+            html.push_str("</mark>");
+        } else {
+            html.push_str(&word);
+        }
+        end = spans[i].end;
+    }
+    // Punctuation after the last word is part of the passage.
+    match spans.get(range.end) {
+        Some(next) => {
+            html.push_str(&escape(&text[end..next.start]));
+            html.push_str(" …");
+        }
+        None => html.push_str(&escape(&text[end..])),
+    }
+    html.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Up to [MAX_PASSAGES] passages of `text` with the most of `words` in them, best first. Empty
+/// when none of the words are in the text.
+pub(crate) fn passages(text: &str, words: &HashSet<String>) -> Vec<String> {
+    let spans = word_spans(text);
+    let lowered = spans
+        .iter()
+        .map(|span| text[span.clone()].to_lowercase())
+        .collect::<Vec<_>>();
+    let matched = lowered
+        .iter()
+        .map(|word| words.contains(word))
+        .collect::<Vec<_>>();
+    // Passages start a little before each match, and score by how many different words they
+    // match, then by how many matches they have.
+    let mut candidates = (0..spans.len())
+        .filter(|&i| matched[i])
+        .map(|i| {
+            let start = i.saturating_sub(LEADING_WORDS);
+            let range = start..(start + PASSAGE_WORDS).min(spans.len());
+            let found = range.clone().filter(|&j| matched[j]);
+            let distinct = found.clone().map(|j| &lowered[j]).collect::<HashSet<_>>();
+            ((distinct.len(), found.count()), range)
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.start.cmp(&b.1.start)));
+    let mut chosen: Vec<Range<usize>> = Vec::new();
+    for (_, range) in candidates {
+        if chosen.len() == MAX_PASSAGES {
+            break;
+        }
+        if chosen
+            .iter()
+            .all(|other| range.end <= other.start || other.end <= range.start)
+        {
+            chosen.push(range);
+        }
+    }
+    chosen
+        .into_iter()
+        .map(|range| render(text, &spans, range, &matched))
+        .collect()
+}
+
+/// The start of a text, for notes that matched a filter rather than words.
+fn opening(text: &str) -> Option<String> {
+    let spans = word_spans(text);
+    if spans.is_empty() {
+        return None;
+    }
+    let range = 0..PASSAGE_WORDS.min(spans.len());
+    Some(render(text, &spans, range, &vec![false; spans.len()]))
+}
+
+/// Passages of a note, or of its archived page when the note itself has none of the words.
+pub(crate) fn snippets(title: &str, words: &HashSet<String>, source: &impl Source) -> Vec<String> {
+    let body = source.body(title);
+    if let Some(body) = &body {
+        let found = passages(body, words);
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    let archive = source.archive_text(title);
+    if let Some(archive) = &archive {
+        let found = passages(archive, words);
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    body.or(archive)
+        .and_then(|text| opening(&text))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::searcher::query::parse_query;
+
+    fn words(query: &str) -> HashSet<String> {
+        highlighted_words(&parse_query(query).unwrap())
+    }
+
+    #[test]
+    fn highlights_variations() {
+        let text = "Reviewing the design <draft> took a while. The review itself was short.";
+        assert_eq!(
+            passages(text, &words("review -draft")),
+            vec![
+                "<mark>Reviewing</mark> the design &lt;draft&gt; took a while. The \
+                 <mark>review</mark> itself was short."
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_the_best_passages() {
+        let filler = "lorem ipsum ".repeat(40);
+        let text = format!(
+            "A design here.\n{}Then a design review,\nwith notes.\n{}And one more design.",
+            filler, filler
+        );
+        let found = passages(&text, &words("design review"));
+        assert_eq!(found.len(), 2);
+        assert!(found[0].contains("<mark>design</mark> <mark>review</mark>, with notes."));
+        assert!(found[0].starts_with("… lorem"));
+        assert!(found[1].starts_with("A <mark>design</mark> here. lorem"));
+        assert!(found[1].ends_with(" …"));
+        assert!(passages(&text, &words("elephant")).is_empty());
+    }
+}
@@ -0,0 +1,500 @@
+//! The search index is a handful of segment files in `search-index/`. A segment maps search terms
+//! to their postings with an FST and is memory-mapped, so a query only reads the postings of the
+//! terms it looks up. Updates are written as small delta segments, which have the final say on
+//! the notes they cover, and segments are merged back into one once there are too many of them.
+//!
+//! Term names are blinded and every postings list is encrypted on its own, so an encrypted
+//! notebook gives away no more than it did with a file per term.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use byteorder::{ByteOrder, LittleEndian};
+use fst::{Map, MapBuilder, Streamer};
+use memmap2::Mmap;
+use persistance::crypto::{blind_name, open, protect};
+
+use crate::{DocTitle, NormalizedFrequency, Positions, SearchIndexErr, Tokens};
+
+const MAGIC: &[u8; 4] = b"TSEG";
+/// Version 2 added term positions to the postings.
+const VERSION: u32 = 2;
+/// Magic, version, length of the FST and where the list of notes starts.
+const HEADER_LEN: usize = 24;
+const EXTENSION: &str = "seg";
+/// Segments are merged once there are more than this many.
+const MAX_SEGMENTS: usize = 8;
+
+static MERGES: AtomicU64 = AtomicU64::new(0);
+
+pub type Posting = (DocTitle, NormalizedFrequency, Positions);
+
+/// Part of a mapped segment file, which the FST is read from directly.
+#[derive(Clone)]
+struct MappedBytes {
+    mmap: Arc<Mmap>,
+    start: usize,
+    end: usize,
+}
+
+impl AsRef<[u8]> for MappedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap[self.start..self.end]
+    }
+}
+
+fn corrupt(path: &Path, reason: &str) -> SearchIndexErr {
+    SearchIndexErr::CorruptSegment(format!("{:?}: {}", path, reason))
+}
+
+/// Segments are named after the generations they cover, a merged segment covers all of those of
+/// the segments it replaced.
+fn file_name(first: u64, last: u64) -> String {
+    format!("{:010}-{:010}.{}", first, last, EXTENSION)
+}
+
+fn parse_file_name(path: &Path) -> Option<(u64, u64)> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+    let (first, last) = path.file_stem()?.to_str()?.split_once('-')?;
+    Some((first.parse().ok()?, last.parse().ok()?))
+}
+
+fn write_blob(out: &mut Vec<u8>, bytes: Vec<u8>) {
+    let bytes = protect(bytes);
+    let mut len = [0; 4];
+    LittleEndian::write_u32(&mut len, bytes.len() as u32);
+    out.extend_from_slice(&len);
+    out.extend_from_slice(&bytes);
+}
+
+/// Writes a segment to a temporary file next to `path` and renames it into place. The postings are
+/// keyed by blinded term name.
+fn write_segment(
+    path: &Path,
+    postings: &BTreeMap<String, Vec<Posting>>,
+    docs: &BTreeSet<DocTitle>,
+) -> Result<(), SearchIndexErr> {
+    let mut body = Vec::new();
+    let mut terms = MapBuilder::memory();
+    for (key, list) in postings.iter().filter(|(_, list)| !list.is_empty()) {
+        terms
+            .insert(key, body.len() as u64)
+            .map_err(|e| SearchIndexErr::WriteErr(e.to_string()))?;
+        write_blob(
+            &mut body,
+            bincode::serialize(list).map_err(SearchIndexErr::DeserErr)?,
+        );
+    }
+    let terms = terms
+        .into_inner()
+        .map_err(|e| SearchIndexErr::WriteErr(e.to_string()))?;
+    let docs_offset = HEADER_LEN + terms.len() + body.len();
+    write_blob(
+        &mut body,
+        bincode::serialize(docs).map_err(SearchIndexErr::DeserErr)?,
+    );
+
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(MAGIC);
+    LittleEndian::write_u32(&mut header[4..8], VERSION);
+    LittleEndian::write_u64(&mut header[8..16], terms.len() as u64);
+    LittleEndian::write_u64(&mut header[16..24], docs_offset as u64);
+    let temp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap().to_string_lossy(),
+        process::id()
+    ));
+    let result = (|| -> io::Result<()> {
+        let mut file = File::create(&temp)?;
+        file.write_all(&header)?;
+        file.write_all(&terms)?;
+        file.write_all(&body)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if let Err(e) = result {
+        fs::remove_file(&temp).ok();
+        return Err(SearchIndexErr::WriteErr(format!(
+            "Could not write {:?}\n  {}",
+            path, e
+        )));
+    }
+    Ok(())
+}
+
+pub struct Segment {
+    first: u64,
+    last: u64,
+    path: PathBuf,
+    mmap: Arc<Mmap>,
+    terms: Map<MappedBytes>,
+    postings_start: usize,
+    /// Every note the segment has the final say on, including the ones it removes from the index.
+    docs: HashSet<DocTitle>,
+}
+
+impl Segment {
+    fn open(path: &Path, first: u64, last: u64) -> Result<Segment, SearchIndexErr> {
+        let file = File::open(path).map_err(SearchIndexErr::Io)?;
+        // Segments are never changed once written, only replaced.
+        let mmap = Arc::new(unsafe { Mmap::map(&file) }.map_err(SearchIndexErr::Io)?);
+        if mmap.len() < HEADER_LEN || &mmap[..4] != MAGIC {
+            return Err(corrupt(path, "not a search index segment"));
+        }
+        let version = LittleEndian::read_u32(&mmap[4..8]);
+        if version != VERSION {
+            return Err(corrupt(path, &format!("unsupported version {}", version)));
+        }
+        let postings_start = HEADER_LEN + LittleEndian::read_u64(&mmap[8..16]) as usize;
+        let docs_offset = LittleEndian::read_u64(&mmap[16..24]) as usize;
+        if postings_start > docs_offset || docs_offset > mmap.len() {
+            return Err(corrupt(path, "truncated"));
+        }
+        let terms = Map::new(MappedBytes {
+            mmap: mmap.clone(),
+            start: HEADER_LEN,
+            end: postings_start,
+        })
+        .map_err(|e| corrupt(path, &e.to_string()))?;
+        let mut segment = Segment {
+            first,
+            last,
+            path: path.to_owned(),
+            mmap,
+            terms,
+            postings_start,
+            docs: HashSet::new(),
+        };
+        let docs: Vec<DocTitle> = bincode::deserialize(&segment.read_blob(docs_offset)?)
+            .map_err(SearchIndexErr::DeserErr)?;
+        segment.docs = docs.into_iter().collect();
+        Ok(segment)
+    }
+
+    fn read_blob(&self, offset: usize) -> Result<Vec<u8>, SearchIndexErr> {
+        let start = offset + 4;
+        let len = self
+            .mmap
+            .get(offset..start)
+            .map(LittleEndian::read_u32)
+            .ok_or_else(|| corrupt(&self.path, "postings out of bounds"))?;
+        let bytes = self
+            .mmap
+            .get(start..start + len as usize)
+            .ok_or_else(|| corrupt(&self.path, "postings out of bounds"))?;
+        open(bytes.to_vec()).map_err(SearchIndexErr::DecryptErr)
+    }
+
+    fn read_postings(&self, offset: u64) -> Result<Vec<Posting>, SearchIndexErr> {
+        let bytes = self.read_blob(self.postings_start + offset as usize)?;
+        bincode::deserialize(&bytes).map_err(SearchIndexErr::DeserErr)
+    }
+
+    /// Postings of a blinded term, `None` when the segment doesn't have the term.
+    fn postings(&self, key: &str) -> Result<Option<Vec<Posting>>, SearchIndexErr> {
+        match self.terms.get(key) {
+            Some(offset) => self.read_postings(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn covers(&self, other: &Segment) -> bool {
+        self.first <= other.first && other.last <= self.last
+    }
+}
+
+/// A merged segment that's been written but not yet put in place of the segments it replaces.
+pub struct Merged {
+    temp: PathBuf,
+    first: u64,
+    last: u64,
+    inputs: Vec<Arc<Segment>>,
+}
+
+/// The segments of the search index, oldest first.
+pub struct Index {
+    location: PathBuf,
+    segments: Vec<Arc<Segment>>,
+}
+
+impl Index {
+    /// Opens the segments in `location`, creating it if needed. Segments left behind by a merge
+    /// that was interrupted are covered by the merged one and removed.
+    pub fn open(location: &Path) -> Result<Index, SearchIndexErr> {
+        fs::create_dir_all(location).map_err(SearchIndexErr::Io)?;
+        let mut ranges = Vec::new();
+        for entry in fs::read_dir(location).map_err(SearchIndexErr::Io)? {
+            let path = entry.map_err(SearchIndexErr::Io)?.path();
+            if let Some((first, last)) = parse_file_name(&path) {
+                ranges.push((first, last, path));
+            } else if path.extension().map(|ext| ext == "tmp").unwrap_or(false) {
+                fs::remove_file(&path).ok();
+            }
+        }
+        let mut segments = Vec::new();
+        for (first, last, path) in ranges.iter() {
+            let covered = ranges.iter().any(|(other_first, other_last, other)| {
+                other != path && other_first <= first && last <= other_last
+            });
+            if covered {
+                fs::remove_file(path).ok();
+                continue;
+            }
+            segments.push(Arc::new(Segment::open(path, *first, *last)?));
+        }
+        segments.sort_by_key(|segment| segment.last);
+        Ok(Index {
+            location: location.to_owned(),
+            segments,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn needs_merge(&self) -> bool {
+        self.segments.len() > MAX_SEGMENTS
+    }
+
+    /// The postings of a term across every segment. A note's postings come from the newest
+    /// segment that covers the note.
+    pub fn postings(&self, term: &str) -> Result<Vec<Posting>, SearchIndexErr> {
+        let key = blind_name(term);
+        let mut found = Vec::new();
+        for (position, segment) in self.segments.iter().enumerate() {
+            if let Some(postings) = segment.postings(&key)? {
+                // There are only a few segments, so asking each newer one is cheaper than
+                // collecting the notes they cover, which for the oldest is every note.
+                let newer = &self.segments[position + 1..];
+                found.extend(postings.into_iter().filter(|(title, ..)| {
+                    !newer.iter().any(|segment| segment.docs.contains(title))
+                }));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Writes a new segment that replaces whatever older segments say about `docs`. Notes in
+    /// `docs` without postings are removed from the index.
+    pub fn add(&mut self, tokens: Tokens, docs: Vec<DocTitle>) -> Result<(), SearchIndexErr> {
+        let postings = tokens
+            .into_iter()
+            .map(|(term, postings)| (blind_name(&term), postings))
+            .collect::<BTreeMap<String, Vec<Posting>>>();
+        let docs = docs.into_iter().collect();
+        let generation = self.segments.last().map(|s| s.last + 1).unwrap_or(1);
+        let path = self.location.join(file_name(generation, generation));
+        write_segment(&path, &postings, &docs)?;
+        let segment = Segment::open(&path, generation, generation)?;
+        self.segments.push(Arc::new(segment));
+        Ok(())
+    }
+
+    /// The segments to merge. Merging takes a while on a large wiki, so it happens on these
+    /// without holding on to the index, and [Index::replace] swaps the result in.
+    pub fn merge_inputs(&self) -> Vec<Arc<Segment>> {
+        self.segments.clone()
+    }
+
+    /// Merges segments, oldest first, into one. Notes that were removed are dropped for good,
+    /// since there's nothing older left for them to shadow.
+    pub fn merge(location: &Path, inputs: Vec<Arc<Segment>>) -> Result<Merged, SearchIndexErr> {
+        let mut postings: BTreeMap<String, Vec<Posting>> = BTreeMap::new();
+        let mut docs = BTreeSet::new();
+        let mut shadowed: HashSet<&str> = HashSet::new();
+        for segment in inputs.iter().rev() {
+            let mut stream = segment.terms.stream();
+            while let Some((key, offset)) = stream.next() {
+                let key = String::from_utf8(key.to_vec())
+                    .map_err(|_| corrupt(&segment.path, "term is not UTF-8"))?;
+                let kept = segment
+                    .read_postings(offset)?
+                    .into_iter()
+                    .filter(|(title, ..)| !shadowed.contains(title.as_str()))
+                    .collect::<Vec<Posting>>();
+                docs.extend(kept.iter().map(|(title, ..)| title.clone()));
+                postings.entry(key).or_default().extend(kept);
+            }
+            shadowed.extend(segment.docs.iter().map(|title| title.as_str()));
+        }
+        let first = inputs.first().map(|s| s.first).unwrap_or(1);
+        let last = inputs.last().map(|s| s.last).unwrap_or(1);
+        let temp = location.join(format!(
+            ".merge-{}-{}.tmp",
+            MERGES.fetch_add(1, Ordering::Relaxed),
+            file_name(first, last)
+        ));
+        write_segment(&temp, &postings, &docs)?;
+        Ok(Merged {
+            temp,
+            first,
+            last,
+            inputs,
+        })
+    }
+
+    /// Puts a merged segment in place of its inputs. If the index changed under the merge (it was
+    /// rebuilt, or merged already) the merged segment is thrown away.
+    pub fn replace(&mut self, merged: Merged) -> Result<(), SearchIndexErr> {
+        let current = merged.inputs.iter().all(|input| {
+            self.segments
+                .iter()
+                .any(|segment| Arc::ptr_eq(segment, input))
+        });
+        if !current || merged.inputs.is_empty() {
+            fs::remove_file(&merged.temp).ok();
+            return Ok(());
+        }
+        let path = self.location.join(file_name(merged.first, merged.last));
+        fs::rename(&merged.temp, &path).map_err(SearchIndexErr::Io)?;
+        let segment = Segment::open(&path, merged.first, merged.last)?;
+        let (replaced, kept): (Vec<_>, Vec<_>) = self
+            .segments
+            .drain(..)
+            .partition(|existing| segment.covers(existing));
+        self.segments = kept;
+        self.segments.push(Arc::new(segment));
+        self.segments.sort_by_key(|segment| segment.last);
+        // A search may still be reading from the old segments, their files stay readable on
+        // Unix until they're unmapped. Whatever can't be removed now goes on the next open.
+        for old in replaced {
+            fs::remove_file(&old.path).ok();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/search-index/";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = PathBuf::from(TEST_DIR).join(name);
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+        dir
+    }
+
+    fn doc(title: &str, terms: &[(&str, f32)]) -> (Tokens, Vec<DocTitle>) {
+        let tokens = terms
+            .iter()
+            .map(|(term, score)| (term.to_string(), vec![(title.to_string(), *score, vec![0])]))
+            .collect::<HashMap<_, _>>();
+        (tokens, vec![title.to_string()])
+    }
+
+    fn titles(index: &Index, term: &str) -> Vec<String> {
+        let mut titles = index
+            .postings(term)
+            .unwrap()
+            .into_iter()
+            .map(|(title, ..)| title)
+            .collect::<Vec<_>>();
+        titles.sort();
+        titles
+    }
+
+    #[test]
+    fn deltas_shadow_older_segments() {
+        let dir = temp_dir("deltas");
+        let mut index = Index::open(&dir).unwrap();
+        let mut tokens = Tokens::new();
+        tokens.insert(
+            "rust".into(),
+            vec![
+                ("Crabs".into(), 0.5, vec![3]),
+                ("Iron".into(), 0.2, vec![1, 8]),
+            ],
+        );
+        tokens.insert("metal".into(), vec![("Iron".into(), 0.4, vec![2])]);
+        index
+            .add(tokens, vec!["Crabs".into(), "Iron".into()])
+            .unwrap();
+        assert_eq!(titles(&index, "rust"), vec!["Crabs", "Iron"]);
+
+        let (tokens, docs) = doc("Iron", &[("metal", 0.6), ("ore", 0.3)]);
+        index.add(tokens, docs).unwrap();
+        assert_eq!(titles(&index, "rust"), vec!["Crabs"]);
+        assert_eq!(
+            index.postings("metal").unwrap(),
+            vec![("Iron".into(), 0.6, vec![0])]
+        );
+        // Removing a note is a segment that covers it without any postings.
+        index.add(Tokens::new(), vec!["Crabs".into()]).unwrap();
+        assert!(titles(&index, "rust").is_empty());
+        assert!(index.postings("missing").unwrap().is_empty());
+
+        let reopened = Index::open(&dir).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(titles(&reopened, "ore"), vec!["Iron"]);
+    }
+
+    #[test]
+    fn merges_into_one_segment() {
+        let dir = temp_dir("merge");
+        let mut index = Index::open(&dir).unwrap();
+        for (title, term) in [("One", "first"), ("Two", "second"), ("One", "again")] {
+            let (tokens, docs) = doc(title, &[(term, 1.0), ("shared", 0.5)]);
+            index.add(tokens, docs).unwrap();
+        }
+        index.add(Tokens::new(), vec!["Two".into()]).unwrap();
+        let merged = Index::merge(&dir, index.merge_inputs()).unwrap();
+        // Updates made while merging stay on top of the merged segment.
+        let (tokens, docs) = doc("Three", &[("shared", 0.1)]);
+        index.add(tokens, docs).unwrap();
+        index.replace(merged).unwrap();
+        assert_eq!(index.len(), 2);
+        assert!(index.postings("first").unwrap().is_empty());
+        assert!(index.postings("second").unwrap().is_empty());
+        assert_eq!(titles(&index, "again"), vec!["One"]);
+        assert_eq!(titles(&index, "shared"), vec!["One", "Three"]);
+
+        // A merge of segments that are gone is dropped.
+        let stale = Index::merge(&dir, index.merge_inputs()).unwrap();
+        let fresh = Index::merge(&dir, index.merge_inputs()).unwrap();
+        index.replace(fresh).unwrap();
+        index.replace(stale).unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(titles(&index, "shared"), vec!["One", "Three"]);
+        let files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn open_removes_segments_a_merge_replaced() {
+        let dir = temp_dir("interrupted");
+        let mut index = Index::open(&dir).unwrap();
+        for title in ["One", "Two"] {
+            let (tokens, docs) = doc(title, &[("shared", 1.0)]);
+            index.add(tokens, docs).unwrap();
+        }
+        let merged = Index::merge(&dir, index.merge_inputs()).unwrap();
+        // Stop after the merged segment is in place, before the old ones are removed.
+        fs::rename(&merged.temp, dir.join(file_name(1, 2))).unwrap();
+        let reopened = Index::open(&dir).unwrap();
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(titles(&reopened, "shared"), vec!["One", "Two"]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
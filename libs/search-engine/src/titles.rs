@@ -0,0 +1,211 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDateTime;
+
+use crate::searcher::note_time;
+
+/// Queries shorter than this only complete, they are too short to say what a typo would be.
+const MIN_FUZZY_CHARS: usize = 3;
+/// Queries up to this long allow one typo, longer ones two.
+const ONE_TYPO_CHARS: usize = 5;
+
+/// How a title matched a query, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Match {
+    Exact,
+    Prefix,
+    /// A later word of the title starts with the query.
+    WordPrefix,
+    /// The start of the title or of one of its words is this many edits from the query.
+    Fuzzy(usize),
+}
+
+/// Note titles for completing links and searches, tolerant of typos.
+#[derive(Debug, Default)]
+pub(crate) struct TitleIndex {
+    /// Keyed by the lowercased title, then the title, so titles differing by case both fit.
+    titles: BTreeMap<(String, String), Option<NaiveDateTime>>,
+}
+
+/// When a note was last changed, going by its headers.
+pub(crate) fn recency(header: &HashMap<String, String>) -> Option<NaiveDateTime> {
+    header
+        .get("modified")
+        .or_else(|| header.get("created"))
+        .and_then(|value| note_time(value))
+}
+
+/// Optimal string alignment distance, edits where swapping two neighbouring characters is one.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Where each word of `chars` starts, the start of the text always counting as one.
+fn word_starts(chars: &[char]) -> Vec<usize> {
+    (0..chars.len())
+        .filter(|&i| i == 0 || (chars[i].is_alphanumeric() && !chars[i - 1].is_alphanumeric()))
+        .collect()
+}
+
+/// How `title`, lowercased, matches the lowercased `query`, if it does.
+fn matches(title: &str, query: &str) -> Option<Match> {
+    if title == query {
+        return Some(Match::Exact);
+    }
+    if title.starts_with(query) {
+        return Some(Match::Prefix);
+    }
+    let chars = title.chars().collect::<Vec<_>>();
+    let starts = word_starts(&chars);
+    let query = &query.chars().collect::<Vec<_>>();
+    if starts
+        .iter()
+        .skip(1)
+        .any(|&start| chars[start..].starts_with(query))
+    {
+        return Some(Match::WordPrefix);
+    }
+    if query.len() < MIN_FUZZY_CHARS {
+        return None;
+    }
+    let allowed = if query.len() <= ONE_TYPO_CHARS { 1 } else { 2 };
+    // Compare with the start of each word a character or so either side of the query's length,
+    // so both dropped and doubled characters are found.
+    starts
+        .iter()
+        .flat_map(|&start| {
+            let rest = &chars[start..];
+            (query.len().saturating_sub(allowed)..=query.len() + allowed)
+                .filter(move |&len| len > 0 && len <= rest.len())
+                .map(move |len| edit_distance(query, &rest[..len]))
+        })
+        .min()
+        .filter(|&distance| distance <= allowed)
+        .map(Match::Fuzzy)
+}
+
+impl TitleIndex {
+    pub(crate) fn insert(&mut self, title: &str, recency: Option<NaiveDateTime>) {
+        self.titles
+            .insert((title.to_lowercase(), title.to_owned()), recency);
+    }
+
+    pub(crate) fn remove(&mut self, title: &str) {
+        self.titles
+            .remove(&(title.to_lowercase(), title.to_owned()));
+    }
+
+    /// Up to `limit` titles matching `query`, exact matches then completions then typos. Titles
+    /// that match as well as each other go by how many notes link to them, then by how recently
+    /// they changed.
+    pub(crate) fn suggest(
+        &self,
+        query: &str,
+        limit: usize,
+        backlinks: impl Fn(&str) -> usize,
+    ) -> Vec<String> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let mut found = self
+            .titles
+            .iter()
+            .filter_map(|((lowered, title), recency)| {
+                matches(lowered, &query).map(|found| (found, backlinks(title), recency, title))
+            })
+            .collect::<Vec<_>>();
+        found.sort_by(|a, b| {
+            a.0.cmp(&b.0)
+                .then(b.1.cmp(&a.1))
+                .then(b.2.cmp(a.2))
+                .then(a.3.cmp(b.3))
+        });
+        found
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, _, title)| title.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(titles: &[(&str, &str)]) -> TitleIndex {
+        let mut index = TitleIndex::default();
+        for (title, modified) in titles {
+            index.insert(title, note_time(modified));
+        }
+        index
+    }
+
+    #[test]
+    fn ranks_by_how_titles_match() {
+        let index = index(&[
+            ("Weekly meeting notes", "20220601090000"),
+            ("Meeting", "20220101090000"),
+            ("Meetings with Sam", "20220102090000"),
+            ("Meteorology", "20220103090000"),
+            ("Cooking", "20220104090000"),
+        ]);
+        assert_eq!(
+            index.suggest("meeting", 10, |_| 0),
+            vec!["Meeting", "Meetings with Sam", "Weekly meeting notes"]
+        );
+        assert_eq!(
+            index.suggest("meetnig", 10, |_| 0),
+            vec!["Weekly meeting notes", "Meetings with Sam", "Meeting"]
+        );
+        assert_eq!(index.suggest("METEO", 10, |_| 0), vec!["Meteorology"]);
+        assert_eq!(index.suggest("me", 1, |_| 0), vec!["Meteorology"]);
+        assert!(index.suggest("cx", 10, |_| 0).is_empty());
+        assert!(index.suggest("  ", 10, |_| 0).is_empty());
+    }
+
+    #[test]
+    fn ties_go_to_linked_then_recent_titles() {
+        let mut index = index(&[
+            ("Reading list", "20220101090000"),
+            ("Recipes", "2022-06-01"),
+            ("Reviews", "20220301090000"),
+        ]);
+        let backlinks = |title: &str| usize::from(title == "Reviews") * 3;
+        assert_eq!(
+            index.suggest("re", 10, backlinks),
+            vec!["Reviews", "Recipes", "Reading list"]
+        );
+        index.remove("Reviews");
+        index.insert("Reviewed", None);
+        assert_eq!(
+            index.suggest("re", 10, |_| 0),
+            vec!["Recipes", "Reading list", "Reviewed"]
+        );
+    }
+
+    #[test]
+    fn allows_more_typos_in_longer_queries() {
+        let index = index(&[("Photography", ""), ("Cat", "")]);
+        assert_eq!(index.suggest("fotograph", 10, |_| 0), vec!["Photography"]);
+        assert!(index.suggest("fotog", 10, |_| 0).is_empty());
+        assert_eq!(index.suggest("act", 10, |_| 0), vec!["Cat"]);
+    }
+}
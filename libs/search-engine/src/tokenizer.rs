@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 use regex::Regex;
 
@@ -21,7 +21,8 @@ lazy_static::lazy_static! {
     };
 }
 
-pub(crate) fn tokenize(slice: &str) -> Vec<String> {
+/// Every word of a line, stop words included.
+pub(crate) fn words(slice: &str) -> Vec<String> {
     let punct_to_whitespace = PUNCT_RGX.replace_all(slice, " ");
     punct_to_whitespace
         .split(' ')
@@ -29,7 +30,44 @@ pub(crate) fn tokenize(slice: &str) -> Vec<String> {
             let word = w.to_lowercase();
             word.replace('\n', "")
         })
-        .filter(|w| STOP_WORD_MAP.get(w.as_str()).is_none() && !w.is_empty() && w.len() <= 80)
+        .filter(|w| !w.is_empty())
+        .collect()
+}
+
+/// Where the words of `text` are, split like [words] splits them.
+pub(crate) fn word_spans(text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        let separates = c.is_whitespace() || c.is_ascii_punctuation();
+        match (start, separates) {
+            (None, false) => start = Some(i),
+            (Some(from), true) => {
+                spans.push(from..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(from) = start {
+        spans.push(from..text.len());
+    }
+    spans
+}
+
+/// Whether a word is worth indexing.
+pub(crate) fn is_token(word: &str) -> bool {
+    STOP_WORD_MAP.get(word).is_none() && word.len() <= 80
+}
+
+/// Tokens along with their position among all the words, so stop words still count towards
+/// how far apart two tokens are.
+pub(crate) fn tokenize_with_positions(slice: &str) -> Vec<(u32, String)> {
+    words(slice)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, w)| is_token(w))
+        .map(|(position, w)| (position as u32, w))
         .collect()
 }
 
@@ -37,6 +75,13 @@ pub(crate) fn tokenize(slice: &str) -> Vec<String> {
 mod tests {
     use super::*;
 
+    fn tokenize(slice: &str) -> Vec<String> {
+        tokenize_with_positions(slice)
+            .into_iter()
+            .map(|(_, w)| w)
+            .collect()
+    }
+
     #[test]
     fn tokenizes_strings() {
         let test_string =
@@ -62,4 +107,13 @@ mod tests {
         let tokenized = tokenize(test_string);
         assert_eq!(tokenized, tokens);
     }
+
+    #[test]
+    fn positions_count_stop_words() {
+        let tokenized = tokenize_with_positions("The state of the art, again.");
+        assert_eq!(
+            tokenized,
+            [(1, "state".into()), (4, "art".into()), (5, "again".into())]
+        );
+    }
 }
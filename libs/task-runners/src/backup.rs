@@ -0,0 +1,600 @@
+//! Backs up a whole notebook into one zip archive, and restores it again. Notes are kept as JSON
+//! read through the note store, so a backup can be restored into a wiki using other storage.
+//! Everything else is copied as it is on disk, next to a manifest with the checksum of every
+//! file that's checked before anything is restored.
+
+use std::{
+    cmp::Reverse,
+    fs,
+    io::{self, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use blake2::{Blake2s256, Digest};
+use chrono::{Local, NaiveDateTime};
+use persistance::{
+    crypto::{open, protect, CryptoError},
+    fs::{
+        config::read_config,
+        utils::{
+            get_backup_location, get_config_location, get_media_location,
+            get_notebook_data_location,
+        },
+    },
+    store::{note_store, StoreError},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::{spawn, time::sleep};
+use zip::{result::ZipError, write::FileOptions, ZipArchive, ZipWriter};
+
+use crate::{messages::Message, JobQueue, Queue};
+
+const FORMAT: &str = "tendril-backup";
+/// Raised whenever the layout of a backup changes, older versions can still be restored.
+const VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const NOTES: &str = "notes.json";
+const NAME_PREFIX: &str = "tendril-backup-";
+const NAME_FORMAT: &str = "%Y%m%d%H%M%S";
+const EXTENSION: &str = "zip";
+const DEFAULT_KEEP: usize = 7;
+/// Files and directories of the notebook's data location that are backed up. The search index
+/// is left out, it's rebuilt after a restore.
+const DATA_FILES: [&str; 5] = [
+    "todo.txt",
+    "note_cache",
+    "media.toml",
+    "redirects.toml",
+    "encryption.toml",
+];
+const DATA_DIRS: [&str; 3] = ["archive", "history", "trash"];
+const CONFIG_FILE: &str = "config.toml";
+const USER_STYLES: &str = "userstyles.css";
+/// A backed up config is written next to the current one instead of over it, since the paths in
+/// it may not fit the machine it's restored on.
+const RESTORED_CONFIG_FILE: &str = "config.restored.toml";
+
+/// Set while a scheduled backup is being taken, so a slow one isn't started twice.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Error, Debug)]
+pub enum BackupError {
+    #[error("could not read {0:?}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("could not read notes: {0}")]
+    Store(#[from] StoreError),
+    #[error("{0}")]
+    Zip(#[from] ZipError),
+    #[error("could not read the manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("not a tendril backup")]
+    NotABackup,
+    #[error("the backup is format version {0}, this version of tendril reads up to {VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("{0} is damaged, its checksum doesn't match the manifest")]
+    Checksum(String),
+    #[error("{0} is listed in the manifest but missing from the backup")]
+    Missing(String),
+    #[error("{0} is in the backup but not in the manifest")]
+    Unlisted(String),
+    #[error("{0:?} is not a path a backup can restore to")]
+    InvalidPath(String),
+    #[error("could not decrypt the notes: {0}")]
+    Crypto(#[from] CryptoError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    /// RFC 3339 timestamp of when the backup was taken.
+    pub created: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: String,
+    /// Size in bytes.
+    pub size: u64,
+    /// Hex encoded BLAKE2s-256 hash of the content.
+    pub checksum: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredNote {
+    title: String,
+    content: String,
+}
+
+fn checksum(bytes: &[u8]) -> String {
+    Blake2s256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Writes files into a backup archive, keeping track of them for the manifest.
+struct BackupWriter<W: Write + Seek> {
+    zip: ZipWriter<W>,
+    entries: Vec<Entry>,
+}
+
+impl<W: Write + Seek> BackupWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            zip: ZipWriter::new(writer),
+            entries: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, path: &str, bytes: &[u8]) -> Result<(), BackupError> {
+        self.zip.start_file(path, FileOptions::default())?;
+        self.zip.write_all(bytes)?;
+        self.entries.push(Entry {
+            path: path.to_string(),
+            size: bytes.len() as u64,
+            checksum: checksum(bytes),
+        });
+        Ok(())
+    }
+
+    /// Adds every file below `dir` under `prefix`, leaving out hidden ones like thumbnails.
+    fn add_dir(&mut self, prefix: &str, dir: &Path) -> Result<(), BackupError> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(BackupError::Read(dir.to_owned(), e)),
+        };
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()
+            .map_err(|e| BackupError::Read(dir.to_owned(), e))?;
+        paths.sort();
+        for path in paths {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let entry = format!("{}/{}", prefix, name);
+            if path.is_dir() {
+                self.add_dir(&entry, &path)?;
+            } else {
+                self.add_file(&entry, &path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &str, file: &Path) -> Result<(), BackupError> {
+        match fs::read(file) {
+            Ok(bytes) => self.add(path, &bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(BackupError::Read(file.to_owned(), e)),
+        }
+    }
+
+    fn finish(mut self) -> Result<Manifest, BackupError> {
+        let manifest = Manifest {
+            format: FORMAT.into(),
+            version: VERSION,
+            created: Local::now().to_rfc3339(),
+            entries: self.entries,
+        };
+        self.zip.start_file(MANIFEST, FileOptions::default())?;
+        self.zip.write_all(&serde_json::to_vec_pretty(&manifest)?)?;
+        self.zip.finish()?;
+        Ok(manifest)
+    }
+}
+
+/// Writes a backup of the notebook to `path`.
+pub fn backup_to(path: &Path) -> Result<Manifest, BackupError> {
+    // Written under another name first, so an interrupted backup never looks like a finished one.
+    let partial = path.with_extension("partial");
+    let result = fs::File::create(&partial)
+        .map_err(BackupError::from)
+        .and_then(write_backup);
+    match result {
+        Ok(manifest) => {
+            fs::rename(&partial, path)?;
+            Ok(manifest)
+        }
+        Err(e) => {
+            fs::remove_file(&partial).ok();
+            Err(e)
+        }
+    }
+}
+
+fn write_backup<W: Write + Seek>(writer: W) -> Result<Manifest, BackupError> {
+    let mut backup = BackupWriter::new(writer);
+    let store = note_store();
+    let mut notes = Vec::new();
+    for title in store.list()? {
+        let content = store.read(&title)?;
+        notes.push(StoredNote { title, content });
+    }
+    // Encrypted notes stay encrypted in the backup while the notebook is unlocked.
+    let notes = protect(serde_json::to_vec(&notes)?);
+    backup.add(NOTES, &notes)?;
+    backup.add_dir("media", &get_media_location())?;
+    let data = get_notebook_data_location();
+    for file in DATA_FILES {
+        backup.add_file(&format!("data/{}", file), &data.join(file))?;
+    }
+    for dir in DATA_DIRS {
+        backup.add_dir(&format!("data/{}", dir), &data.join(dir))?;
+    }
+    let (config_dir, config_file) = get_config_location();
+    backup.add_file(&format!("config/{}", CONFIG_FILE), &config_file)?;
+    backup.add_file(
+        &format!("config/{}", USER_STYLES),
+        &config_dir.join(USER_STYLES),
+    )?;
+    backup.finish()
+}
+
+/// Takes a backup into the backup location, returning where it was written.
+pub fn backup() -> Result<(PathBuf, Manifest), BackupError> {
+    let location = get_backup_location();
+    fs::create_dir_all(&location)?;
+    let name = format!(
+        "{}{}.{}",
+        NAME_PREFIX,
+        Local::now().format(NAME_FORMAT),
+        EXTENSION
+    );
+    let path = location.join(name);
+    let manifest = backup_to(&path)?;
+    Ok((path, manifest))
+}
+
+/// Backups in `location` by when they were taken, newest first.
+fn list_backups(location: &Path) -> Vec<(NaiveDateTime, PathBuf)> {
+    let mut backups = match fs::read_dir(location) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if !matches!(path.extension(), Some(ext) if ext == EXTENSION) {
+                    return None;
+                }
+                let stem = path.file_stem()?.to_str()?;
+                let taken =
+                    NaiveDateTime::parse_from_str(stem.strip_prefix(NAME_PREFIX)?, NAME_FORMAT)
+                        .ok()?;
+                Some((taken, path))
+            })
+            .collect::<Vec<(NaiveDateTime, PathBuf)>>(),
+        Err(_) => Vec::new(),
+    };
+    backups.sort_by_key(|(taken, _)| Reverse(*taken));
+    backups
+}
+
+/// Removes all but the `keep` newest backups in `location`, returning those removed.
+pub fn prune(location: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for (_, path) in list_backups(location).into_iter().skip(keep) {
+        fs::remove_file(&path)?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Takes a backup and removes the ones that are past the configured retention.
+pub fn scheduled_backup() {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    match backup() {
+        Ok((path, manifest)) => {
+            println!("<backed up {} files to {:?}>", manifest.entries.len(), path);
+            let keep = read_config()
+                .backup
+                .as_ref()
+                .and_then(|b| b.keep)
+                .unwrap_or(DEFAULT_KEEP);
+            if let Err(e) = prune(&get_backup_location(), keep) {
+                eprintln!("Could not remove old backups: {}", e);
+            }
+        }
+        Err(e) => eprintln!("Could not back up the wiki: {}", e),
+    }
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+/// Queues a backup whenever the last one is older than `interval_hours`. The config is read on
+/// every check, so scheduled backups can be turned on and off without a restart.
+pub fn schedule(queue: Arc<JobQueue>) {
+    spawn(async move {
+        loop {
+            let interval = read_config().backup.as_ref().and_then(|b| b.interval_hours);
+            if let Some(hours) = interval {
+                let last = list_backups(&get_backup_location())
+                    .first()
+                    .map(|(taken, _)| *taken);
+                let due = match last {
+                    Some(taken) => {
+                        Local::now().naive_local() - taken >= chrono::Duration::hours(hours as i64)
+                    }
+                    None => true,
+                };
+                if due && !RUNNING.load(Ordering::SeqCst) {
+                    queue.push(Message::Backup).await.unwrap();
+                }
+            }
+            sleep(Duration::from_secs(60)).await;
+        }
+    });
+}
+
+/// Where an entry of the backup is restored to. Only the places a backup writes to are allowed.
+enum Target<'a> {
+    Media(&'a str),
+    Data(&'a str),
+    Config(&'a str),
+}
+
+impl<'a> Target<'a> {
+    fn parse(path: &'a str) -> Result<Self, BackupError> {
+        let invalid = || BackupError::InvalidPath(path.to_string());
+        let is_relative = Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_relative {
+            return Err(invalid());
+        }
+        let (root, rest) = path.split_once('/').ok_or_else(invalid)?;
+        let first = rest.split('/').next().unwrap_or_default();
+        match root {
+            "media" => Ok(Target::Media(rest)),
+            "data" if DATA_FILES.contains(&rest) => Ok(Target::Data(rest)),
+            "data" if DATA_DIRS.contains(&first) && rest.len() > first.len() => {
+                Ok(Target::Data(rest))
+            }
+            "config" if rest == CONFIG_FILE || rest == USER_STYLES => Ok(Target::Config(rest)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn location(&self) -> PathBuf {
+        match self {
+            Target::Media(path) => get_media_location().join(path),
+            Target::Data(path) => get_notebook_data_location().join(path),
+            Target::Config(CONFIG_FILE) => get_config_location().0.join(RESTORED_CONFIG_FILE),
+            Target::Config(name) => get_config_location().0.join(name),
+        }
+    }
+}
+
+/// A backup whose manifest has been checked against its content.
+pub struct Backup<R: Read + Seek> {
+    archive: ZipArchive<R>,
+    pub manifest: Manifest,
+}
+
+impl Backup<fs::File> {
+    pub fn open(path: &Path) -> Result<Self, BackupError> {
+        let file = fs::File::open(path).map_err(|e| BackupError::Read(path.to_owned(), e))?;
+        Backup::read(file)
+    }
+}
+
+impl<R: Read + Seek> Backup<R> {
+    /// Reads the manifest and checks that every file in the backup is listed in it, with the
+    /// size and checksum it gives.
+    pub fn read(reader: R) -> Result<Self, BackupError> {
+        let mut archive = ZipArchive::new(reader).map_err(|_| BackupError::NotABackup)?;
+        let manifest: Manifest = match archive.by_name(MANIFEST) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(ZipError::FileNotFound) => return Err(BackupError::NotABackup),
+            Err(e) => return Err(e.into()),
+        };
+        if manifest.format != FORMAT {
+            return Err(BackupError::NotABackup);
+        }
+        if manifest.version > VERSION {
+            return Err(BackupError::UnsupportedVersion(manifest.version));
+        }
+        let mut backup = Self { archive, manifest };
+        let listed = backup
+            .manifest
+            .entries
+            .iter()
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<&str>>();
+        for name in backup.archive.file_names() {
+            if name != MANIFEST && !listed.contains(&name) {
+                return Err(BackupError::Unlisted(name.to_string()));
+            }
+        }
+        for entry in backup.manifest.entries.clone() {
+            if entry.path != NOTES {
+                Target::parse(&entry.path)?;
+            }
+            let bytes = backup.read_entry(&entry)?;
+            if bytes.len() as u64 != entry.size || checksum(&bytes) != entry.checksum {
+                return Err(BackupError::Checksum(entry.path));
+            }
+        }
+        Ok(backup)
+    }
+
+    fn read_entry(&mut self, entry: &Entry) -> Result<Vec<u8>, BackupError> {
+        let mut file = match self.archive.by_name(&entry.path) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Err(BackupError::Missing(entry.path.clone())),
+            Err(e) => return Err(e.into()),
+        };
+        let mut bytes = Vec::with_capacity(entry.size as usize);
+        file.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Puts back media, archives, tasks and the other data files, returning how many were
+    /// restored. The config is written next to the current one, as `config.restored.toml`.
+    pub fn restore_files(&mut self) -> Result<usize, BackupError> {
+        let mut restored = 0;
+        for entry in self.manifest.entries.clone() {
+            if entry.path == NOTES {
+                continue;
+            }
+            let path = Target::parse(&entry.path)?.location();
+            let bytes = self.read_entry(&entry)?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, bytes)?;
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// Writes every note in the backup to the store, replacing notes with the same title. Run
+    /// after [Backup::restore_files] and unlocking the notebook, when the notes are encrypted.
+    pub fn restore_notes(&mut self) -> Result<usize, BackupError> {
+        let entry = self
+            .manifest
+            .entries
+            .iter()
+            .find(|entry| entry.path == NOTES)
+            .cloned()
+            .ok_or_else(|| BackupError::Missing(NOTES.into()))?;
+        let notes: Vec<StoredNote> = serde_json::from_slice(&open(self.read_entry(&entry)?)?)?;
+        let store = note_store();
+        for note in &notes {
+            store.write(&note.title, &note.content)?;
+        }
+        Ok(notes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const TEST_DIR: &str = "/tmp/tendril-test/backup/";
+
+    fn archive(files: &[(&str, &[u8])], tamper: impl Fn(&mut Manifest)) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut entries = Vec::new();
+        for (path, bytes) in files {
+            zip.start_file(*path, FileOptions::default()).unwrap();
+            zip.write_all(bytes).unwrap();
+            entries.push(Entry {
+                path: path.to_string(),
+                size: bytes.len() as u64,
+                checksum: checksum(bytes),
+            });
+        }
+        let mut manifest = Manifest {
+            format: FORMAT.into(),
+            version: VERSION,
+            created: String::new(),
+            entries,
+        };
+        tamper(&mut manifest);
+        zip.start_file(MANIFEST, FileOptions::default()).unwrap();
+        zip.write_all(&serde_json::to_vec(&manifest).unwrap())
+            .unwrap();
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = BackupWriter::new(&mut cursor);
+        writer.add(NOTES, b"[]").unwrap();
+        writer.add("data/todo.txt", b"x buy milk").unwrap();
+        let manifest = writer.finish().unwrap();
+        assert_eq!(manifest.version, VERSION);
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(manifest.entries[1].size, 10);
+        cursor.set_position(0);
+        let mut backup = Backup::read(cursor).unwrap();
+        assert_eq!(backup.manifest, manifest);
+        assert_eq!(
+            backup.read_entry(&manifest.entries[1]).unwrap(),
+            b"x buy milk"
+        );
+    }
+
+    #[test]
+    fn rejects_damaged_backups() {
+        let todo: (&str, &[u8]) = ("data/todo.txt", b"x buy milk");
+        assert!(Backup::read(archive(&[todo], |_| {})).is_ok());
+        let damaged = archive(&[todo], |manifest| {
+            manifest.entries[0].checksum = checksum(b"x buy eggs")
+        });
+        assert!(matches!(
+            Backup::read(damaged),
+            Err(BackupError::Checksum(_))
+        ));
+        let missing = archive(&[], |manifest| {
+            manifest.entries.push(Entry {
+                path: "data/todo.txt".into(),
+                size: 0,
+                checksum: checksum(b""),
+            })
+        });
+        assert!(matches!(
+            Backup::read(missing),
+            Err(BackupError::Missing(_))
+        ));
+        let unlisted = archive(&[todo], |manifest| manifest.entries.clear());
+        assert!(matches!(
+            Backup::read(unlisted),
+            Err(BackupError::Unlisted(_))
+        ));
+        let newer = archive(&[todo], |manifest| manifest.version = VERSION + 1);
+        assert!(matches!(
+            Backup::read(newer),
+            Err(BackupError::UnsupportedVersion(_))
+        ));
+        for path in [
+            "data/../../etc/passwd",
+            "data/search-index/term",
+            "/etc/passwd",
+        ] {
+            let escaping = archive(&[(path, b"root")], |_| {});
+            assert!(matches!(
+                Backup::read(escaping),
+                Err(BackupError::InvalidPath(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_backups() {
+        let location = PathBuf::from(TEST_DIR);
+        if location.exists() {
+            fs::remove_dir_all(&location).unwrap();
+        }
+        fs::create_dir_all(&location).unwrap();
+        for stamp in ["20220501000000", "20220502000000", "20220503000000"] {
+            fs::write(location.join(format!("{}{}.zip", NAME_PREFIX, stamp)), "").unwrap();
+        }
+        fs::write(location.join("notes.zip"), "").unwrap();
+        let removed = prune(&location, 2).unwrap();
+        assert_eq!(
+            removed,
+            vec![location.join(format!("{}20220501000000.zip", NAME_PREFIX))]
+        );
+        assert!(location.join("notes.zip").exists());
+        assert_eq!(list_backups(&location).len(), 2);
+    }
+}
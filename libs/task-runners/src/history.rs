@@ -0,0 +1,246 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Duration, Local, NaiveDateTime};
+use diffy::Line;
+use persistance::{
+    archive::{compress, decompress},
+    fs::{
+        config::read_config,
+        utils::{get_history_location, title_path},
+    },
+    store::{note_store, revision, StoreError},
+};
+use render::history_page::DiffLine;
+
+/// Revisions are named `<id>-<revision>.bz2`, where the id is the time the revision was replaced.
+const ID_FORMAT: &str = "%Y%m%d%H%M%S%3f";
+const EXTENSION: &str = "bz2";
+const DEFAULT_MAX_REVISIONS: usize = 50;
+/// Pseudo revision id for the note as it currently is.
+pub const CURRENT: &str = "current";
+
+/// How many revisions are kept.
+fn retention() -> Retention {
+    let config = read_config();
+    let history = config.history.as_ref();
+    Retention {
+        max_revisions: history
+            .and_then(|h| h.max_revisions)
+            .unwrap_or(DEFAULT_MAX_REVISIONS),
+        max_age: history
+            .and_then(|h| h.max_age_days)
+            .map(|days| Duration::days(days as i64)),
+    }
+}
+
+struct Retention {
+    max_revisions: usize,
+    max_age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub id: String,
+    pub saved: NaiveDateTime,
+    /// Revision of the note content, see [persistance::store::revision].
+    pub revision: String,
+    path: PathBuf,
+}
+
+impl Revision {
+    fn from_path(path: PathBuf) -> Option<Self> {
+        if !matches!(path.extension(), Some(ext) if ext == EXTENSION) {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let (id, revision) = stem.split_once('-')?;
+        let saved = NaiveDateTime::parse_from_str(id, ID_FORMAT).ok()?;
+        Some(Revision {
+            id: id.to_string(),
+            saved,
+            revision: revision.to_string(),
+            path,
+        })
+    }
+}
+
+fn history_dir(title: &str) -> io::Result<PathBuf> {
+    Ok(title_path(&get_history_location(), title)?)
+}
+
+/// Saved revisions of a note, newest first.
+pub fn list(title: &str) -> Vec<Revision> {
+    let mut revisions = match history_dir(title).and_then(fs::read_dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            // Namespaced notes have their history in sub directories, skip those.
+            .filter(|entry| matches!(entry.file_type(), Ok(file_type) if file_type.is_file()))
+            .filter_map(|entry| Revision::from_path(entry.path()))
+            .collect::<Vec<Revision>>(),
+        Err(_) => Vec::new(),
+    };
+    revisions.sort_by(|a, b| b.id.cmp(&a.id));
+    revisions
+}
+
+/// Saves the current content of a note as a revision, call this before overwriting it. Nothing is
+/// saved for new notes or if the content matches the latest saved revision.
+pub fn record(title: &str) -> io::Result<()> {
+    let content = match note_store().read(title) {
+        Ok(content) => content,
+        Err(StoreError::NotFound) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let content_revision = revision(&content);
+    let revisions = list(title);
+    if matches!(revisions.first(), Some(latest) if latest.revision == content_revision) {
+        return Ok(());
+    }
+    let dir = history_dir(title)?;
+    fs::create_dir_all(&dir)?;
+    let id = Local::now().naive_local().format(ID_FORMAT).to_string();
+    fs::write(
+        dir.join(format!("{}-{}.{}", id, content_revision, EXTENSION)),
+        compress(&content),
+    )?;
+    prune(title);
+    Ok(())
+}
+
+/// Reads a saved revision, or the current note for [CURRENT].
+pub fn read(title: &str, id: &str) -> io::Result<String> {
+    if id == CURRENT {
+        return Ok(note_store().read(title)?);
+    }
+    let revision = list(title)
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no revision with that id"))?;
+    decompress(fs::read(&revision.path)?)
+}
+
+/// Keeps the history of a renamed note with it.
+pub fn move_history(old_title: &str, new_title: &str) -> io::Result<()> {
+    let new_dir = history_dir(new_title)?;
+    let revisions = list(old_title);
+    if revisions.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(&new_dir)?;
+    for revision in revisions {
+        fs::rename(
+            &revision.path,
+            new_dir.join(revision.path.file_name().unwrap()),
+        )?;
+    }
+    remove_if_empty(&history_dir(old_title)?);
+    Ok(())
+}
+
+fn remove_if_empty(dir: &Path) {
+    // Fails if there are still revisions or histories of namespaced notes in there.
+    fs::remove_dir(dir).ok();
+}
+
+fn expired<'a>(
+    revisions: &'a [Revision],
+    retention: &Retention,
+    now: NaiveDateTime,
+) -> Vec<&'a Revision> {
+    revisions
+        .iter()
+        .enumerate()
+        .filter(|(idx, revision)| {
+            *idx >= retention.max_revisions
+                || matches!(retention.max_age, Some(max_age) if now - revision.saved > max_age)
+        })
+        .map(|(_, revision)| revision)
+        .collect()
+}
+
+fn prune(title: &str) {
+    let revisions = list(title);
+    for revision in expired(&revisions, &retention(), Local::now().naive_local()) {
+        if let Err(e) = fs::remove_file(&revision.path) {
+            eprintln!("Could not remove old revision {:?}: {}", revision.path, e);
+        }
+    }
+}
+
+/// Line based changes from `old` to `new`, unchanged stretches are collapsed to a few lines of
+/// context around each change.
+pub fn diff(old: &str, new: &str) -> Vec<DiffLine> {
+    let patch = diffy::create_patch(old, new);
+    let mut lines = Vec::new();
+    for (idx, hunk) in patch.hunks().iter().enumerate() {
+        if idx > 0 {
+            lines.push(DiffLine::Skipped);
+        }
+        for line in hunk.lines() {
+            lines.push(match line {
+                Line::Context(text) => DiffLine::Unchanged(text.trim_end_matches('\n').into()),
+                Line::Insert(text) => DiffLine::Added(text.trim_end_matches('\n').into()),
+                Line::Delete(text) => DiffLine::Removed(text.trim_end_matches('\n').into()),
+            });
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn revision_at(id: &str) -> Revision {
+        Revision::from_path(PathBuf::from(format!("/history/note/{}-abc.bz2", id))).unwrap()
+    }
+
+    #[test]
+    fn parses_revision_file_names() {
+        let revision = revision_at("20220301120000250");
+        assert_eq!(revision.id, "20220301120000250");
+        assert_eq!(revision.revision, "abc");
+        assert_eq!(
+            revision.saved.format("%Y-%m-%d %H:%M").to_string(),
+            "2022-03-01 12:00"
+        );
+        assert!(Revision::from_path(PathBuf::from("/history/note/Alpha")).is_none());
+    }
+
+    #[test]
+    fn expires_old_and_excess_revisions() {
+        let revisions = vec![
+            revision_at("20220310000000000"),
+            revision_at("20220305000000000"),
+            revision_at("20220101000000000"),
+        ];
+        let now = NaiveDateTime::parse_from_str("20220311000000000", ID_FORMAT).unwrap();
+        let retention = Retention {
+            max_revisions: 2,
+            max_age: None,
+        };
+        let pruned = expired(&revisions, &retention, now);
+        assert_eq!(pruned, vec![&revisions[2]]);
+        let retention = Retention {
+            max_revisions: 10,
+            max_age: Some(Duration::days(3)),
+        };
+        let pruned = expired(&revisions, &retention, now);
+        assert_eq!(pruned, vec![&revisions[1], &revisions[2]]);
+    }
+
+    #[test]
+    fn diffs_lines_with_context() {
+        let old = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+        let new = "one\ntwo!\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten?\n";
+        let lines = diff(old, new);
+        assert!(matches!(&lines[1], DiffLine::Removed(text) if text == "two"));
+        assert!(matches!(&lines[2], DiffLine::Added(text) if text == "two!"));
+        assert!(lines.iter().any(|line| matches!(line, DiffLine::Skipped)));
+        assert!(matches!(lines.last(), Some(DiffLine::Added(text)) if text == "ten?"));
+        assert!(diff("same\n", "same\n").is_empty());
+    }
+}
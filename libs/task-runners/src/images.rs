@@ -0,0 +1,398 @@
+use std::{
+    fs,
+    io::{self, Cursor},
+};
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageOutputFormat};
+use persistance::media::{media_library, thumbnail_widths, MediaFile};
+
+const JPEG_QUALITY: u8 = 90;
+/// APP1 holds EXIF (GPS included) and XMP, APP13 holds IPTC. Other segments, like the colour
+/// profile in APP2, are kept.
+const JPEG_METADATA_SEGMENTS: [u8; 2] = [0xE1, 0xED];
+/// Chunks that can carry EXIF or free text such as the camera or software used.
+const PNG_METADATA_CHUNKS: [&[u8]; 4] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt"];
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageKind {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+fn image_kind(bytes: &[u8]) -> Option<ImageKind> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ImageKind::Jpeg)
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ImageKind::Png)
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some(ImageKind::Webp)
+    } else {
+        None
+    }
+}
+
+/// A JPEG marker and the whole segment it starts, marker included.
+type Segment<'a> = (u8, &'a [u8]);
+
+/// The marker segments of a JPEG before the image data, along with the offset the image data
+/// starts at.
+fn jpeg_segments(bytes: &[u8]) -> Option<(Vec<Segment>, usize)> {
+    let mut segments = Vec::new();
+    // Skip the start of image marker.
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        match marker {
+            // Padding before a marker.
+            0xFF => pos += 1,
+            // Start of scan, everything after is image data.
+            0xDA => return Some((segments, pos)),
+            _ => {
+                let length = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]);
+                let end = pos + 2 + length as usize;
+                if length < 2 || end > bytes.len() {
+                    return None;
+                }
+                segments.push((marker, &bytes[pos..end]));
+                pos = end;
+            }
+        }
+    }
+}
+
+/// Reads the orientation from the first IFD of the TIFF structure inside an EXIF segment.
+fn exif_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    };
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries)
+        .map(|idx| ifd + 2 + idx * 12)
+        .find(|entry| read_u16(*entry) == Some(EXIF_ORIENTATION_TAG))
+        .and_then(|entry| read_u16(entry + 8))
+}
+
+fn jpeg_orientation(bytes: &[u8]) -> Option<u16> {
+    let (segments, _) = jpeg_segments(bytes)?;
+    segments
+        .iter()
+        .filter(|(marker, _)| *marker == 0xE1)
+        .find_map(|(_, segment)| {
+            segment
+                .get(4..)?
+                .strip_prefix(b"Exif\0\0")
+                .and_then(exif_orientation)
+        })
+}
+
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    let (segments, scan) = jpeg_segments(bytes)?;
+    let mut stripped = Vec::with_capacity(bytes.len());
+    stripped.extend_from_slice(&bytes[..2]);
+    for (marker, segment) in segments {
+        if !JPEG_METADATA_SEGMENTS.contains(&marker) {
+            stripped.extend_from_slice(segment);
+        }
+    }
+    stripped.extend_from_slice(&bytes[scan..]);
+    Some(stripped)
+}
+
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = bytes[..8].to_vec();
+    let mut pos = 8;
+    while pos < bytes.len() {
+        let length = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        // Length, type, data and CRC.
+        let chunk = bytes.get(pos..pos.checked_add(12 + length)?)?;
+        if !PNG_METADATA_CHUNKS.contains(&&chunk[4..8]) {
+            stripped.extend_from_slice(chunk);
+        }
+        pos += chunk.len();
+    }
+    Some(stripped)
+}
+
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut chunks = Vec::with_capacity(bytes.len());
+    let mut pos = 12;
+    while pos < bytes.len() {
+        let size = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even size, the padding isn't always there for the last one.
+        let end = pos.checked_add(8 + size)?;
+        let padded_end = (end + size % 2).min(bytes.len());
+        let chunk = bytes.get(pos..end).map(|_| &bytes[pos..padded_end])?;
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" => {
+                let mut header = chunk.to_vec();
+                // Clear the flags announcing EXIF and XMP chunks.
+                if let Some(flags) = header.get_mut(8) {
+                    *flags &= !(0x08 | 0x04);
+                }
+                chunks.extend(header);
+            }
+            _ => chunks.extend_from_slice(chunk),
+        }
+        pos = padded_end;
+    }
+    let mut stripped = b"RIFF".to_vec();
+    stripped.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    stripped.extend_from_slice(b"WEBP");
+    stripped.extend(chunks);
+    Some(stripped)
+}
+
+/// Applies an EXIF orientation so the image no longer needs it to be shown upright.
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn encode(image: &DynamicImage, format: ImageOutputFormat) -> io::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut encoded), format)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(encoded)
+}
+
+/// Re-encoding drops all metadata along with the orientation.
+fn reorient_jpeg(bytes: &[u8], orientation: u16) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(bytes).ok()?;
+    encode(
+        &orient(image, orientation),
+        ImageOutputFormat::Jpeg(JPEG_QUALITY),
+    )
+    .ok()
+}
+
+/// Removes location and camera metadata from uploaded images, turning photos upright first if
+/// the camera only recorded which way they should be rotated. Anything that isn't a JPEG, PNG or
+/// WebP image is stored as it was uploaded.
+pub fn prepare_upload(bytes: Vec<u8>) -> Vec<u8> {
+    let prepared = match image_kind(&bytes) {
+        Some(ImageKind::Jpeg) => match jpeg_orientation(&bytes) {
+            Some(orientation) if orientation != 1 => {
+                reorient_jpeg(&bytes, orientation).or_else(|| strip_jpeg(&bytes))
+            }
+            _ => strip_jpeg(&bytes),
+        },
+        Some(ImageKind::Png) => strip_png(&bytes),
+        Some(ImageKind::Webp) => strip_webp(&bytes),
+        None => None,
+    };
+    // Keep files we can't make sense of rather than failing the upload.
+    prepared.unwrap_or(bytes)
+}
+
+fn thumbnail_format(content_type: &str) -> Option<ImageOutputFormat> {
+    match content_type {
+        "image/jpeg" => Some(ImageOutputFormat::Jpeg(JPEG_QUALITY)),
+        "image/png" => Some(ImageOutputFormat::Png),
+        _ => None,
+    }
+}
+
+/// Writes the thumbnails of an uploaded JPEG or PNG image that don't exist yet. Images narrower
+/// than a thumbnail are copied as they are, so every size a `srcset` lists can be loaded.
+pub fn generate_thumbnails(file: &MediaFile) -> io::Result<()> {
+    if thumbnail_format(&file.content_type).is_none() {
+        return Ok(());
+    }
+    let library = media_library();
+    let missing = thumbnail_widths()
+        .into_iter()
+        .map(|width| (width, library.thumbnail_path(&file.name, width)))
+        .filter(|(_, path)| !path.exists())
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let source = library.path_of(&file.name)?;
+    let image = image::load_from_memory(&fs::read(&source)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let image_width = GenericImageView::width(&image);
+    for (width, path) in missing {
+        fs::create_dir_all(path.parent().unwrap())?;
+        if image_width <= width {
+            fs::copy(&source, &path)?;
+            continue;
+        }
+        let thumbnail = image.resize(width, u32::MAX, FilterType::Lanczos3);
+        fs::write(
+            &path,
+            encode(&thumbnail, thumbnail_format(&file.content_type).unwrap())?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Generates thumbnails for images uploaded before thumbnails existed or before a width was
+/// added to the config.
+pub fn generate_missing_thumbnails() {
+    let files = match media_library().list() {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Could not list media for thumbnails: {}", e);
+            return;
+        }
+    };
+    for file in files {
+        if let Err(e) = generate_thumbnails(&file) {
+            eprintln!("Could not generate thumbnails for {}: {}", file.name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exif_segment(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II".to_vec();
+        tiff.extend_from_slice(&42u16.to_le_bytes());
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&EXIF_ORIENTATION_TAG.to_le_bytes());
+        // SHORT, one value
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        let mut payload = b"Exif\0\0".to_vec();
+        payload.extend(tiff);
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        segment.extend(payload);
+        segment
+    }
+
+    fn jpeg_with(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        for segment in segments {
+            jpeg.extend_from_slice(segment);
+        }
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn reads_exif_orientation() {
+        let jfif = vec![0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46];
+        let jpeg = jpeg_with(&[jfif, exif_segment(6)]);
+        assert_eq!(image_kind(&jpeg), Some(ImageKind::Jpeg));
+        assert_eq!(jpeg_orientation(&jpeg), Some(6));
+        assert_eq!(jpeg_orientation(&jpeg_with(&[])), None);
+    }
+
+    #[test]
+    fn strips_jpeg_metadata() {
+        let jfif = vec![0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46];
+        let icc = vec![0xFF, 0xE2, 0x00, 0x03, 0x01];
+        let jpeg = jpeg_with(&[jfif.clone(), exif_segment(1), icc.clone()]);
+        assert_eq!(prepare_upload(jpeg), jpeg_with(&[jfif, icc]));
+    }
+
+    fn png_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        // The CRC isn't checked when stripping.
+        chunk.extend_from_slice(&[0, 0, 0, 0]);
+        chunk
+    }
+
+    #[test]
+    fn strips_png_metadata() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let header = png_chunk(b"IHDR", &[0; 13]);
+        let data = png_chunk(b"IDAT", &[1, 2, 3]);
+        png.extend_from_slice(&header);
+        png.extend(png_chunk(b"tEXt", b"Software\0phone"));
+        png.extend_from_slice(&data);
+        png.extend(png_chunk(b"eXIf", &[0; 8]));
+        png.extend(png_chunk(b"IEND", &[]));
+        let mut expected = b"\x89PNG\r\n\x1a\n".to_vec();
+        expected.extend(header);
+        expected.extend(data);
+        expected.extend(png_chunk(b"IEND", &[]));
+        assert_eq!(prepare_upload(png), expected);
+    }
+
+    fn webp_chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn webp_with(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body = chunks.concat();
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        webp.extend_from_slice(b"WEBP");
+        webp.extend(body);
+        webp
+    }
+
+    #[test]
+    fn strips_webp_metadata() {
+        let image = webp_chunk(b"VP8 ", &[1, 2, 3]);
+        let webp = webp_with(&[
+            webp_chunk(b"VP8X", &[0x08 | 0x04 | 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            image.clone(),
+            webp_chunk(b"EXIF", &[0; 5]),
+            webp_chunk(b"XMP ", &[0; 4]),
+        ]);
+        let expected = webp_with(&[
+            webp_chunk(b"VP8X", &[0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+            image,
+        ]);
+        assert_eq!(prepare_upload(webp), expected);
+    }
+
+    #[test]
+    fn leaves_other_files_alone() {
+        let pdf = b"%PDF-1.4 not an image".to_vec();
+        assert_eq!(prepare_upload(pdf.clone()), pdf);
+        let broken = vec![0xFF, 0xD8, 0xFF, 0xE1, 0xFF];
+        assert_eq!(prepare_upload(broken.clone()), broken);
+    }
+}
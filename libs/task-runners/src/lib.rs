@@ -7,12 +7,17 @@ use thiserror::Error;
 use ulid::Ulid;
 
 pub mod archive;
+pub mod backup;
 pub mod cache;
+pub mod history;
+pub mod images;
 pub mod messages;
 pub mod password;
 pub mod runners;
 pub mod sync;
+pub mod trash;
 pub mod verify;
+pub mod watcher;
 
 pub use self::password::*;
 pub use self::sync::*;
@@ -12,6 +12,14 @@ pub enum Message {
     Delete {
         title: String,
     },
+    /// A note was deleted outside of the wiki, so there's nothing left to move to the trash.
+    Removed {
+        title: String,
+    },
+    /// A note was put back from the trash and needs to be indexed again.
+    Restore {
+        title: String,
+    },
     Archive {
         url: String,
         title: String,
@@ -28,6 +36,8 @@ pub enum Message {
         title: String,
         body: String,
     },
+    /// Take a scheduled backup and remove those past the retention.
+    Backup,
     VerifyDataInstallation {
         dataset: Vec<String>,
         install_location: PathBuf,
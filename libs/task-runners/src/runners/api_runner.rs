@@ -1,12 +1,17 @@
 use std::{collections::HashMap, io, time::Instant};
 
 use bytes::Bytes;
-use persistance::fs::{read, utils::get_config_location, write_media};
+use persistance::{
+    fs::{read, utils::get_config_location},
+    media::MediaFile,
+};
 use render::{search_results_page::SearchResultsPage, Render};
-use search_engine::semantic_search;
+use search_engine::{search_results, suggest_titles, QueryError, SearchResult};
 use thiserror::Error;
 use urlencoding::decode;
-use wikitext::parsers::Note;
+use wikitext::{parsers::Note, GlobalBacklinks};
+
+use super::media_runner::MediaRunner;
 
 pub struct APIRunner {}
 
@@ -19,9 +24,13 @@ pub enum FileError {
 }
 
 impl APIRunner {
-    pub async fn file(filename: String, data: Vec<u8>) -> Result<(), FileError> {
-        match write_media(&filename, &data).await {
-            Ok(()) => Ok(()),
+    pub async fn file(
+        filename: String,
+        data: Vec<u8>,
+        uploader: String,
+    ) -> Result<MediaFile, FileError> {
+        match MediaRunner::upload(filename, data, uploader).await {
+            Ok(media_file) => Ok(media_file),
             Err(e) => {
                 eprintln!("Could not write media: {}", e);
                 Err(FileError::FileWrite)
@@ -37,22 +46,51 @@ impl APIRunner {
         }
     }
 
-    pub async fn process_image(filename: String, bytes: Bytes) -> Result<(), io::Error> {
-        write_media(&filename, bytes.as_ref()).await
+    /// Stores a pasted image, returning the name it was stored under.
+    pub async fn process_image(
+        filename: String,
+        bytes: Bytes,
+        uploader: String,
+    ) -> Result<String, io::Error> {
+        let media_file = MediaRunner::upload(filename, bytes.to_vec(), uploader).await?;
+        Ok(media_file.name)
     }
 
     pub async fn note_search(term: String) -> String {
         let now = Instant::now();
-        let found_pages = semantic_search(&term).await;
+        let (found_pages, error) = match search_results(&term).await {
+            Ok(results) => {
+                let found_pages = results
+                    .into_iter()
+                    .map(|result| (result.title, result.snippets))
+                    .collect();
+                (found_pages, None)
+            }
+            Err(e) => (Vec::new(), Some(e.to_string())),
+        };
         let num_results = found_pages.len();
         let ctx = SearchResultsPage {
             pages: found_pages,
             num_results,
             time: now.elapsed(),
+            error,
         };
         ctx.render().await
     }
 
+    /// Search results for the JSON API.
+    pub async fn search(term: String) -> Result<Vec<SearchResult>, QueryError> {
+        search_results(&term).await
+    }
+
+    /// Note titles completing `query`, typos and all, the most linked to first.
+    pub async fn suggest(query: String, limit: usize, links: GlobalBacklinks) -> Vec<String> {
+        let links = links.lock().await;
+        suggest_titles(&query, limit, |title| {
+            links.get(title).map_or(0, |linked_by| linked_by.len())
+        })
+    }
+
     pub async fn update_styles(form_body: HashMap<String, String>) -> Result<(), io::Error> {
         let (path, _) = get_config_location();
         let style_location = path.join("userstyles.css");
@@ -0,0 +1,129 @@
+use std::{
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use bibliography::{CitationStyle, Library};
+use persistance::fs::{config::read_config, utils::get_bibliography_location};
+use render::{
+    bibliography_page::{BibliographyEntry, BibliographyPage},
+    render_page_references, Render,
+};
+use wikitext::{parsers::get_citations, GlobalBacklinks};
+
+/// The configured citation style.
+fn citation_style() -> CitationStyle {
+    let config = read_config();
+    match config
+        .bibliography
+        .as_ref()
+        .and_then(|bib| bib.style.as_ref())
+    {
+        Some(style) => CitationStyle::from_str(style).unwrap_or_else(|e| {
+            eprintln!("{}, falling back to APA", e);
+            CitationStyle::default()
+        }),
+        None => CitationStyle::default(),
+    }
+}
+
+/// The parsed bibliography, kept until the file changes or the config points somewhere else.
+struct CachedLibrary {
+    location: PathBuf,
+    modified: SystemTime,
+    len: u64,
+    library: Arc<Library>,
+}
+
+static LIBRARY: Mutex<Option<CachedLibrary>> = Mutex::new(None);
+
+fn load_library() -> Result<Arc<Library>, String> {
+    let location = get_bibliography_location();
+    let unreadable = |e| format!("Could not read {}: {}", location.display(), e);
+    let metadata = fs::metadata(&location).map_err(unreadable)?;
+    let modified = metadata.modified().map_err(unreadable)?;
+    let mut cached = LIBRARY.lock().unwrap();
+    if let Some(cached) = cached.as_ref().filter(|cached| {
+        cached.location == location && cached.modified == modified && cached.len == metadata.len()
+    }) {
+        return Ok(cached.library.clone());
+    }
+    let contents = fs::read_to_string(&location).map_err(unreadable)?;
+    let library = Arc::new(
+        Library::from_str(&contents)
+            .map_err(|e| format!("Could not parse {}: {}", location.display(), e))?,
+    );
+    *cached = Some(CachedLibrary {
+        location,
+        modified,
+        len: metadata.len(),
+        library: library.clone(),
+    });
+    Ok(library)
+}
+
+/// The author and year label of a cited entry, see [wikitext::parsers::set_citation_resolver].
+pub fn citation_label(key: &str) -> Option<String> {
+    load_library().ok()?.get(key).map(|entry| entry.label())
+}
+
+pub struct BibliographyRunner {}
+
+impl BibliographyRunner {
+    pub async fn render(links: GlobalBacklinks) -> String {
+        let ctx = match load_library() {
+            Ok(library) => {
+                let links = links.lock().await;
+                let entries = library
+                    .entries()
+                    .iter()
+                    .map(|entry| {
+                        let mut cited_by = links
+                            .get(&format!("@{}", entry.key))
+                            .cloned()
+                            .unwrap_or_default();
+                        cited_by.sort_unstable();
+                        cited_by.dedup();
+                        BibliographyEntry {
+                            key: entry.key.clone(),
+                            reference: entry.format(citation_style()),
+                            cited_by,
+                        }
+                    })
+                    .collect();
+                BibliographyPage {
+                    entries,
+                    error: None,
+                }
+            }
+            Err(e) => BibliographyPage {
+                entries: Vec::with_capacity(0),
+                error: Some(e),
+            },
+        };
+        ctx.render().await
+    }
+
+    /// Appends the reference list for every entry the note cites.
+    pub async fn add_references(content: &str, body: &mut String) {
+        let citations = get_citations(content);
+        if citations.is_empty() {
+            return;
+        }
+        let library = load_library().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            Arc::new(Library::default())
+        });
+        let references = citations
+            .into_iter()
+            .map(|key| {
+                let reference = library.get(key).map(|entry| entry.format(citation_style()));
+                (key.to_string(), reference)
+            })
+            .collect();
+        body.push_str(&render_page_references(references));
+    }
+}
@@ -4,7 +4,11 @@ use persistance::fs::write;
 use regex::Regex;
 use tokio::time::timeout;
 use urlencoding::encode;
-use wikitext::{processors::sanitize_html, PatchData};
+use wikitext::{
+    parsers::{format_links, wiki_path},
+    processors::sanitize_html,
+    PatchData,
+};
 
 use crate::{archive::extract, messages::Message, Queue, QueueHandle};
 
@@ -23,6 +27,7 @@ impl BookmarkRunner {
                 title,
                 old_title: String::with_capacity(0),
                 metadata,
+                revision: String::new(),
             };
             Ok((product.text, patch))
         } else {
@@ -48,7 +53,7 @@ impl BookmarkRunner {
         .unwrap()
         {
             match write(&patch).await {
-                Ok(()) => {
+                Ok(_) => {
                     queue
                         .push(Message::Patch {
                             patch: patch.clone(),
@@ -63,11 +68,11 @@ impl BookmarkRunner {
                         })
                         .await
                         .unwrap();
-                    return format!("/{}", encode(&patch.title));
+                    return format_links(&patch.title);
                 }
                 Err(e) => {
                     eprintln!("  {}\n", e);
-                    return format!("/error?msg={}", encode(&format!("{:?}", e)));
+                    return wiki_path(&format!("/error?msg={}", encode(&format!("{:?}", e))));
                 }
             }
         } else {
@@ -79,7 +84,7 @@ impl BookmarkRunner {
                 .await
                 .unwrap();
         }
-        String::from("/bookmark")
+        wiki_path("/bookmark")
     }
 }
 
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use persistance::fs::WriteWikiError;
+use render::{
+    history_page::{HistoryEntry, HistoryPage, RevisionDiff},
+    Render,
+};
+use tokio::task::spawn_blocking;
+use wikitext::{
+    parsers::{format_links, Note},
+    PatchData,
+};
+
+use crate::{history, QueueHandle};
+
+use super::wiki_runner::WikiRunner;
+
+pub struct HistoryRunner {}
+
+impl HistoryRunner {
+    fn label(title: &str, id: &str) -> String {
+        if id == history::CURRENT {
+            return String::from("the current version");
+        }
+        history::list(title)
+            .into_iter()
+            .find(|r| r.id == id)
+            .map(|r| r.saved.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    fn revision_diff(title: &str, from: &str, to: &str) -> RevisionDiff {
+        let lines = match (history::read(title, from), history::read(title, to)) {
+            (Ok(old), Ok(new)) => history::diff(&old, &new),
+            (Err(e), _) | (_, Err(e)) => {
+                eprintln!("Could not read revision of {}: {}", title, e);
+                Vec::new()
+            }
+        };
+        RevisionDiff {
+            from: HistoryRunner::label(title, from),
+            to: HistoryRunner::label(title, to),
+            lines,
+        }
+    }
+
+    /// Lists the saved revisions of a note, `from` and `to` query params pick two revisions
+    /// to compare.
+    pub async fn render(title: String, query_params: HashMap<String, String>) -> String {
+        let page = spawn_blocking(move || {
+            let entries = history::list(&title)
+                .into_iter()
+                .map(|r| HistoryEntry {
+                    saved: r.saved.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    id: r.id,
+                })
+                .collect();
+            let diff = match (query_params.get("from"), query_params.get("to")) {
+                (Some(from), Some(to)) => Some(HistoryRunner::revision_diff(&title, from, to)),
+                _ => None,
+            };
+            HistoryPage {
+                title,
+                entries,
+                diff,
+            }
+        })
+        .await
+        .unwrap();
+        page.render().await
+    }
+
+    /// Saves an old revision as the newest version of the note. The version being replaced is
+    /// kept in the history like any other edit, so restoring can be undone.
+    pub async fn restore(
+        queue: QueueHandle,
+        form_body: HashMap<String, String>,
+    ) -> Result<String, WriteWikiError> {
+        let title = form_body.get("title").unwrap().to_owned();
+        let id = form_body.get("id").unwrap().to_owned();
+        let lookup_title = title.clone();
+        let content = spawn_blocking(move || history::read(&lookup_title, &id))
+            .await
+            .unwrap()
+            .map_err(|e| {
+                eprintln!("{}", e);
+                WriteWikiError::WriteError(e)
+            })?;
+        let mut patch: PatchData = Note::from(content).into();
+        // The revision may be from before the note was renamed.
+        patch.title = title.clone();
+        patch.old_title = title;
+        let location = format_links(&patch.title);
+        WikiRunner::edit(patch, queue).await?;
+        Ok(location)
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, io};
+
+use persistance::{
+    media::{media_library, MediaFile},
+    store::note_store,
+};
+use regex::Regex;
+use tokio::task::spawn_blocking;
+use urlencoding::encode;
+use wikitext::{Backlinks, GlobalBacklinks};
+
+use crate::images::{generate_thumbnails, prepare_upload};
+
+pub struct MediaRunner {}
+
+/// What can follow a file name in a note without being part of it. A `.` only ends the name when
+/// it ends a sentence, `cat.png.bak` is a different file than `cat.png`.
+const NAME_END: &str = r"(\.?(?:[^\w.%-]|$))";
+
+/// Matches a file name as written in notes, where it may also appear URL encoded.
+fn name_pattern(name: &str) -> String {
+    let encoded = encode(name);
+    if encoded == name {
+        regex::escape(name)
+    } else {
+        format!("(?:{}|{})", regex::escape(name), regex::escape(&encoded))
+    }
+}
+
+/// Points `[[files:old]]` links and `/files/old` URLs at a renamed file.
+fn relink_media(content: &str, old_name: &str, new_name: &str) -> String {
+    let rgx = Regex::new(&format!(
+        r"(files:|/files/){}{}",
+        name_pattern(old_name),
+        NAME_END
+    ))
+    .unwrap();
+    rgx.replace_all(content, |caps: &regex::Captures| {
+        let name = if &caps[1] == "files:" {
+            new_name.to_string()
+        } else {
+            encode(new_name).into_owned()
+        };
+        format!("{}{}{}", &caps[1], name, &caps[2])
+    })
+    .into_owned()
+}
+
+/// Replaces links to a deleted file with plain text, keeping the label of labelled links.
+fn unlink_media(content: &str, name: &str) -> String {
+    let pattern = name_pattern(name);
+    let labelled = Regex::new(&format!(r"\[\[([^\[\]|]*)\|files:{}\]\]", pattern)).unwrap();
+    let bare = Regex::new(&format!(r"\[\[files:{}\]\]", pattern)).unwrap();
+    let url = Regex::new(&format!(
+        r"(?:https?://[^\s/]+)?/files/{}{}",
+        pattern, NAME_END
+    ))
+    .unwrap();
+    let content = labelled.replace_all(content, "$1");
+    let content = bare.replace_all(&content, regex::NoExpand(name));
+    url.replace_all(&content, |caps: &regex::Captures| {
+        format!("{}{}", name, &caps[1])
+    })
+    .into_owned()
+}
+
+/// Applies `rewrite` to every note it changes.
+fn update_references(rewrite: &dyn Fn(&str) -> String) -> io::Result<()> {
+    let store = note_store();
+    for title in store.list()? {
+        let content = match store.read(&title) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        if rewrite(&content) == content {
+            continue;
+        }
+        store.update(&title, &mut |current| {
+            Ok(rewrite(&current.unwrap_or_default()))
+        })?;
+    }
+    Ok(())
+}
+
+/// The notes using a file, sorted by title.
+pub(crate) fn used_by(links: &Backlinks, name: &str) -> Vec<String> {
+    let mut titles = links
+        .get(&format!("files:{}", name))
+        .cloned()
+        .unwrap_or_default();
+    titles.sort_unstable();
+    titles.dedup();
+    titles
+}
+
+/// Files no note links to, embeds or uses as its icon or cover.
+pub(crate) fn orphans(files: Vec<MediaFile>, links: &Backlinks) -> Vec<MediaFile> {
+    files
+        .into_iter()
+        .filter(|file| used_by(links, &file.name).is_empty())
+        .collect()
+}
+
+impl MediaRunner {
+    /// Stores an upload with its metadata removed and generates the thumbnails of images.
+    pub async fn upload(
+        filename: String,
+        data: Vec<u8>,
+        uploader: String,
+    ) -> Result<MediaFile, io::Error> {
+        spawn_blocking(move || {
+            let media_file = media_library().save(&filename, &prepare_upload(data), &uploader)?;
+            if let Err(e) = generate_thumbnails(&media_file) {
+                eprintln!(
+                    "Could not generate thumbnails for {}: {}",
+                    media_file.name, e
+                );
+            }
+            Ok(media_file)
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Renames a file and updates the notes linking to it.
+    pub async fn rename(
+        form_body: HashMap<String, String>,
+        links: GlobalBacklinks,
+    ) -> Result<(), io::Error> {
+        let name = form_body.get("name").unwrap().to_owned();
+        let new_name = form_body.get("new_name").unwrap().to_owned();
+        let old_name = name.clone();
+        let renamed = spawn_blocking(move || {
+            let renamed = media_library().rename(&name, &new_name)?;
+            if renamed.name != name {
+                update_references(&|content| relink_media(content, &name, &renamed.name))?;
+            }
+            Ok::<_, io::Error>(renamed)
+        })
+        .await
+        .unwrap()?;
+        let mut links = links.lock().await;
+        if let Some(linked_by) = links.remove(&format!("files:{}", old_name)) {
+            links.insert(format!("files:{}", renamed.name), linked_by);
+        }
+        Ok(())
+    }
+
+    /// Deletes a file and removes the links to it from notes.
+    pub async fn delete(
+        form_body: HashMap<String, String>,
+        links: GlobalBacklinks,
+    ) -> Result<(), io::Error> {
+        let name = form_body.get("name").unwrap().to_owned();
+        let deleted = name.clone();
+        spawn_blocking(move || {
+            media_library().delete(&name)?;
+            update_references(&|content| unlink_media(content, &name))
+        })
+        .await
+        .unwrap()?;
+        links.lock().await.remove(&format!("files:{}", deleted));
+        Ok(())
+    }
+
+    /// Deletes the selected files from the orphan report, skipping any a note started using
+    /// since the report was shown. Returns how many files were deleted.
+    pub async fn delete_orphans(
+        form_body: Vec<(String, String)>,
+        links: GlobalBacklinks,
+    ) -> Result<usize, io::Error> {
+        let selected = form_body
+            .into_iter()
+            .filter(|(key, _)| key == "name")
+            .map(|(_, name)| name)
+            .collect::<Vec<String>>();
+        let orphaned = {
+            let links = links.lock().await;
+            selected
+                .into_iter()
+                .filter(|name| used_by(&links, name).is_empty())
+                .collect::<Vec<String>>()
+        };
+        spawn_blocking(move || {
+            let library = media_library();
+            for name in orphaned.iter() {
+                library.delete(name)?;
+            }
+            Ok(orphaned.len())
+        })
+        .await
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relinks_renamed_files() {
+        let content = "See [[files:cat.png]] and [[my cat|files:cat.png]].\n\
+            ![cat](http://localhost:6683/files/cat.png)\n\
+            Not [[files:cat.png.bak]] or /files/cat.pngs";
+        assert_eq!(
+            relink_media(content, "cat.png", "tabby cat.png"),
+            "See [[files:tabby cat.png]] and [[my cat|files:tabby cat.png]].\n\
+            ![cat](http://localhost:6683/files/tabby%20cat.png)\n\
+            Not [[files:cat.png.bak]] or /files/cat.pngs"
+        );
+        assert_eq!(
+            relink_media("/files/tabby%20cat.png", "tabby cat.png", "cat.png"),
+            "/files/cat.png"
+        );
+    }
+
+    #[test]
+    fn unlinks_deleted_files() {
+        let content = "See [[files:report.pdf]], [[the report|files:report.pdf]] \
+            and http://localhost:6683/files/report.pdf.";
+        assert_eq!(
+            unlink_media(content, "report.pdf"),
+            "See report.pdf, the report and report.pdf."
+        );
+    }
+
+    fn media_file(name: &str) -> MediaFile {
+        MediaFile {
+            name: name.into(),
+            size: 0,
+            content_type: String::from("image/png"),
+            uploaded: String::new(),
+            uploader: String::new(),
+            hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn finds_orphaned_files() {
+        let mut links = Backlinks::new();
+        links.insert("files:cat.png".into(), vec!["Cats".into(), "Cats".into()]);
+        links.insert("files:dog.png".into(), Vec::new());
+        links.insert("dog.png".into(), vec!["Dogs".into()]);
+        assert_eq!(used_by(&links, "cat.png"), vec![String::from("Cats")]);
+        let files = vec![
+            media_file("cat.png"),
+            media_file("dog.png"),
+            media_file("bird.png"),
+        ];
+        let orphaned = orphans(files, &links)
+            .into_iter()
+            .map(|file| file.name)
+            .collect::<Vec<String>>();
+        assert_eq!(orphaned, vec!["dog.png", "bird.png"]);
+    }
+}
@@ -1,5 +1,9 @@
 pub mod api_runner;
+pub mod bibliography_runner;
 pub mod bookmark_runner;
+pub mod history_runner;
+pub mod media_runner;
 pub mod static_page_runner;
 pub mod todo_runner;
 pub mod wiki_runner;
+pub mod trash_runner;
@@ -1,28 +1,48 @@
 use std::collections::HashMap;
 
-use persistance::fs::utils::get_config_location;
+use persistance::media::MediaFile;
+use persistance::{fs::utils::get_config_location, media::media_library};
 use render::{
-    error_page::ErrorPage, styles_page::StylesPage, uploaded_files_page::UploadedFilesPage, Render,
+    error_page::ErrorPage,
+    orphaned_files_page::OrphanedFilesPage,
+    styles_page::StylesPage,
+    uploaded_files_page::{MediaEntry, UploadedFilesPage},
+    Render,
 };
-use tokio::fs::{self, read_dir};
+use tokio::{fs, task::spawn_blocking};
+use wikitext::GlobalBacklinks;
+
+use super::media_runner::{orphans, used_by};
 
 pub struct StaticPageRunner {}
 
 impl StaticPageRunner {
-    pub async fn list_files(media_location: String) -> String {
-        let mut entry_list = Vec::new();
-        let mut entries = read_dir(media_location).await.unwrap();
-        while let Ok(entry) = entries.next_entry().await {
-            if entry.is_some() {
-                let entry = entry.unwrap();
-                entry_list.push(entry.file_name().into_string().unwrap());
-            } else {
-                break;
-            }
-        }
-        let ctx = UploadedFilesPage {
-            entries: entry_list,
-        };
+    async fn media_files() -> Vec<MediaFile> {
+        spawn_blocking(|| media_library().list())
+            .await
+            .unwrap()
+            .unwrap_or_else(|e| {
+                eprintln!("Could not list media: {}", e);
+                Vec::new()
+            })
+    }
+    pub async fn list_files(links: GlobalBacklinks) -> String {
+        let files = StaticPageRunner::media_files().await;
+        let links = links.lock().await;
+        let entries = files
+            .into_iter()
+            .map(|file| MediaEntry {
+                used_by: used_by(&links, &file.name),
+                file,
+            })
+            .collect();
+        let ctx = UploadedFilesPage { entries };
+        ctx.render().await
+    }
+    pub async fn list_orphans(links: GlobalBacklinks) -> String {
+        let files = StaticPageRunner::media_files().await;
+        let entries = orphans(files, &*links.lock().await);
+        let ctx = OrphanedFilesPage { entries };
         ctx.render().await
     }
     pub async fn render_styles() -> String {
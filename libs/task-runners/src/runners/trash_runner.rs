@@ -0,0 +1,52 @@
+use std::{collections::HashMap, io};
+
+use render::{
+    trash_page::{TrashEntry, TrashPage},
+    Render,
+};
+use tokio::task::spawn_blocking;
+use wikitext::parsers::format_links;
+
+use crate::{messages::Message, trash, Queue, QueueHandle};
+
+const DISPLAY_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+pub struct TrashRunner {}
+
+impl TrashRunner {
+    pub async fn render() -> String {
+        let entries = spawn_blocking(|| {
+            trash::purge_expired();
+            trash::list()
+                .into_iter()
+                .map(|t| TrashEntry {
+                    deleted: t.deleted.format(DISPLAY_FORMAT).to_string(),
+                    expires: t.expires().format(DISPLAY_FORMAT).to_string(),
+                    title: t.title,
+                    id: t.id,
+                })
+                .collect()
+        })
+        .await
+        .unwrap();
+        TrashPage { entries }.render().await
+    }
+
+    /// Puts a note back and queues it to be linked and indexed again, returning the location of
+    /// the restored note.
+    pub async fn restore(
+        queue: QueueHandle,
+        form_body: HashMap<String, String>,
+    ) -> Result<String, io::Error> {
+        let id = form_body.get("id").unwrap().to_owned();
+        let title = spawn_blocking(move || trash::restore(&id)).await.unwrap()?;
+        let location = format_links(&title);
+        queue.push(Message::Restore { title }).await.unwrap();
+        Ok(location)
+    }
+
+    pub async fn purge(form_body: HashMap<String, String>) -> Result<(), io::Error> {
+        let id = form_body.get("id").unwrap().to_owned();
+        spawn_blocking(move || trash::purge(&id)).await.unwrap()
+    }
+}
@@ -1,15 +1,37 @@
-use std::collections::HashMap;
-use std::fmt::Write as _;
+use std::collections::{BTreeSet, HashMap};
 
-use persistance::fs::{create_journal_entry, read, write, ReadPageError, WriteWikiError};
-use render::{injected_html::InjectedHTML, new_page::NewPage, wiki_page::WikiPage, Render};
-use urlencoding::decode;
-use wikitext::{parsers::Note, PatchData};
+use persistance::fs::{
+    create_journal_entry, get_note_titles,
+    journal::{today, update_rollups},
+    read, read_with_revision,
+    utils::validate_title,
+    write, ReadPageError, WriteWikiError,
+};
+use render::{
+    injected_html::InjectedHTML, namespace_page::NamespacePage, new_page::NewPage,
+    wiki_page::WikiPage, Render,
+};
+use tokio::task::spawn_blocking;
+use wikitext::{
+    parsers::{format_links, wiki_path, Note},
+    MergeRequest, MergeResult, PatchData,
+};
 
-use crate::{cache::purge_mru_cache, messages::Message, Queue, QueueHandle};
+use crate::{cache::purge_mru_cache, history, messages::Message, Queue, QueueHandle};
+
+use super::bibliography_runner::BibliographyRunner;
 
 pub struct WikiRunner {}
 
+/// Merges `mine` and `theirs`, both edited from `base`. Returns the merged text and whether it
+/// contains conflict markers.
+fn merge_bodies(base: &str, theirs: &str, mine: &str) -> (String, bool) {
+    match diffy::merge(base, theirs, mine) {
+        Ok(merged) => (merged, false),
+        Err(conflicted) => (conflicted, true),
+    }
+}
+
 impl WikiRunner {
     pub async fn render_file(
         &self,
@@ -17,49 +39,28 @@ impl WikiRunner {
         reflinks: Option<&Vec<String>>,
         query_params: HashMap<String, String>,
     ) -> String {
-        let path = decode(&path).unwrap();
-        self.render_from_path(path.to_string(), reflinks, query_params)
+        self.render_from_path(path, reflinks, query_params)
             .await
             .unwrap()
     }
 
-    async fn note_to_html(&self, note: Note, links: Option<&Vec<String>>) -> String {
-        let templatted = note.to_template();
-        match note.header.get("content-type") {
-            Some(content_type) => {
-                if content_type == "html" {
-                    return InjectedHTML::new(&templatted.page, links).render().await;
-                }
-                WikiPage::new(&templatted.page, links).render().await
-            }
-            None => WikiPage::new(&templatted.page, links).render().await,
-        }
-    }
-
-    pub async fn render_nested_file(
+    async fn note_to_html(
         &self,
-        mut main_path: String,
-        sub_path: String,
+        note: Note,
+        revision: &str,
         links: Option<&Vec<String>>,
-    ) -> Result<String, ReadPageError> {
-        // I don't know why warp doesn't decode the sub path here...
-        let sub_path_decoded = decode(&sub_path).unwrap();
-        write!(main_path, "/{}", sub_path_decoded).unwrap();
-        match read(main_path.clone()).await {
-            Ok(note) => Ok(self.note_to_html(note, links).await),
-            Err(ReadPageError::PageNotFoundError) => {
-                let ctx = NewPage {
-                    title: Some(urlencoding::decode(&sub_path).unwrap().into_owned()),
-                    linkto: None,
-                    action_params: None,
-                };
-                Ok(ctx.render().await)
-            }
-            e => {
-                eprint!("{:?}", e);
-                Err(ReadPageError::Unknown)
+    ) -> String {
+        let mut templatted = note.to_template();
+        if let Some(content_type) = note.header.get("content-type") {
+            if content_type == "html" {
+                return InjectedHTML::new(&templatted.page, links).render().await;
             }
         }
+        BibliographyRunner::add_references(&note.content, &mut templatted.page.body).await;
+        WikiPage::new(&templatted.page, links)
+            .with_revision(revision)
+            .render()
+            .await
     }
 
     pub async fn render_from_path(
@@ -68,11 +69,20 @@ impl WikiRunner {
         links: Option<&Vec<String>>,
         query_params: HashMap<String, String>,
     ) -> Result<String, ReadPageError> {
-        match read(path.clone()).await {
-            Ok(note) => Ok(self.note_to_html(note, links).await),
+        // A trailing slash always asks for the namespace listing, even if there's a note with the
+        // same name as the namespace.
+        if let Some(namespace) = path.strip_suffix('/') {
+            return Ok(Self::render_namespace(namespace).await);
+        }
+        match read_with_revision(path.clone()).await {
+            Ok((note, revision)) => Ok(self.note_to_html(note, &revision, links).await),
             Err(ReadPageError::PageNotFoundError) => {
+                let namespace = Self::namespace_page(&path);
+                if !namespace.namespaces.is_empty() || !namespace.notes.is_empty() {
+                    return Ok(namespace.render().await);
+                }
                 let ctx = NewPage {
-                    title: Some(urlencoding::decode(&path).unwrap().into_owned()),
+                    title: Some(path),
                     linkto: query_params.get("linkto"),
                     action_params: None,
                 };
@@ -84,6 +94,36 @@ impl WikiRunner {
             }
         }
     }
+
+    fn namespace_page(namespace: &str) -> NamespacePage {
+        let namespace = namespace.trim_matches('/');
+        let prefix = format!("{}/", namespace);
+        let mut namespaces = BTreeSet::new();
+        let mut notes = Vec::new();
+        for title in get_note_titles().unwrap_or_default() {
+            let child = match title.strip_prefix(&prefix) {
+                Some(child) => child,
+                None => continue,
+            };
+            match child.split_once('/') {
+                Some((sub_namespace, _)) => {
+                    namespaces.insert(format!("{}{}", prefix, sub_namespace));
+                }
+                None => notes.push(title.clone()),
+            }
+        }
+        notes.sort_by_key(|n| n.to_lowercase());
+        NamespacePage {
+            namespace: namespace.to_string(),
+            namespaces: namespaces.into_iter().collect(),
+            notes,
+        }
+    }
+
+    pub async fn render_namespace(namespace: &str) -> String {
+        Self::namespace_page(namespace).render().await
+    }
+
     pub async fn render_new(query_params: HashMap<String, String>) -> String {
         let ctx = NewPage {
             title: None,
@@ -93,7 +133,8 @@ impl WikiRunner {
         ctx.render().await
     }
 
-    pub async fn edit(body: PatchData, queue: QueueHandle) -> Result<(), WriteWikiError> {
+    /// Saves an edit from the web editor, returning the new revision of the note.
+    pub async fn edit(body: PatchData, queue: QueueHandle) -> Result<String, WriteWikiError> {
         if body
             .tags
             .iter()
@@ -120,10 +161,34 @@ impl WikiRunner {
                 }
             }
         }
+        let is_rename = !body.old_title.is_empty() && body.old_title != body.title;
+        let title_on_disk = if is_rename {
+            body.old_title.clone()
+        } else {
+            body.title.clone()
+        };
+        // Keep the version we're about to overwrite.
+        let recorded_title = title_on_disk.clone();
+        if let Err(e) = spawn_blocking(move || history::record(&recorded_title))
+            .await
+            .unwrap()
+        {
+            eprintln!("Could not save revision of {}: {}", title_on_disk, e);
+        }
         match write(&body).await {
-            Ok(()) => {
+            Ok(revision) => {
+                if is_rename {
+                    let (old_title, new_title) = (body.old_title.clone(), body.title.clone());
+                    if let Err(e) =
+                        spawn_blocking(move || history::move_history(&old_title, &new_title))
+                            .await
+                            .unwrap()
+                    {
+                        eprintln!("Could not move history of {}: {}", body.old_title, e);
+                    }
+                }
                 queue.push(Message::Patch { patch: body }).await.unwrap();
-                Ok(())
+                Ok(revision)
             }
             Err(e) => {
                 eprintln!("{}", e);
@@ -132,10 +197,37 @@ impl WikiRunner {
         }
     }
 
+    /// Three-way merges the body of a save that was rejected as out of date with the current
+    /// version of the note. Conflicting sections are left marked for the editor to resolve.
+    pub async fn merge(request: MergeRequest) -> Result<MergeResult, ReadPageError> {
+        let patch = request.patch;
+        let current_title = if !patch.old_title.is_empty() && patch.old_title != patch.title {
+            patch.old_title
+        } else {
+            patch.title
+        };
+        let (current, revision) = read_with_revision(current_title).await?;
+        let (body, conflicted) = merge_bodies(&request.base, &current.content, &patch.body);
+        Ok(MergeResult {
+            body,
+            revision,
+            conflicted,
+        })
+    }
+
     pub async fn append(body: PatchData, queue: QueueHandle) -> Result<(), WriteWikiError> {
         match create_journal_entry(body.body).await {
             Ok(patch) => {
                 queue.push(Message::Patch { patch }).await.unwrap();
+                // The weekly and monthly notes list the daily note.
+                match update_rollups(today()) {
+                    Ok(rollups) => {
+                        for patch in rollups {
+                            queue.push(Message::Patch { patch }).await.unwrap();
+                        }
+                    }
+                    Err(e) => eprintln!("Could not update journal rollups: {}", e),
+                }
                 Ok(())
             }
             Err(e) => {
@@ -145,6 +237,43 @@ impl WikiRunner {
         }
     }
 
+    /// Moves a note into another namespace, keeping its name. An empty namespace moves the note
+    /// to the top level. Links to the note are updated the same way as a rename from the editor.
+    pub async fn move_note(
+        queue: QueueHandle,
+        form_body: HashMap<String, String>,
+    ) -> Result<String, WriteWikiError> {
+        let title = form_body.get("title").unwrap();
+        let name = title.rsplit('/').next().unwrap_or(title);
+        let namespace = form_body
+            .get("namespace")
+            .map(|n| n.trim().trim_matches('/'))
+            .unwrap_or_default();
+        let new_title = if namespace.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", namespace, name)
+        };
+        if &new_title == title {
+            return Ok(format_links(title));
+        }
+        validate_title(&new_title).map_err(|e| {
+            eprintln!("{}", e);
+            WriteWikiError::TitleInvalid
+        })?;
+        let note = read(title.clone()).await.map_err(|e| {
+            eprintln!("{}", e);
+            WriteWikiError::TitleInvalid
+        })?;
+        let mut patch: PatchData = note.into();
+        patch.old_title = title.clone();
+        patch.title = new_title;
+        let location = format_links(&patch.title);
+        Self::edit(patch, queue.clone()).await?;
+        purge_mru_cache(title).await;
+        Ok(location)
+    }
+
     pub async fn delete(queue: QueueHandle, form_body: HashMap<String, String>) -> String {
         let title = form_body.get("title").unwrap();
         queue
@@ -155,6 +284,36 @@ impl WikiRunner {
             .unwrap();
 
         purge_mru_cache(title).await;
-        String::from("/")
+        wiki_path("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_edits_to_different_lines() {
+        let base = "first line\nsecond line\nthird line\n";
+        let theirs = "first line, edited elsewhere\nsecond line\nthird line\n";
+        let mine = "first line\nsecond line\nthird line, edited here\n";
+        let (merged, conflicted) = merge_bodies(base, theirs, mine);
+        assert!(!conflicted);
+        assert_eq!(
+            merged,
+            "first line, edited elsewhere\nsecond line\nthird line, edited here\n"
+        );
+    }
+
+    #[test]
+    fn marks_conflicting_edits() {
+        let base = "first line\nsecond line\n";
+        let theirs = "first line\nsecond line from another tab\n";
+        let mine = "first line\nsecond line from this tab\n";
+        let (merged, conflicted) = merge_bodies(base, theirs, mine);
+        assert!(conflicted);
+        assert!(merged.contains("<<<<<<<"));
+        assert!(merged.contains("second line from another tab"));
+        assert!(merged.contains("second line from this tab"));
     }
 }
@@ -5,6 +5,7 @@ use std::{
     time::Duration,
 };
 
+use persistance::fs::config::read_config;
 use tokio::task::spawn;
 use tokio::time::sleep;
 
@@ -81,25 +82,26 @@ impl Git {
         }
     }
     // Note: this will fall apart if there are merge conflicts!
-    async fn sync(&self, sync_interval: u8, branch: String, queue: Arc<JobQueue>) {
+    async fn sync(&self, queue: Arc<JobQueue>) {
         loop {
+            let sync = read_config().sync.clone();
             let changed_file_count = self.status();
             if changed_file_count > 0 {
                 println!("<syncing>");
                 self.add();
                 self.commit();
-                self.pull(&branch);
-                self.push(&branch);
+                self.pull(&sync.branch);
+                self.push(&sync.branch);
             }
             queue.push(Message::Rebuild).await.unwrap();
-            sleep(Duration::from_secs(sync_interval.into())).await
+            sleep(Duration::from_secs(sync.sync_interval.into())).await
         }
     }
 }
 
-pub async fn sync(wiki_location: &str, sync_interval: u8, branch: String, queue: Arc<JobQueue>) {
+pub async fn sync(wiki_location: &str, queue: Arc<JobQueue>) {
     let git = Git::new(wiki_location.to_owned());
-    spawn(async move { git.sync(sync_interval, branch, queue).await });
+    spawn(async move { git.sync(queue).await });
 }
 
 pub fn git_update(wiki_location: &str, branch: String) {
@@ -0,0 +1,184 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Duration, Local, NaiveDateTime};
+use persistance::{
+    crypto::{open, protect},
+    fs::{
+        config::read_config,
+        utils::{get_archive_file_path, get_trash_location},
+    },
+    store::note_store,
+};
+
+/// Each deleted note gets a directory named after the time it was deleted, holding the note as it
+/// was on disk, its title and its archive, if it had one. The note and its title are encrypted in
+/// encrypted notebooks.
+const ID_FORMAT: &str = "%Y%m%d%H%M%S%3f";
+const NOTE_FILE: &str = "note";
+const TITLE_FILE: &str = "title";
+const ARCHIVE_FILE: &str = "archive";
+const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// How long notes stay in the trash.
+fn retention() -> Duration {
+    let days = read_config()
+        .trash
+        .as_ref()
+        .and_then(|t| t.retention_days)
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    Duration::days(days as i64)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashedNote {
+    pub id: String,
+    pub title: String,
+    pub deleted: NaiveDateTime,
+    dir: PathBuf,
+}
+
+impl TrashedNote {
+    fn from_dir(dir: PathBuf) -> Option<Self> {
+        let id = dir.file_name()?.to_str()?.to_string();
+        let deleted = NaiveDateTime::parse_from_str(&id, ID_FORMAT).ok()?;
+        let title = open(fs::read(dir.join(TITLE_FILE)).ok()?).ok()?;
+        let title = String::from_utf8(title).ok()?;
+        Some(TrashedNote {
+            id,
+            title,
+            deleted,
+            dir,
+        })
+    }
+
+    /// When the note will be purged from the trash.
+    pub fn expires(&self) -> NaiveDateTime {
+        self.deleted + retention()
+    }
+}
+
+/// Notes in the trash, most recently deleted first.
+pub fn list() -> Vec<TrashedNote> {
+    let mut trashed = match fs::read_dir(get_trash_location()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| TrashedNote::from_dir(entry.path()))
+            .collect::<Vec<TrashedNote>>(),
+        Err(_) => Vec::new(),
+    };
+    trashed.sort_by(|a, b| b.id.cmp(&a.id));
+    trashed
+}
+
+fn find(id: &str) -> io::Result<TrashedNote> {
+    list()
+        .into_iter()
+        .find(|t| t.id == id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no deleted note with that id"))
+}
+
+/// Moves a file across directories, falling back to a copy when the data dir and the wiki are on
+/// different file systems.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Moves a note and its archive to the trash.
+pub fn move_to_trash(title: &str) -> io::Result<TrashedNote> {
+    let archive = get_archive_file_path(title)?;
+    let content = note_store().read(title)?;
+    let dir = get_trash_location().join(Local::now().naive_local().format(ID_FORMAT).to_string());
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(NOTE_FILE), protect(content.into_bytes()))?;
+    fs::write(dir.join(TITLE_FILE), protect(title.as_bytes().to_vec()))?;
+    if archive.is_file() {
+        move_file(&archive, &dir.join(ARCHIVE_FILE))?;
+    }
+    note_store().delete(title)?;
+    purge_expired();
+    TrashedNote::from_dir(dir)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "could not read trashed note"))
+}
+
+/// Puts a deleted note and its archive back, returning the note's title. Fails if a note with the
+/// same title has been created since.
+pub fn restore(id: &str) -> io::Result<String> {
+    let trashed = find(id)?;
+    let archive_path = get_archive_file_path(&trashed.title)?;
+    let store = note_store();
+    if store.exists(&trashed.title) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("a note called {} already exists", trashed.title),
+        ));
+    }
+    let archive = trashed.dir.join(ARCHIVE_FILE);
+    if archive.is_file() {
+        move_file(&archive, &archive_path)?;
+    }
+    let content = String::from_utf8(open(fs::read(trashed.dir.join(NOTE_FILE))?)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    store.write(&trashed.title, &content)?;
+    fs::remove_dir_all(&trashed.dir)?;
+    Ok(trashed.title)
+}
+
+/// Permanently removes a note from the trash.
+pub fn purge(id: &str) -> io::Result<()> {
+    fs::remove_dir_all(find(id)?.dir)
+}
+
+fn expired(trashed: &[TrashedNote], retention: Duration, now: NaiveDateTime) -> Vec<&TrashedNote> {
+    trashed
+        .iter()
+        .filter(|t| t.deleted + retention <= now)
+        .collect()
+}
+
+/// Purges every note that has been in the trash for longer than the configured retention.
+pub fn purge_expired() {
+    let trashed = list();
+    for note in expired(&trashed, retention(), Local::now().naive_local()) {
+        if let Err(e) = fs::remove_dir_all(&note.dir) {
+            eprintln!("Could not purge {} from the trash: {}", note.title, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trashed_at(id: &str) -> TrashedNote {
+        TrashedNote {
+            id: id.into(),
+            title: String::from("Alpha"),
+            deleted: NaiveDateTime::parse_from_str(id, ID_FORMAT).unwrap(),
+            dir: PathBuf::from("/trash").join(id),
+        }
+    }
+
+    #[test]
+    fn expires_notes_past_retention() {
+        let trashed = vec![
+            trashed_at("20220310000000000"),
+            trashed_at("20220201000000000"),
+        ];
+        let now = NaiveDateTime::parse_from_str("20220311000000000", ID_FORMAT).unwrap();
+        assert_eq!(
+            expired(&trashed, Duration::days(30), now),
+            vec![&trashed[1]]
+        );
+        assert!(expired(&trashed, Duration::days(60), now).is_empty());
+    }
+}
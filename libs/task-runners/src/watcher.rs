@@ -0,0 +1,82 @@
+use std::{sync::Arc, thread};
+
+use persistance::{
+    fs::utils::archive_file_exists,
+    ids::note_ids,
+    store::{note_store, StoreEvent},
+};
+use tokio::runtime::Handle;
+use wikitext::PatchData;
+
+use crate::{history, messages::Message, JobQueue, Queue};
+
+/// Turns a note changed outside of the wiki into the patch an edit from the web would have sent.
+fn patch_for(title: &str) -> Option<PatchData> {
+    let note = match note_store().read_note(title) {
+        Ok(note) => note,
+        Err(e) => {
+            eprintln!("Could not read changed note {}: {}", title, e);
+            return None;
+        }
+    };
+    let mut patch: PatchData = note.into();
+    // The file name wins over a title left in the header.
+    patch.title = title.into();
+    patch.old_title = String::new();
+    Some(patch)
+}
+
+fn messages_for(event: StoreEvent) -> Vec<Message> {
+    match event {
+        StoreEvent::Changed(title) => {
+            let patch = match patch_for(&title) {
+                Some(patch) => patch,
+                None => return Vec::new(),
+            };
+            if let Some(id) = patch.metadata.get("id") {
+                note_ids().insert(id, &title);
+            }
+            vec![Message::Patch { patch }]
+        }
+        StoreEvent::Removed(title) => vec![Message::Removed { title }],
+        StoreEvent::Renamed { from, to } => {
+            let mut patch = match patch_for(&to) {
+                Some(patch) => patch,
+                None => return Vec::new(),
+            };
+            let mut messages = Vec::new();
+            if archive_file_exists(&from) {
+                messages.push(Message::ArchiveMove {
+                    old_title: from.clone(),
+                    new_title: to.clone(),
+                });
+            }
+            // The same bookkeeping as a rename from the editor, links to the note are updated
+            // when the patch is processed.
+            if let Err(e) = note_ids().rename(&from, &to) {
+                eprintln!("Could not redirect {} to {}: {}", from, to, e);
+            }
+            if let Err(e) = history::move_history(&from, &to) {
+                eprintln!("Could not move history of {}: {}", from, e);
+            }
+            patch.old_title = from;
+            messages.push(Message::Patch { patch });
+            messages
+        }
+    }
+}
+
+/// Queues updates for notes changed outside of the wiki, e.g. in a text editor, so backlinks and
+/// the search index pick them up without waiting for a sync or a restart. Changes the wiki made
+/// itself are filtered out by the store.
+pub fn watch(queue: Arc<JobQueue>) {
+    let events = note_store().watch();
+    let runtime = Handle::current();
+    thread::spawn(move || {
+        for event in events {
+            for message in messages_for(event) {
+                runtime.block_on(queue.push(message)).unwrap();
+            }
+        }
+    });
+}
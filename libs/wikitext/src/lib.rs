@@ -19,6 +19,25 @@ pub struct PatchData {
     pub title: String,
     pub old_title: String,
     pub metadata: HashMap<String, String>,
+    /// Revision of the note the edit was based on, empty to skip the conflict check.
+    #[serde(default)]
+    pub revision: String,
+}
+
+/// A save that was rejected as out of date, along with the note body it started from.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MergeRequest {
+    pub base: String,
+    pub patch: PatchData,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct MergeResult {
+    /// The merged note body, containing conflict markers if `conflicted` is set.
+    pub body: String,
+    /// Revision of the note the merge was made against.
+    pub revision: String,
+    pub conflicted: bool,
 }
 
 impl From<HashMap<String, String>> for PatchData {
@@ -67,6 +86,7 @@ impl From<HashMap<String, String>> for PatchData {
             title,
             old_title,
             metadata,
+            revision: String::new(),
         }
     }
 }
@@ -14,6 +14,7 @@ pub(crate) enum BlockElement<'a> {
     EmptySpace(&'a str),
     Text(&'a str),
     HyperLink(&'a str),
+    Citation(Vec<&'a str>),
     IndentationLevel(u32),
 }
 
@@ -88,6 +89,34 @@ fn parse_link(slice: &str) -> BlockResult {
         }
         return Ok((BlockElement::PageLink(window(slice, 2, idx)), idx + 1));
     }
+    if slice.starts_with("[@") {
+        return parse_citation(slice);
+    }
+    Ok((BlockElement::Text(window(slice, 0, 1)), 0))
+}
+
+pub(crate) fn is_citation_key(key: &str) -> bool {
+    key.len() > 1
+        && key.starts_with('@')
+        && key[1..]
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-:./+".contains(c))
+}
+
+/// Parses pandoc style citations, e.g. `[@knuth1984]` or `[@knuth1984; @sicp]`.
+/// If anything between the brackets isn't a citation key, we treat the bracket as plain text.
+fn parse_citation(slice: &str) -> BlockResult {
+    if let Some(end) = slice.find(']') {
+        let keys = window(slice, 1, end)
+            .split(';')
+            .map(|key| key.trim())
+            .collect::<Vec<&str>>();
+        if keys.iter().all(|key| is_citation_key(key)) {
+            // The block iterator advances by characters, not bytes.
+            let steps = slice[..=end].chars().count() - 1;
+            return Ok((BlockElement::Citation(keys), steps));
+        }
+    }
     Ok((BlockElement::Text(window(slice, 0, 1)), 0))
 }
 fn parse_quote(slice: &str) -> BlockResult {
@@ -305,6 +334,20 @@ mod tests {
         assert_eq!(block[2], matching_block);
     }
 
+    #[test]
+    fn parses_citations() {
+        let test_string = "as shown [@knuth1984; @sicp], it works";
+        let block = parse_block(test_string);
+        assert_eq!(block.len(), 10);
+        let matching_block = BlockElement::Citation(vec!["@knuth1984", "@sicp"]);
+        assert_eq!(block[4], matching_block);
+        assert_eq!(block[5], BlockElement::Text(","));
+
+        let test_string = "[@ not a citation]";
+        let block = parse_block(test_string);
+        assert_eq!(block[0], BlockElement::Text("["));
+    }
+
     #[test]
     fn parses_raw_links_in_brackets() {
         let test_string = "[https://example.com]";
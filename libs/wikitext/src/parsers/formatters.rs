@@ -1,4 +1,4 @@
-use std::fmt::Write as _;
+use std::{borrow::Cow, fmt::Write as _, sync::RwLock};
 use urlencoding::encode;
 
 use super::block::BlockElement;
@@ -10,24 +10,21 @@ impl BlockElement<'_> {
                 write!(target, "<h2>{}</h2>", content).unwrap();
             }
             BlockElement::PageLink(content) => {
-                let aliases = content.split('|').collect::<Vec<&str>>();
-                if aliases.len() > 1 {
-                    write!(
-                        target,
-                        r#"<a href="{}">{}</a>"#,
-                        format_links(aliases[1]),
-                        aliases[0]
-                    )
-                    .unwrap();
+                let (link, label) = split_link(content);
+                let resolved = resolve_link(link);
+                // Links by id show the note's current title unless they're given a label.
+                let label = if label == link {
+                    resolved.as_ref()
                 } else {
-                    write!(
-                        target,
-                        r#"<a href="{}">{}</a>"#,
-                        format_links(aliases[0]),
-                        aliases[0]
-                    )
-                    .unwrap();
-                }
+                    label
+                };
+                write!(
+                    target,
+                    r#"<a href="{}">{}</a>"#,
+                    format_links(&resolved),
+                    label
+                )
+                .unwrap();
             }
             BlockElement::Quote(content) => {
                 write!(target, "<blockquote>").unwrap();
@@ -65,6 +62,11 @@ impl BlockElement<'_> {
                     write_to_string(target, format!(r#"<a href="{}">{}</a>"#, content, content));
                 }
             }
+            BlockElement::Citation(keys) => {
+                let resolver = *CITATION_RESOLVER.read().unwrap();
+                let label = |key: &str| resolver.and_then(|resolver| resolver(key));
+                write_to_string(target, citation_tag(keys, label));
+            }
             BlockElement::IndentationLevel(_) => {
                 // noop
             }
@@ -80,18 +82,160 @@ pub fn transform_audio_url(text: &str) -> String {
     format!(r#"<audio src="{}" controls></audio>"#, text)
 }
 
+/// Widths of the thumbnails generated for uploaded images, see [set_thumbnail_widths].
+static THUMBNAIL_WIDTHS: RwLock<Vec<u32>> = RwLock::new(Vec::new());
+
+/// Lets images link to the thumbnails generated for uploads. Thumbnails are expected at
+/// `/files/.thumbnails/<width>/<name>` for every width.
+pub fn set_thumbnail_widths(widths: Vec<u32>) {
+    *THUMBNAIL_WIDTHS.write().unwrap() = widths;
+}
+
 pub fn transform_image_url(text: &str) -> String {
-    format!(r#"<img src={} />"#, text)
+    image_tag(text, &THUMBNAIL_WIDTHS.read().unwrap())
+}
+
+fn image_tag(text: &str, thumbnail_widths: &[u32]) -> String {
+    // Thumbnails are only made for uploaded jpeg and png images.
+    let uploaded = match text.split_once("/files/") {
+        Some((origin, name))
+            if !name.contains('/')
+                && [".png", ".jpg", ".jpeg"]
+                    .iter()
+                    .any(|extension| name.to_lowercase().ends_with(extension)) =>
+        {
+            Some((origin, name))
+        }
+        _ => None,
+    };
+    match uploaded {
+        Some((origin, name)) if !thumbnail_widths.is_empty() => {
+            let srcset = thumbnail_widths
+                .iter()
+                .map(|width| format!("{}/files/.thumbnails/{}/{} {}w", origin, width, name, width))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!(
+                r#"<img src="{}" srcset="{}" loading="lazy" />"#,
+                text, srcset
+            )
+        }
+        _ => format!(r#"<img src={} />"#, text),
+    }
+}
+
+/// Looks up the label of a citation key, like `Knuth, 1984`, see [set_citation_resolver].
+type CitationResolver = fn(&str) -> Option<String>;
+
+static CITATION_RESOLVER: RwLock<Option<CitationResolver>> = RwLock::new(None);
+
+/// Lets citations show the author and year of the entries they cite. Keys the resolver doesn't
+/// know are shown as `@key`.
+pub fn set_citation_resolver(resolver: CitationResolver) {
+    *CITATION_RESOLVER.write().unwrap() = Some(resolver);
+}
+
+fn citation_tag(keys: &[&str], label: impl Fn(&str) -> Option<String>) -> String {
+    let citations = keys
+        .iter()
+        .map(|key| {
+            let key = key.trim_start_matches('@');
+            let label = match label(key) {
+                Some(label) => label
+                    .replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;"),
+                None => format!("@{}", key),
+            };
+            format!(r##"<a href="#ref-{}">{}</a>"##, key, label)
+        })
+        .collect::<Vec<String>>()
+        .join("; ");
+    format!(r#"<cite class="citation">[{}]</cite>"#, citations)
+}
+
+/// Looks up the title of the note with an id, see [set_id_resolver].
+type IdResolver = fn(&str) -> Option<String>;
+
+static ID_RESOLVER: RwLock<Option<IdResolver>> = RwLock::new(None);
+
+/// Lets `[[id:<id>]]` links point at the note's current title. Links to ids the resolver
+/// doesn't know go to `/id/<id>`.
+pub fn set_id_resolver(resolver: IdResolver) {
+    *ID_RESOLVER.write().unwrap() = Some(resolver);
+}
+
+/// Splits the content of a wiki link into the link and its label. Links are written as
+/// `[[title]]`, `[[label|title]]` or `[[id:<id>|label]]`.
+pub fn split_link(content: &str) -> (&str, &str) {
+    match content.split_once('|') {
+        Some((link, label)) if link.starts_with("id:") => (link, label),
+        Some((label, link)) => (link, label),
+        None => (content, content),
+    }
+}
+
+/// The title an `id:` link points at, other links are returned as they are.
+pub fn resolve_link(link: &str) -> Cow<'_, str> {
+    let title = link.strip_prefix("id:").and_then(|id| {
+        ID_RESOLVER
+            .read()
+            .unwrap()
+            .and_then(|resolver| resolver(id))
+    });
+    match title {
+        Some(title) => Cow::Owned(title),
+        None => Cow::Borrowed(link),
+    }
+}
+
+/// The path the wiki is served under, see [set_base_path].
+static BASE_PATH: RwLock<String> = RwLock::new(String::new());
+
+/// Serves the wiki under a path like `/work` instead of the root, the links to notes, ids and
+/// files made by [format_links] start with it.
+pub fn set_base_path(path: &str) {
+    *BASE_PATH.write().unwrap() = path.trim_end_matches('/').to_string();
+}
+
+/// The path the wiki is served under, without a trailing slash. Empty when it's served from the
+/// root.
+pub fn base_path() -> String {
+    BASE_PATH.read().unwrap().clone()
+}
+
+/// A page of the wiki itself, like `/trash`, under the path the wiki is served from.
+pub fn wiki_path(path: &str) -> String {
+    format!("{}{}", BASE_PATH.read().unwrap(), path)
 }
 
 pub fn format_links(link: &str) -> String {
+    link_under(&BASE_PATH.read().unwrap(), link)
+}
+
+fn link_under(base: &str, link: &str) -> String {
     let proto_prefixes = link.split(':').collect::<Vec<&str>>();
     match proto_prefixes[0] {
         "http" | "https" => link.to_string(),
         "files" => {
-            format!("/files/{}", encode(link.strip_prefix("files:").unwrap()))
+            format!(
+                "{}/files/{}",
+                base,
+                encode(link.strip_prefix("files:").unwrap())
+            )
         }
-        _ => format!("/{}", encode(link)), // HACK: deal with warp decoding this later
+        "id" => format!("{}/id/{}", base, encode(link.strip_prefix("id:").unwrap())),
+        // HACK: deal with warp decoding this later
+        // Namespaced notes are stored as directories, so keep the separators intact and only
+        // encode the individual segments.
+        _ => format!(
+            "{}/{}",
+            base,
+            link.split('/')
+                .map(|segment| encode(segment).into_owned())
+                .collect::<Vec<String>>()
+                .join("/")
+        ),
     }
 }
 
@@ -162,6 +306,59 @@ mod tests {
         assert_eq!(String::from("https://example.com"), format_links(http_link));
         let wiki_page = "My Cool Page";
         assert_eq!(String::from("/My%20Cool%20Page"), format_links(wiki_page));
+        assert_eq!(
+            String::from("/id/20220503101112"),
+            format_links("id:20220503101112")
+        );
+        assert_eq!(
+            link_under("/work", "Projects/My Plans"),
+            "/work/Projects/My%20Plans"
+        );
+        assert_eq!(link_under("/work", "files:cat.jpg"), "/work/files/cat.jpg");
+        assert_eq!(link_under("/work", http_link), http_link);
+    }
+
+    #[test]
+    fn links_to_notes_by_id() {
+        assert_eq!(split_link("Page"), ("Page", "Page"));
+        assert_eq!(split_link("label|Page"), ("Page", "label"));
+        assert_eq!(
+            split_link("id:20220503101112|label"),
+            ("id:20220503101112", "label")
+        );
+        set_id_resolver(|id| match id {
+            "20220503101112" => Some("Projects/Plans".into()),
+            _ => None,
+        });
+        let mut html = String::new();
+        BlockElement::PageLink("id:20220503101112|the plan").collapse_to(&mut html);
+        BlockElement::PageLink("id:20220503101112").collapse_to(&mut html);
+        BlockElement::PageLink("id:20000101000000|gone").collapse_to(&mut html);
+        assert_eq!(
+            html,
+            r#"<a href="/Projects/Plans">the plan</a><a href="/Projects/Plans">Projects/Plans</a><a href="/id/20000101000000">gone</a>"#
+        );
+    }
+
+    #[test]
+    fn links_uploaded_images_to_thumbnails() {
+        let widths = [320, 640];
+        assert_eq!(
+            image_tag("http://localhost:6683/files/cat.jpg", &widths),
+            r#"<img src="http://localhost:6683/files/cat.jpg" srcset="http://localhost:6683/files/.thumbnails/320/cat.jpg 320w, http://localhost:6683/files/.thumbnails/640/cat.jpg 640w" loading="lazy" />"#
+        );
+        assert_eq!(
+            image_tag("https://example.com/cat.jpg", &widths),
+            "<img src=https://example.com/cat.jpg />"
+        );
+        assert_eq!(
+            image_tag("http://localhost:6683/files/cat.webp", &widths),
+            "<img src=http://localhost:6683/files/cat.webp />"
+        );
+        assert_eq!(
+            image_tag("http://localhost:6683/files/cat.jpg", &[]),
+            "<img src=http://localhost:6683/files/cat.jpg />"
+        );
     }
 
     #[test]
@@ -194,4 +391,13 @@ mod tests {
         let transformed_string = transform_cp_url(link);
         assert_eq!(*final_string, transformed_string);
     }
+
+    #[test]
+    fn labels_citations_with_their_entries() {
+        let label = |key: &str| (key == "knuth1984").then(|| String::from("Knuth & Levy, 1984"));
+        assert_eq!(
+            citation_tag(&["@knuth1984", "@sicp"], label),
+            r##"<cite class="citation">[<a href="#ref-knuth1984">Knuth &amp; Levy, 1984</a>; <a href="#ref-sicp">@sicp</a>]</cite>"##
+        );
+    }
 }
@@ -6,7 +6,14 @@ use serde::{Deserialize, Serialize};
 use crate::processors::tags::{tag_string_from_vec, TagsArray};
 use crate::PatchData;
 
-use super::{get_outlinks, to_html, Html, ParsedTemplate, TemplattedPage};
+use super::{
+    get_markdown_outlinks, get_media_references, get_outlinks,
+    markdown::{parse_front_matter, write_front_matter},
+    markdown_to_html, to_html, Html, ParsedTemplate, TemplattedPage,
+};
+
+/// Metadata keys whose value can point at an uploaded file.
+const MEDIA_METADATA_KEYS: [&str; 2] = ["icon", "cover"];
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 enum MetaParserState {
@@ -48,13 +55,13 @@ impl Note {
         } else {
             "text"
         };
-        let html = if content_type == "html" {
-            Html {
+        let html = match content_type {
+            "html" => Html {
                 body: self.content.clone(),
                 outlinks: Vec::with_capacity(0),
-            }
-        } else {
-            to_html(&self.content)
+            },
+            "markdown" => markdown_to_html(&self.content),
+            _ => to_html(&self.content),
         };
         let title = self.header.get("title").unwrap();
         let tags = self.parse_tags();
@@ -86,8 +93,41 @@ impl Note {
             page,
         }
     }
+    /// The uploaded files the note uses, as `files:name` keys for the backlinks store. Besides
+    /// links and URLs in the body, the `icon` and `cover` metadata can name a file.
+    pub fn media_links(&self) -> Vec<String> {
+        let mut names = get_media_references(&self.content);
+        for key in MEDIA_METADATA_KEYS {
+            let value = match self.header.get(key) {
+                Some(value) => value.trim(),
+                None => continue,
+            };
+            if let Some(name) = value.strip_prefix("files:") {
+                names.push(name.to_string());
+            } else if value.contains("/files/") {
+                names.extend(get_media_references(value));
+            } else if key == "icon" && !value.is_empty() {
+                // Icons are served from the uploaded files by name.
+                names.push(value.to_string());
+            }
+        }
+        names.sort_unstable();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| format!("files:{}", name))
+            .collect()
+    }
+    /// Markdown notes are written with YAML front matter instead of a wikitext header.
+    pub fn is_markdown(&self) -> bool {
+        matches!(self.header.get("content-type"), Some(content_type) if content_type == "markdown")
+    }
     pub fn to_structured(&self) -> StructuredNote {
-        let mut links = get_outlinks(&self.content);
+        let mut links = if self.is_markdown() {
+            get_markdown_outlinks(&self.content)
+        } else {
+            get_outlinks(&self.content)
+        };
         links.extend(self.parse_tags());
         StructuredNote {
             title: self.header.get("title").unwrap(),
@@ -132,8 +172,9 @@ impl From<PatchData> for Note {
 #[allow(clippy::from_over_into)]
 impl Into<PatchData> for Note {
     fn into(self) -> PatchData {
-        let title = self.header.get("title").unwrap().to_owned();
-        let tags = self.header.get("tags").unwrap().to_owned();
+        // Older notes may be missing either header.
+        let title = self.header.get("title").cloned().unwrap_or_default();
+        let tags = self.header.get("tags").cloned().unwrap_or_default();
         let old_title = title.clone();
         PatchData {
             body: self.content,
@@ -145,6 +186,7 @@ impl Into<PatchData> for Note {
             title,
             old_title,
             metadata: self.header,
+            revision: String::new(),
         }
     }
 }
@@ -170,21 +212,18 @@ impl From<String> for Note {
 #[allow(clippy::from_over_into)]
 impl Into<String> for Note {
     fn into(self) -> String {
-        let mut formatted_string = String::new();
-        for key in self.header.keys() {
-            formatted_string.push_str(key);
-            formatted_string.push_str(": ");
-            formatted_string.push_str(self.header.get(key).unwrap());
-            formatted_string.push('\n');
-        }
-        formatted_string.push('\n');
-        formatted_string.push_str(&self.content);
-        formatted_string
+        (&self).into()
     }
 }
 #[allow(clippy::from_over_into)]
 impl Into<String> for &Note {
     fn into(self) -> String {
+        if self.is_markdown() {
+            let mut formatted_string = write_front_matter(&self.header);
+            formatted_string.push('\n');
+            formatted_string.push_str(&self.content);
+            return formatted_string;
+        }
         let mut formatted_string = String::new();
         for key in self.header.keys() {
             formatted_string.push_str(key);
@@ -198,7 +237,12 @@ impl Into<String> for &Note {
     }
 }
 
+/// Parses a note's header and content. Notes starting with YAML front matter are Markdown.
 pub fn parse_meta<'a>(lines: impl Iterator<Item = &'a str>, debug_marker: &str) -> Note {
+    let mut lines = lines.peekable();
+    if matches!(lines.peek(), Some(line) if line.trim_end() == "---") {
+        return parse_front_matter(lines);
+    }
     let mut parser = HeaderParserMachine::new();
     let mut notemeta = Note::default();
     for line in lines {
@@ -231,3 +275,37 @@ pub fn parse_meta<'a>(lines: impl Iterator<Item = &'a str>, debug_marker: &str)
     }
     notemeta
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_media_links_from_metadata() {
+        let mut header = HashMap::new();
+        header.insert("title".to_string(), "Cats".to_string());
+        header.insert("icon".to_string(), "cat.ico".to_string());
+        header.insert(
+            "cover".to_string(),
+            "http://localhost:6683/files/cover.jpg".to_string(),
+        );
+        let note = Note {
+            header,
+            content: String::from("[[files:cat.png]] and [[files:cat.ico]]"),
+        };
+        assert_eq!(
+            note.media_links(),
+            vec!["files:cat.ico", "files:cat.png", "files:cover.jpg"]
+        );
+    }
+
+    #[test]
+    fn round_trips_markdown_notes() {
+        let raw = "---\ntitle: Cats\ncontent-type: markdown\ntags: [animals]\n---\n\n# Cats\n\nSee [[Dogs]].\n\n`[[not a link]]`";
+        let note = parse_meta(raw.lines(), "Cats");
+        assert!(note.is_markdown());
+        assert_eq!(note.to_structured().links_and_tags, vec!["Dogs", "animals"]);
+        let written: String = (&note).into();
+        assert_eq!(written, raw);
+    }
+}
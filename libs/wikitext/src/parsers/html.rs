@@ -1,4 +1,9 @@
-use super::block::{parse_block, BlockElement};
+use urlencoding::decode;
+
+use super::{
+    block::{parse_block, BlockElement},
+    split_link,
+};
 
 pub struct Html<'a> {
     pub outlinks: Vec<&'a str>,
@@ -53,12 +58,12 @@ pub fn to_html(text: &str) -> Html {
             for entity in block {
                 match entity {
                     BlockElement::PageLink(outlink) => {
-                        let aliases = outlink.split('|').collect::<Vec<&str>>();
-                        if aliases.len() > 1 {
-                            outlinks.push(aliases[1]);
-                        } else {
-                            outlinks.push(aliases[0]);
-                        }
+                        outlinks.push(split_link(outlink).0);
+                    }
+                    // Citations are stored in the backlinks store as `@key`, so the
+                    // bibliography can list every note citing an entry.
+                    BlockElement::Citation(keys) => {
+                        outlinks.extend(keys.iter());
                     }
                     BlockElement::IndentationLevel(level) => {
                         final_block.update_indentation(*level);
@@ -86,18 +91,77 @@ pub fn get_outlinks(text: &str) -> Vec<&str> {
         let blocks = parse_block(line);
         for block in blocks {
             if let BlockElement::PageLink(link) = block {
-                let aliases = link.split('|').collect::<Vec<&str>>();
-                if aliases.len() > 1 {
-                    outlinks.push(aliases[1]);
-                } else {
-                    outlinks.push(aliases[0]);
-                }
+                outlinks.push(split_link(link).0);
+            } else if let BlockElement::Citation(keys) = block {
+                outlinks.extend(keys);
             }
         }
     }
     outlinks
 }
 
+/// Returns the cited keys, without the `@` prefix, in the order they first appear.
+pub fn get_citations(text: &str) -> Vec<&str> {
+    let mut citations: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        for block in parse_block(line) {
+            if let BlockElement::Citation(keys) = block {
+                for key in keys {
+                    let key = key.trim_start_matches('@');
+                    if !citations.contains(&key) {
+                        citations.push(key);
+                    }
+                }
+            }
+        }
+    }
+    citations
+}
+
+/// Whether a character ends a `/files/` URL written in note text.
+fn ends_media_url(c: char) -> bool {
+    c.is_whitespace()
+        || matches!(
+            c,
+            '(' | ')' | '[' | ']' | '<' | '>' | '"' | '\'' | '|' | '`'
+        )
+}
+
+/// Returns the names of the uploaded files a text points at, through `[[files:name]]` links or
+/// `/files/name` URLs like embedded images. A thumbnail URL counts as a use of its original.
+pub fn get_media_references(text: &str) -> Vec<String> {
+    let mut names = get_outlinks(text)
+        .into_iter()
+        .filter_map(|link| link.strip_prefix("files:"))
+        .map(String::from)
+        .collect::<Vec<String>>();
+    for (idx, prefix) in text.match_indices("/files/") {
+        let url = text[idx + prefix.len()..]
+            .split(ends_media_url)
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        let url = match url
+            .strip_prefix(".thumbnails/")
+            .and_then(|thumbnail| thumbnail.split_once('/'))
+        {
+            Some((_, name)) => name,
+            None => url,
+        };
+        if url.is_empty() || url.contains('/') {
+            continue;
+        }
+        names.push(
+            decode(url)
+                .map(|name| name.into_owned())
+                .unwrap_or_else(|_| url.into()),
+        );
+    }
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +185,40 @@ mod tests {
         assert_eq!(parsed.outlinks, test_html.outlinks);
         assert_eq!(parsed.body, test_html.body);
     }
+
+    #[test]
+    fn parses_wikitext_to_html_with_namespaced_links() {
+        let test_string = "[[Projects/Alpha Notes]] and [[the roadmap|Projects/Road map]]";
+        let parsed = to_html(test_string);
+        assert_eq!(
+            parsed.outlinks,
+            vec!["Projects/Alpha Notes", "Projects/Road map"]
+        );
+        assert_eq!(
+            parsed.body,
+            r#"<div data-indent="0" class="text-block"><a href="/Projects/Alpha%20Notes">Projects/Alpha Notes</a> and <a href="/Projects/Road%20map">the roadmap</a></div>"#
+        );
+    }
+    #[test]
+    fn parses_wikitext_to_html_with_citations() {
+        let test_string = "Literate code [@knuth1984].\nSee also [@sicp; @knuth1984]";
+        let parsed = to_html(test_string);
+        assert_eq!(parsed.outlinks, vec!["@knuth1984", "@sicp", "@knuth1984"]);
+        assert_eq!(
+            parsed.body,
+            r##"<div data-indent="0" class="text-block">Literate code <cite class="citation">[<a href="#ref-knuth1984">@knuth1984</a>]</cite>.</div><div data-indent="0" class="text-block">See also <cite class="citation">[<a href="#ref-sicp">@sicp</a>; <a href="#ref-knuth1984">@knuth1984</a>]</cite></div>"##
+        );
+        assert_eq!(get_citations(test_string), vec!["knuth1984", "sicp"]);
+    }
+    #[test]
+    fn finds_media_references() {
+        let test_string = "See [[files:report.pdf]] and [[the slides|files:talk.pdf]].\n\
+            ![cat](http://localhost:6683/files/tabby%20cat.png)\n\
+            <img src=\"/files/.thumbnails/320/dog.jpg\" /> or /files/report.pdf.";
+        assert_eq!(
+            get_media_references(test_string),
+            vec!["dog.jpg", "report.pdf", "tabby cat.png", "talk.pdf"]
+        );
+        assert!(get_media_references("[[Some Page]] and /files/").is_empty());
+    }
 }
@@ -0,0 +1,440 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use pulldown_cmark::{html::push_html, Options, Parser};
+
+use crate::processors::{sanitize_html, tags::TagsArray};
+
+use super::{
+    block::{is_citation_key, BlockElement},
+    split_link, Html, Note,
+};
+
+/// Opens and closes the YAML front matter of a Markdown note.
+const FENCE: &str = "---";
+const BLOCK_SCALARS: [&str; 6] = ["|", "|-", "|+", ">", ">-", ">+"];
+
+/// Whether a note's raw text starts with YAML front matter, which is what marks it as Markdown.
+pub fn has_front_matter(raw: &str) -> bool {
+    matches!(raw.lines().next(), Some(line) if line.trim_end() == FENCE)
+}
+
+/// Parts of a Markdown text. Wikilinks and citations are only recognized outside of code.
+enum Segment<'a> {
+    Prose(&'a str),
+    Code(&'a str),
+}
+
+enum Inline<'a> {
+    Text(&'a str),
+    Link(&'a str),
+    Citation(Vec<&'a str>),
+}
+
+/// The marker and length of a code fence opened or closed on this line.
+fn code_fence(line: &str) -> Option<(char, usize)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let marker = trimmed.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let length = trimmed.chars().take_while(|c| *c == marker).count();
+    if length >= 3 {
+        Some((marker, length))
+    } else {
+        None
+    }
+}
+
+fn backtick_run(bytes: &[u8], from: usize) -> usize {
+    bytes[from..].iter().take_while(|b| **b == b'`').count()
+}
+
+/// Splits a line at its code spans, which end with a run of as many backticks as they start with.
+fn split_code_spans<'a>(line: &'a str, segments: &mut Vec<Segment<'a>>) {
+    let bytes = line.as_bytes();
+    let mut start = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] != b'`' {
+            idx += 1;
+            continue;
+        }
+        let opening = backtick_run(bytes, idx);
+        let mut end = idx + opening;
+        let mut closed = false;
+        while end < bytes.len() {
+            if bytes[end] == b'`' {
+                let closing = backtick_run(bytes, end);
+                end += closing;
+                if closing == opening {
+                    closed = true;
+                    break;
+                }
+            } else {
+                end += 1;
+            }
+        }
+        if closed {
+            segments.push(Segment::Prose(&line[start..idx]));
+            segments.push(Segment::Code(&line[idx..end]));
+            start = end;
+            idx = end;
+        } else {
+            idx += opening;
+        }
+    }
+    segments.push(Segment::Prose(&line[start..]));
+}
+
+fn segments(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut fence: Option<(char, usize)> = None;
+    for line in text.split_inclusive('\n') {
+        match (fence, code_fence(line)) {
+            (None, Some(opening)) => {
+                fence = Some(opening);
+                segments.push(Segment::Code(line));
+            }
+            (Some((marker, length)), Some((closing, closing_length)))
+                if closing == marker
+                    && closing_length >= length
+                    && line.trim().chars().all(|c| c == marker) =>
+            {
+                fence = None;
+                segments.push(Segment::Code(line));
+            }
+            (Some(_), _) => segments.push(Segment::Code(line)),
+            (None, None) => split_code_spans(line, &mut segments),
+        }
+    }
+    segments
+}
+
+/// Finds `[[wikilinks]]` and `[@citations]`, written the same way as in wikitext.
+fn inlines(prose: &str) -> Vec<Inline> {
+    let mut inlines = Vec::new();
+    let mut start = 0;
+    let mut idx = 0;
+    while let Some(offset) = prose[idx..].find('[') {
+        idx += offset;
+        let rest = &prose[idx..];
+        if let Some(link) = rest.strip_prefix("[[") {
+            if let Some(end) = link.find("]]") {
+                inlines.push(Inline::Text(&prose[start..idx]));
+                inlines.push(Inline::Link(&link[..end]));
+                idx += end + 4;
+                start = idx;
+                continue;
+            }
+        } else if rest.starts_with("[@") {
+            if let Some(end) = rest.find(']') {
+                let keys = rest[1..end]
+                    .split(';')
+                    .map(|key| key.trim())
+                    .collect::<Vec<&str>>();
+                if keys.iter().all(|key| is_citation_key(key)) {
+                    inlines.push(Inline::Text(&prose[start..idx]));
+                    inlines.push(Inline::Citation(keys));
+                    idx += end + 1;
+                    start = idx;
+                    continue;
+                }
+            }
+        }
+        idx += 1;
+    }
+    inlines.push(Inline::Text(&prose[start..]));
+    inlines
+}
+
+/// Links and citations in a Markdown text, in the same form [super::get_outlinks] returns them
+/// for wikitext so both formats end up alike in the backlinks store.
+pub fn get_markdown_outlinks(text: &str) -> Vec<&str> {
+    let mut outlinks = Vec::new();
+    for segment in segments(text) {
+        if let Segment::Prose(prose) = segment {
+            for inline in inlines(prose) {
+                match inline {
+                    Inline::Link(link) => outlinks.push(split_link(link).0),
+                    Inline::Citation(keys) => outlinks.extend(keys),
+                    Inline::Text(_) => {}
+                }
+            }
+        }
+    }
+    outlinks
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Renders a Markdown note as CommonMark, with tables, footnotes, strikethrough and task lists.
+/// The source is kept on the block so the editor can edit it as Markdown.
+pub fn markdown_to_html(text: &str) -> Html {
+    let mut source = String::with_capacity(text.len());
+    for segment in segments(text) {
+        match segment {
+            Segment::Code(code) => source.push_str(code),
+            Segment::Prose(prose) => {
+                for inline in inlines(prose) {
+                    match inline {
+                        Inline::Text(text) => source.push_str(text),
+                        Inline::Link(link) => BlockElement::PageLink(link).collapse_to(&mut source),
+                        Inline::Citation(keys) => {
+                            BlockElement::Citation(keys).collapse_to(&mut source)
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    let mut rendered = String::new();
+    push_html(&mut rendered, Parser::new_ext(&source, options));
+    Html {
+        body: format!(
+            r#"<div class="text-block markdown" data-source="{}">{}</div>"#,
+            escape_attribute(text),
+            sanitize_html(&rendered)
+        ),
+        outlinks: get_markdown_outlinks(text),
+    }
+}
+
+fn strip_comment(value: &str) -> &str {
+    match value.find(" #") {
+        Some(idx) => value[..idx].trim_end(),
+        None => value,
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if let Some(quoted) = value.strip_prefix('"') {
+        let mut unquoted = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match chars.next() {
+                    Some('n') => unquoted.push('\n'),
+                    Some('t') => unquoted.push('\t'),
+                    Some(escaped) => unquoted.push(escaped),
+                    None => {}
+                },
+                c => unquoted.push(c),
+            }
+        }
+        unquoted
+    } else if let Some(quoted) = value.strip_prefix('\'') {
+        let end = quoted.rfind('\'').unwrap_or(quoted.len());
+        quoted[..end].replace("''", "'")
+    } else {
+        strip_comment(value).to_string()
+    }
+}
+
+/// Lists are kept the way tags are written in wikitext headers, e.g. `[one,two]`.
+fn list<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    let items = items
+        .map(unquote)
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<String>>();
+    format!("[{}]", items.join(","))
+}
+
+fn block_scalar(indicator: &str, lines: &[&str]) -> String {
+    let lines = lines.iter().map(|line| line.trim()).collect::<Vec<&str>>();
+    let separator = if indicator.starts_with('|') {
+        "\n"
+    } else {
+        " "
+    };
+    lines.join(separator).trim_end().to_string()
+}
+
+/// Reads the front matter into a header. Only flat front matter is understood: plain, quoted
+/// and block scalars, and lists of scalars. Keys holding anything else are left out.
+fn parse_yaml_header(lines: &[&str]) -> HashMap<String, String> {
+    let mut header = HashMap::new();
+    let mut idx = 0;
+    while idx < lines.len() {
+        let line = lines[idx];
+        idx += 1;
+        if line.trim().is_empty() || line.starts_with([' ', '\t', '#', '-']) {
+            continue;
+        }
+        let (key, value) = match line.split_once(": ") {
+            Some((key, value)) => (key, value.trim()),
+            None => match line.trim_end().strip_suffix(':') {
+                Some(key) => (key, ""),
+                None => continue,
+            },
+        };
+        // Indented lines and list items after a key belong to it.
+        let nested_start = idx;
+        while idx < lines.len()
+            && (lines[idx].starts_with([' ', '\t', '-']) || lines[idx].trim().is_empty())
+        {
+            idx += 1;
+        }
+        let nested = lines[nested_start..idx]
+            .iter()
+            .copied()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<&str>>();
+        let value = if value.is_empty() {
+            let items = nested
+                .iter()
+                .map(|line| line.trim_start().strip_prefix('-').map(str::trim))
+                .collect::<Option<Vec<&str>>>();
+            match items {
+                Some(items) if items.is_empty() => String::new(),
+                Some(items) => list(items.into_iter()),
+                None => continue,
+            }
+        } else if BLOCK_SCALARS.contains(&value) {
+            block_scalar(value, &nested)
+        } else {
+            match strip_comment(value)
+                .strip_prefix('[')
+                .and_then(|items| items.strip_suffix(']'))
+            {
+                Some(items) => list(items.split(',')),
+                None => unquote(value),
+            }
+        };
+        header.insert(unquote(key), value);
+    }
+    header
+}
+
+/// Parses a Markdown note, the YAML front matter between the `---` lines becomes its header.
+pub(crate) fn parse_front_matter<'a>(mut lines: impl Iterator<Item = &'a str>) -> Note {
+    // Skip the opening fence.
+    lines.next();
+    let mut front_matter = Vec::new();
+    for line in lines.by_ref() {
+        if line.trim_end() == FENCE {
+            break;
+        }
+        front_matter.push(line);
+    }
+    let mut header = parse_yaml_header(&front_matter);
+    header
+        .entry("content-type".into())
+        .or_insert_with(|| "markdown".into());
+    let content = lines
+        .skip_while(|line| line.trim().is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n");
+    Note { header, content }
+}
+
+fn yaml_scalar(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value != value.trim()
+        || value.starts_with([
+            '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%',
+            '@', '`',
+        ])
+        || value.contains(": ")
+        || value.contains(" #")
+        || value.ends_with(':')
+        || value.contains('\n');
+    if !needs_quotes {
+        return value.to_string();
+    }
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn yaml_value(value: &str) -> String {
+    let trimmed = value.trim();
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+        let items = TagsArray::new(trimmed)
+            .values
+            .iter()
+            .map(|item| yaml_scalar(item))
+            .collect::<Vec<String>>();
+        return format!("[{}]", items.join(", "));
+    }
+    yaml_scalar(value)
+}
+
+/// Writes a header as YAML front matter, title first and the other keys in order.
+pub(crate) fn write_front_matter(header: &HashMap<String, String>) -> String {
+    let mut keys = header.keys().map(String::as_str).collect::<Vec<&str>>();
+    keys.sort_by_key(|key| (*key != "title", *key));
+    let mut front_matter = format!("{}\n", FENCE);
+    for key in keys {
+        writeln!(front_matter, "{}: {}", key, yaml_value(&header[key])).unwrap();
+    }
+    writeln!(front_matter, "{}", FENCE).unwrap();
+    front_matter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_front_matter() {
+        let raw = "---\ntitle: \"Markdown: a note\"\ntags:\n  - reading\n  - 'to do'\naliases: [md, mark down] # old names\nsummary: >\n  folded\n  text\nauthor:\n  name: me\n---\n\n# Heading\n\nBody";
+        let note = parse_front_matter(raw.lines());
+        assert_eq!(note.header["title"], "Markdown: a note");
+        assert_eq!(note.header["tags"], "[reading,to do]");
+        assert_eq!(note.header["aliases"], "[md,mark down]");
+        assert_eq!(note.header["summary"], "folded text");
+        assert_eq!(note.header["content-type"], "markdown");
+        assert!(!note.header.contains_key("author"));
+        assert_eq!(note.content, "# Heading\n\nBody");
+    }
+
+    #[test]
+    fn writes_front_matter_that_parses_back() {
+        let mut header = HashMap::new();
+        header.insert("title".to_string(), "Markdown: a note".to_string());
+        header.insert("tags".to_string(), "[reading,#later]".to_string());
+        header.insert("content-type".to_string(), "markdown".to_string());
+        header.insert("url".to_string(), "https://example.com/a#b".to_string());
+        let written = write_front_matter(&header);
+        assert_eq!(
+            written,
+            "---\ntitle: \"Markdown: a note\"\ncontent-type: markdown\ntags: [reading, \"#later\"]\nurl: https://example.com/a#b\n---\n"
+        );
+        let note = parse_front_matter(written.lines());
+        assert_eq!(note.header, header);
+    }
+
+    #[test]
+    fn ignores_links_in_code() {
+        let text = "See [[Rust]] and [[the book|Rust Book]] [@klabnik2019]\n\n```\n[[not a link]]\n```\n\nInline `[[code]]` too.";
+        assert_eq!(
+            get_markdown_outlinks(text),
+            vec!["Rust", "Rust Book", "@klabnik2019"]
+        );
+    }
+
+    #[test]
+    fn renders_wikilinks_in_markdown() {
+        let html = markdown_to_html("# Notes\n\nSee [[My Note]].\n\n```\n[[My Note]]\n```");
+        assert!(html.body.contains(r#"<a href="/My%20Note">My Note</a>"#));
+        assert!(html.body.contains("<pre><code>[[My Note]]\n</code></pre>"));
+        assert!(html.body.contains(r##"data-source="# Notes"##));
+        assert_eq!(html.outlinks, vec!["My Note"]);
+    }
+}
@@ -2,9 +2,11 @@ pub mod block;
 pub mod formatters;
 pub mod headers;
 pub mod html;
+pub mod markdown;
 pub mod templates;
 
 pub use self::formatters::*;
 pub use self::headers::*;
 pub use self::html::*;
+pub use self::markdown::*;
 pub use self::templates::*;
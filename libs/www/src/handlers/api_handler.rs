@@ -1,38 +1,62 @@
 use crate::services::{create_jwt, MONTH};
 use bytes::BufMut;
 use futures::TryStreamExt;
-use persistance::fs::{get_note_titles, read_note_cache};
-use std::collections::HashMap;
-use task_runners::runners::api_runner::{APIRunner, FileError};
+use persistance::{
+    fs::{get_note_titles, read_note_cache},
+    ids::note_ids,
+};
+use serde_json::json;
+use std::{collections::HashMap, sync::Arc};
+use task_runners::runners::{
+    api_runner::{APIRunner, FileError},
+    media_runner::MediaRunner,
+};
 use urlencoding::encode;
 use warp::{
     filters::BoxedFilter,
-    http::{header, HeaderValue, Response},
-    hyper::{StatusCode, Uri},
+    http::{header, Response},
+    hyper::StatusCode,
     multipart::{self, Part},
+    path::Tail,
     Filter, Reply,
 };
+use wikitext::{
+    parsers::{format_links, wiki_path},
+    GlobalBacklinks,
+};
 
 use super::{
-    filters::{with_auth, AuthError},
-    MAX_BODY_SIZE,
+    filters::{with_auth, with_links, with_user, AuthError},
+    wiki_uri, MAX_BODY_SIZE,
 };
 
-pub struct APIRouter {}
+const DEFAULT_SUGGESTIONS: usize = 10;
+const MAX_SUGGESTIONS: usize = 50;
+
+pub struct APIRouter {
+    user: Arc<String>,
+    links: GlobalBacklinks,
+}
 
-#[allow(clippy::new_without_default)]
 impl APIRouter {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(user: Arc<String>, links: GlobalBacklinks) -> Self {
+        Self { user, links }
     }
     pub fn routes(&self) -> BoxedFilter<(impl Reply,)> {
         self.login()
             .or(self.logout())
             .or(self.styles())
+            .or(self.rename_file())
+            .or(self.delete_file())
+            .or(self.delete_orphans())
             .or(self.img())
             .or(self.files())
             .or(self.titles())
             .or(self.mru())
+            .or(self.permalink())
+            .or(self.search_json())
+            .or(self.suggest())
+            .or(self.opensearch_suggest())
             .or(self.json_page())
             .or(self.search_from_qs())
             .or(self.version())
@@ -54,6 +78,27 @@ impl APIRouter {
             .with(warp::cors().allow_any_origin())
             .boxed()
     }
+    /// A link to the note that keeps working after it's renamed.
+    fn permalink(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "permalink" / ..))
+            .and(warp::path::tail())
+            .and_then(|tail: Tail| async move {
+                let title = urlencoding::decode(tail.as_str())
+                    .map_err(|_| warp::reject::not_found())?
+                    .into_owned();
+                match note_ids().id_for(&title) {
+                    Some(id) => Ok(warp::reply::json(&json!({
+                        "id": id,
+                        "url": format_links(&format!("id:{}", id)),
+                        "link": format!("[[id:{}|{}]]", id, title),
+                    }))),
+                    None => Err(warp::reject::not_found()),
+                }
+            })
+            .boxed()
+    }
     fn titles(&self) -> BoxedFilter<(impl Reply,)> {
         warp::get()
             .and(with_auth())
@@ -92,13 +137,15 @@ impl APIRouter {
                     warp::body::content_length_limit(MAX_BODY_SIZE)
                         .and(warp::header::<String>("filename"))
                         .and(warp::body::bytes())
-                        .then(|filename, bytes| async {
-                            match APIRunner::process_image(filename, bytes).await {
-                                Ok(()) => warp::reply::with_status("ok", StatusCode::OK),
+                        .and(with_user(self.user.to_string()))
+                        .then(|filename, bytes, uploader| async {
+                            // Reply with the stored name, it can differ from the one sent.
+                            match APIRunner::process_image(filename, bytes, uploader).await {
+                                Ok(name) => warp::reply::with_status(name, StatusCode::OK),
                                 Err(e) => {
                                     eprintln!("{}", e);
                                     warp::reply::with_status(
-                                        "internal server error",
+                                        String::from("internal server error"),
                                         StatusCode::INTERNAL_SERVER_ERROR,
                                     )
                                 }
@@ -113,7 +160,8 @@ impl APIRouter {
             .and(with_auth())
             .and(warp::body::content_length_limit(MAX_BODY_SIZE))
             .and(warp::filters::multipart::form())
-            .then(|form_body: multipart::FormData| async {
+            .and(with_user(self.user.to_string()))
+            .then(|form_body: multipart::FormData, uploader: String| async {
                 let parts: Vec<Part> = form_body
                     .try_collect()
                     .await
@@ -132,17 +180,80 @@ impl APIRouter {
                     })
                     .await
                     .unwrap_or_default();
-                match APIRunner::file(filename, data).await {
-                    Ok(()) => warp::redirect(Uri::from_static("/")),
+                match APIRunner::file(filename, data, uploader).await {
+                    Ok(_) => warp::redirect(wiki_uri("/files/list")),
                     Err(e) => {
                         eprintln!("{}", e);
                         let redir_url = format!("/error?msg={}", encode(&format!("{:?}", e)));
-                        warp::redirect(redir_url.parse::<Uri>().unwrap())
+                        warp::redirect(wiki_uri(&redir_url))
                     }
                 }
             })
             .boxed()
     }
+    fn rename_file(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("files" / "rename"))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
+            .and(with_links(self.links.to_owned()))
+            .then(
+                |form_body: HashMap<String, String>, links: GlobalBacklinks| async {
+                    match MediaRunner::rename(form_body, links).await {
+                        Ok(()) => warp::redirect(wiki_uri("/files/list")),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            let redir_url = format!("/error?msg={}", encode(&e.to_string()));
+                            warp::redirect(wiki_uri(&redir_url))
+                        }
+                    }
+                },
+            )
+            .boxed()
+    }
+    fn delete_file(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("files" / "delete"))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
+            .and(with_links(self.links.to_owned()))
+            .then(
+                |form_body: HashMap<String, String>, links: GlobalBacklinks| async {
+                    match MediaRunner::delete(form_body, links).await {
+                        Ok(()) => warp::redirect(wiki_uri("/files/list")),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            let redir_url = format!("/error?msg={}", encode(&e.to_string()));
+                            warp::redirect(wiki_uri(&redir_url))
+                        }
+                    }
+                },
+            )
+            .boxed()
+    }
+    fn delete_orphans(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("files" / "orphans" / "delete"))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
+            .and(with_links(self.links.to_owned()))
+            .then(
+                |form_body: Vec<(String, String)>, links: GlobalBacklinks| async {
+                    match MediaRunner::delete_orphans(form_body, links).await {
+                        Ok(_) => warp::redirect(wiki_uri("/files/orphans")),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            let redir_url = format!("/error?msg={}", encode(&e.to_string()));
+                            warp::redirect(wiki_uri(&redir_url))
+                        }
+                    }
+                },
+            )
+            .boxed()
+    }
     fn login(&self) -> BoxedFilter<(impl Reply,)> {
         warp::post()
             .and(warp::path("login"))
@@ -155,17 +266,23 @@ impl APIRouter {
                         match create_jwt(username, pwd) {
                             Ok(token) => Ok(Response::builder()
                                 .status(StatusCode::MOVED_PERMANENTLY)
-                                .header(header::LOCATION, HeaderValue::from_static("/"))
+                                .header(header::LOCATION, wiki_path("/"))
                                 .header(
                                     header::SET_COOKIE,
                                     format!(
-                                        "token={}; Secure; HttpOnly; Max-Age={}; Path=/",
-                                        token, MONTH
+                                        "token={}; Secure; HttpOnly; Max-Age={}; Path={}",
+                                        token,
+                                        MONTH,
+                                        wiki_path("/")
                                     ),
                                 )
                                 .header(
                                     header::SET_COOKIE,
-                                    format!("login=true; Secure; Max-Age={}; Path=/", MONTH),
+                                    format!(
+                                        "login=true; Secure; Max-Age={}; Path={}",
+                                        MONTH,
+                                        wiki_path("/")
+                                    ),
                                 )
                                 .body("ok")),
                             Err(e) => {
@@ -180,7 +297,7 @@ impl APIRouter {
                                 }
                                 Ok(Response::builder()
                                     .status(status)
-                                    .header(header::LOCATION, HeaderValue::from_static("/"))
+                                    .header(header::LOCATION, wiki_path("/"))
                                     .body(body))
                             }
                         }
@@ -194,10 +311,13 @@ impl APIRouter {
             .then(|| async {
                 Ok(Response::builder()
                     .status(StatusCode::MOVED_PERMANENTLY)
-                    .header(header::LOCATION, HeaderValue::from_static("/"))
+                    .header(header::LOCATION, wiki_path("/"))
                     .header(
                         header::SET_COOKIE,
-                        "token=; Secure; HttpOnly; Max-Age=0; Path=/",
+                        format!(
+                            "token=; Secure; HttpOnly; Max-Age=0; Path={}",
+                            wiki_path("/")
+                        ),
                     )
                     .body("ok"))
             })
@@ -215,18 +335,81 @@ impl APIRouter {
             })
             .boxed()
     }
+    /// Search results with highlighted snippets, `/api/search?q=<query>`.
+    fn search_json(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "search"))
+            .and(warp::query::<HashMap<String, String>>())
+            .then(|query_params: HashMap<String, String>| async move {
+                let query = query_params.get("q").cloned().unwrap_or_default();
+                match APIRunner::search(query).await {
+                    Ok(results) => warp::reply::with_status(
+                        warp::reply::json(&json!({ "results": results })),
+                        StatusCode::OK,
+                    ),
+                    Err(e) => warp::reply::with_status(
+                        warp::reply::json(&json!({ "error": e.to_string() })),
+                        StatusCode::BAD_REQUEST,
+                    ),
+                }
+            })
+            .boxed()
+    }
+    /// Titles for completing links in the editor, `limit` of them at most.
+    fn suggest(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "suggest"))
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_links(self.links.to_owned()))
+            .then(
+                |query_params: HashMap<String, String>, links: GlobalBacklinks| async move {
+                    let query = query_params.get("q").cloned().unwrap_or_default();
+                    let limit = query_params
+                        .get("limit")
+                        .and_then(|limit| limit.parse::<usize>().ok())
+                        .unwrap_or(DEFAULT_SUGGESTIONS)
+                        .min(MAX_SUGGESTIONS);
+                    warp::reply::json(&APIRunner::suggest(query, limit, links).await)
+                },
+            )
+            .boxed()
+    }
+    /// Suggestions for the browser's address bar, as the OpenSearch suggestions extension has
+    /// them.
+    fn opensearch_suggest(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("api" / "suggest" / "opensearch"))
+            .and(warp::query::<HashMap<String, String>>())
+            .and(with_links(self.links.to_owned()))
+            .then(
+                |query_params: HashMap<String, String>, links: GlobalBacklinks| async move {
+                    let query = query_params.get("q").cloned().unwrap_or_default();
+                    let titles =
+                        APIRunner::suggest(query.clone(), DEFAULT_SUGGESTIONS, links).await;
+                    warp::reply::with_header(
+                        warp::reply::json(&json!([query, titles])),
+                        header::CONTENT_TYPE,
+                        "application/x-suggestions+json",
+                    )
+                },
+            )
+            .boxed()
+    }
     fn styles(&self) -> BoxedFilter<(impl Reply,)> {
         warp::path("styles")
             .and(warp::post().and(with_auth()).and(
                 warp::body::content_length_limit(MAX_BODY_SIZE).and(warp::body::form().then(
                     |form_body| async {
                         match APIRunner::update_styles(form_body).await {
-                            Ok(()) => warp::redirect(Uri::from_static("/")),
+                            Ok(()) => warp::redirect(wiki_uri("/")),
                             Err(e) => {
                                 eprintln!("{}", e);
                                 let redir_url =
                                     format!("/error?msg={}", encode(&format!("{:?}", e)));
-                                warp::redirect(redir_url.parse::<Uri>().unwrap())
+                                warp::redirect(wiki_uri(&redir_url))
                             }
                         }
                     },
@@ -17,11 +17,21 @@ use std::convert::Infallible;
 
 use render::{login_page::LoginPage, Render};
 use warp::body::BodyDeserializeError;
-use warp::{http::StatusCode, Rejection, Reply};
+use warp::{
+    http::{StatusCode, Uri},
+    Rejection, Reply,
+};
+use wikitext::parsers::wiki_path;
 
 // 40MB file limit
 pub const MAX_BODY_SIZE: u64 = 40_000_000;
 
+/// Where to redirect to for a page of the wiki like `/trash`, which is under the path the wiki
+/// is served from.
+pub(crate) fn wiki_uri(path: &str) -> Uri {
+    wiki_path(path).parse::<Uri>().unwrap()
+}
+
 pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply, Infallible> {
     let (code, message) = if err.is_not_found() {
         (StatusCode::NOT_FOUND, "Not Found".to_string())
@@ -4,39 +4,30 @@ use render::{
     index_page::IndexPage, opensearch_page::OpenSearchPage, Render,
 };
 use std::{collections::HashMap, sync::Arc};
-use task_runners::runners::static_page_runner::StaticPageRunner;
+use task_runners::runners::{
+    bibliography_runner::BibliographyRunner, static_page_runner::StaticPageRunner,
+};
 use warp::{filters::BoxedFilter, Filter, Reply};
 use wikitext::GlobalBacklinks;
 
-use crate::handlers::filters::with_location;
-
 use super::filters::{with_auth, with_host, with_links, with_user};
 
 pub struct StaticPageRouter {
     user: Arc<String>,
-    media_location: Arc<String>,
     host: Arc<String>,
     links: GlobalBacklinks,
 }
 
 impl StaticPageRouter {
-    pub fn new(
-        user: Arc<String>,
-        media_location: Arc<String>,
-        host: Arc<String>,
-        links: GlobalBacklinks,
-    ) -> Self {
-        Self {
-            user,
-            media_location,
-            host,
-            links,
-        }
+    pub fn new(user: Arc<String>, host: Arc<String>, links: GlobalBacklinks) -> Self {
+        Self { user, host, links }
     }
     pub fn routes(&self) -> BoxedFilter<(impl Reply,)> {
         self.file_list()
+            .or(self.orphaned_files())
             .or(self.upload())
             .or(self.all_pages())
+            .or(self.bibliography())
             .or(self.help())
             .or(self.open_search())
             .or(self.styles())
@@ -92,6 +83,17 @@ impl StaticPageRouter {
             })
             .boxed()
     }
+    fn bibliography(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path("bibliography"))
+            .and(with_links(self.links.to_owned()))
+            .then(|links: GlobalBacklinks| async move {
+                let response = BibliographyRunner::render(links).await;
+                warp::reply::html(response)
+            })
+            .boxed()
+    }
     fn open_search(&self) -> BoxedFilter<(impl Reply,)> {
         let user = self.user.clone();
         let host = self.host.clone();
@@ -133,9 +135,20 @@ impl StaticPageRouter {
         warp::get()
             .and(with_auth())
             .and(warp::path!("files" / "list"))
-            .and(with_location(self.media_location.clone()))
-            .then(move |location: String| async move {
-                let response = StaticPageRunner::list_files(location).await;
+            .and(with_links(self.links.to_owned()))
+            .then(|links: GlobalBacklinks| async move {
+                let response = StaticPageRunner::list_files(links).await;
+                warp::reply::html(response)
+            })
+            .boxed()
+    }
+    fn orphaned_files(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("files" / "orphans"))
+            .and(with_links(self.links.to_owned()))
+            .then(|links: GlobalBacklinks| async move {
+                let response = StaticPageRunner::list_orphans(links).await;
                 warp::reply::html(response)
             })
             .boxed()
@@ -1,17 +1,41 @@
 use std::collections::HashMap;
 
-use task_runners::{runners::wiki_runner::WikiRunner, QueueHandle};
+use persistance::{
+    fs::{utils::validate_title, WriteWikiError},
+    ids::note_ids,
+    store::note_store,
+};
+use serde_json::json;
+use task_runners::{
+    runners::{history_runner::HistoryRunner, trash_runner::TrashRunner, wiki_runner::WikiRunner},
+    QueueHandle,
+};
 use urlencoding::decode;
-use warp::{filters::BoxedFilter, hyper::Uri, Filter, Reply};
-use wikitext::{GlobalBacklinks, PatchData};
+use warp::{
+    filters::BoxedFilter,
+    hyper::{StatusCode, Uri},
+    path::Tail,
+    Filter, Rejection, Reply,
+};
+use wikitext::{
+    parsers::{format_links, wiki_path},
+    GlobalBacklinks, MergeRequest, PatchData,
+};
 
 use crate::RefHubParts;
 
 use super::{
     filters::{reply_on_result, with_auth, with_links, with_queue},
-    MAX_BODY_SIZE,
+    wiki_uri, MAX_BODY_SIZE,
 };
 
+/// Decodes a title from the path, malformed percent-encoding can't be a note.
+fn decode_title(path: &str) -> Result<String, Rejection> {
+    decode(path)
+        .map(|title| title.into_owned())
+        .map_err(|_| warp::reject::not_found())
+}
+
 pub struct WikiPageRouter {
     parts: RefHubParts,
 }
@@ -21,11 +45,18 @@ impl WikiPageRouter {
         Self { parts }
     }
     pub fn routes(&self) -> BoxedFilter<(impl Reply,)> {
-        self.get_nested()
-            .or(self.delete())
+        self.delete()
+            .or(self.move_note())
+            .or(self.merge())
             .or(self.edit())
             .or(self.quick_add())
             .or(self.new_page())
+            .or(self.restore())
+            .or(self.history())
+            .or(self.restore_from_trash())
+            .or(self.purge())
+            .or(self.trash())
+            .or(self.permalink())
             .or(self.get())
             .boxed()
     }
@@ -34,56 +65,181 @@ impl WikiPageRouter {
         let (links, _) = &self.parts;
         warp::get()
             .and(with_auth())
-            .and(warp::path::param())
+            .and(warp::path::tail())
+            .and_then(|tail: Tail| async move {
+                // Leave the bare root for the index page.
+                if tail.as_str().is_empty() {
+                    return Err(warp::reject::not_found());
+                }
+                let path = decode_title(tail.as_str())?;
+                // A trailing slash asks for a namespace listing.
+                validate_title(path.strip_suffix('/').unwrap_or(&path))
+                    .map_err(|_| warp::reject::not_found())?;
+                Ok(path)
+            })
             .and(with_links(links.clone()))
             .and(warp::query::<HashMap<String, String>>())
             .then(
                 |path: String,
                  reflinks: GlobalBacklinks,
                  query_params: HashMap<String, String>| async move {
+                    // Old titles of renamed notes go to the note's current title.
+                    if !note_store().exists(&path) {
+                        if let Some(title) = note_ids().redirect_for(&path) {
+                            let location = format_links(&title).parse::<Uri>().unwrap();
+                            return warp::redirect::temporary(location).into_response();
+                        }
+                    }
                     let links = reflinks.lock().await;
-                    let path = decode(&path).unwrap();
-                    let path = path.to_string();
                     let links = links.get(&path);
                     let runner = WikiRunner {};
                     let response = runner.render_file(path, links, query_params).await;
+                    warp::reply::html(response).into_response()
+                },
+            )
+            .boxed()
+    }
+
+    /// Permalinks to notes, `/id/<id>` goes to wherever the note is now.
+    fn permalink(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path!("id" / String))
+            .and_then(|id: String| async move {
+                let id = decode(&id).map_err(|_| warp::reject::not_found())?;
+                match note_ids().title_for(&id) {
+                    Some(title) => {
+                        let location = format_links(&title).parse::<Uri>().unwrap();
+                        Ok(warp::redirect::temporary(location))
+                    }
+                    // Let notes in an `id` namespace through.
+                    None => Err(warp::reject::not_found()),
+                }
+            })
+            .boxed()
+    }
+
+    fn delete(&self) -> BoxedFilter<(impl Reply,)> {
+        let (_, queue) = &self.parts;
+        warp::post()
+            .and(with_auth())
+            .and(warp::path("delete"))
+            .and(with_queue(queue.to_owned()))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
+            .then(
+                |queue: QueueHandle, form_body: HashMap<String, String>| async {
+                    let response = WikiRunner::delete(queue, form_body).await;
+                    let response = response.parse::<Uri>().unwrap();
+                    warp::redirect(response)
+                },
+            )
+            .boxed()
+    }
+
+    fn history(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::get()
+            .and(with_auth())
+            .and(warp::path("history"))
+            .and(warp::path::tail())
+            .and_then(|tail: Tail| async move {
+                let title = decode_title(tail.as_str())?;
+                validate_title(&title).map_err(|_| warp::reject::not_found())?;
+                Ok::<_, Rejection>(title)
+            })
+            .and(warp::query::<HashMap<String, String>>())
+            .then(
+                |title: String, query_params: HashMap<String, String>| async move {
+                    let response = HistoryRunner::render(title, query_params).await;
                     warp::reply::html(response)
                 },
             )
             .boxed()
     }
 
-    fn get_nested(&self) -> BoxedFilter<(impl Reply,)> {
-        let (links, _) = &self.parts;
+    fn restore(&self) -> BoxedFilter<(impl Reply,)> {
+        let (_, queue) = &self.parts;
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("history" / "restore"))
+            .and(with_queue(queue.to_owned()))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
+            .then(
+                |queue: QueueHandle, form_body: HashMap<String, String>| async {
+                    let response = match HistoryRunner::restore(queue, form_body).await {
+                        Ok(location) => location,
+                        Err(_) => wiki_path("/"),
+                    };
+                    let response = response.parse::<Uri>().unwrap();
+                    warp::redirect(response)
+                },
+            )
+            .boxed()
+    }
+
+    fn trash(&self) -> BoxedFilter<(impl Reply,)> {
         warp::get()
             .and(with_auth())
-            .and(warp::path!(String / String))
-            .and(with_links(links.to_owned()))
+            .and(warp::path("trash"))
+            .and(warp::path::end())
+            .then(|| async { warp::reply::html(TrashRunner::render().await) })
+            .boxed()
+    }
+
+    fn restore_from_trash(&self) -> BoxedFilter<(impl Reply,)> {
+        let (_, queue) = &self.parts;
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("trash" / "restore"))
+            .and(with_queue(queue.to_owned()))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
             .then(
-                |main_path: String, sub_path: String, reflinks: GlobalBacklinks| async move {
-                    let runner = WikiRunner {};
-                    let main_path = decode(&main_path).unwrap().to_string();
-                    let sub_path = decode(&sub_path).unwrap().to_string();
-                    let links = reflinks.lock().await;
-                    let links = links.get(&*sub_path);
-                    let response = runner.render_nested_file(main_path, sub_path, links).await;
-                    warp::reply::html(response.unwrap())
+                |queue: QueueHandle, form_body: HashMap<String, String>| async {
+                    let response = match TrashRunner::restore(queue, form_body).await {
+                        Ok(location) => location,
+                        Err(e) => {
+                            eprintln!("Could not restore note: {}", e);
+                            wiki_path("/trash")
+                        }
+                    };
+                    let response = response.parse::<Uri>().unwrap();
+                    warp::redirect(response)
                 },
             )
             .boxed()
     }
 
-    fn delete(&self) -> BoxedFilter<(impl Reply,)> {
+    fn purge(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("trash" / "purge"))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::form())
+            .then(|form_body: HashMap<String, String>| async {
+                if let Err(e) = TrashRunner::purge(form_body).await {
+                    eprintln!("Could not purge note: {}", e);
+                }
+                warp::redirect(wiki_uri("/trash"))
+            })
+            .boxed()
+    }
+
+    fn move_note(&self) -> BoxedFilter<(impl Reply,)> {
         let (_, queue) = &self.parts;
         warp::post()
             .and(with_auth())
-            .and(warp::path("delete"))
+            .and(warp::path("move"))
             .and(with_queue(queue.to_owned()))
             .and(warp::body::content_length_limit(MAX_BODY_SIZE))
             .and(warp::body::form())
             .then(
                 |queue: QueueHandle, form_body: HashMap<String, String>| async {
-                    let response = WikiRunner::delete(queue, form_body).await;
+                    let response = match WikiRunner::move_note(queue, form_body).await {
+                        Ok(location) => location,
+                        Err(_) => wiki_path("/"),
+                    };
                     let response = response.parse::<Uri>().unwrap();
                     warp::redirect(response)
                 },
@@ -110,18 +266,52 @@ impl WikiPageRouter {
         warp::post()
             .and(with_auth())
             .and(
-                warp::path("edit").and(
+                warp::path("edit").and(warp::path::end()).and(
                     warp::body::content_length_limit(MAX_BODY_SIZE)
                         .and(warp::body::json())
                         .and(with_queue(queue.to_owned()))
                         .then(|body: PatchData, queue: QueueHandle| async {
-                            reply_on_result(WikiRunner::edit(body, queue).await)
+                            let (status, revision) = match WikiRunner::edit(body, queue).await {
+                                Ok(revision) => (StatusCode::OK, revision),
+                                // Let the editor know which revision it's out of date with.
+                                Err(WriteWikiError::Conflict(revision)) => {
+                                    (StatusCode::CONFLICT, revision)
+                                }
+                                Err(_) => (StatusCode::BAD_REQUEST, String::new()),
+                            };
+                            warp::reply::with_status(
+                                warp::reply::json(&json!({ "revision": revision })),
+                                status,
+                            )
                         }),
                 ),
             )
             .boxed()
     }
 
+    fn merge(&self) -> BoxedFilter<(impl Reply,)> {
+        warp::post()
+            .and(with_auth())
+            .and(warp::path!("edit" / "merge"))
+            .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+            .and(warp::body::json())
+            .then(|request: MergeRequest| async {
+                match WikiRunner::merge(request).await {
+                    Ok(result) => {
+                        warp::reply::with_status(warp::reply::json(&result), StatusCode::OK)
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        warp::reply::with_status(
+                            warp::reply::json(&json!({ "error": e.to_string() })),
+                            StatusCode::NOT_FOUND,
+                        )
+                    }
+                }
+            })
+            .boxed()
+    }
+
     fn quick_add(&self) -> BoxedFilter<(impl Reply,)> {
         let (_, queue) = &self.parts;
         warp::post()
@@ -0,0 +1,155 @@
+use std::{convert::Infallible, sync::Arc};
+
+use bytes::Buf;
+use futures::{Stream, TryStreamExt};
+use hyper::{
+    client::HttpConnector,
+    header::{self, HeaderMap, HeaderValue},
+    Body, Client, Method, Request, Response, StatusCode,
+};
+use warp::{path::FullPath, Filter};
+
+/// A notebook run by its own tendril process, served under `/<prefix>/`. The notebook is told
+/// its prefix and puts its links, redirects and cookies under it, so requests and responses are
+/// passed through as they are.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub prefix: String,
+    pub port: u16,
+}
+
+/// Prefixes become the first segment of every URL in the notebook, so keep them to one plain
+/// path segment.
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty()
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+/// Splits a request path into the prefix of the notebook it's for and the path within the
+/// notebook.
+fn split_prefix(path: &str) -> (&str, &str) {
+    let trimmed = path.trim_start_matches('/');
+    match trimmed.find('/') {
+        Some(idx) => (&trimmed[..idx], &trimmed[idx..]),
+        None => (trimmed, ""),
+    }
+}
+
+/// Serves every mounted notebook from one port, passing requests under a prefix on to the
+/// notebook's own server.
+pub async fn hub(port: u16, mounts: Vec<Mount>) {
+    let mounts = Arc::new(mounts);
+    let client = Client::new();
+    let routes = warp::method()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::stream())
+        .and_then(move |method, path: FullPath, query, headers, body| {
+            forward(
+                client.clone(),
+                mounts.clone(),
+                method,
+                path.as_str().to_string(),
+                query,
+                headers,
+                body,
+            )
+        });
+    pretty_env_logger::init();
+    let log = warp::log("hub");
+    println!("┌──────────────────────────────────────────────┐");
+    println!("│Starting notebook hub @ http://127.0.0.1:{} │", port);
+    println!("└──────────────────────────────────────────────┘");
+    warp::serve(routes.with(log))
+        .run(([0, 0, 0, 0], port))
+        .await;
+}
+
+async fn forward<B>(
+    client: Client<HttpConnector>,
+    mounts: Arc<Vec<Mount>>,
+    method: Method,
+    path: String,
+    query: String,
+    mut headers: HeaderMap,
+    body: impl Stream<Item = Result<B, warp::Error>> + Send + Sync + 'static,
+) -> Result<Response<Body>, Infallible>
+where
+    B: Buf + Send + 'static,
+{
+    let (prefix, rest) = split_prefix(&path);
+    if prefix.is_empty() {
+        return Ok(reply(StatusCode::OK, index_page(&mounts)));
+    }
+    let mount = match mounts.iter().find(|mount| mount.prefix == prefix) {
+        Some(mount) => mount,
+        None => return Ok(reply(StatusCode::NOT_FOUND, "Not Found".into())),
+    };
+    if rest.is_empty() {
+        let mut response = reply(StatusCode::MOVED_PERMANENTLY, String::new());
+        response.headers_mut().insert(
+            header::LOCATION,
+            HeaderValue::from_str(&format!("/{}/", prefix)).unwrap(),
+        );
+        return Ok(response);
+    }
+    let mut uri = format!("http://127.0.0.1:{}{}", mount.port, rest);
+    if !query.is_empty() {
+        uri.push('?');
+        uri.push_str(&query);
+    }
+    headers.remove(header::HOST);
+    headers.remove(header::CONNECTION);
+    let body = Body::wrap_stream(body.map_ok(|mut buf| buf.copy_to_bytes(buf.remaining())));
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .unwrap();
+    *request.headers_mut() = headers;
+    match client.request(request).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            eprintln!("Could not reach the {} notebook: {}", mount.prefix, e);
+            Ok(reply(StatusCode::BAD_GATEWAY, "Bad Gateway".into()))
+        }
+    }
+}
+
+fn reply(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn index_page(mounts: &[Mount]) -> String {
+    let links = mounts
+        .iter()
+        .map(|mount| format!(r#"<li><a href="/{0}/">{0}</a></li>"#, mount.prefix))
+        .collect::<String>();
+    format!(
+        "<!DOCTYPE html><html lang=\"en-US\"><head><meta charset=\"utf-8\" /><title>Tendril Wiki</title></head><body><h1>Notebooks</h1><ul>{}</ul></body></html>",
+        links
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_requests_by_prefix() {
+        assert_eq!(split_prefix("/"), ("", ""));
+        assert_eq!(split_prefix("/work"), ("work", ""));
+        assert_eq!(split_prefix("/work/"), ("work", "/"));
+        assert_eq!(split_prefix("/work/files/list"), ("work", "/files/list"));
+        assert!(is_valid_prefix("home-notes_2"));
+        assert!(!is_valid_prefix("work/notes"));
+        assert!(!is_valid_prefix(""));
+    }
+}
@@ -5,9 +5,10 @@ use persistance::fs::{config::General, utils::normalize_wiki_location};
 use std::{path::PathBuf, sync::Arc};
 use task_runners::JobQueue;
 use warp::Filter;
-use wikitext::GlobalBacklinks;
+use wikitext::{parsers::base_path, GlobalBacklinks};
 
 pub mod handlers;
+pub mod hub;
 pub mod services;
 
 use crate::handlers::*;
@@ -17,17 +18,13 @@ pub(crate) type RefHubParts = (GlobalBacklinks, Arc<JobQueue>);
 pub async fn server(config: General, parts: RefHubParts) {
     let media_location = Arc::new(normalize_wiki_location(&config.media_location));
     let cloned = parts.clone();
-    let static_page_router = StaticPageRouter::new(
-        Arc::new(config.user),
-        media_location.clone(),
-        Arc::new(config.host),
-        cloned.0,
-    );
+    let user = Arc::new(config.user);
+    let static_page_router = StaticPageRouter::new(user.clone(), Arc::new(config.host), cloned.0);
     let wiki_router = WikiPageRouter::new(parts.clone());
 
     let task_router = TaskPageRouter::new();
     let static_files_router = StaticFileRouter::new(media_location.clone());
-    let api_router = APIRouter::new();
+    let api_router = APIRouter::new(user, parts.0.clone());
     let bookmark_router = bookmark_handler::BookmarkPageRouter::new(parts.1.clone());
     pretty_env_logger::init();
     // Order matters!!
@@ -51,7 +48,13 @@ pub async fn server(config: General, parts: RefHubParts) {
     println!("┌──────────────────────────────────────────────┐");
     println!("│Starting web backend @ http://127.0.0.1:{}  │", port);
     println!("└──────────────────────────────────────────────┘");
-    warp::serve(routes).run(([0, 0, 0, 0], port)).await;
+    // Notebooks served by the hub under a prefix are only reached through it.
+    let address = if base_path().is_empty() {
+        [0, 0, 0, 0]
+    } else {
+        [127, 0, 0, 1]
+    };
+    warp::serve(routes).run((address, port)).await;
 }
 
 #[cfg(debug_assertions)]
@@ -1,6 +1,12 @@
 (function () {
   const linkTo = document.getElementById("linkto");
   const del = document.getElementById("delete-form");
+  const move = document.getElementById("move-form");
+  const history = document.getElementById("history-link");
+  const permalink = document.getElementById("permalink");
   linkTo.remove();
   del.remove();
+  move.remove();
+  history.remove();
+  permalink.remove();
 })();
@@ -1,3 +1,4 @@
+const basePath = document.documentElement.dataset.base ?? "";
 const sidebar = document.querySelector("nav");
 const additionalActionsDialog = document.getElementById("additional-actions");
 const showAA = document.querySelector("#additional-actions + button");
@@ -7,14 +8,14 @@ const closeRecents = document.querySelector("#recently-edited button");
 
 const openRecents = document.getElementById("get-recents");
 openRecents.onclick = async () => {
-  const response = await fetch("/api/mru");
+  const response = await fetch(`${basePath}/api/mru`);
   const data = await response.json();
   const list = document.createElement("ul");
   list.style.listStyle = "none";
   for (const item of data) {
     const listItem = document.createElement("li");
     const link = document.createElement("a");
-    link.href = `/${item}`;
+    link.href = `${basePath}/${item}`;
     link.textContent = item;
     listItem.appendChild(link);
     listItem.style.margin = "0.5rem 0";
@@ -35,3 +36,19 @@ showAA.addEventListener("click", () => {
 closeAA.addEventListener("click", () => {
   additionalActionsDialog.close();
 });
+
+// Permalinks go through the note's id, so they keep working after renames.
+const permalink = document.getElementById("permalink");
+if (permalink) {
+  permalink.addEventListener("click", async (e) => {
+    e.preventDefault();
+    const response = await fetch(permalink.href);
+    if (!response.ok) {
+      permalink.textContent = "\u{1F4CC} save the note to get a permalink";
+      return;
+    }
+    const data = await response.json();
+    await navigator.clipboard.writeText(new URL(`${basePath}/id/${data.id}`, location.origin).href);
+    permalink.textContent = "\u{1F4CC} copied permalink";
+  });
+}
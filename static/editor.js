@@ -28,6 +28,6 @@ const shouldRunTests = false;
 (async () => {
   if (shouldRunTests) {
     // import testing module for side effects
-    await import("/static/tests.js");
+    await import("./tests.js");
   }
 })();
@@ -1,6 +1,5 @@
-import { assign, StateMachine } from "./utils.js";
-import { Scorer } from "./score.js";
-import caretPos from "/static/vendors/caretposition.js";
+import { assign, BASE, StateMachine } from "./utils.js";
+import caretPos from "../vendors/caretposition.js";
 
 const stateChart = {
   initial: "idle",
@@ -124,7 +123,7 @@ export function autocomplete(e) {
   }
 }
 
-const matcher = new Scorer(3);
+const MAX_SUGGESTIONS = 15;
 
 function setupMenu(e) {
   caretPos();
@@ -148,10 +147,13 @@ function setupMenu(e) {
 async function updateSuggestionMenu() {
   const context = machine.context().completionContext;
   if (context.length < 2) return;
-  const candidates = await matcher.test(context);
-  const opts = candidates.sort((a, b) => a.score - b.score).slice(0, 15);
+  const params = new URLSearchParams({ q: context, limit: MAX_SUGGESTIONS });
+  const opts = await fetch(`${BASE}/api/suggest?${params}`).then((res) => res.json());
+  // Typing carries on while waiting, only the latest suggestions are wanted.
+  if (context !== machine.context().completionContext) return;
   const container = document.getElementById("autocomplete-list");
-  const elements = opts.map(({ value: opt }, idx) => {
+  if (!container) return;
+  const elements = opts.map((opt, idx) => {
     const item = document.createElement("li");
     const innerButton = document.createElement("button");
     innerButton.innerText = opt;
@@ -192,5 +194,5 @@ export function removeAutoCompleteMenu() {
 
 const styles = document.createElement("link");
 styles.rel = "stylesheet";
-styles.href = "/static/autocomplete.css";
+styles.href = `${BASE}/static/autocomplete.css`;
 document.head.appendChild(styles);
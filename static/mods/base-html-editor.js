@@ -23,7 +23,8 @@ export class HTMLEditor {
     this.element = element;
     this.eventTarget = undefined;
     // plain-text tag array
-    this.content = htmlToText(this.element);
+    // Markdown notes are rendered on the server, the block carries their source.
+    this.content = this.element.dataset.source ?? htmlToText(this.element);
     this.bc = new BroadcastChannel(`tendril-wiki${location.pathname}`);
     this.machine = new StateMachine({
       ...stateChart,
@@ -1,7 +1,7 @@
 import { textToHtml } from "./parsing.js";
 import { moveCaretToEnd, moveCaretToStart } from "./dom.js";
 import { HTMLEditor } from "./base-html-editor.js";
-import { nanoid } from "./utils.js";
+import { BASE, nanoid } from "./utils.js";
 import {
   setAsFocused,
   updateInputHeight,
@@ -19,6 +19,8 @@ export class BlockEditor extends HTMLEditor {
     super(element);
     this.id = `block@${nanoid()}`;
     this.indent = parseInt(element.dataset.indent || 0, 10);
+    // A Markdown note is edited as a single block, Enter starts a new line instead of a block.
+    this.markdown = element.classList.contains("markdown");
     if (element.nodeName === "TEXTAREA") {
       this.setupTextblockListeners(element);
     } else {
@@ -36,6 +38,7 @@ export class BlockEditor extends HTMLEditor {
     const el = document.createElement("div");
     el.innerHTML = html;
     el.classList.add("text-block");
+    if (this.markdown) el.classList.add("markdown");
     el.addEventListener("click", this.setupEditor);
     el.addEventListener("keyup", (e) => {
       if (e.key === "Enter") {
@@ -59,6 +62,7 @@ export class BlockEditor extends HTMLEditor {
     }
     textblock.setAttribute("spellcheck", true);
     textblock.classList.add("text-block");
+    if (this.markdown) textblock.classList.add("markdown");
     this.setupTextblockListeners(textblock);
     this.element.replaceWith(textblock);
     setAsFocused(textblock);
@@ -71,7 +75,7 @@ export class BlockEditor extends HTMLEditor {
     this.#shouldStopExecution = autocompleteState() === "completing";
     switch (e.key) {
       case "Tab": {
-        if (!this.#shouldStopExecution) {
+        if (!this.#shouldStopExecution && !this.markdown) {
           e.preventDefault();
           if (this.indent) {
             // Max indent is 3 levels, min is 0
@@ -104,7 +108,7 @@ export class BlockEditor extends HTMLEditor {
         break;
       }
       case "Enter": {
-        if (!e.shiftKey && !this.#shouldStopExecution) {
+        if (!e.shiftKey && !this.#shouldStopExecution && !this.markdown) {
           e.preventDefault();
           const indentation = this.indent;
           this.addBlock(indentation);
@@ -137,7 +141,7 @@ export class BlockEditor extends HTMLEditor {
           `image-${new Date().valueOf()}.${extension}`,
         );
         const blob = formData.get("file");
-        fetch("/files", {
+        fetch(`${BASE}/files`, {
           method: "POST",
           headers: {
             "Content-Type": "application/octet-stream",
@@ -145,9 +149,16 @@ export class BlockEditor extends HTMLEditor {
           },
           body: blob,
         })
-          .then(() => {
+          .then((res) => {
+            if (!res.ok) throw new Error(`upload failed with ${res.status}`);
+            // The server may store the image under a different name.
+            return res.text();
+          })
+          .then((name) => {
             // TODO figure out alt text...
-            e.target.value += `${window.location.origin}/files/${blob.name}`;
+            e.target.value += `${window.location.origin}/files/${encodeURIComponent(
+              name,
+            )}`;
             e.target.dispatchEvent(new Event("change"));
           })
           .catch((e) => {
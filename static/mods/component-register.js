@@ -1,5 +1,5 @@
 import { htmlToText } from "./parsing.js";
-import { StateMachine, LinkedList } from "./utils.js";
+import { BASE, StateMachine, LinkedList, noteHref } from "./utils.js";
 
 const stateChart = {
   initial: "idle",
@@ -96,6 +96,9 @@ export class ComponentRegister {
   dump = () => this.components;
   savePage = (messageData) => {
     this.#machine.send("SUBMITTING");
+    // The body as it was before this edit, used as the common ancestor if the note was changed
+    // somewhere else in the meantime.
+    const base = this.components.blocks.toContentString();
     if (messageData) {
       if (!this.isBlock(messageData.id)) {
         this.components[messageData.id].content = messageData.content;
@@ -114,16 +117,16 @@ export class ComponentRegister {
       old_title: CURRENT_TITLE,
       tags,
       metadata,
+      revision: typeof CURRENT_REVISION === "undefined" ? "" : CURRENT_REVISION,
     };
-    fetch("/edit", {
-      method: "POST",
-      body: JSON.stringify(constructedBody),
-      headers: {
-        "content-type": "application/json",
-      },
-    })
-      .then((res) => {
+    this.submit(constructedBody)
+      .then(async (res) => {
+        if (res.status === 409) {
+          await this.merge(base, constructedBody);
+          return;
+        }
         if (res.status < 400) {
+          await this.updateRevision(res);
           if (document.title === "New Entry") {
             history.pushState(
               {},
@@ -139,5 +142,48 @@ export class ComponentRegister {
         this.#machine.send("ERROR", e);
       });
   };
+  submit = (constructedBody) =>
+    fetch(`${BASE}/edit`, {
+      method: "POST",
+      body: JSON.stringify(constructedBody),
+      headers: {
+        "content-type": "application/json",
+      },
+    });
+  updateRevision = async (res) => {
+    const { revision } = await res.json();
+    if (typeof CURRENT_REVISION !== "undefined") {
+      CURRENT_REVISION = revision;
+    }
+  };
+  // Someone else saved this note since it was loaded. Merge both versions on the server, save
+  // the result and reload so the editor shows the merged note.
+  merge = async (base, constructedBody) => {
+    const res = await fetch(`${BASE}/edit/merge`, {
+      method: "POST",
+      body: JSON.stringify({ base, patch: constructedBody }),
+      headers: {
+        "content-type": "application/json",
+      },
+    });
+    if (res.status >= 400) {
+      throw new Error("could not merge with the latest version of the note");
+    }
+    const merged = await res.json();
+    const saved = await this.submit({
+      ...constructedBody,
+      body: merged.body,
+      revision: merged.revision,
+    });
+    if (saved.status >= 400) {
+      throw new Error("could not save the merged note");
+    }
+    if (merged.conflicted) {
+      alert(
+        "This note was changed somewhere else while you were editing. Both versions were kept, resolve the sections between <<<<<<< and >>>>>>>.",
+      );
+    }
+    location.assign(noteHref(constructedBody.title));
+  };
   isBlock = (id) => id.includes("block");
 }
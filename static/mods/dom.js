@@ -1,3 +1,5 @@
+import { BASE, noteHref } from "./utils.js";
+
 export function updateMRU(title) {
   const mru = document.getElementById("mru");
   const links = mru.querySelectorAll("a");
@@ -18,14 +20,14 @@ export function updateMRU(title) {
   }
   if (!found) {
     const newEntry = document.createElement("li");
-    newEntry.innerHTML = `<a href="${title}">${title}</a>`;
+    newEntry.innerHTML = `<a href="${noteHref(title)}">${title}</a>`;
     mru.insertBefore(newEntry, mru.firstChild);
     if (links.length >= 8) {
       // Trim off the last link. List should only be 8 entries long.
       mru.removeChild(links[7].parentNode);
     }
   }
-  if (CURRENT_TITLE !== title || window.location.pathname === "/new") {
+  if (CURRENT_TITLE !== title || window.location.pathname === `${BASE}/new`) {
     history.pushState({ name: "edit page title" }, "", title);
     document.title = title;
     CURRENT_TITLE = title;
@@ -5,9 +5,10 @@ import {
   transformVimeoUrl,
   transformSpotifyUrl,
 } from "./transformers.js";
+import { noteHref } from "./utils.js";
 
 const WIKI_LINK_REGEXP = new RegExp(
-  /\[\[([a-zA-Z0-9\s?\-?'?:?_?’?(\|)?]+)\]\]/,
+  /\[\[([a-zA-Z0-9\s?\-?'?:?_?’?(\|)?\/]+)\]\]/,
   "g"
 );
 const EMAIL_REGEXP = new RegExp(
@@ -40,12 +41,12 @@ function parseWikiLinks(text) {
     if (alias.length > 1) {
       finalString = finalString.replaceAll(
         match[0],
-        `<a href="/${encodeURIComponent(alias[1])}">${alias[0]}</a>`
+        `<a href="${noteHref(alias[1])}">${alias[0]}</a>`
       );
     } else {
       finalString = finalString.replaceAll(
         match[0],
-        `<a href="/${encodeURIComponent(match[1])}">${match[1]}</a>`
+        `<a href="${noteHref(match[1])}">${match[1]}</a>`
       );
     }
   }
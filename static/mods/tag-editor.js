@@ -1,5 +1,5 @@
 import { setAsFocused } from "./block-actions.js";
-import { isIOS, normalizePunctuation } from "./utils.js";
+import { isIOS, normalizePunctuation, noteHref } from "./utils.js";
 
 export class TagEditor {
   constructor(element) {
@@ -33,7 +33,7 @@ export class TagEditor {
     this.content.forEach((tag) => {
       const trimmed = tag.trim();
       const child = document.createElement("li");
-      child.innerHTML = `<a href="${trimmed}">#${trimmed}</a>`;
+      child.innerHTML = `<a href="${noteHref(trimmed)}">#${trimmed}</a>`;
       list.appendChild(child);
     });
     container.appendChild(list);
@@ -1,5 +1,6 @@
 import { HTMLEditor } from "./base-html-editor.js";
 import { setAsFocused } from "./block-actions.js";
+import { BASE } from "./utils.js";
 
 export class TitleEditor extends HTMLEditor {
   #titles;
@@ -39,7 +40,7 @@ export class TitleEditor extends HTMLEditor {
 
   change = async (e) => {
     if (!this.#titles) {
-      this.#titles = await fetch("/titles")
+      this.#titles = await fetch(`${BASE}/titles`)
         .then((res) => res.json())
         .then((titles) => titles.map((t) => t.toLowerCase()));
     }
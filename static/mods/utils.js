@@ -116,6 +116,14 @@ const punctMap = {
   [0x2013]: "-", // and "--" with en-dash
 };
 
+// The path the wiki is served under, empty unless the notebook hub serves it under a prefix.
+export const BASE = document.documentElement.dataset.base ?? "";
+
+// Encodes each namespace segment of a note title, keeping the `/` separators.
+export function noteHref(title) {
+  return `${BASE}/${title.split("/").map(encodeURIComponent).join("/")}`;
+}
+
 export function isIOS() {
   return (
     ["iPad", "iPhone", "iPod"].some((p) => navigator.platform.includes(p)) ||
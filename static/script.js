@@ -1,4 +1,6 @@
 (function () {
+  const base = document.documentElement.dataset.base ?? "";
+
   function replaceOGMeta() {
     const metas = document.querySelectorAll("meta");
     for (const meta of metas) {
@@ -21,7 +23,7 @@
   function removeLogoutIfNotLoggedIn() {
     if (!document.cookie.login) {
       const footer = document.querySelector(".footer");
-      const logout = footer.querySelector('a[href="/logout"]');
+      const logout = footer.querySelector(`a[href="${base}/logout"]`);
       logout.remove();
     }
   }
@@ -39,7 +41,7 @@
   }
 
   function jump(location) {
-    const url = new URL(`/${location}`, window.location.origin);
+    const url = new URL(`${base}/${location}`, window.location.origin);
     window.location.href = url;
   }
 
@@ -58,8 +60,8 @@
         jump("new");
         break;
       case "l": {
-        // Remove leading '/' of the current note
-        const currentWiki = window.location.pathname.slice(1);
+        // Remove the base and leading '/' of the current note
+        const currentWiki = window.location.pathname.slice(base.length + 1);
         jump(`new?linkto=${currentWiki}`);
         break;
       }
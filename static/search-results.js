@@ -1,4 +1,5 @@
 import { textToHtml } from "./mods/parsing.js";
+import { BASE } from "./mods/utils.js";
 
 const expandButtons = document.querySelectorAll(".expand");
 
@@ -17,7 +18,7 @@ async function expand(event) {
   }
   const requestedDoc =
     event.currentTarget.parentElement.querySelector("a").innerText;
-  const body = await fetch(`/api/${requestedDoc}`).then((response) =>
+  const body = await fetch(`${BASE}/api/${requestedDoc}`).then((response) =>
     response.json()
   );
   const { content } = body;
@@ -1,10 +1,6 @@
 const CACHE_NAME = "1.0.1";
-const PAGES = [
-  "/static/style.css",
-  "/static/mobile.css",
-  "/",
-  "/static/script.js",
-];
+// Relative to the worker, so notebooks served under a prefix cache their own pages.
+const PAGES = ["./style.css", "./mobile.css", "../", "./script.js"];
 
 // install pages
 self.addEventListener("install", installWorker);
@@ -1,4 +1,5 @@
 (function () {
+  const base = document.documentElement.dataset.base ?? "";
   const SORT_DIR = {
     ASC: "ascending",
     DESC: "descending",
@@ -31,7 +32,7 @@
       content: `${formatDate()} ${task}`,
     };
     try {
-      const request = await fetch("/tasks/create", {
+      const request = await fetch(`${base}/tasks/create`, {
         method: "POST",
         headers: {
           "Content-Type": "application/json",
@@ -96,7 +97,7 @@
       throw new Error("All cells should render with a data index.");
     }
     try {
-      const request = await fetch(`/tasks/delete/${dataIdx}`, {
+      const request = await fetch(`${base}/tasks/delete/${dataIdx}`, {
         method: "DELETE",
         credentials: "same-origin",
       });
@@ -287,7 +288,7 @@
    * @param task TaskRecord { id: number, data: Record<String, String> }
    */
   async function updateTask(task) {
-    return fetch(`/tasks/update/${task.id}`, {
+    return fetch(`${base}/tasks/update/${task.id}`, {
       method: "PUT",
       headers: {
         "Content-Type": "application/json",
@@ -4,7 +4,8 @@
   const { tag_name } = await fetch(
     "https://api.github.com/repos/jamestthompson3/tendril-wiki/releases/latest"
   ).then((res) => res.json());
-  const currentVersion = await fetch("/version").then((res) => res.json());
+  const base = document.documentElement.dataset.base ?? "";
+  const currentVersion = await fetch(`${base}/version`).then((res) => res.json());
   if (currentVersion !== tag_name) {
     const dismissed = localStorage.getItem(tag_name);
     if (dismissed) return;